@@ -1,5 +1,7 @@
 fn main() {
-    prost_build::compile_protos(&[
+    // the descriptors drive the otlp json decoding
+    let descriptors = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("otlp_descriptors.bin");
+    prost_build::Config::new().file_descriptor_set_path(descriptors).compile_protos(&[
         "src/proto/opentelemetry-proto/opentelemetry/proto/common/v1/common.proto",
         "src/proto/opentelemetry-proto/opentelemetry/proto/resource/v1/resource.proto",
         "src/proto/opentelemetry-proto/opentelemetry/proto/trace/v1/trace.proto",
@@ -10,4 +12,8 @@ fn main() {
         "src/proto/opentelemetry-proto/opentelemetry/proto/collector/metrics/v1/metrics_service.proto",
         "src/proto/opentelemetry-proto/opentelemetry/proto/collector/logs/v1/logs_service.proto",
     ], &["src/proto/opentelemetry-proto"]).expect("Error generating protobuf");
+    prost_build::compile_protos(&[
+        "src/proto/jaeger/model.proto",
+        "src/proto/zipkin/zipkin.proto",
+    ], &["src/proto/jaeger", "src/proto/zipkin"]).expect("Error generating protobuf");
 }
@@ -0,0 +1,170 @@
+use crate::cmd_conformance::percent_decode;
+use crate::common::parse_trace_id;
+use crate::otk_error::OTKError;
+use crate::otlp_json;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value as Json};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// what conformance --listen accepted, kept in memory for the --api endpoints
+/// and --emit-scenario
+#[derive(Debug, Default)]
+pub struct Received {
+    requests: u64,
+    traces: Vec<ExportTraceServiceRequest>,
+    log_records: usize,
+    metrics: usize,
+}
+
+impl Received {
+    pub fn add_traces(&mut self, req: &ExportTraceServiceRequest) {
+        self.requests += 1;
+        self.traces.push(req.clone());
+    }
+
+    pub fn add_logs(&mut self, req: &ExportLogsServiceRequest) {
+        self.requests += 1;
+        self.log_records += req
+            .resource_logs
+            .iter()
+            .flat_map(|rl| &rl.scope_logs)
+            .map(|sl| sl.log_records.len())
+            .sum::<usize>();
+    }
+
+    pub fn add_metrics(&mut self, req: &ExportMetricsServiceRequest) {
+        self.requests += 1;
+        self.metrics += req
+            .resource_metrics
+            .iter()
+            .flat_map(|rm| &rm.scope_metrics)
+            .map(|sm| sm.metrics.len())
+            .sum::<usize>();
+    }
+
+    pub fn spans(&self) -> impl Iterator<Item = &Span> {
+        self.traces
+            .iter()
+            .flat_map(|req| &req.resource_spans)
+            .flat_map(|rs| &rs.scope_spans)
+            .flat_map(|ss| &ss.spans)
+    }
+
+    /// the received spans `keep` selects, as one request grouped by
+    /// resource and scope like they were sent
+    fn select(&self, keep: impl Fn(&Span) -> bool) -> ExportTraceServiceRequest {
+        let mut selected = ExportTraceServiceRequest::default();
+        for rs in self.traces.iter().flat_map(|req| &req.resource_spans) {
+            let scope_spans = rs
+                .scope_spans
+                .iter()
+                .map(|ss| ScopeSpans { spans: ss.spans.iter().filter(|s| keep(s)).cloned().collect(), ..ss.clone() })
+                .filter(|ss| !ss.spans.is_empty())
+                .collect::<Vec<_>>();
+            if !scope_spans.is_empty() {
+                selected.resource_spans.push(ResourceSpans { scope_spans, ..rs.clone() });
+            }
+        }
+        selected
+    }
+
+    fn stats(&self) -> Json {
+        let traces = self.spans().map(|s| &s.trace_id).collect::<HashSet<_>>();
+        json!({
+            "requests": self.requests,
+            "spans": self.spans().count(),
+            "traces": traces.len(),
+            "log_records": self.log_records,
+            "metrics": self.metrics,
+        })
+    }
+}
+
+/// address of --api, `:8080` is short for all interfaces
+pub fn parse_api_addr(s: &str) -> Result<SocketAddr, OTKError> {
+    let full = match s.starts_with(':') {
+        true => format!("0.0.0.0{}", s),
+        false => s.to_string(),
+    };
+    full.parse()
+        .map_err(|_| OTKError::ParseError(format!("invalid address {} (expect host:port or :port)", s)))
+}
+
+/// serve the endpoints in the background, returning the bound address. must
+/// be called within a tokio runtime
+pub fn serve(addr: SocketAddr, received: Arc<Mutex<Received>>) -> Result<SocketAddr, Box<dyn error::Error>> {
+    let make_service = make_service_fn(move |_| {
+        let received = received.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let received = received.clone();
+                async move { Ok::<_, Infallible>(handle(&req, &received.lock().unwrap())) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("api: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+/// `GET /traces/{id}` the spans of a trace, `GET /spans[?name=...]` the
+/// spans (of a name) and `GET /stats` the counts, spans as otlp json
+fn handle(req: &Request<Body>, received: &Received) -> Response<Body> {
+    if req.method() != Method::GET {
+        return respond(StatusCode::METHOD_NOT_ALLOWED, json!({"error": "only GET is supported"}));
+    }
+    let path = req.uri().path();
+    if let Some(id) = path.strip_prefix("/traces/") {
+        let trace_id = match parse_trace_id(id) {
+            Ok(trace_id) => trace_id.to_bytes(),
+            Err(e) => return respond(StatusCode::BAD_REQUEST, json!({"error": e.to_string()})),
+        };
+        let trace = received.select(|s| s.trace_id == trace_id);
+        if trace.resource_spans.is_empty() {
+            return respond(StatusCode::NOT_FOUND, json!({"error": format!("trace {} not received", id)}));
+        }
+        return respond(StatusCode::OK, otlp_json::trace_request(&trace));
+    }
+    match path {
+        "/spans" => {
+            let name = query(req, "name");
+            let spans = received.select(|s| name.as_ref().is_none_or(|name| s.name == *name));
+            respond(StatusCode::OK, otlp_json::trace_request(&spans))
+        }
+        "/stats" => respond(StatusCode::OK, received.stats()),
+        _ => respond(
+            StatusCode::NOT_FOUND,
+            json!({"error": "unknown endpoint (expect /traces/{id}, /spans?name=... or /stats)"}),
+        ),
+    }
+}
+
+/// a decoded query parameter
+fn query(req: &Request<Body>, key: &str) -> Option<String> {
+    req.uri().query()?.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then(|| percent_decode(v.replace('+', " ").as_bytes()))
+    })
+}
+
+fn respond(status: StatusCode, body: Json) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
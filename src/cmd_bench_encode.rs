@@ -0,0 +1,131 @@
+use crate::common::{parse_count, INSTRUMENTATION_LIB_NAME};
+use crate::otk_error::OTKError;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::any_value::Value;
+use crate::proto::common::v1::{AnyValue, InstrumentationScope, KeyValue as ProtoKeyValue};
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+use clap::Parser;
+use prost::Message;
+use std::error;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// measure how fast otk constructs and encodes spans, to size it as a load
+/// generator and catch regressions (nothing is sent)
+#[derive(Parser, Debug)]
+pub struct BenchEncode {
+    /// spans to construct and encode per attribute count (e.g. 500k, 1M)
+    #[clap(long, default_value = "100k", value_parser = parse_count)]
+    spans: u64,
+
+    /// attribute counts per span to measure, one run each (comma separated)
+    #[clap(long, value_delimiter = ',', default_value = "0,10,50")]
+    attrs: Vec<usize>,
+
+    /// spans per export request
+    #[clap(long, default_value_t = 512)]
+    batch: u64,
+}
+
+/// time spent and bytes produced by one run
+#[derive(Debug, Default)]
+struct Run {
+    construct: Duration,
+    encode: Duration,
+    bytes: u64,
+}
+
+pub fn do_bench_encode(bench: BenchEncode) -> Result<(), Box<dyn error::Error>> {
+    if bench.spans == 0 || bench.batch == 0 {
+        return Err(Box::new(OTKError::InvalidArgumentError("--spans and --batch must be positive".into())));
+    }
+    println!("{} spans per run, {} per request", bench.spans, bench.batch);
+    println!(
+        "{:>6} {:>12} {:>12} {:>14} {:>10} {:>11}",
+        "attrs", "construct", "encode", "spans/s", "MB/s", "bytes/span"
+    );
+    for &attrs in &bench.attrs {
+        let run = run(bench.spans, attrs, bench.batch);
+        let total = (run.construct + run.encode).as_secs_f64();
+        println!(
+            "{:>6} {:>12} {:>12} {:>14.0} {:>10.1} {:>11.1}",
+            attrs,
+            format!("{:.2?}", run.construct),
+            format!("{:.2?}", run.encode),
+            bench.spans as f64 / total,
+            run.bytes as f64 / total / 1e6,
+            run.bytes as f64 / bench.spans as f64
+        );
+    }
+    Ok(())
+}
+
+/// construct and encode `spans` spans of `attrs` attributes, `batch` to a
+/// request
+fn run(spans: u64, attrs: usize, batch: u64) -> Run {
+    let keys = (0..attrs).map(|i| format!("otk.bench.attr_{}", i)).collect::<Vec<_>>();
+    let mut run = Run::default();
+    let mut buf = vec![];
+    let mut left = spans;
+    while left > 0 {
+        let n = left.min(batch);
+        let start = Instant::now();
+        let req = request(n, &keys);
+        run.construct += start.elapsed();
+
+        let start = Instant::now();
+        buf.clear();
+        // a Vec grows as needed, encoding into it can't fail
+        req.encode(&mut buf).unwrap();
+        run.encode += start.elapsed();
+        run.bytes += buf.len() as u64;
+        left -= n;
+    }
+    run
+}
+
+/// a request of `n` spans, each with an attribute of every key, strings and
+/// ints alternating
+fn request(n: u64, keys: &[String]) -> ExportTraceServiceRequest {
+    let end = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    let spans = (0..n)
+        .map(|i| Span {
+            trace_id: rand::random::<[u8; 16]>().to_vec(),
+            span_id: rand::random::<[u8; 8]>().to_vec(),
+            name: "otk_bench_span".into(),
+            start_time_unix_nano: end - 1_000_000,
+            end_time_unix_nano: end,
+            attributes: keys
+                .iter()
+                .enumerate()
+                .map(|(j, key)| ProtoKeyValue {
+                    key: key.clone(),
+                    value: Some(AnyValue {
+                        value: Some(match j % 2 {
+                            0 => Value::StringValue(format!("value-{}", i)),
+                            _ => Value::IntValue(i as i64),
+                        }),
+                    }),
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+    ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: Some(Resource {
+                attributes: vec![ProtoKeyValue {
+                    key: "service.name".into(),
+                    value: Some(AnyValue { value: Some(Value::StringValue("otk-bench".into())) }),
+                }],
+                ..Default::default()
+            }),
+            scope_spans: vec![ScopeSpans {
+                scope: Some(InstrumentationScope { name: INSTRUMENTATION_LIB_NAME.into(), ..Default::default() }),
+                spans,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
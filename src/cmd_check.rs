@@ -0,0 +1,216 @@
+use crate::filter;
+use crate::otk_error::OTKError;
+use crate::proto;
+use crate::sanity;
+use clap::Parser;
+use hex::ToHex;
+use prost::Message;
+use std::collections::{BTreeMap, HashSet};
+use std::error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// check trace captures for integrity problems like spans whose parent or
+/// linked span is missing, or whose status contradicts their http status code
+/// (input is base64 encoded binary)
+#[derive(Parser, Debug)]
+pub struct Check {
+    /// file to read (- for stdin)
+    input: String,
+
+    /// report traces with fewer spans than this as truncated
+    #[clap(long)]
+    expect_spans_per_trace: Option<usize>,
+}
+
+/// spans of one trace seen in the capture
+#[derive(Debug, Default)]
+struct Trace {
+    spans: HashSet<String>,
+    /// (span id, parent span id) of every non-root span
+    parents: Vec<(String, String)>,
+    /// (span id, linked trace id, linked span id) of every span link
+    links: Vec<(String, String, String)>,
+    /// (span id, problem) of spans whose status contradicts their http status
+    status_mismatches: Vec<(String, String)>,
+}
+
+pub fn do_check(check: Check) -> Result<(), Box<dyn error::Error>> {
+    let mut traces: BTreeMap<String, Trace> = BTreeMap::new();
+    if check.input == "-" {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            collect(&line?, &mut traces)?;
+        }
+    } else {
+        let file = File::open(&check.input)?;
+        let reader = BufReader::new(file);
+        for line in reader.lines() {
+            collect(&line?, &mut traces)?;
+        }
+    }
+
+    let mut problems = 0;
+    let mut spans = 0;
+    let mut external = vec![];
+    for (trace_id, trace) in &traces {
+        spans += trace.spans.len();
+        for (span_id, parent) in &trace.parents {
+            if !trace.spans.contains(parent) {
+                println!("trace {}: span {} has missing parent {}", trace_id, span_id, parent);
+                problems += 1;
+            }
+        }
+        // links into traces outside the capture can't be verified
+        for (span_id, linked_trace, linked_span) in &trace.links {
+            match traces.get(linked_trace) {
+                None => external.push((trace_id, span_id, linked_trace, linked_span)),
+                Some(linked) if !linked.spans.contains(linked_span) => {
+                    println!(
+                        "trace {}: span {} links to missing span {} of trace {}",
+                        trace_id, span_id, linked_span, linked_trace
+                    );
+                    problems += 1;
+                }
+                Some(_) => {}
+            }
+        }
+        for (span_id, mismatch) in &trace.status_mismatches {
+            println!("trace {}: span {} {}", trace_id, span_id, mismatch);
+            problems += 1;
+        }
+        if let Some(expected) = check.expect_spans_per_trace {
+            if trace.spans.len() < expected {
+                println!("trace {}: truncated, {} of {} spans", trace_id, trace.spans.len(), expected);
+                problems += 1;
+            }
+        }
+    }
+    for (trace_id, span_id, linked_trace, linked_span) in &external {
+        println!(
+            "trace {}: span {} links to external trace {} span {}",
+            trace_id, span_id, linked_trace, linked_span
+        );
+    }
+    println!(
+        "{} traces, {} spans, {} external links, {} problems",
+        traces.len(),
+        spans,
+        external.len(),
+        problems
+    );
+    if problems > 0 {
+        return Err(Box::new(OTKError::CheckError(format!("{} problems found", problems))));
+    }
+    Ok(())
+}
+
+fn collect(payload: &str, traces: &mut BTreeMap<String, Trace>) -> Result<(), Box<dyn error::Error>> {
+    let bs = base64::decode_config(payload, base64::STANDARD)?;
+    let req = proto::collector::trace::v1::ExportTraceServiceRequest::decode(&bs as &[u8])?;
+    for span in filter::spans(&req) {
+        let trace = traces.entry(span.span.trace_id.encode_hex()).or_default();
+        let span_id: String = span.span.span_id.encode_hex();
+        if !span.span.parent_span_id.is_empty() {
+            trace.parents.push((span_id.clone(), span.span.parent_span_id.encode_hex()));
+        }
+        for link in &span.span.links {
+            trace.links.push((span_id.clone(), link.trace_id.encode_hex(), link.span_id.encode_hex()));
+        }
+        if let Some(mismatch) = sanity::http_status_mismatch(span.span) {
+            trace.status_mismatches.push((span_id.clone(), mismatch));
+        }
+        trace.spans.insert(span_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::proto::common::v1::any_value::Value;
+    use crate::proto::common::v1::{AnyValue, KeyValue};
+    use crate::proto::trace::v1::span::Link;
+    use crate::proto::trace::v1::status::StatusCode;
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span, Status};
+
+    /// a span of trace `trace` with id `id`, child of `parent` unless 0
+    fn span(trace: u8, id: u8, parent: u8) -> Span {
+        Span {
+            trace_id: vec![trace; 16],
+            span_id: vec![id; 8],
+            parent_span_id: if parent == 0 { vec![] } else { vec![parent; 8] },
+            ..Default::default()
+        }
+    }
+
+    /// run check over a capture of one request per line, returning the
+    /// problem count
+    fn problems(lines: Vec<Vec<Span>>, expect_spans_per_trace: Option<usize>) -> usize {
+        let path = std::env::temp_dir().join(format!("otk-check-{}-{:?}.b64", std::process::id(), std::thread::current().id()));
+        let capture = lines
+            .into_iter()
+            .map(|spans| {
+                let request = ExportTraceServiceRequest {
+                    resource_spans: vec![ResourceSpans {
+                        scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+                        ..Default::default()
+                    }],
+                };
+                base64::encode(request.encode_to_vec()) + "\n"
+            })
+            .collect::<String>();
+        std::fs::write(&path, capture).unwrap();
+        let result = do_check(Check { input: path.to_str().unwrap().into(), expect_spans_per_trace });
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Ok(()) => 0,
+            Err(e) => e.to_string().trim_start_matches("Check failed: ").split(' ').next().unwrap().parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn missing_parents() {
+        // spans of a trace may come in separate requests
+        assert_eq!(problems(vec![vec![span(1, 1, 0), span(1, 2, 1)], vec![span(1, 3, 2)]], None), 0);
+        assert_eq!(problems(vec![vec![span(1, 1, 0), span(1, 2, 9)], vec![span(2, 3, 1)]], None), 2);
+    }
+
+    #[test]
+    fn truncated_traces() {
+        let lines = vec![vec![span(1, 1, 0), span(1, 2, 1)], vec![span(2, 1, 0)]];
+        assert_eq!(problems(lines.clone(), Some(2)), 1);
+        assert_eq!(problems(lines, Some(1)), 0);
+    }
+
+
+    fn linked(mut span: Span, trace: u8, id: u8) -> Span {
+        span.links.push(Link { trace_id: vec![trace; 16], span_id: vec![id; 8], ..Default::default() });
+        span
+    }
+
+    #[test]
+    fn links() {
+        // a link within the capture must find its span, one outside it can't
+        // be verified
+        assert_eq!(problems(vec![vec![span(1, 1, 0)], vec![linked(span(2, 2, 0), 1, 1)]], None), 0);
+        assert_eq!(problems(vec![vec![span(1, 1, 0)], vec![linked(span(2, 2, 0), 1, 9)]], None), 1);
+        assert_eq!(problems(vec![vec![linked(span(2, 2, 0), 3, 1)]], None), 0);
+    }
+
+
+    #[test]
+    fn status_mismatches() {
+        let failed = Span {
+            attributes: vec![KeyValue {
+                key: "http.status_code".into(),
+                value: Some(AnyValue { value: Some(Value::IntValue(500)) }),
+            }],
+            ..span(1, 1, 0)
+        };
+        assert_eq!(problems(vec![vec![failed.clone()]], None), 1);
+        let failed = Span { status: Some(Status { code: StatusCode::Error as i32, ..Default::default() }), ..failed };
+        assert_eq!(problems(vec![vec![failed]], None), 0);
+    }
+}
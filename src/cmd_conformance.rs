@@ -0,0 +1,1131 @@
+use crate::api::{self, parse_api_addr, Received};
+use crate::compression::Compression;
+use crate::cmd_search::Signal;
+use crate::common::{parse_duration, KeyValue};
+use crate::grade::Grader;
+use crate::hook::{self, Hook};
+use crate::otk_error::OTKError;
+use crate::otlp_json;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::{any_value::Value, AnyValue, ArrayValue, KeyValue as ProtoKeyValue, KeyValueList};
+use crate::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+use crate::proto::metrics::v1::{
+    exponential_histogram_data_point::Buckets, metric, number_data_point, summary_data_point::ValueAtQuantile,
+    AggregationTemporality, ExponentialHistogram, ExponentialHistogramDataPoint, Gauge, Histogram,
+    HistogramDataPoint, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum, Summary, SummaryDataPoint,
+};
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+use crate::render::{Color, Renderer};
+use crate::scenario;
+use crate::store::{parse_store, Store};
+use crate::transport::Protocol;
+use clap::Parser;
+use bytes::Bytes;
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Request, Response, Server, StatusCode};
+use prost::Message;
+use regex::Regex;
+use serde_json::json;
+use std::convert::Infallible;
+use std::error;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+
+const TRACES_REQUEST: &str = "opentelemetry.proto.collector.trace.v1.ExportTraceServiceRequest";
+const METRICS_REQUEST: &str = "opentelemetry.proto.collector.metrics.v1.ExportMetricsServiceRequest";
+const LOGS_REQUEST: &str = "opentelemetry.proto.collector.logs.v1.ExportLogsServiceRequest";
+
+/// run a battery of valid and malformed requests (empty, huge, every value
+/// type, compressed, broken framing) against an otlp receiver and report
+/// whether each one is accepted or rejected as expected, or (with --listen)
+/// receive what an sdk under test sends and grade it
+#[derive(Parser, Debug)]
+pub struct Conformance {
+    /// receiver endpoint, e.g. http://localhost:4318
+    #[clap(long, required_unless_present = "listen")]
+    target: Option<String>,
+
+    /// receive otlp requests (grpc, http protobuf or http json, plain, gzip or
+    /// zstd compressed) on this address, e.g. 0.0.0.0:4317, and grade them: required resource attributes, id
+    /// validity, timestamps, semantic conventions and batch sizes
+    #[clap(long, conflicts_with = "target")]
+    listen: Option<SocketAddr>,
+
+    /// with --listen, stop and report after this long (default: on ctrl-c)
+    #[clap(long, value_parser = parse_duration, requires = "listen")]
+    duration: Option<Duration>,
+
+    /// with --listen, stop and report after this many requests
+    #[clap(long, requires = "listen")]
+    requests: Option<u64>,
+
+    /// with --listen, resource attributes every resource must have
+    #[clap(long, num_args = 0.., default_values = &["service.name", "telemetry.sdk.name", "telemetry.sdk.language", "telemetry.sdk.version"])]
+    require_resource: Vec<String>,
+
+    /// with --listen, reject requests over this many MiB, as sent or once
+    /// decompressed
+    #[clap(long, default_value = "64")]
+    max_request_size: usize,
+
+    /// with --listen, requests with more items than this count as oversized
+    #[clap(long, default_value = "512")]
+    max_batch: usize,
+
+    /// with --listen, fail if the score is below this
+    #[clap(long, requires = "listen")]
+    min_score: Option<f64>,
+
+    /// with --listen, hooks fired for every request that is rejected or has
+    /// grading problems (`exec:<command>` or `webhook:<url>`, see search
+    /// --on-match), can be repeated
+    #[clap(long, num_args = 0.., requires = "listen")]
+    on_error: Vec<Hook>,
+
+    /// with --listen, also serve what was received over http on this address
+    /// (e.g. :8080) for tests to assert on: GET /traces/{id} and
+    /// /spans?name=... answer the spans as otlp json, /stats the counts.
+    /// everything received is kept in memory
+    #[clap(long, value_parser = parse_api_addr, requires = "listen")]
+    api: Option<SocketAddr>,
+
+    /// with --listen, write the most common shape of the traces received to
+    /// this file when stopping, as a report-trace --scenario with the median
+    /// timings of that shape. everything received is kept in memory
+    #[clap(long, requires = "listen")]
+    emit_scenario: Option<String>,
+
+    /// with --listen, also append every span, log record and metric point
+    /// received to a sqlite database (sqlite:path.db) for otk query
+    #[clap(long, value_parser = parse_store, requires = "listen")]
+    store: Option<String>,
+
+    /// with --listen, print every request received as a line of otlp json
+    #[clap(long, requires = "listen")]
+    print: bool,
+
+    /// with --print, pretty print the requests
+    #[clap(long, requires = "print")]
+    pretty: bool,
+
+    /// with --print, color trace and span ids, each id in a color of its own
+    /// (auto, always or never)
+    #[clap(long, default_value = "auto")]
+    color: Color,
+
+    /// protocol to use (grpc or http)
+    #[clap(long, default_value = "grpc")]
+    protocol: Protocol,
+
+    /// size of the huge payload case in MiB
+    #[clap(long, default_value = "16")]
+    huge_size: usize,
+
+    /// only run cases whose name matches this regex
+    #[clap(long)]
+    case: Option<Regex>,
+
+    /// list the cases instead of running them
+    #[clap(long)]
+    list: bool,
+
+    /// headers (grpc metadata) sent with every request
+    #[clap(long, num_args = 0..)]
+    metadata: Vec<KeyValue>,
+
+    /// timeout of each request
+    #[clap(long, value_parser = parse_duration, default_value = "10s")]
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Accept,
+    Reject,
+    /// accepting and rejecting cleanly are both fine, errors are not
+    Either,
+}
+
+/// how the receiver answered a case
+enum Outcome {
+    Accepted(String),
+    Rejected(String),
+    /// server errors, dropped connections and timeouts
+    Failed(String),
+}
+
+struct Case {
+    name: &'static str,
+    signal: Signal,
+    payload: Vec<u8>,
+    expect: Expect,
+    /// compression of the payload
+    compression: Compression,
+    /// content (grpc message) encoding announced in the headers
+    encoding: Option<&'static str>,
+    /// content type instead of the protocol's own
+    content_type: Option<&'static str>,
+    /// grpc length prefix instead of the payload length, grpc only
+    frame_len: Option<u32>,
+}
+
+impl Case {
+    fn new(name: &'static str, signal: Signal, payload: Vec<u8>, expect: Expect) -> Case {
+        Case {
+            name,
+            signal,
+            payload,
+            expect,
+            compression: Compression::None,
+            encoding: None,
+            content_type: None,
+            frame_len: None,
+        }
+    }
+
+    fn compressed(mut self, compression: Compression, encoding: Option<&'static str>) -> Case {
+        self.compression = compression;
+        self.encoding = encoding;
+        self
+    }
+}
+
+pub fn do_conformance(conformance: Conformance) -> Result<(), Box<dyn error::Error>> {
+    if conformance.protocol == Protocol::HttpJson {
+        return Err(Box::new(OTKError::UnimplementedError("httpjson".into())));
+    }
+    if let Some(addr) = conformance.listen {
+        let listener = TcpListener::bind(addr)?;
+        let mut grader = Runtime::new()?.block_on(listen(&conformance, listener, Arc::new(Notify::new())))?;
+        let score = grader.report();
+        if let Some(min) = conformance.min_score.filter(|min| score < *min) {
+            return Err(Box::new(OTKError::CheckError(format!("score {:.1} is below {}", score, min))));
+        }
+        return Ok(());
+    }
+    let target = conformance.target.as_deref().unwrap_or_default();
+    if target.starts_with("https") {
+        return Err(Box::new(OTKError::UnimplementedError(
+            "conformance does not support tls for now".into(),
+        )));
+    }
+    let cases = cases(&conformance)
+        .into_iter()
+        .filter(|c| conformance.case.as_ref().is_none_or(|re| re.is_match(c.name)))
+        .collect::<Vec<_>>();
+    if conformance.list {
+        for case in &cases {
+            println!("{:<28}{:?} {}", case.name, case.expect, case.signal);
+        }
+        return Ok(());
+    }
+    let failed = Runtime::new()?.block_on(run(&conformance, &cases))?;
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    if failed > 0 {
+        return Err(Box::new(OTKError::CheckError(format!(
+            "{} of {} cases failed",
+            failed,
+            cases.len()
+        ))));
+    }
+    Ok(())
+}
+
+/// run every case in order, returning the number of failures
+async fn run(conformance: &Conformance, cases: &[Case]) -> Result<usize, Box<dyn error::Error>> {
+    let client = Client::builder()
+        .http2_only(conformance.protocol == Protocol::Grpc)
+        .build_http();
+    let mut failed = 0;
+    for case in cases {
+        let send = send(&client, conformance, case);
+        let outcome = match tokio::time::timeout(conformance.timeout, send).await {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(err)) => Outcome::Failed(err.to_string()),
+            Err(_) => Outcome::Failed("timed out".into()),
+        };
+        let (pass, detail) = match (&outcome, case.expect) {
+            (Outcome::Accepted(d), Expect::Accept | Expect::Either) => (true, d.clone()),
+            (Outcome::Rejected(d), Expect::Reject | Expect::Either) => (true, d.clone()),
+            (Outcome::Accepted(d), _) => (false, format!("expected a rejection, got {}", d)),
+            (Outcome::Rejected(d), _) => (false, format!("expected acceptance, got {}", d)),
+            (Outcome::Failed(d), _) => (false, d.clone()),
+        };
+        println!("{:<6}{:<28}{}", if pass { "PASS" } else { "FAIL" }, case.name, detail);
+        if !pass {
+            failed += 1;
+        }
+    }
+    Ok(failed)
+}
+
+async fn send(client: &Client<HttpConnector>, conformance: &Conformance, case: &Case) -> Result<Outcome, Box<dyn error::Error>> {
+    let grpc = conformance.protocol == Protocol::Grpc;
+    let payload = case.compression.compress(&case.payload);
+    let (path, body, content_type, encoding_header) = if grpc {
+        // flagged compressed whenever an encoding is declared, so corrupt-gzip
+        // really is a corrupt compressed message
+        let mut frame = vec![(case.compression != Compression::None || case.encoding.is_some()) as u8];
+        frame.extend(case.frame_len.unwrap_or(payload.len() as u32).to_be_bytes());
+        frame.extend(payload);
+        (grpc_path(case.signal), frame, "application/grpc", "grpc-encoding")
+    } else {
+        (http_path(case.signal), payload, "application/x-protobuf", "content-encoding")
+    };
+    let target = conformance.target.as_deref().unwrap_or_default();
+    let mut request = Request::post(format!("{}{}", target.trim_end_matches('/'), path))
+        .header("content-type", case.content_type.unwrap_or(content_type));
+    if grpc {
+        request = request.header("te", "trailers");
+    }
+    if let Some(encoding) = case.encoding {
+        request = request.header(encoding_header, encoding);
+    }
+    for kv in &conformance.metadata {
+        request = request.header(kv.k.as_str(), kv.v.as_str());
+    }
+    let response = client.request(request.body(Body::from(body))?).await?;
+    let status = response.status();
+    if !grpc || !status.is_success() {
+        let detail = format!("HTTP {}", status.as_u16());
+        return Ok(if status.is_success() {
+            Outcome::Accepted(detail)
+        } else if status.is_client_error() {
+            Outcome::Rejected(detail)
+        } else {
+            Outcome::Failed(detail)
+        });
+    }
+    // trailers-only responses carry the status in the headers
+    let mut headers = response.headers().clone();
+    if !headers.contains_key("grpc-status") {
+        let mut body = response.into_body();
+        while let Some(chunk) = body.data().await {
+            chunk?;
+        }
+        headers = body.trailers().await?.unwrap_or_default();
+    }
+    let Some(code) = headers.get("grpc-status") else {
+        return Ok(Outcome::Failed("no grpc-status in the response".into()));
+    };
+    let code = code.to_str()?;
+    Ok(if code == "0" {
+        Outcome::Accepted("grpc status 0".into())
+    } else {
+        let message = headers.get("grpc-message").map(|m| percent_decode(m.as_bytes())).unwrap_or_default();
+        Outcome::Rejected(format!("grpc status {} {}", code, message))
+    })
+}
+
+/// grpc-message is percent-encoded
+pub fn percent_decode(s: &[u8]) -> String {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (s[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// serve on `listener` until ctrl-c, --duration, --requests or `enough` is
+/// notified, grading every request
+pub async fn listen(
+    conformance: &Conformance,
+    listener: TcpListener,
+    enough: Arc<Notify>,
+) -> Result<Grader, Box<dyn error::Error>> {
+    let grader = Arc::new(Mutex::new(Grader::new(conformance.require_resource.clone(), conformance.max_batch)));
+    let max_requests = conformance.requests;
+    let limit = conformance.max_request_size << 20;
+    let hooks = Arc::new(conformance.on_error.clone());
+    let received = (conformance.api.is_some() || conformance.emit_scenario.is_some())
+        .then(|| Arc::new(Mutex::new(Received::default())));
+    if let (Some(addr), Some(received)) = (conformance.api, &received) {
+        let addr = api::serve(addr, received.clone())?;
+        eprintln!("serving what is received on http://{}", addr);
+    }
+    let store = match &conformance.store {
+        Some(path) => Some(Arc::new(Mutex::new(Store::open(path).map_err(|e| {
+            OTKError::InvalidArgumentError(format!("open store {} failed: {}", path, e))
+        })?))),
+        None => None,
+    };
+    let print = conformance.print.then(|| Print { renderer: Renderer::new(conformance.color), pretty: conformance.pretty });
+    let make_service = {
+        let (grader, enough, hooks, received, store) =
+            (grader.clone(), enough.clone(), hooks.clone(), received.clone(), store.clone());
+        make_service_fn(move |_| {
+            let (grader, enough, hooks, received, store) =
+                (grader.clone(), enough.clone(), hooks.clone(), received.clone(), store.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let (grader, enough, hooks, received, store) =
+                        (grader.clone(), enough.clone(), hooks.clone(), received.clone(), store.clone());
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let (response, problems) =
+                            receive(req, &grader, received.as_deref(), store.as_deref(), print, limit).await?;
+                        if !problems.is_empty() && !hooks.is_empty() {
+                            let event = json!({"event": "error", "path": path, "problems": problems});
+                            // hooks block, keep them off the server's threads
+                            tokio::task::spawn_blocking(move || hook::fire_all(&hooks, &event));
+                        }
+                        if max_requests.is_some_and(|max| grader.lock().unwrap().requests() >= max) {
+                            enough.notify_one();
+                        }
+                        Ok::<_, hyper::Error>(response)
+                    }
+                }))
+            }
+        })
+    };
+    let server = Server::from_tcp(listener)?.serve(make_service);
+    eprintln!("listening on {}, send otlp requests to it and stop with ctrl-c", server.local_addr());
+    let duration = conformance.duration;
+    server
+        .with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = enough.notified() => {},
+                _ = async {
+                    match duration {
+                        Some(d) => tokio::time::sleep(d).await,
+                        None => std::future::pending().await,
+                    }
+                } => {},
+            }
+        })
+        .await?;
+    if let (Some(path), Some(received)) = (&conformance.emit_scenario, &received) {
+        emit_scenario(path, &received.lock().unwrap())?;
+    }
+    let grader = std::mem::replace(&mut *grader.lock().unwrap(), Grader::new(vec![], 0));
+    Ok(grader)
+}
+
+fn emit_scenario(path: &str, received: &Received) -> Result<(), Box<dyn error::Error>> {
+    let Some(recording) = scenario::record(received.spans()) else {
+        eprintln!("no traces received, {} not written", path);
+        return Ok(());
+    };
+    let header = format!(
+        "# recorded by otk conformance --listen: {} of {} traces had this shape, {} shapes in all\n",
+        recording.recorded, recording.traces, recording.shapes
+    );
+    std::fs::write(path, header + &recording.yaml)?;
+    eprintln!("wrote the shape of {} of {} traces to {}", recording.recorded, recording.traces, path);
+    Ok(())
+}
+
+/// body encoding of a received request, answered in kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wire {
+    Grpc,
+    Protobuf,
+    Json,
+}
+
+/// grade one request (keeping it in `received` for --api, in `store` for
+/// --store, printing it for --print) and answer it like a receiver would,
+/// returning the problems found with it too. bodies over `limit` bytes, as
+/// sent or decompressed, are rejected
+async fn receive(
+    req: Request<Body>,
+    grader: &Mutex<Grader>,
+    received: Option<&Mutex<Received>>,
+    store: Option<&Mutex<Store>>,
+    print: Option<Print>,
+    limit: usize,
+) -> Result<(Response<Body>, Vec<String>), hyper::Error> {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let content_type = header("content-type");
+    let wire = match content_type.split(';').next().unwrap_or_default().trim() {
+        "application/grpc" | "application/grpc+proto" => Some(Wire::Grpc),
+        "application/x-protobuf" | "application/protobuf" => Some(Wire::Protobuf),
+        "application/json" => Some(Wire::Json),
+        _ => None,
+    };
+    let encoding = header(if wire == Some(Wire::Grpc) { "grpc-encoding" } else { "content-encoding" });
+    let signal = match req.uri().path() {
+        "/v1/traces" | "/opentelemetry.proto.collector.trace.v1.TraceService/Export" => Signal::Traces,
+        "/v1/metrics" | "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export" => Signal::Metrics,
+        "/v1/logs" | "/opentelemetry.proto.collector.logs.v1.LogsService/Export" => Signal::Logs,
+        _ => return Ok((http_response(StatusCode::NOT_FOUND, Wire::Protobuf), vec![])),
+    };
+    let mut body = req.into_body();
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > limit {
+            let mut grader = grader.lock().unwrap();
+            grader.reject(request_name(signal), format!("request larger than {} bytes", limit));
+            let response = too_large(wire.unwrap_or(Wire::Protobuf), limit);
+            return Ok((response, grader.take_problems()));
+        }
+    }
+    let mut grader = grader.lock().unwrap();
+    let mut received = received.map(|r| r.lock().unwrap());
+    let mut store = store.map(|s| s.lock().unwrap());
+    let response = grade(
+        &mut grader,
+        received.as_deref_mut(),
+        store.as_deref_mut(),
+        print,
+        signal,
+        wire,
+        &content_type,
+        &encoding,
+        &bytes,
+        limit,
+    );
+    Ok((response, grader.take_problems()))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn grade(
+    grader: &mut Grader,
+    received: Option<&mut Received>,
+    store: Option<&mut Store>,
+    print: Option<Print>,
+    signal: Signal,
+    wire: Option<Wire>,
+    content_type: &str,
+    encoding: &str,
+    body: &[u8],
+    limit: usize,
+) -> Response<Body> {
+    let name = request_name(signal);
+    let Some(wire) = wire else {
+        grader.reject(name, format!("request with unsupported content type {:?}", content_type));
+        return http_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, Wire::Protobuf);
+    };
+    let Some(compression) = Compression::from_encoding(encoding) else {
+        grader.reject(name, format!("request compressed with unsupported {}", encoding));
+        return match wire {
+            Wire::Grpc => grpc_response(12, &format!("compression {} is not supported", encoding)),
+            _ => http_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, wire),
+        };
+    };
+    let (compression, payload) = if wire == Wire::Grpc {
+        // a single message: compressed flag, 4 byte length, message
+        match body.get(5..) {
+            Some(message)
+                if body[0] <= (compression != Compression::None) as u8
+                    && message.len() == u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize =>
+            {
+                (if body[0] == 1 { compression } else { Compression::None }, message)
+            }
+            _ => {
+                grader.reject(name, "malformed grpc frame".to_string());
+                return grpc_response(13, "malformed grpc frame");
+            }
+        }
+    } else {
+        (compression, body)
+    };
+    let payload = match compression.decompress(payload, limit) {
+        Ok(payload) => payload,
+        // the one error that isn't the body's fault
+        Err(OTKError::InvalidArgumentError(_)) => {
+            grader.reject(name, format!("{} request larger than {} bytes once decompressed", name, limit));
+            return too_large(wire, limit);
+        }
+        Err(err) => {
+            grader.reject(name, format!("{} request with invalid {} body", name, compression));
+            return match wire {
+                Wire::Grpc => grpc_response(13, &err.to_string()),
+                _ => http_response(StatusCode::BAD_REQUEST, wire),
+            };
+        }
+    };
+    let mut stored = Ok(());
+    let decoded = match signal {
+        Signal::Traces => decode::<ExportTraceServiceRequest>(wire, TRACES_REQUEST, &payload).map(|r| {
+            grader.add_traces(&r);
+            if let Some(received) = received {
+                received.add_traces(&r);
+            }
+            if let Some(store) = store {
+                stored = store.add_traces(&r);
+            }
+            if let Some(print) = print {
+                print.request(TRACES_REQUEST, &r);
+            }
+        }),
+        Signal::Metrics => decode::<ExportMetricsServiceRequest>(wire, METRICS_REQUEST, &payload).map(|r| {
+            grader.add_metrics(&r);
+            if let Some(received) = received {
+                received.add_metrics(&r);
+            }
+            if let Some(store) = store {
+                stored = store.add_metrics(&r);
+            }
+            if let Some(print) = print {
+                print.request(METRICS_REQUEST, &r);
+            }
+        }),
+        Signal::Logs => decode::<ExportLogsServiceRequest>(wire, LOGS_REQUEST, &payload).map(|r| {
+            grader.add_logs(&r);
+            if let Some(received) = received {
+                received.add_logs(&r);
+            }
+            if let Some(store) = store {
+                stored = store.add_logs(&r);
+            }
+            if let Some(print) = print {
+                print.request(LOGS_REQUEST, &r);
+            }
+        }),
+    };
+    if let (Ok(()), Err(err)) = (&decoded, stored) {
+        // the request is fine, the receiver is not
+        let message = format!("storing the request failed: {}", err);
+        return match wire {
+            Wire::Grpc => grpc_response(13, &message),
+            _ => http_response(StatusCode::INTERNAL_SERVER_ERROR, wire),
+        };
+    }
+    match (decoded, wire) {
+        (Ok(()), Wire::Grpc) => grpc_response(0, ""),
+        (Ok(()), _) => http_response(StatusCode::OK, wire),
+        (Err(err), wire) => {
+            grader.reject(name, format!("undecodable {} request", name));
+            match wire {
+                Wire::Grpc => grpc_response(3, &err.to_string()),
+                _ => http_response(StatusCode::BAD_REQUEST, wire),
+            }
+        }
+    }
+}
+
+fn request_name(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Traces => "traces",
+        Signal::Metrics => "metrics",
+        Signal::Logs => "logs",
+    }
+}
+
+/// the answer to a request over --max-request-size
+fn too_large(wire: Wire, limit: usize) -> Response<Body> {
+    match wire {
+        Wire::Grpc => grpc_response(8, &format!("request larger than {} bytes", limit)),
+        _ => http_response(StatusCode::PAYLOAD_TOO_LARGE, wire),
+    }
+}
+
+/// how --print shows the received requests
+#[derive(Debug, Clone, Copy)]
+struct Print {
+    renderer: Renderer,
+    pretty: bool,
+}
+
+impl Print {
+    /// print a received request as otlp json
+    fn request(self, name: &str, req: &impl Message) {
+        let json = match otlp_json::to_json(name, &req.encode_to_vec()) {
+            Ok(json) => json,
+            Err(err) => return eprintln!("can't print the request: {}", err),
+        };
+        let text = match self.pretty {
+            true => serde_json::to_string_pretty(&json).unwrap_or_default(),
+            false => json.to_string(),
+        };
+        println!("{}", self.renderer.ids(&text));
+    }
+}
+
+/// decode a request body of the given full protobuf message name
+fn decode<M: Message + Default>(wire: Wire, name: &str, payload: &[u8]) -> Result<M, Box<dyn error::Error>> {
+    match wire {
+        Wire::Json => otlp_json::from_json(name, payload),
+        _ => Ok(M::decode(payload)?),
+    }
+}
+
+/// an empty export response (its protobuf encoding is empty too)
+fn http_response(status: StatusCode, wire: Wire) -> Response<Body> {
+    let (body, content_type) = match wire {
+        Wire::Json => (Body::from("{}"), "application/json"),
+        _ => (Body::empty(), "application/x-protobuf"),
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response.headers_mut().insert("content-type", HeaderValue::from_static(content_type));
+    response
+}
+
+/// an empty export response message with the status in the trailers, or
+/// just the status for errors
+fn grpc_response(code: u32, message: &str) -> Response<Body> {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(code));
+    if let Ok(message) = HeaderValue::from_str(message) {
+        trailers.insert("grpc-message", message);
+    }
+    let mut response = if code == 0 {
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move {
+            if tx.send_data(Bytes::from_static(&[0, 0, 0, 0, 0])).await.is_ok() {
+                let _ = tx.send_trailers(trailers).await;
+            }
+        });
+        Response::new(body)
+    } else {
+        let mut response = Response::new(Body::empty());
+        response.headers_mut().extend(trailers);
+        response
+    };
+    response.headers_mut().insert("content-type", HeaderValue::from_static("application/grpc"));
+    response
+}
+
+pub fn http_path(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Traces => "/v1/traces",
+        Signal::Metrics => "/v1/metrics",
+        Signal::Logs => "/v1/logs",
+    }
+}
+
+pub fn grpc_path(signal: Signal) -> &'static str {
+    match signal {
+        Signal::Traces => "/opentelemetry.proto.collector.trace.v1.TraceService/Export",
+        Signal::Metrics => "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export",
+        Signal::Logs => "/opentelemetry.proto.collector.logs.v1.LogsService/Export",
+    }
+}
+
+fn cases(conformance: &Conformance) -> Vec<Case> {
+    let valid = traces(vec![span("otk.conformance", vec![])]).encode_to_vec();
+    let mut cases = vec![
+        Case::new("empty-traces", Signal::Traces, vec![], Expect::Accept),
+        Case::new("empty-metrics", Signal::Metrics, vec![], Expect::Accept),
+        Case::new("empty-logs", Signal::Logs, vec![], Expect::Accept),
+        Case::new("empty-resource-spans", Signal::Traces, empty_resource_spans().encode_to_vec(), Expect::Accept),
+        Case::new("span", Signal::Traces, valid.clone(), Expect::Accept),
+        Case::new("span-value-types", Signal::Traces, traces(vec![span("otk.value_types", value_types())]).encode_to_vec(), Expect::Accept),
+        Case::new("log-value-types", Signal::Logs, logs().encode_to_vec(), Expect::Accept),
+        Case::new("metric-types", Signal::Metrics, metrics().encode_to_vec(), Expect::Accept),
+        Case::new("many-spans", Signal::Traces, many_spans(10_000, 0).encode_to_vec(), Expect::Accept),
+        Case::new("large-attribute", Signal::Traces, many_spans(1, 1 << 20).encode_to_vec(), Expect::Accept),
+        // receivers may refuse requests over their size limit, but must not break
+        Case::new("huge-payload", Signal::Traces, many_spans(conformance.huge_size * 16, 64 << 10).encode_to_vec(), Expect::Either),
+        Case::new("gzip", Signal::Traces, valid.clone(), Expect::Accept).compressed(Compression::Gzip, Some("gzip")),
+        // zstd is optional, but a receiver without it must refuse it cleanly
+        Case::new("zstd", Signal::Traces, valid.clone(), Expect::Either).compressed(Compression::Zstd, Some("zstd")),
+        Case::new("unknown-encoding", Signal::Traces, valid.clone(), Expect::Reject)
+            .compressed(Compression::None, Some("otk-unknown")),
+        Case::new("corrupt-gzip", Signal::Traces, valid.clone(), Expect::Reject).compressed(Compression::None, Some("gzip")),
+        Case::new("undeclared-gzip", Signal::Traces, valid.clone(), Expect::Reject).compressed(Compression::Gzip, None),
+        Case::new("malformed-protobuf", Signal::Traces, vec![0xff; 16], Expect::Reject),
+        Case::new("truncated-protobuf", Signal::Traces, valid[..valid.len() / 2].to_vec(), Expect::Reject),
+        Case {
+            content_type: Some("text/plain"),
+            ..Case::new("wrong-content-type", Signal::Traces, valid.clone(), Expect::Reject)
+        },
+    ];
+    if conformance.protocol == Protocol::Grpc {
+        cases.push(Case {
+            frame_len: Some(valid.len() as u32 + 100),
+            ..Case::new("short-frame", Signal::Traces, valid.clone(), Expect::Reject)
+        });
+    }
+    cases
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn kv(key: &str, value: Value) -> ProtoKeyValue {
+    ProtoKeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+/// one attribute of every AnyValue variant, nested ones included
+fn value_types() -> Vec<ProtoKeyValue> {
+    vec![
+        kv("string", Value::StringValue("otk".into())),
+        kv("bool", Value::BoolValue(true)),
+        kv("int", Value::IntValue(-42)),
+        kv("double", Value::DoubleValue(3.5)),
+        kv("bytes", Value::BytesValue(vec![0, 1, 0xfe, 0xff])),
+        kv(
+            "array",
+            Value::ArrayValue(ArrayValue {
+                values: vec![
+                    AnyValue { value: Some(Value::IntValue(1)) },
+                    AnyValue { value: Some(Value::StringValue("two".into())) },
+                ],
+            }),
+        ),
+        kv(
+            "kvlist",
+            Value::KvlistValue(KeyValueList {
+                values: vec![kv("nested", Value::BoolValue(false))],
+            }),
+        ),
+        kv("unicode", Value::StringValue("ünïcødé ✓".into())),
+        ProtoKeyValue { key: "empty".into(), value: None },
+    ]
+}
+
+fn resource() -> Option<Resource> {
+    Some(Resource {
+        attributes: vec![kv("service.name", Value::StringValue("otk-conformance".into()))],
+        ..Default::default()
+    })
+}
+
+fn span(name: &str, attributes: Vec<ProtoKeyValue>) -> Span {
+    let end = now();
+    Span {
+        trace_id: rand::random::<[u8; 16]>().to_vec(),
+        span_id: rand::random::<[u8; 8]>().to_vec(),
+        name: name.to_string(),
+        start_time_unix_nano: end - 1_000_000,
+        end_time_unix_nano: end,
+        attributes,
+        ..Default::default()
+    }
+}
+
+fn traces(spans: Vec<Span>) -> ExportTraceServiceRequest {
+    ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: resource(),
+            scope_spans: vec![ScopeSpans {
+                spans,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+fn empty_resource_spans() -> ExportTraceServiceRequest {
+    ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans::default()],
+    }
+}
+
+/// `n` spans, each with a padding attribute of `padding` bytes
+fn many_spans(n: usize, padding: usize) -> ExportTraceServiceRequest {
+    traces(
+        (0..n)
+            .map(|i| {
+                let attributes = if padding > 0 {
+                    vec![kv("otk.padding", Value::StringValue("x".repeat(padding)))]
+                } else {
+                    vec![]
+                };
+                span(&format!("otk.span.{}", i), attributes)
+            })
+            .collect(),
+    )
+}
+
+/// log records with a body of every AnyValue variant
+fn logs() -> ExportLogsServiceRequest {
+    let time = now();
+    let log_records = value_types()
+        .into_iter()
+        .map(|attr| LogRecord {
+            time_unix_nano: time,
+            observed_time_unix_nano: time,
+            severity_number: 9,
+            severity_text: "INFO".into(),
+            body: attr.value,
+            attributes: vec![kv("body.type", Value::StringValue(attr.key))],
+            ..Default::default()
+        })
+        .collect();
+    ExportLogsServiceRequest {
+        resource_logs: vec![ResourceLogs {
+            resource: resource(),
+            scope_logs: vec![ScopeLogs {
+                log_records,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+/// one metric of every data type
+fn metrics() -> ExportMetricsServiceRequest {
+    let time = now();
+    let start = time - 60_000_000_000;
+    let number = |value| NumberDataPoint {
+        start_time_unix_nano: start,
+        time_unix_nano: time,
+        value: Some(value),
+        ..Default::default()
+    };
+    let metric = |name: &str, data| Metric {
+        name: name.to_string(),
+        data: Some(data),
+        ..Default::default()
+    };
+    let cumulative = AggregationTemporality::Cumulative as i32;
+    let metrics = vec![
+        metric(
+            "otk.gauge",
+            metric::Data::Gauge(Gauge {
+                data_points: vec![number(number_data_point::Value::AsDouble(0.5))],
+            }),
+        ),
+        metric(
+            "otk.sum",
+            metric::Data::Sum(Sum {
+                data_points: vec![number(number_data_point::Value::AsInt(7))],
+                aggregation_temporality: cumulative,
+                is_monotonic: true,
+            }),
+        ),
+        metric(
+            "otk.histogram",
+            metric::Data::Histogram(Histogram {
+                data_points: vec![HistogramDataPoint {
+                    start_time_unix_nano: start,
+                    time_unix_nano: time,
+                    count: 3,
+                    sum: Some(6.0),
+                    bucket_counts: vec![1, 1, 1],
+                    explicit_bounds: vec![1.0, 2.0],
+                    min: Some(1.0),
+                    max: Some(3.0),
+                    ..Default::default()
+                }],
+                aggregation_temporality: cumulative,
+            }),
+        ),
+        metric(
+            "otk.exponential_histogram",
+            metric::Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: vec![ExponentialHistogramDataPoint {
+                    start_time_unix_nano: start,
+                    time_unix_nano: time,
+                    count: 3,
+                    sum: Some(6.0),
+                    zero_count: 1,
+                    positive: Some(Buckets {
+                        offset: 0,
+                        bucket_counts: vec![1, 1],
+                    }),
+                    ..Default::default()
+                }],
+                aggregation_temporality: cumulative,
+            }),
+        ),
+        metric(
+            "otk.summary",
+            metric::Data::Summary(Summary {
+                data_points: vec![SummaryDataPoint {
+                    start_time_unix_nano: start,
+                    time_unix_nano: time,
+                    count: 3,
+                    sum: 6.0,
+                    quantile_values: vec![
+                        ValueAtQuantile { quantile: 0.5, value: 2.0 },
+                        ValueAtQuantile { quantile: 1.0, value: 3.0 },
+                    ],
+                    ..Default::default()
+                }],
+            }),
+        ),
+    ];
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: resource(),
+            scope_metrics: vec![ScopeMetrics {
+                metrics,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// serve --listen on a free port until `stop` is notified
+    fn serve(args: &[&str], stop: Arc<Notify>) -> (SocketAddr, tokio::task::JoinHandle<Grader>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let conformance = Conformance::try_parse_from([&["conformance", "--listen", "127.0.0.1:0"], args].concat()).unwrap();
+        let server = tokio::spawn(async move { listen(&conformance, listener, stop).await.unwrap() });
+        (addr, server)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn listen_passes_every_case() {
+        let stop = Arc::new(Notify::new());
+        let (addr, server) = serve(&[], stop.clone());
+        let target = format!("http://{}", addr);
+        for protocol in ["grpc", "http"] {
+            let args = ["conformance", "--target", &target, "--protocol", protocol, "--huge-size", "1"];
+            let conformance = Conformance::try_parse_from(args).unwrap();
+            assert_eq!(run(&conformance, &cases(&conformance)).await.unwrap(), 0, "{}", protocol);
+        }
+        stop.notify_one();
+        server.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fails_cases_of_a_broken_receiver() {
+        // answers everything with a 200, so every rejection case fails
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(Response::new(Body::empty())) }))
+        }));
+        let target = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        let conformance = Conformance::try_parse_from(["conformance", "--target", &target, "--protocol", "http"]).unwrap();
+        let cases = cases(&conformance).into_iter().filter(|c| c.name != "huge-payload").collect::<Vec<_>>();
+        let rejections = cases.iter().filter(|c| c.expect == Expect::Reject).count();
+        assert_eq!(run(&conformance, &cases).await.unwrap(), rejections);
+    }
+
+    #[test]
+    fn percent_decodes() {
+        assert_eq!(percent_decode(b"bad%20request%3A%e2%9c%93"), "bad request:\u{2713}");
+        // not an escape
+        assert_eq!(percent_decode(b"100%"), "100%");
+        assert_eq!(percent_decode(b"%zz"), "%zz");
+    }
+
+
+    /// the http status and, for grpc errors, the grpc status `grade` answers
+    /// a request with
+    fn answer(grader: &mut Grader, wire: Option<Wire>, encoding: &str, body: &[u8], limit: usize) -> (u16, Option<String>) {
+        let response = grade(grader, None, None, None, Signal::Traces, wire, "text/plain", encoding, body, limit);
+        let grpc_status = response.headers().get("grpc-status").map(|s| s.to_str().unwrap().to_string());
+        (response.status().as_u16(), grpc_status)
+    }
+
+    fn grpc_frame(compressed: bool, message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![compressed as u8];
+        frame.extend((message.len() as u32).to_be_bytes());
+        frame.extend(message);
+        frame
+    }
+
+    #[tokio::test]
+    async fn grades_requests() {
+        let mut grader = Grader::new(vec![], 10);
+        let valid = traces(vec![span("otk.test", vec![])]).encode_to_vec();
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "", &valid, 1 << 20), (200, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Grpc), "", &grpc_frame(false, &valid), 1 << 20), (200, None));
+        assert!(grader.take_problems().is_empty());
+
+        assert_eq!(answer(&mut grader, None, "", &valid, 1 << 20), (415, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "", &[0xff; 16], 1 << 20), (400, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Grpc), "", &valid, 1 << 20), (200, Some("13".into())));
+        assert_eq!(
+            grader.take_problems(),
+            [
+                "malformed grpc frame",
+                "request with unsupported content type \"text/plain\"",
+                "undecodable traces request",
+            ]
+        );
+        assert_eq!(grader.requests(), 5);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stops_after_requests() {
+        let (addr, server) = serve(&["--requests", "2"], Arc::new(Notify::new()));
+        let client = Client::new();
+        for _ in 0..2 {
+            let request = Request::post(format!("http://{}/v1/traces", addr))
+                .header("content-type", "application/x-protobuf")
+                .body(Body::from(traces(vec![span("otk.test", vec![])]).encode_to_vec()))
+                .unwrap();
+            assert_eq!(client.request(request).await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(server.await.unwrap().requests(), 2);
+    }
+
+
+    #[tokio::test]
+    async fn accepts_json_and_gzip() {
+        let mut grader = Grader::new(vec![], 10);
+        let valid = traces(vec![span("otk.test", vec![])]);
+        let json = otlp_json::to_json(TRACES_REQUEST, &valid.encode_to_vec()).unwrap().to_string();
+        let response = grade(&mut grader, None, None, None, Signal::Traces, Some(Wire::Json), "", "", json.as_bytes(), 1 << 20);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "{}");
+
+        let gzipped = Compression::Gzip.compress(&valid.encode_to_vec());
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "gzip", &gzipped, 1 << 20), (200, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Grpc), "gzip", &grpc_frame(true, &gzipped), 1 << 20), (200, None));
+        assert!(grader.take_problems().is_empty());
+
+        assert_eq!(answer(&mut grader, Some(Wire::Json), "", b"{\"resourceSpans\": 1}", 1 << 20), (400, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "gzip", b"not gzip", 1 << 20), (400, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "br", &gzipped, 1 << 20), (415, None));
+        assert_eq!(
+            grader.take_problems(),
+            [
+                "request compressed with unsupported br",
+                "traces request with invalid gzip body",
+                "undecodable traces request",
+            ]
+        );
+    }
+
+
+    #[tokio::test]
+    async fn limits_request_size() {
+        let mut grader = Grader::new(vec![], 100_000);
+        let large = many_spans(100, 1 << 10).encode_to_vec();
+        let zstd = Compression::Zstd.compress(&large);
+        assert!(zstd.len() < 10_000);
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "zstd", &zstd, large.len()), (200, None));
+        assert!(grader.take_problems().is_empty());
+
+        // over the limit once decompressed
+        assert_eq!(answer(&mut grader, Some(Wire::Protobuf), "zstd", &zstd, 10_000), (413, None));
+        assert_eq!(answer(&mut grader, Some(Wire::Grpc), "zstd", &grpc_frame(true, &zstd), 10_000), (200, Some("8".into())));
+        assert_eq!(grader.take_problems(), ["traces request larger than 10000 bytes once decompressed"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejects_large_bodies() {
+        let stop = Arc::new(Notify::new());
+        let (addr, server) = serve(&["--max-request-size", "1"], stop.clone());
+        let request = Request::post(format!("http://{}/v1/traces", addr))
+            .header("content-type", "application/x-protobuf")
+            .body(Body::from(many_spans(1, 2 << 20).encode_to_vec()))
+            .unwrap();
+        assert_eq!(Client::new().request(request).await.unwrap().status(), StatusCode::PAYLOAD_TOO_LARGE);
+        stop.notify_one();
+        assert_eq!(server.await.unwrap().requests(), 1);
+    }
+}
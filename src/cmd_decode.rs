@@ -1,97 +1,289 @@
 use clap::Parser;
 use rand::{distributions::Alphanumeric, Rng};
 use std::error;
-use prost::Message;
-use crate::proto;
-use std::io::{BufReader, BufRead, Read};
-use strum::IntoEnumIterator;
-use strum_macros::{EnumIter, EnumString, Display};
-use std::fs::File;
-
-#[derive(Debug, Clone, Display, EnumString, EnumIter)]
-enum DecodeType {
-    Direct,
-    Span,
-    Metric,
-    LogRecord,
-    ScopeSpans,
-    ScopeMetrics,
-    ScopeLogs,
-    Resource,
-    ResourceSpans,
-    ResourceMetrics,
-    ResourceLogs,
-    ExportTraceServiceRequest,
-    ExportMetricsServiceRequest,
-    ExportLogsServiceRequest,
+use std::ops::ControlFlow;
+use prost::{DecodeError, Message};
+use crate::proto::common::v1 as common;
+use crate::proto::resource::v1 as resource;
+use crate::proto::trace::v1 as trace;
+use crate::proto::metrics::v1 as metrics;
+use crate::proto::logs::v1 as logs;
+use crate::proto::collector::trace::v1 as trace_service;
+use crate::proto::collector::metrics::v1 as metrics_service;
+use crate::proto::collector::logs::v1 as logs_service;
+use crate::proto::jaeger::api_v2 as jaeger;
+use crate::proto::zipkin::proto3 as zipkin;
+use crate::convert;
+use crate::otk_error::OTKError;
+use crate::framing::{self, Framing};
+use crate::render::{Color, Renderer};
+use crate::sanity;
+use crate::sizes::SizeReport;
+use std::fmt;
+
+/// reads a message from its encoding
+type DecodeFn<T> = fn(&[u8]) -> Result<T, DecodeError>;
+
+/// a message type decode knows
+struct Decoder {
+    /// the name --list prints
+    name: &'static str,
+    /// full protobuf name, also accepted by --name
+    full_name: &'static str,
+    decode: DecodeFn<Box<dyn fmt::Debug>>,
+    /// for --sizes
+    sizes: Option<DecodeFn<SizeReport>>,
+    /// for --warn
+    check: Option<DecodeFn<Vec<String>>>,
+    /// for jaeger/zipkin, the otlp conversion --otlp prints and --sizes and
+    /// --warn look at
+    to_otlp: Option<DecodeFn<trace_service::ExportTraceServiceRequest>>,
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// an otlp message `name` of `package` (like `trace` or `collector.trace`),
+/// optionally with --sizes (`|report, msg| ...`) and --warn (`|msg| ...`)
+macro_rules! otlp {
+    ($name:literal, $package:literal, $ty:ty) => {
+        otlp!($name, $package, $ty, None, None)
+    };
+    ($name:literal, $package:literal, $ty:ty, sizes: $sizes:expr, check: $check:expr) => {
+        otlp!(
+            $name,
+            $package,
+            $ty,
+            Some(|bs| {
+                let mut report = SizeReport::new(bs.len());
+                ($sizes)(&mut report, &<$ty>::decode(bs)?);
+                Ok(report)
+            }),
+            Some(|bs| Ok(($check)(&<$ty>::decode(bs)?)))
+        )
+    };
+    ($name:literal, $package:literal, $ty:ty, sizes: $sizes:expr) => {
+        otlp!(
+            $name,
+            $package,
+            $ty,
+            Some(|bs| {
+                let mut report = SizeReport::new(bs.len());
+                ($sizes)(&mut report, &<$ty>::decode(bs)?);
+                Ok(report)
+            }),
+            None
+        )
+    };
+    ($name:literal, $package:literal, $ty:ty, $sizes:expr, $check:expr) => {
+        Decoder {
+            name: $name,
+            full_name: concat!("opentelemetry.proto.", $package, ".v1.", $name),
+            decode: |bs| Ok(Box::new(<$ty>::decode(bs)?)),
+            sizes: $sizes,
+            check: $check,
+            to_otlp: None,
+        }
+    };
+}
+
+/// a jaeger or zipkin message `name` (`full_name` in its own package),
+/// converted to otlp by `to_otlp`
+macro_rules! foreign {
+    ($name:literal, $full_name:literal, $ty:ty, $to_otlp:expr) => {
+        Decoder {
+            name: $name,
+            full_name: $full_name,
+            decode: |bs| Ok(Box::new(<$ty>::decode(bs)?)),
+            sizes: None,
+            check: None,
+            to_otlp: Some(|bs| Ok(($to_otlp)(<$ty>::decode(bs)?))),
+        }
+    };
+}
+
+/// every message --name takes, in the order --list prints them
+static DECODERS: &[Decoder] = &[
+    Decoder {
+        name: "Direct",
+        full_name: "Direct",
+        decode: |bs| Ok(Box::new(bs.to_vec())),
+        sizes: Some(|bs| Ok(SizeReport::new(bs.len()))),
+        check: None,
+        to_otlp: None,
+    },
+    otlp!("Span", "trace", trace::Span, sizes: SizeReport::add_span),
+    otlp!("Metric", "metrics", metrics::Metric, sizes: SizeReport::add_metric),
+    otlp!("LogRecord", "logs", logs::LogRecord, sizes: SizeReport::add_log_record),
+    otlp!("ScopeSpans", "trace", trace::ScopeSpans, sizes: SizeReport::add_scope_spans),
+    otlp!("ScopeMetrics", "metrics", metrics::ScopeMetrics, sizes: SizeReport::add_scope_metrics),
+    otlp!("ScopeLogs", "logs", logs::ScopeLogs, sizes: SizeReport::add_scope_logs),
+    otlp!("Resource", "resource", resource::Resource, sizes: SizeReport::add_resource),
+    otlp!("ResourceSpans", "trace", trace::ResourceSpans,
+        sizes: SizeReport::add_resource_spans,
+        check: |rs: &trace::ResourceSpans| sanity::check_resource_spans(std::slice::from_ref(rs))),
+    otlp!("ResourceMetrics", "metrics", metrics::ResourceMetrics,
+        sizes: SizeReport::add_resource_metrics,
+        check: |rm: &metrics::ResourceMetrics| sanity::check_resource_metrics(std::slice::from_ref(rm))),
+    otlp!("ResourceLogs", "logs", logs::ResourceLogs,
+        sizes: SizeReport::add_resource_logs,
+        check: |rl: &logs::ResourceLogs| sanity::check_resource_logs(std::slice::from_ref(rl))),
+    otlp!("ExportTraceServiceRequest", "collector.trace", trace_service::ExportTraceServiceRequest,
+        sizes: |report: &mut SizeReport, req: &trace_service::ExportTraceServiceRequest| {
+            req.resource_spans.iter().for_each(|rs| report.add_resource_spans(rs))
+        },
+        check: |req: &trace_service::ExportTraceServiceRequest| sanity::check_resource_spans(&req.resource_spans)),
+    otlp!("ExportMetricsServiceRequest", "collector.metrics", metrics_service::ExportMetricsServiceRequest,
+        sizes: |report: &mut SizeReport, req: &metrics_service::ExportMetricsServiceRequest| {
+            req.resource_metrics.iter().for_each(|rm| report.add_resource_metrics(rm))
+        },
+        check: |req: &metrics_service::ExportMetricsServiceRequest| sanity::check_resource_metrics(&req.resource_metrics)),
+    otlp!("ExportLogsServiceRequest", "collector.logs", logs_service::ExportLogsServiceRequest,
+        sizes: |report: &mut SizeReport, req: &logs_service::ExportLogsServiceRequest| {
+            req.resource_logs.iter().for_each(|rl| report.add_resource_logs(rl))
+        },
+        check: |req: &logs_service::ExportLogsServiceRequest| sanity::check_resource_logs(&req.resource_logs)),
+    foreign!("JaegerSpan", "jaeger.api_v2.Span", jaeger::Span, |span| convert::jaeger_span_to_otlp(&span)),
+    foreign!("JaegerBatch", "jaeger.api_v2.Batch", jaeger::Batch, |batch| convert::jaeger_batch_to_otlp(&batch)),
+    foreign!("ZipkinSpan", "zipkin.proto3.Span", zipkin::Span, |span| convert::zipkin_spans_to_otlp(&[span])),
+    foreign!("ZipkinListOfSpans", "zipkin.proto3.ListOfSpans", zipkin::ListOfSpans,
+        |list: zipkin::ListOfSpans| convert::zipkin_spans_to_otlp(&list.spans)),
+    otlp!("TracesData", "trace", trace::TracesData,
+        sizes: |report: &mut SizeReport, data: &trace::TracesData| {
+            data.resource_spans.iter().for_each(|rs| report.add_resource_spans(rs))
+        },
+        check: |data: &trace::TracesData| sanity::check_resource_spans(&data.resource_spans)),
+    otlp!("MetricsData", "metrics", metrics::MetricsData,
+        sizes: |report: &mut SizeReport, data: &metrics::MetricsData| {
+            data.resource_metrics.iter().for_each(|rm| report.add_resource_metrics(rm))
+        },
+        check: |data: &metrics::MetricsData| sanity::check_resource_metrics(&data.resource_metrics)),
+    otlp!("LogsData", "logs", logs::LogsData,
+        sizes: |report: &mut SizeReport, data: &logs::LogsData| {
+            data.resource_logs.iter().for_each(|rl| report.add_resource_logs(rl))
+        },
+        check: |data: &logs::LogsData| sanity::check_resource_logs(&data.resource_logs)),
+    otlp!("Span.Event", "trace", trace::span::Event),
+    otlp!("Span.Link", "trace", trace::span::Link),
+    otlp!("Status", "trace", trace::Status),
+    otlp!("Gauge", "metrics", metrics::Gauge),
+    otlp!("Sum", "metrics", metrics::Sum),
+    otlp!("Histogram", "metrics", metrics::Histogram),
+    otlp!("ExponentialHistogram", "metrics", metrics::ExponentialHistogram),
+    otlp!("Summary", "metrics", metrics::Summary),
+    otlp!("NumberDataPoint", "metrics", metrics::NumberDataPoint),
+    otlp!("HistogramDataPoint", "metrics", metrics::HistogramDataPoint),
+    otlp!("ExponentialHistogramDataPoint", "metrics", metrics::ExponentialHistogramDataPoint),
+    otlp!("ExponentialHistogramDataPoint.Buckets", "metrics", metrics::exponential_histogram_data_point::Buckets),
+    otlp!("SummaryDataPoint", "metrics", metrics::SummaryDataPoint),
+    otlp!("SummaryDataPoint.ValueAtQuantile", "metrics", metrics::summary_data_point::ValueAtQuantile),
+    otlp!("Exemplar", "metrics", metrics::Exemplar),
+    otlp!("AnyValue", "common", common::AnyValue),
+    otlp!("ArrayValue", "common", common::ArrayValue),
+    otlp!("KeyValueList", "common", common::KeyValueList),
+    otlp!("KeyValue", "common", common::KeyValue),
+    otlp!("InstrumentationScope", "common", common::InstrumentationScope),
+    otlp!("ExportTraceServiceResponse", "collector.trace", trace_service::ExportTraceServiceResponse),
+    otlp!("ExportTracePartialSuccess", "collector.trace", trace_service::ExportTracePartialSuccess),
+    otlp!("ExportMetricsServiceResponse", "collector.metrics", metrics_service::ExportMetricsServiceResponse),
+    otlp!("ExportMetricsPartialSuccess", "collector.metrics", metrics_service::ExportMetricsPartialSuccess),
+    otlp!("ExportLogsServiceResponse", "collector.logs", logs_service::ExportLogsServiceResponse),
+    otlp!("ExportLogsPartialSuccess", "collector.logs", logs_service::ExportLogsPartialSuccess),
+];
+
+/// the decoder for `name`, by its --list or full protobuf name
+fn decoder(name: &str) -> Result<&'static Decoder, OTKError> {
+    DECODERS
+        .iter()
+        .find(|d| d.name == name || d.full_name == name)
+        .ok_or_else(|| OTKError::ParseError(format!("unknown message {} (see decode --list)", name)))
 }
 
 /// decode proto struct from input
 #[derive(Parser, Debug)]
 pub struct Decode {
-    /// name of struct
-    #[clap(short, long, default_value="ExportTraceServiceRequest")]
-    name: DecodeType,
-    /// file to read (- for stdin)
-    input: String,
+    /// name of the message, as decode --list prints it (like Span.Event) or
+    /// its full protobuf name (like opentelemetry.proto.common.v1.AnyValue)
+    #[clap(short, long, default_value="ExportTraceServiceRequest", value_parser = decoder)]
+    name: &'static Decoder,
+    /// files to read (- for stdin), glob patterns are expanded. with several
+    /// files, output is prefixed with file name and line (or message) number
+    #[clap(required_unless_present = "list")]
+    inputs: Vec<String>,
     /// input is base64-ed (streaming support for stdin)
     #[clap(short, long)]
     base64: bool,
+    /// message framing of the input (base64, raw, length-delimited or
+    /// length-prefixed), defaults to raw or base64 with -b
+    #[clap(short, long, conflicts_with = "base64")]
+    framing: Option<Framing>,
     /// list available format
     #[clap(short, long)]
     list: bool,
     /// pretty print output
     #[clap(short, long)]
     pretty: bool,
+    /// color trace and span ids, each id in a color of its own so related
+    /// messages stand out (auto, always or never)
+    #[clap(long, default_value = "auto")]
+    color: Color,
+    #[clap(skip)]
+    renderer: Renderer,
+    /// report encoded size per resource, scope, item and attribute key
+    /// instead of printing the struct
+    #[clap(long)]
+    sizes: bool,
+    /// convert jaeger/zipkin input into an otlp ExportTraceServiceRequest
+    #[clap(long)]
+    otlp: bool,
+    /// warn (on stderr) about suspicious data like zero ids, spans ending
+    /// before they start, implausible timestamps, attribute keys with
+    /// whitespace or empty resources
+    #[clap(short, long)]
+    warn: bool,
 }
 
-pub fn do_decode(decode: Decode) -> Result<(), Box<dyn error::Error>> {
+pub fn do_decode(mut decode: Decode) -> Result<(), Box<dyn error::Error>> {
     // println!("{:?}", decode);
+    decode.renderer = Renderer::new(decode.color);
     if decode.list {
-        for p in DecodeType::iter() {
-            println!("{:?}", p);
+        for decoder in DECODERS {
+            println!("{}", decoder.name);
         }
         return Ok(());
     }
-    eprintln!("decoding as proto {}", decode.name);
-    if decode.base64 {
-        // stream enabled
-        if decode.input == "-" {
-            let stdin = std::io::stdin();
-            for line in stdin.lock().lines() {
-                decode_struct_b64(&decode.name, line.unwrap(), decode.pretty)?;
+    eprintln!("decoding as proto {}", decode.name.name);
+    let framing = decode.framing.unwrap_or(if decode.base64 { Framing::Base64 } else { Framing::Raw });
+    let inputs = framing::expand_inputs(&decode.inputs)?;
+    for input in &inputs {
+        let mut number = 0;
+        framing::for_each_message(input, framing, |bs| {
+            number += 1;
+            let prefix = if inputs.len() > 1 {
+                format!("{}:{}:", framing::input_name(input), number)
+            } else {
+                String::new()
+            };
+            if framing == Framing::Raw {
+                decode_struct(&decode, bs, &prefix)?;
+            } else {
+                // keep going on a broken message of a stream
+                decode_struct_or_dump(&decode, bs, &prefix)?;
             }
-        } else {
-            let file = File::open(decode.input)?;
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                decode_struct_b64(&decode.name, line.unwrap(), decode.pretty)?;
-            }
-        }
-    } else {
-        // optimization: support incremental consuming
-        if decode.input == "-" {
-            let stdin = std::io::stdin();
-            let mut stdin_lock = stdin.lock();
-            let bytes = stdin_lock.fill_buf()?;
-            decode_struct(&decode.name, bytes, decode.pretty)?;
-        } else {
-            let file = File::open(decode.input)?;
-            let mut reader = BufReader::new(file);
-            let mut buf = vec![];
-            reader.read_to_end(&mut buf)?;
-            decode_struct(&decode.name, &buf, decode.pretty)?;
-        }
+            Ok(ControlFlow::Continue(()))
+        })?;
     }
     Ok(())
 }
 
-fn decode_struct_b64(name: &DecodeType, payload: String, pretty: bool) -> Result<(), Box<dyn error::Error>> {
-    let bs = base64::decode_config(payload, base64::STANDARD)?;
-    match decode_struct(name, &bs, pretty) {
+fn decode_struct_or_dump(decode: &Decode, bs: &[u8], prefix: &str) -> Result<(), Box<dyn error::Error>> {
+    match decode_struct(decode, bs, prefix) {
         Ok(_) => {},
         Err(err) => {
-            eprintln!("error during decoding: {}", err);
+            eprintln!("{}error during decoding: {}", prefix, err);
             let rs: String = rand::thread_rng()
                 .sample_iter(&Alphanumeric)
                 .take(7)
@@ -105,59 +297,81 @@ fn decode_struct_b64(name: &DecodeType, payload: String, pretty: bool) -> Result
     Ok(())
 }
 
-fn decode_struct(name: &DecodeType, payload: &[u8], pretty: bool) -> Result<(), Box<dyn error::Error>> {
+fn decode_struct(decode: &Decode, payload: &[u8], prefix: &str) -> Result<(), Box<dyn error::Error>> {
     // println!("{:?}", payload);
-    match *name {
-        DecodeType::Direct => {
-            print_stuffs(payload, pretty);
-        },
-        DecodeType::Span => {
-            print_stuffs(proto::trace::v1::Span::decode(payload)?, pretty);
-        },
-        DecodeType::Metric => {
-            print_stuffs(proto::metrics::v1::Metric::decode(payload)?, pretty);
-        },
-        DecodeType::LogRecord => {
-            print_stuffs(proto::logs::v1::LogRecord::decode(payload)?, pretty);
-        },
-        DecodeType::ScopeSpans => {
-            print_stuffs(proto::trace::v1::ScopeSpans::decode(payload)?, pretty);
-        },
-        DecodeType::ScopeMetrics => {
-            print_stuffs(proto::metrics::v1::ScopeMetrics::decode(payload)?, pretty);
-        },
-        DecodeType::ScopeLogs => {
-            print_stuffs(proto::logs::v1::ScopeLogs::decode(payload)?, pretty);
-        },
-        DecodeType::Resource => {
-            print_stuffs(proto::resource::v1::Resource::decode(payload)?, pretty);
-        },
-        DecodeType::ResourceSpans => {
-            print_stuffs(proto::trace::v1::ResourceSpans::decode(payload)?, pretty);
-        },
-        DecodeType::ResourceMetrics => {
-            print_stuffs(proto::metrics::v1::ResourceMetrics::decode(payload)?, pretty);
-        },
-        DecodeType::ResourceLogs => {
-            print_stuffs(proto::logs::v1::ResourceLogs::decode(payload)?, pretty);
-        },
-        DecodeType::ExportTraceServiceRequest => {
-            print_stuffs(proto::collector::trace::v1::ExportTraceServiceRequest::decode(payload)?, pretty);
-        },
-        DecodeType::ExportMetricsServiceRequest => {
-            print_stuffs(proto::collector::metrics::v1::ExportMetricsServiceRequest::decode(payload)?, pretty);
+    if decode.sizes {
+        return report_sizes(decode.name, payload, prefix);
+    }
+    if decode.warn {
+        for warning in check_payload(decode, payload)? {
+            eprintln!("{}warning: {}", prefix, warning);
+        }
+    }
+    let obj = match decode.name.to_otlp {
+        Some(to_otlp) if decode.otlp => Box::new(to_otlp(payload)?),
+        _ => (decode.name.decode)(payload)?,
+    };
+    let text = match decode.pretty {
+        true => format!("{:#?}", obj),
+        false => format!("{:?}", obj),
+    };
+    println!("{}{}", prefix, decode.renderer.ids(&text));
+    Ok(())
+}
+
+fn check_payload(decode: &Decode, payload: &[u8]) -> Result<Vec<String>, Box<dyn error::Error>> {
+    let warnings = match (decode.name.check, decode.name.to_otlp) {
+        (Some(check), _) => check(payload)?,
+        // foreign formats are checked on their otlp conversion
+        (None, Some(to_otlp)) if decode.otlp => sanity::check_resource_spans(&to_otlp(payload)?.resource_spans),
+        _ => vec![format!("--warn does not check {} payloads", decode.name.name)],
+    };
+    Ok(warnings)
+}
+
+fn report_sizes(decoder: &Decoder, payload: &[u8], prefix: &str) -> Result<(), Box<dyn error::Error>> {
+    let report = match (decoder.sizes, decoder.to_otlp) {
+        (Some(sizes), _) => sizes(payload)?,
+        // sizes of foreign formats are reported on their otlp conversion
+        (None, Some(to_otlp)) => {
+            let req = to_otlp(payload)?;
+            let mut report = SizeReport::new(req.encoded_len());
+            req.resource_spans.iter().for_each(|rs| report.add_resource_spans(rs));
+            report
         },
-        DecodeType::ExportLogsServiceRequest => {
-            print_stuffs(proto::collector::logs::v1::ExportLogsServiceRequest::decode(payload)?, pretty);
+        (None, None) => {
+            return Err(Box::new(OTKError::UnimplementedError(format!("--sizes of {} payloads", decoder.name))));
         },
     };
+    if !prefix.is_empty() {
+        println!("{}", prefix);
+    }
+    report.print();
     Ok(())
 }
 
-fn print_stuffs<T: std::fmt::Debug>(obj: T, pretty: bool) {
-    if pretty {
-        println!("{:#?}", obj);
-    } else {
-        println!("{:?}", obj);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoders_by_name() {
+        for d in DECODERS {
+            assert_eq!(decoder(d.name).unwrap().full_name, d.full_name);
+            assert_eq!(decoder(d.full_name).unwrap().name, d.name);
+        }
+        assert_eq!(decoder("opentelemetry.proto.trace.v1.Span.Event").unwrap().name, "Span.Event");
+        assert!(decoder("Event").is_err());
+    }
+
+    #[test]
+    fn decoders_print_debug() {
+        let event = trace::span::Event { name: "boom".to_string(), ..Default::default() };
+        let obj = (decoder("Span.Event").unwrap().decode)(&event.encode_to_vec()).unwrap();
+        assert_eq!(format!("{:?}", obj), format!("{:?}", event));
+
+        let span = zipkin::Span { name: "get".to_string(), ..Default::default() };
+        let req = (decoder("ZipkinSpan").unwrap().to_otlp.unwrap())(&span.encode_to_vec()).unwrap();
+        assert_eq!(req.resource_spans[0].scope_spans[0].spans[0].name, "get");
     }
 }
@@ -0,0 +1,101 @@
+use crate::cmd_conformance::{self, Conformance};
+use crate::cmd_report_log;
+use crate::cmd_report_metric;
+use crate::cmd_report_trace;
+use crate::otk_error::OTKError;
+use crate::render::Color;
+use crate::transport::Protocol;
+use clap::Parser;
+use std::error;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+
+/// start a receiver on a random local port, send it a small trace, metric
+/// points and log records, and pretty print what it received: a smoke test
+/// of otk needing nothing else
+#[derive(Parser, Debug)]
+pub struct Demo {
+    /// protocol to send with (grpc or http)
+    #[clap(long, default_value = "grpc")]
+    protocol: Protocol,
+
+    /// color trace and span ids of the received requests (auto, always or
+    /// never)
+    #[clap(long, default_value = "auto")]
+    color: Color,
+}
+
+pub fn do_demo(demo: Demo) -> Result<(), Box<dyn error::Error>> {
+    if demo.protocol == Protocol::HttpJson {
+        return Err(Box::new(OTKError::UnimplementedError("demo over http_json".into())));
+    }
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let receiver = Conformance::try_parse_from([
+        "conformance",
+        "--listen",
+        &addr.to_string(),
+        "--print",
+        "--pretty",
+        "--color",
+        &demo.color.to_string(),
+        "--require-resource",
+        "service.name",
+    ])?;
+    let stop = Arc::new(Notify::new());
+    let received = {
+        let stop = stop.clone();
+        thread::spawn(move || -> Result<_, String> {
+            let runtime = Runtime::new().map_err(|e| e.to_string())?;
+            runtime.block_on(cmd_conformance::listen(&receiver, listener, stop)).map_err(|e| e.to_string())
+        })
+    };
+
+    let url = format!("http://{}", addr);
+    let transport = ["--url", &url, "--protocol", &demo.protocol.to_string(), "--retries", "0"];
+    let resource = ["-r", "service.name=otk-demo", "deployment.environment=demo"];
+    let sent = send(|| {
+        eprintln!("\nsending a trace of a server span and its children");
+        let args = ["report-trace", "-n", "GET /checkout", "--kind", "server", "--depth", "2", "--fanout", "2"];
+        let mut args = args.iter().chain(&transport).chain(&resource).copied().collect::<Vec<_>>();
+        args.extend(["-a", "http.route=/checkout", "--event", "cache.miss@+2ms,key=cart"]);
+        cmd_report_trace::do_report(cmd_report_trace::Report::try_parse_from(args)?)
+    })
+    .and_then(|_| {
+        send(|| {
+            eprintln!("\nsending histogram points");
+            let args = ["report-metric", "-m", "histogram", "-n", "demo.request.duration"];
+            let mut args = args.iter().chain(&transport).chain(&resource).copied().collect::<Vec<_>>();
+            // values last, they may be negative
+            args.extend(["-v", "12", "40", "250"]);
+            cmd_report_metric::do_report(cmd_report_metric::Report::try_parse_from(args)?)
+        })
+    })
+    .and_then(|_| {
+        send(|| {
+            eprintln!("\nsending a log record");
+            let args = ["report-log", "-b", "order placed", "-s", "warn", "-a", "order.id=42"];
+            let args = args.iter().chain(&transport).chain(&resource).copied().collect::<Vec<_>>();
+            cmd_report_log::do_report(cmd_report_log::Report::try_parse_from(args)?)
+        })
+    });
+
+    stop.notify_one();
+    let mut grader = received.join().map_err(|_| OTKError::CheckError("the receiver panicked".into()))??;
+    eprintln!();
+    grader.report();
+    sent?;
+    if grader.requests() == 0 {
+        return Err(Box::new(OTKError::CheckError("nothing was received".into())));
+    }
+    Ok(())
+}
+
+/// run one of the report commands, its error kept for the end so the
+/// receiver still stops and reports
+fn send(report: impl FnOnce() -> Result<(), Box<dyn error::Error>>) -> Result<(), String> {
+    report().map_err(|e| e.to_string())
+}
@@ -0,0 +1,268 @@
+use crate::otk_error::OTKError;
+use clap::{CommandFactory, Parser};
+use std::error;
+
+/// print runnable example invocations of a command with what they do (all
+/// commands if none is given)
+#[derive(Parser, Debug)]
+pub struct Examples {
+    /// command (or one of its aliases)
+    command: Option<String>,
+}
+
+struct Example {
+    /// arguments after `otk <command>`
+    args: &'static [&'static str],
+    about: &'static str,
+}
+
+const fn ex(args: &'static [&'static str], about: &'static str) -> Example {
+    Example { args, about }
+}
+
+/// examples per command, every one is checked against the current flags
+/// when printed
+const EXAMPLES: &[(&str, &[Example])] = &[
+    ("decode", &[
+        ex(&["-b", "-p", "capture.b64"], "pretty print every request of a base64 capture"),
+        ex(&["-b", "-n", "ExportLogsServiceRequest", "logs.b64"], "decode captured log requests"),
+        ex(&["-f", "length-prefixed", "--sizes", "traces.pb"],
+            "show what takes the bytes in a collector file exporter dump (format: proto)"),
+        ex(&["-b", "-w", "captures/*.b64"], "decode several captures, warning about zero ids and other suspicious data"),
+        ex(&["-n", "HistogramDataPoint", "-p", "point.bin"], "pretty print a single histogram data point"),
+        ex(&["-l"], "list the message types decode knows"),
+    ]),
+    ("report-trace", &[
+        ex(&[], "one span to localhost:4317 over grpc"),
+        ex(&["--protocol", "http_json", "--kind", "server", "-a", "http.request.method=GET", "http.response.status_code:int=200"],
+            "a server span with a string and an int attribute over otlp/http json"),
+        ex(&["--batch", "100", "--duration", "200ms", "--depth", "3", "--fanout", "2", "--level-duration", "50ms,10ms"],
+            "100 traces of 7 spans: a 200ms root, 2 children of 50ms with 2 children of 10ms each"),
+        ex(&["--preset", "http-server", "-n", "POST /orders", "-a", "http.request.method=POST", "http.route=/orders"],
+            "a server span with the http semantic convention attributes, overriding the method and route"),
+        ex(&["--scope-name", "io.opentelemetry.okhttp-3.0", "--scope-version", "1.32.0", "--scope-attrs", "team=mobile"],
+            "a span from a versioned instrumentation scope with an attribute"),
+        ex(&["--batch", "20", "--wordlist", "routes.txt", "--kind", "server"],
+            "spans named after random lines of routes.txt, with http.route set to the same"),
+        ex(&["--batch", "1000", "--orphan-rate", "0.05", "--late-fraction", "0.1", "--late-delay", "1m"],
+            "5% of spans with a missing parent, 10% sent a minute late"),
+        ex(&["--start-time", "-2h", "--duration", "300ms", "--batch", "10"],
+            "10 back to back spans of 300ms starting two hours ago, to test late and skewed data"),
+        ex(&["--start-time", "2024-01-02T03:04:05Z", "--end-time", "2024-01-02T03:04:06.5Z"],
+            "a span with exact historical timestamps"),
+        ex(&["--traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01", "--kind", "client"],
+            "a client span continuing a real trace, as the child of the span in the traceparent"),
+        ex(&["--scenario", "checkout.yaml", "--batch", "10"],
+            "10 traces with the spans, parents, timings, attributes and statuses described in checkout.yaml"),
+        ex(&["--verify", "http://tempo:3200/api/traces/{trace_id}", "--max-e2e-latency", "5s"],
+            "ingestion latency probe: fail unless the trace can be queried from tempo within 5s"),
+        ex(&["--batch", "3", "--print-traceparent"],
+            "3 spans, printing the curl headers that make a request part of each trace"),
+        ex(&["--batch", "5", "--duplicate-ids"], "5 spans sharing a trace and span id, to test deduplication"),
+        ex(&["--status", "error", "--status-msg", "payment declined", "--kind", "server"],
+            "a failed server span with an error description"),
+        ex(&["--status", "unset", "--batch", "10"], "10 spans without a status, like most instrumentation sends"),
+        ex(&["--call-deadline", "200ms", "--duration", "1s"],
+            "a grpc call with a 200ms deadline, to see whether proxies propagate it"),
+        ex(&["--url", "https://otlp.example.com:4317", "--tls", "--metadata", "authorization=Bearer TOKEN"],
+            "report over tls with an auth header"),
+        ex(&["--protocol", "http", "--tls", "--host", "otlp.staging.internal", "--port", "443", "--insecure-skip-verify"],
+            "otlp/http through a tls ingress with a self-signed certificate"),
+        ex(&["--rate", "200", "--run-duration", "10m", "--depth", "2"],
+            "200 traces of 3 spans per second for ten minutes, or until ctrl-c"),
+        ex(&["--workers", "8", "--rate", "20000", "--run-duration", "5m"],
+            "20k spans/s from 8 parallel exporters, to find a collector's capacity"),
+        ex(&["--run-id", "auto", "--batch", "50"], "50 spans tagged with a printed otk.run_id, to find exactly them in the backend"),
+        ex(&["--from-capture", "capture.b64", "--url", "http://staging-collector:4317"],
+            "re-send the captured requests of decode or search to another collector, byte for byte"),
+        ex(&["--from-capture", "capture.b64", "--add-resource-attr", "environment=staging"],
+            "re-send a capture with environment=staging on every resource, like the collector's resource processor"),
+        ex(&["--batch", "100", "--ids-out", "ids.txt"],
+            "100 traces, appending their trace and root span ids to ids.txt for search or backend queries"),
+        ex(&["--preset", "http-server", "--dry-run"], "print the OTLP/JSON request of an http server span instead of sending it"),
+        ex(&["--batch", "1000", "--sampler", "traceidratio", "--sampler-arg", "0.1"],
+            "1000 traces through a 10% ratio sampler, printing how many it kept"),
+        ex(&["--batch", "5000", "--bsp-max-queue-size", "1000", "--bsp-max-export-batch-size", "100"],
+            "5000 spans through a 1000 span queue, exported 100 per request, to reproduce drops and batch limits"),
+        ex(&["--vendor", "honeycomb"], "a span to honeycomb, authenticated with $HONEYCOMB_API_KEY"),
+        ex(&["--protocol", "http", "--compression", "zstd", "--batch", "1000"], "a zstd compressed http export, to test a receiver's decoding and limits"),
+        ex(&["--compression", "gzip", "--batch", "1000"], "a gzip compressed grpc export"),
+        ex(&["--endpoint", "unix:///var/run/otel/otlp.sock"], "a span over grpc to an agent listening on a unix domain socket"),
+        ex(&["--batch", "1000", "--duration", "80ms", "--duration-jitter", "40ms", "--duration-dist", "pareto"],
+            "1000 spans of 80ms plus a long tail, so latency percentiles look like real traffic"),
+        ex(&["--batch", "1000", "--retries", "5"],
+            "retry unavailable or throttled exports up to 5 times, exiting non-zero if one still fails"),
+        ex(&["--processor", "simple", "--batch", "20", "--retries", "0"],
+            "20 spans in 20 requests, to see which ones a rate limiting collector rejects"),
+    ]),
+    ("report-metric", &[
+        ex(&["-n", "requests", "-m", "counter", "-d", "u64", "-v", "1", "2", "3"], "a u64 counter adding 1, 2 and 3"),
+        ex(&["-n", "latency", "-m", "histogram", "--histograms", "5", "10", "50", "-v", "3", "7", "40"],
+            "a histogram with buckets up to 5, 10 and 50"),
+        ex(&["-m", "histogram", "--histograms", "100", "250", "500", "--expect", "-v", "80", "120", "300", "900"],
+            "a histogram, printing the count, sum, min, max and buckets the backend should show"),
+        ex(&["-m", "counter", "-d", "i64", "--monotonic", "false", "-v", "10", "7", "12"],
+            "a non-monotonic cumulative sum, which backends often treat as a counter reset"),
+        ex(&["-m", "gauge", "-d", "i64", "-v", "3", "5", "4"], "an int gauge of three points a second apart"),
+        ex(&["--schema-url", "https://opentelemetry.io/schemas/1.21.0", "-r", "service.name=checkout"],
+            "a counter with a schema url on its resource and scope, to test schema translation"),
+        ex(&["--out-of-order", "--duplicate-timestamps", "-v", "1", "2", "3", "4"],
+            "shuffled points sharing timestamps, to test how a backend orders them"),
+        ex(&["--dry-run", "base64", "-m", "gauge", "-d", "f64", "-v", "0.5"],
+            "a gauge request as a base64 line, a fixture for decode -b or --from-capture"),
+        ex(&["--retries", "0", "-n", "requests", "-v", "1"], "a counter sent once, failing on the first unavailable or rejected export"),
+    ]),
+    ("report-log", &[
+        ex(&["-b", "payment failed", "-s", "ERROR", "-a", "order.id=42"], "an error log with an attribute"),
+        ex(&["--protocol", "http", "--batch", "100", "-b", "hello"], "100 logs over otlp/http protobuf"),
+        ex(&["--protocol", "http", "-b", "hello", "--metadata", "authorization=Bearer TOKEN", "x-scope-orgid=tenant1"],
+            "a log over otlp/http with auth and tenant headers"),
+        ex(&["-i", "access.log", "--extract", r"(?P<status>\d{3}) (?P<path>\S+) (?P<ms>[\d.]+)ms"],
+            "ship the lines of access.log as logs, with their status, path and ms as typed attributes"),
+        ex(&["-i", "-", "--body-field", "msg", "--severity-field", "level"],
+            "ship json lines from stdin, msg as the body, level as the severity and the other fields as attributes"),
+        ex(&["--from-capture", "otlp-logs.pb", "--framing", "length-prefixed"],
+            "replay the log requests a collector file exporter wrote"),
+        ex(&["-b", "hello", "--dry-run", "raw"], "the protobuf body of a log request, to save as a test fixture"),
+    ]),
+    ("search", &[
+        ex(&["--trace-id", "0af7651916cd43dd8448eb211c80319c", "capture.b64"],
+            "the spans of one trace (fast after `otk index capture.b64`)"),
+        ex(&["--query", "service.name == \"checkout\" && duration > 500ms", "capture.b64"], "slow checkout spans"),
+        ex(&["--group-by", "service", "captures/*.b64"], "span counts per service over several captures"),
+        ex(&["--status", "error", "--extract", "trace_id,name,duration", "capture.b64"], "failed spans as csv rows"),
+        ex(&["--signal", "logs", "--severity", "error", "--body-contains", "timeout", "logs.b64"],
+            "error logs mentioning a timeout"),
+        ex(&["-f", "--name-regex", "^GET ", "capture.b64"], "keep printing matching spans as the capture grows"),
+        ex(&["-f", "--status", "error", "capture.b64", "--on-match", "exec:./page-oncall.sh"],
+            "run a script with each new request holding failed spans (as json on its stdin)"),
+        ex(&["--only-matching", "--name-regex", "^GET ", "--color", "always", "capture.b64"],
+            "matching spans with each trace and span id in a color of its own, for `less -R`"),
+    ]),
+    ("inject", &[
+        ex(&["--traceparent-for", "0af7651916cd43dd8448eb211c80319c", "-v"],
+            "curl headers joining a trace, and the report-trace command creating their parent span"),
+        ex(&["--traceparent-for", "0af7651916cd43dd8448eb211c80319c", "--baggage", "user.id=42"],
+            "a traceparent with baggage"),
+    ]),
+    ("soak", &[
+        ex(&["--duration", "12h", "--rps", "50", "--batch", "10", "--openmetrics", "soak.prom"],
+            "half a day of 500 spans/s, with health scrapeable from soak.prom"),
+        ex(&["--duration", "30m", "--chaos"], "random batch sizes, trace shapes, attribute sizes and gaps"),
+        ex(&["--duration", "8h", "--url", "https://otlp.vendor.example:443", "--tls", "--token-command", "gcloud auth print-access-token"],
+            "a long soak against an oauth protected endpoint, refreshing the token as it expires"),
+    ]),
+    ("stats", &[
+        ex(&["capture.b64"], "span, trace and service counts of a capture"),
+        ex(&["--compare", "before.b64", "after.b64"], "what changed between two captures"),
+        ex(&["capture.b64", "--attr-dist", "http.status_code"], "how many spans have each status code, and how many have none"),
+    ]),
+    ("index", &[
+        ex(&["capture.b64"], "index trace ids and attributes, so search --trace-id and --attr skip the scan"),
+    ]),
+    ("values", &[ex(&["-k", "http.route", "--top", "20", "capture.b64"], "the 20 most frequent routes")]),
+    ("check", &[
+        ex(&["--expect-spans-per-trace", "7", "capture.b64"], "find traces with missing or extra spans"),
+        ex(&["capture.b64"], "missing parents and link targets, and 5xx spans not marked as errors"),
+    ]),
+    ("conformance", &[
+        ex(&["--target", "http://localhost:4317"], "test a grpc receiver with valid and malformed requests"),
+        ex(&["--target", "http://localhost:4318", "--protocol", "http", "--case", "gzip"],
+            "only the compression cases, over http"),
+        ex(&["--listen", "0.0.0.0:4317", "--duration", "1m"], "grade what an sdk sends for a minute"),
+        ex(&["--listen", "127.0.0.1:4318", "--api", ":8080"],
+            "a local receiver whose spans tests fetch from http://localhost:8080/traces/{id}"),
+        ex(&["--listen", "0.0.0.0:4318", "--on-error", "webhook:http://alerts.internal/otk"],
+            "POST every rejected or problematic request's findings to a webhook"),
+        ex(&["--listen", "127.0.0.1:4317", "--print"],
+            "print what an sdk sends as otlp json, spans of a trace sharing a color"),
+        ex(&["--listen", "0.0.0.0:4317", "--duration", "5m", "--emit-scenario", "checkout.yaml"],
+            "record the usual trace of a service, to replay it with report-trace --scenario checkout.yaml"),
+        ex(&["--listen", "127.0.0.1:4317", "--store", "sqlite:otk.db"],
+            "a local backend keeping everything received in otk.db, for otk query"),
+        ex(&["--listen", "0.0.0.0:4318", "--max-request-size", "4"],
+            "grade an sdk against a collector rejecting requests over 4 MiB, compressed or not"),
+    ]),
+    ("watchdog", &[
+        ex(&["--interval", "60s", "--on-error", "webhook:http://alerts.internal/otk"],
+            "a canary span, log and metric every minute, POSTing an alert after 3 failed rounds"),
+        ex(&["--rounds", "1", "--failures", "1", "--exit-on-alert", "--signals", "traces"],
+            "one-shot liveness probe, exiting non-zero when the span can't be sent"),
+    ]),
+    ("explain-sampling", &[
+        ex(&["capture.b64", "--config", "otelcol.yaml", "--trace-id", "0af7651916cd43dd8448eb211c80319c"],
+            "which tail_sampling policies of a collector config keep or drop a captured trace"),
+    ]),
+    ("bench-encode", &[
+        ex(&["--spans", "1M"], "how many spans per second otk can build and encode with 0, 10 and 50 attributes"),
+        ex(&["--spans", "200k", "--attrs", "128", "--batch", "2048"], "the cost of wide spans in large requests"),
+    ]),
+    ("slice", &[
+        ex(&["capture.b64", "--trace-ids", "ids.txt", "--out", "subset.b64"],
+            "the spans of the traces in ids.txt as a capture of their own, to attach to a bug report"),
+        ex(&["otlp-logs.b64", "--signal", "logs", "--trace-ids", "ids.txt"], "the log records of some traces, to stdout"),
+    ]),
+    ("demo", &[
+        ex(&[], "check otk works here: send a trace, metric points and a log record to itself and print them"),
+        ex(&["--protocol", "http", "--color", "never"], "the same over http protobuf, without colors"),
+    ]),
+    ("schema", &[
+        ex(&["scenario"], "the JSON Schema of report-trace --scenario files, e.g. for the yaml language server"),
+    ]),
+    ("query", &[
+        ex(&["SELECT name, count(*), avg(duration_ns) / 1e6 AS avg_ms FROM spans GROUP BY name ORDER BY 3 DESC",
+            "--store", "sqlite:otk.db"], "the slowest span names on average"),
+        ex(&["SELECT * FROM spans WHERE trace_id = '0af7651916cd43dd8448eb211c80319c' ORDER BY start_time_unix_nano",
+            "--store", "sqlite:otk.db", "--json"], "the spans of a trace as json lines"),
+        ex(&["SELECT body FROM logs WHERE json_extract(attributes, '$.\"http.route\"') = '/checkout'",
+            "--store", "sqlite:otk.db"], "log records by attribute"),
+    ]),
+    ("examples", &[ex(&["search"], "the examples of search")]),
+];
+
+pub fn do_examples(examples: Examples) -> Result<(), Box<dyn error::Error>> {
+    let otk = crate::Opts::command();
+    let commands = match &examples.command {
+        Some(name) => vec![otk
+            .find_subcommand(name)
+            .ok_or_else(|| OTKError::InvalidArgumentError(format!("unknown command {}", name)))?],
+        None => otk.get_subcommands().collect(),
+    };
+    for (i, command) in commands.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let name = command.get_name();
+        let about = command.get_about().map(|a| a.to_string()).unwrap_or_default();
+        println!("{}: {}", name, about.lines().next().unwrap_or_default());
+        let list = EXAMPLES.iter().find(|(n, _)| *n == name).map_or(&[][..], |(_, list)| *list);
+        if list.is_empty() {
+            println!("\n  (no examples yet, see otk {} --help)", name);
+        }
+        for example in list {
+            let line = line_args(name, example).map(quote).collect::<Vec<_>>();
+            println!("\n  # {}\n  otk {}", example.about, line.join(" "));
+            // flags drift, an example that no longer parses is a bug
+            let argv = std::iter::once("otk").chain(line_args(name, example));
+            if let Err(e) = crate::Opts::try_parse_from(argv) {
+                let e = e.to_string();
+                let reason = e.lines().take_while(|l| !l.starts_with("Usage")).map(str::trim).collect::<Vec<_>>();
+                eprintln!("  (outdated example: {})", reason.join(" ").trim_start_matches("error: ").trim());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn line_args<'a>(name: &'a str, example: &'a Example) -> impl Iterator<Item = &'a str> {
+    std::iter::once(name).chain(example.args.iter().copied())
+}
+
+/// quote an argument for a posix shell when needed
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:=,@%+".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
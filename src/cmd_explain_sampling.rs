@@ -0,0 +1,413 @@
+use crate::common::{any_value_to_string, parse_trace_id};
+use crate::framing::{self, Framing};
+use crate::index;
+use crate::otk_error::OTKError;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::any_value::Value;
+use crate::proto::common::v1::{AnyValue, KeyValue as ProtoKeyValue};
+use crate::proto::trace::v1::Span;
+use crate::query::status_name;
+use clap::Parser;
+use prost::Message;
+use regex::Regex;
+use serde_json::Value as Json;
+use std::collections::HashSet;
+use std::error;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+/// explain which tail sampling policies of a collector config would keep or
+/// drop a trace of a capture (input is base64 encoded binary)
+#[derive(Parser, Debug)]
+pub struct ExplainSampling {
+    /// file to read (- for stdin)
+    input: String,
+
+    /// collector config, or just the tail_sampling processor part of it
+    #[clap(long)]
+    config: String,
+
+    /// tail_sampling processor to use when the config has several, e.g.
+    /// tail_sampling/errors
+    #[clap(long)]
+    processor: Option<String>,
+
+    /// trace to explain (16 byte hex)
+    #[clap(long)]
+    trace_id: String,
+
+    /// message framing of the input (base64, raw, length-delimited or
+    /// length-prefixed)
+    #[clap(long, default_value = "base64")]
+    framing: Framing,
+}
+
+/// salt of the probabilistic policy when the config has none
+const DEFAULT_HASH_SALT: &str = "default-hash-seed";
+
+/// outcome of a policy for a trace, as the tail_sampling processor names them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decision {
+    Sampled,
+    NotSampled,
+    InvertSampled,
+    InvertNotSampled,
+    /// a drop policy matched, the trace is dropped whatever else samples it
+    Dropped,
+    /// depends on more than the trace, like the rate of spans
+    Unknown,
+}
+
+impl Decision {
+    fn label(self) -> &'static str {
+        match self {
+            Decision::Sampled => "sample",
+            Decision::NotSampled => "no",
+            Decision::InvertSampled => "sample (inverted)",
+            Decision::InvertNotSampled => "no (inverted)",
+            Decision::Dropped => "drop",
+            Decision::Unknown => "unknown",
+        }
+    }
+
+    fn samples(self) -> bool {
+        matches!(self, Decision::Sampled | Decision::InvertSampled)
+    }
+}
+
+/// a span of the explained trace with the resource it was reported under
+struct TraceSpan {
+    resource: Vec<ProtoKeyValue>,
+    span: Span,
+}
+
+struct Trace {
+    id: Vec<u8>,
+    spans: Vec<TraceSpan>,
+}
+
+pub fn do_explain_sampling(explain: ExplainSampling) -> Result<(), Box<dyn error::Error>> {
+    let config: serde_json::Value = serde_yaml::from_str(&std::fs::read_to_string(&explain.config)?)?;
+    let policies = policies(&config, explain.processor.as_deref())?;
+    let trace_id = format!("{:032x}", parse_trace_id(&explain.trace_id)?);
+    let trace = read_trace(&explain, &trace_id)?;
+    if trace.spans.is_empty() {
+        return Err(Box::new(OTKError::InvalidArgumentError(format!(
+            "trace {} not found in {}",
+            trace_id, explain.input
+        ))));
+    }
+    let errors = trace.spans.iter().filter(|s| status_of(&s.span) == "ERROR").count();
+    println!(
+        "trace {}: {} spans, {:?}, {} with error status",
+        trace_id,
+        trace.spans.len(),
+        trace.duration(),
+        errors
+    );
+
+    let mut decisions = vec![];
+    for policy in policies {
+        let (name, kind) = (policy_name(policy), policy_type(policy)?);
+        let (decision, reason) = evaluate(policy, &trace)?;
+        println!("  {:<17} {} ({}): {}", decision.label(), name, kind, reason);
+        decisions.push((name, decision));
+    }
+    let by = |wanted: &dyn Fn(Decision) -> bool| {
+        decisions.iter().filter(|(_, d)| wanted(*d)).map(|(n, _)| n.as_str()).collect::<Vec<_>>()
+    };
+    // the processor's order of precedence: drop, inverted not sampled, sampled
+    let dropped = by(&|d| d == Decision::Dropped);
+    let inverted = by(&|d| d == Decision::InvertNotSampled);
+    let sampled = by(&Decision::samples);
+    let unknown = by(&|d| d == Decision::Unknown);
+    if !dropped.is_empty() {
+        println!("decision: dropped by {}", dropped.join(", "));
+    } else if !inverted.is_empty() {
+        println!("decision: not sampled, inverted match of {}", inverted.join(", "));
+    } else if !sampled.is_empty() {
+        println!("decision: sampled by {}", sampled.join(", "));
+    } else if !unknown.is_empty() {
+        println!("decision: not sampled, unless {} samples it", unknown.join(" or "));
+    } else {
+        println!("decision: not sampled, no policy matched");
+    }
+    Ok(())
+}
+
+/// the policies of the config's tail_sampling processor
+fn policies<'a>(config: &'a Json, processor: Option<&str>) -> Result<&'a Vec<Json>, OTKError> {
+    let invalid = |message: String| OTKError::InvalidArgumentError(message);
+    if let Some(policies) = config.as_array().or_else(|| config["policies"].as_array()) {
+        return Ok(policies);
+    }
+    let processors = config["processors"]
+        .as_object()
+        .ok_or_else(|| invalid("config has neither policies nor processors".into()))?;
+    let names = processors
+        .keys()
+        .filter(|k| *k == "tail_sampling" || k.starts_with("tail_sampling/"))
+        .collect::<Vec<_>>();
+    let name = match (processor, names.as_slice()) {
+        (Some(name), _) => names
+            .iter()
+            .find(|n| n.as_str() == name)
+            .ok_or_else(|| invalid(format!("no processor {} in config", name)))?,
+        (None, [name]) => name,
+        (None, []) => return Err(invalid("no tail_sampling processor in config".into())),
+        (None, names) => {
+            let names = names.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+            return Err(invalid(format!("several tail_sampling processors, pick one with --processor: {}", names.join(", "))));
+        }
+    };
+    processors[name.as_str()]["policies"]
+        .as_array()
+        .ok_or_else(|| invalid(format!("processor {} has no policies", name)))
+}
+
+fn read_trace(explain: &ExplainSampling, trace_id: &str) -> Result<Trace, Box<dyn error::Error>> {
+    let mut trace = Trace { id: hex::decode(trace_id)?, spans: vec![] };
+    let mut collect = |bs: &[u8]| -> Result<ControlFlow<()>, Box<dyn error::Error>> {
+        let req = ExportTraceServiceRequest::decode(bs)?;
+        for rs in &req.resource_spans {
+            let resource = rs.resource.as_ref().map(|r| r.attributes.clone()).unwrap_or_default();
+            for span in rs.scope_spans.iter().flat_map(|ss| &ss.spans) {
+                if span.trace_id == trace.id {
+                    trace.spans.push(TraceSpan { resource: resource.clone(), span: span.clone() });
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    };
+    // only the lines holding the trace when the capture is indexed
+    let indexed = match explain.framing == Framing::Base64 && explain.input != "-" {
+        true => index::lookup(&explain.input, Some(&HashSet::from([trace_id.to_string()])), &[])?,
+        false => None,
+    };
+    match indexed {
+        Some(offsets) => {
+            for line in index::read_lines_at(&explain.input, &offsets)? {
+                let _ = collect(&base64::decode_config(line, base64::STANDARD)?)?;
+            }
+        }
+        None => framing::for_each_message(&explain.input, explain.framing, collect)?,
+    }
+    Ok(trace)
+}
+
+impl Trace {
+    /// from the earliest span start to the latest span end
+    fn duration(&self) -> Duration {
+        let start = self.spans.iter().map(|s| s.span.start_time_unix_nano).min().unwrap_or_default();
+        let end = self.spans.iter().map(|s| s.span.end_time_unix_nano).max().unwrap_or_default();
+        Duration::from_nanos(end.saturating_sub(start))
+    }
+
+    /// the first span whose resource or own attribute `key` satisfies `pred`
+    fn find(&self, key: &str, pred: impl Fn(&AnyValue) -> bool) -> Option<(&Span, &AnyValue)> {
+        self.spans.iter().find_map(|s| {
+            s.resource
+                .iter()
+                .chain(&s.span.attributes)
+                .filter(|kv| kv.key == key)
+                .find_map(|kv| kv.value.as_ref().filter(|v| pred(v)))
+                .map(|v| (&s.span, v))
+        })
+    }
+}
+
+fn status_of(span: &Span) -> &'static str {
+    status_name(span.status.as_ref().map_or(0, |s| s.code))
+}
+
+fn policy_name(policy: &Json) -> String {
+    policy["name"].as_str().map_or_else(|| "(unnamed)".to_string(), |s| s.to_string())
+}
+
+fn policy_type(policy: &Json) -> Result<&str, OTKError> {
+    policy["type"]
+        .as_str()
+        .ok_or_else(|| OTKError::ParseError(format!("policy {} has no type", policy_name(policy))))
+}
+
+/// what the policy decides for the trace, and why
+fn evaluate(policy: &Json, trace: &Trace) -> Result<(Decision, String), OTKError> {
+    let kind = policy_type(policy)?;
+    let config = &policy[kind];
+    let missing = |key: &str| OTKError::ParseError(format!("policy {}: {}.{} missing or invalid", policy_name(policy), kind, key));
+    let int = |key: &str| config[key].as_i64().ok_or_else(|| missing(key));
+    let string = |key: &str| config[key].as_str().ok_or_else(|| missing(key));
+    let flag = |key: &str| config[key].as_bool().unwrap_or(false);
+    let strings = |key: &str| -> Result<Vec<String>, OTKError> {
+        config[key]
+            .as_array()
+            .ok_or_else(|| missing(key))?
+            .iter()
+            .map(|v| v.as_str().map_or_else(|| v.to_string(), |s| s.to_string()))
+            .map(Ok)
+            .collect()
+    };
+    let found = |span: &Span, key: &str, value: &AnyValue| {
+        format!("span {:?} has {}={}", span.name, key, any_value_to_string(value))
+    };
+    let matched = |hit: Option<String>, missed: String, invert: bool| match (hit, invert) {
+        (Some(reason), false) => (Decision::Sampled, reason),
+        (None, false) => (Decision::NotSampled, missed),
+        (Some(reason), true) => (Decision::InvertNotSampled, format!("{}, match inverted", reason)),
+        (None, true) => (Decision::InvertSampled, format!("{}, match inverted", missed)),
+    };
+    Ok(match kind {
+        "always_sample" => (Decision::Sampled, "samples every trace".into()),
+        "latency" => {
+            let (duration, threshold) = (trace.duration(), int("threshold_ms")?);
+            let upper = config["upper_threshold_ms"].as_i64().unwrap_or(0);
+            let ms = duration.as_millis() as i64;
+            let range = match upper {
+                0 => format!("at least {}ms", threshold),
+                _ => format!("between {}ms and {}ms", threshold, upper),
+            };
+            match ms >= threshold && (upper == 0 || ms <= upper) {
+                true => (Decision::Sampled, format!("duration {:?} is {}", duration, range)),
+                false => (Decision::NotSampled, format!("duration {:?} is not {}", duration, range)),
+            }
+        }
+        "span_count" => {
+            let (count, min) = (trace.spans.len() as i64, int("min_spans")?);
+            let max = config["max_spans"].as_i64().unwrap_or(0);
+            let range = match max {
+                0 => format!("at least {}", min),
+                _ => format!("between {} and {}", min, max),
+            };
+            match count >= min && (max == 0 || count <= max) {
+                true => (Decision::Sampled, format!("{} spans is {}", count, range)),
+                false => (Decision::NotSampled, format!("{} spans is not {}", count, range)),
+            }
+        }
+        "status_code" => {
+            let codes = strings("status_codes")?;
+            if let Some(code) = codes.iter().find(|c| !["OK", "ERROR", "UNSET"].contains(&c.as_str())) {
+                return Err(OTKError::ParseError(format!("policy {}: unknown status code {}", policy_name(policy), code)));
+            }
+            match trace.spans.iter().find(|s| codes.iter().any(|c| c == status_of(&s.span))) {
+                Some(s) => (Decision::Sampled, format!("span {:?} has status {}", s.span.name, status_of(&s.span))),
+                None => (Decision::NotSampled, format!("no span has status {}", codes.join(" or "))),
+            }
+        }
+        "probabilistic" => {
+            let percentage = config["sampling_percentage"].as_f64().ok_or_else(|| missing("sampling_percentage"))?;
+            let salt = config["hash_salt"].as_str().filter(|s| !s.is_empty()).unwrap_or(DEFAULT_HASH_SALT);
+            let hash = salt
+                .bytes()
+                .chain(trace.id.iter().copied())
+                .fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3));
+            let threshold = (percentage / 100.0 * u64::MAX as f64) as u64;
+            let position = hash as f64 / u64::MAX as f64 * 100.0;
+            match hash <= threshold {
+                true => (Decision::Sampled, format!("trace id hashes to {:.2}%, within {}%", position, percentage)),
+                false => (Decision::NotSampled, format!("trace id hashes to {:.2}%, above {}%", position, percentage)),
+            }
+        }
+        "string_attribute" => {
+            let (key, values, invert) = (string("key")?, strings("values")?, flag("invert_match"));
+            let hit = if flag("enabled_regex_matching") {
+                let patterns = values
+                    .iter()
+                    .map(|v| Regex::new(v))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| OTKError::ParseError(format!("policy {}: {}", policy_name(policy), e)))?;
+                trace.find(key, |v| matches!(&v.value, Some(Value::StringValue(s)) if patterns.iter().any(|p| p.is_match(s))))
+            } else {
+                trace.find(key, |v| matches!(&v.value, Some(Value::StringValue(s)) if values.contains(s)))
+            };
+            let missed = format!("no span has {} matching {}", key, values.join(" or "));
+            matched(hit.map(|(span, v)| found(span, key, v)), missed, invert)
+        }
+        "numeric_attribute" => {
+            let (key, min, max) = (string("key")?, int("min_value")?, int("max_value")?);
+            let hit = trace.find(key, |v| matches!(v.value, Some(Value::IntValue(i)) if i >= min && i <= max));
+            let missed = format!("no span has {} between {} and {}", key, min, max);
+            matched(hit.map(|(span, v)| found(span, key, v)), missed, flag("invert_match"))
+        }
+        "boolean_attribute" => {
+            let key = string("key")?;
+            let value = config["value"].as_bool().ok_or_else(|| missing("value"))?;
+            let hit = trace.find(key, |v| v.value == Some(Value::BoolValue(value)));
+            let missed = format!("no span has {}={}", key, value);
+            matched(hit.map(|(span, v)| found(span, key, v)), missed, flag("invert_match"))
+        }
+        "trace_state" => {
+            let (key, values) = (string("key")?, strings("values")?);
+            let hit = trace.spans.iter().find_map(|s| {
+                s.span
+                    .trace_state
+                    .split(',')
+                    .filter_map(|entry| entry.trim().split_once('='))
+                    .find(|(k, v)| *k == key && values.iter().any(|x| x == v))
+                    .map(|(k, v)| format!("span {:?} has trace state {}={}", s.span.name, k, v))
+            });
+            match hit {
+                Some(reason) => (Decision::Sampled, reason),
+                None => (Decision::NotSampled, format!("no span has trace state {} in {}", key, values.join(", "))),
+            }
+        }
+        "and" => {
+            let subs = sub_policies(policy, config, "and_sub_policy", trace)?;
+            let decision = if subs.iter().any(|(_, d, _)| !d.samples() && *d != Decision::Unknown) {
+                Decision::NotSampled
+            } else if subs.iter().any(|(_, d, _)| *d == Decision::Unknown) {
+                Decision::Unknown
+            } else {
+                Decision::Sampled
+            };
+            (decision, describe(&subs))
+        }
+        "composite" => {
+            // the rate allocation between sub policies depends on traffic
+            let subs = sub_policies(policy, config, "composite_sub_policy", trace)?;
+            let decision = if subs.iter().any(|(_, d, _)| d.samples()) {
+                Decision::Sampled
+            } else if subs.iter().any(|(_, d, _)| *d == Decision::Unknown) {
+                Decision::Unknown
+            } else {
+                Decision::NotSampled
+            };
+            (decision, format!("{}, rate allocation not evaluated", describe(&subs)))
+        }
+        "drop" => {
+            let subs = sub_policies(policy, config, "drop_sub_policy", trace)?;
+            let decision = if subs.iter().all(|(_, d, _)| d.samples()) {
+                Decision::Dropped
+            } else if subs.iter().any(|(_, d, _)| !d.samples() && *d != Decision::Unknown) {
+                Decision::NotSampled
+            } else {
+                Decision::Unknown
+            };
+            (decision, describe(&subs))
+        }
+        "rate_limiting" | "bytes_limiting" => (Decision::Unknown, "depends on the traffic rate, not evaluated".into()),
+        "ottl_condition" => (Decision::Unknown, "ottl conditions are not evaluated".into()),
+        other => (Decision::Unknown, format!("unknown policy type {}, not evaluated", other)),
+    })
+}
+
+/// name, decision and reason of every sub policy of a composed policy
+fn sub_policies(
+    policy: &Json,
+    config: &Json,
+    key: &str,
+    trace: &Trace,
+) -> Result<Vec<(String, Decision, String)>, OTKError> {
+    let subs = config[key]
+        .as_array()
+        .ok_or_else(|| OTKError::ParseError(format!("policy {}: {} missing", policy_name(policy), key)))?;
+    subs.iter()
+        .map(|sub| evaluate(sub, trace).map(|(decision, reason)| (policy_name(sub), decision, reason)))
+        .collect()
+}
+
+fn describe(subs: &[(String, Decision, String)]) -> String {
+    subs.iter()
+        .map(|(name, decision, reason)| format!("[{} {}: {}]", name, decision.label(), reason))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,34 @@
+use crate::index;
+use clap::{Parser, Subcommand};
+use std::error;
+
+/// manage indexes of capture files, used by search --trace-id, --attr,
+/// --resource and --attr-exists to skip requests that can't match
+#[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true)]
+pub struct Index {
+    #[clap(subcommand)]
+    action: Option<IndexAction>,
+    /// capture file to index, short for `otk index build <input>`
+    #[clap(required = true)]
+    input: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum IndexAction {
+    /// build (or rebuild) the index of a base64 trace capture
+    Build {
+        /// capture file to index
+        input: String,
+    },
+}
+
+pub fn do_index(index: Index) -> Result<(), Box<dyn error::Error>> {
+    let input = match index.action {
+        Some(IndexAction::Build { input }) => input,
+        None => index.input.unwrap_or_default(),
+    };
+    let count = index::build(&input)?;
+    println!("indexed {} trace ids and the attributes of each request into {}", count, index::index_path(&input));
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::common::{curl_trace_headers, parse_span_id, parse_trace_id, KeyValue};
+use clap::Parser;
+use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceState};
+use rand::Rng;
+use std::error;
+
+/// print w3c trace context headers as curl arguments
+#[derive(Parser, Debug)]
+pub struct Inject {
+    /// trace id (32 hex chars) to put in traceparent
+    #[clap(long)]
+    traceparent_for: String,
+
+    /// parent span id (16 hex chars), random if not given
+    #[clap(long)]
+    span_id: Option<String>,
+
+    /// clear the sampled flag
+    #[clap(long)]
+    unsampled: bool,
+
+    /// baggage entries, can be repeated
+    #[clap(long, num_args = 0..)]
+    baggage: Vec<KeyValue>,
+
+    /// also print the report-trace command that creates the parent span
+    #[clap(short, long)]
+    verbose: bool,
+}
+
+pub fn do_inject(inject: Inject) -> Result<(), Box<dyn error::Error>> {
+    let trace_id = parse_trace_id(&inject.traceparent_for)?;
+    let span_id = match &inject.span_id {
+        Some(id) => parse_span_id(id)?,
+        None => random_span_id(),
+    };
+    let flags = if inject.unsampled { TraceFlags::NOT_SAMPLED } else { TraceFlags::SAMPLED };
+    let context = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    let mut args = vec![curl_trace_headers(&context)];
+    if !inject.baggage.is_empty() {
+        let entries = inject
+            .baggage
+            .iter()
+            .map(|kv| format!("{}={}", kv.k.trim(), percent_encode(&kv.v)))
+            .collect::<Vec<_>>();
+        args.push(format!("-H 'baggage: {}'", entries.join(",")));
+    }
+    println!("{}", args.join(" "));
+    if inject.verbose {
+        eprintln!(
+            "create the parent span with: otk report-trace --trace-id {:032x} --span-id {:016x}",
+            trace_id, span_id
+        );
+    }
+    Ok(())
+}
+
+fn random_span_id() -> SpanId {
+    let mut rng = rand::thread_rng();
+    loop {
+        let id = SpanId::from_bytes(rng.gen::<[u8; 8]>());
+        if id != SpanId::INVALID {
+            return id;
+        }
+    }
+}
+
+/// percent-encode everything outside the unreserved set, which also keeps
+/// the value safe inside the single-quoted shell argument
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
@@ -0,0 +1,71 @@
+use crate::otk_error::OTKError;
+use crate::store::parse_store;
+use clap::Parser;
+use hex::ToHex;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::{Map, Value as Json};
+use std::error;
+
+/// run sql against what conformance --listen --store saved
+///
+/// the tables are spans, logs and metric_points (`SELECT sql FROM
+/// sqlite_schema` lists their columns). ids are hex, times nanoseconds since
+/// the epoch and attributes json objects, e.g. `SELECT name, avg(duration_ns)
+/// / 1e6 AS ms FROM spans WHERE json_extract(attributes, '$."http.route"') =
+/// '/checkout' GROUP BY name`
+#[derive(Parser, Debug)]
+pub struct Query {
+    /// the statement to run
+    sql: String,
+
+    /// the database, like sqlite:otk.db
+    #[clap(long, value_parser = parse_store)]
+    store: String,
+
+    /// print every row as a json object instead of tab separated columns
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn do_query(query: Query) -> Result<(), Box<dyn error::Error>> {
+    // a query must not create an empty database where a path was mistyped
+    let conn = Connection::open_with_flags(&query.store, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| OTKError::InvalidArgumentError(format!("open store {} failed: {}", query.store, e)))?;
+    let mut stmt = conn.prepare(&query.sql).map_err(|e| OTKError::InvalidArgumentError(e.to_string()))?;
+    let columns = stmt.column_names().iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    if !query.json {
+        println!("{}", columns.join("\t"));
+    }
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let values = (0..columns.len()).map(|i| row.get_ref(i).map(json_value)).collect::<Result<Vec<_>, _>>()?;
+        if query.json {
+            let object = columns.iter().cloned().zip(values).collect::<Map<_, _>>();
+            println!("{}", Json::Object(object));
+        } else {
+            println!("{}", values.iter().map(text).collect::<Vec<_>>().join("\t"));
+        }
+    }
+    Ok(())
+}
+
+/// a column value, blobs as hex
+fn json_value(value: ValueRef) -> Json {
+    match value {
+        ValueRef::Null => Json::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+        ValueRef::Blob(b) => b.encode_hex::<String>().into(),
+    }
+}
+
+/// a column of a tab separated row, NULL as nothing
+fn text(value: &Json) -> String {
+    match value {
+        Json::Null => String::new(),
+        Json::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
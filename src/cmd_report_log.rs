@@ -1,213 +1,265 @@
-use crate::common::KeyValue;
-use crate::otk_error::OTKError;
+use crate::cmd_search::Signal;
+use crate::common::{parse_time, resource, resolve_run_id, run_id_attr, KeyValue, ValueType, INSTRUMENTATION_LIB_NAME};
+use crate::export::{self, CheckedLogExporter, Exports};
+use crate::framing;
+use crate::replay;
+use crate::report::ReportOpts;
+use crate::transport::TransportOpts;
 use clap::Parser;
-use opentelemetry::logs::{LogRecord, AnyValue, Logger};
+use opentelemetry::logs::{LogRecord, AnyValue, Logger, LoggerProvider as _};
 use opentelemetry::global;
-use opentelemetry_otlp::{NoExporterConfig, WithExportConfig, OtlpLogPipeline};
-use opentelemetry_sdk::{Resource, logs};
+use opentelemetry_sdk::logs;
+use regex::Regex;
+use serde_json::{Map, Value as Json};
 use std::error;
-use std::fs::read_to_string;
-use std::str::FromStr;
-use std::time::SystemTime;
-use strum_macros::{Display, EnumString};
+use std::io::BufRead;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
-use tonic::metadata::{AsciiMetadataKey, MetadataMap};
-use tonic::transport::{Certificate, ClientTlsConfig};
-
-#[derive(Debug, Clone, Display, EnumString)]
-enum Protocol {
-    #[strum(serialize = "grpc", serialize = "g")]
-    Grpc,
-    #[strum(serialize = "http", serialize = "h")]
-    Http,
-    #[strum(serialize = "http_json", serialize = "hj")]
-    HttpJson,
-}
-
-static DEFAULT_GRPC_PORT: u16 = 4317;
-static DEFAULT_HTTP_PORT: u16 = 4318;
-static DEFAULT_HTTP_JSON_PORT: u16 = 4318;
 
 /// report to otlp receiver
 #[derive(Parser, Debug)]
+// the short flags these had before the transport options were shared
+#[clap(mut_arg("metadata", |a| a.short('m')), mut_arg("timeout", |a| a.short('t')))]
 pub struct Report {
-    /// protocol to use (grpc, http or http_json), currently
-    /// only grpc is supported
-    #[clap(long, default_value = "grpc")]
-    protocol: Protocol,
-
-    /// whether to use tls
-    #[clap(long)]
-    tls: bool,
+    #[clap(flatten)]
+    transport: TransportOpts,
 
-    /// CA cert path if tls is enabled
-    #[clap(long, requires = "tls")]
-    ca_cert: Option<String>,
+    #[clap(flatten)]
+    opts: ReportOpts,
 
-    /// server host name to verify
-    #[clap(long, requires = "tls")]
-    domain: Option<String>,
+    /// tag used in resource
+    #[clap(short, long, num_args = 0..)]
+    rtags: Vec<KeyValue>,
 
-    /// full url as base
-    #[clap(long)]
-    url: Option<String>,
+    /// log body!
+    #[clap(short, long, required_unless_present_any = ["from_capture", "input"])]
+    body: Option<String>,
 
-    /// server host
-    #[clap(long, default_value = "localhost", env = "OTK_REPORT_HOST")]
-    host: String,
+    /// ship every line of this file (- for stdin) as the body of a log
+    /// instead of --body, as it is read, e.g. `tail -F app.log | otk
+    /// report-log -i -`
+    #[clap(short, long, conflicts_with_all = ["body", "from_capture"])]
+    input: Option<String>,
 
-    /// server port (default value depends on protocol)
-    #[clap(long, env = "OTK_REPORT_PORT")]
-    port: Option<u16>,
+    /// fields of json lines of --input holding the body, the first one
+    /// present is used. the other fields become attributes, nested ones with
+    /// dotted keys
+    #[clap(long, num_args = 0.., default_values = &["message", "msg"])]
+    body_field: Vec<String>,
 
-    /// tag used in resource
-    #[clap(short, long, num_args = 0..)]
-    rtags: Vec<KeyValue>,
+    /// fields of json lines holding the timestamp (RFC3339, or unix seconds,
+    /// fractional or not, milliseconds, microseconds or nanoseconds)
+    #[clap(long, num_args = 0.., default_values = &["timestamp", "time", "ts", "@timestamp"])]
+    time_field: Vec<String>,
 
-    /// metadata map value
-    #[clap(short, long, num_args = 0..)]
-    metadata: Vec<KeyValue>,
+    /// fields of json lines holding the severity text, instead of --severity
+    #[clap(long, num_args = 0.., default_values = &["level", "severity", "lvl"])]
+    severity_field: Vec<String>,
 
-    /// log body!
-    #[clap(short, long)]
-    body: String,
+    /// regex whose named groups become attributes of the logs whose body
+    /// matches, typed int, double or bool when the text parses as one, e.g.
+    /// `(?P<status>\d{3}) (?P<path>\S+)`
+    #[clap(long)]
+    extract: Option<Regex>,
 
     /// severity text
     #[clap(short, long, default_value = "INFO")]
     severity: String,
 
-    /// span attributes
+    /// log attributes, `key=value` or typed like `count:int=5` (see report-trace
+    /// --attrs)
     #[clap(short, long, num_args = 0..)]
     attrs: Vec<KeyValue>,
 
-    /// send a batch of spans
+    /// send a batch of logs (with --body)
     #[clap(long, default_value = "1")]
     batch: u64,
 
     /// verbose
     #[clap(short, long)]
     verbose: bool,
-
-    /// send timeout in seconds (this is a general timeout and might be restricted by other
-    /// timeout, like batch processor timeout)
-    #[clap(short, long, default_value = "10")]
-    timeout: u64,
 }
 
-pub fn do_report(report: Report) -> Result<(), Box<dyn error::Error>> {
+pub fn do_report(mut report: Report) -> Result<(), Box<dyn error::Error>> {
     if report.verbose {
         println!("{:?}", report);
     }
+    report.transport.dry_run = report.opts.dry_run;
+    report.transport.exports = Arc::new(Exports::new(report.opts.retries, "log records"));
+    export::handle_other_errors();
+    resolve_run_id(&mut report.opts.run_id);
+    report.attrs.extend(run_id_attr(&report.opts.run_id));
     Runtime::new().unwrap().block_on(do_report_log(report))
 }
 
 async fn do_report_log(report: Report) -> Result<(), Box<dyn error::Error>> {
-    let pipeline = opentelemetry_otlp::new_pipeline().logging();
-    let port = report.port.unwrap_or_else(|| match report.protocol {
-        Protocol::Grpc => DEFAULT_GRPC_PORT,
-        Protocol::Http => DEFAULT_HTTP_PORT,
-        Protocol::HttpJson => DEFAULT_HTTP_JSON_PORT,
-    });
-    let scheme = if report.tls { "https" } else { "http" };
-    let endpoint_base = if let Some(url) = &report.url {
-        url.clone()
-    } else {
-        format!("{}://{}:{}", scheme, report.host, port)
-    };
-    let resource = Resource::new(report.rtags.iter().map(|x| x.clone().into()));
-    let log_config = logs::config().with_resource(resource);
-    let pipeline = pipeline.with_log_config(log_config);
-
-    match report.protocol {
-        Protocol::Grpc => do_report_log_grpc(pipeline, report, endpoint_base).await,
-        Protocol::Http => do_report_log_http(pipeline, report, endpoint_base).await,
-        _ => return Err(Box::new(OTKError::UnimplementedError("httpjson".into()))),
-    }
-}
-
-async fn do_report_log_grpc(
-    pipeline: OtlpLogPipeline<NoExporterConfig>,
-    report: Report,
-    endpoint_base: String,
-) -> Result<(), Box<dyn error::Error>> {
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_endpoint(endpoint_base)
-        .with_timeout(std::time::Duration::from_secs(report.timeout));
-    let exporter = if report.tls {
-        let mut tls_config = ClientTlsConfig::new();
-        if report.ca_cert.is_some() {
-            let pem = read_to_string(report.ca_cert.unwrap()).expect("open cacert");
-            tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
-        };
-        if report.domain.is_some() {
-            tls_config = tls_config.domain_name(report.domain.unwrap());
+    for transport in report.transport.sweep()? {
+        if report.verbose {
+            println!("metadata: {:?}", transport.metadata);
         }
-        exporter.with_tls_config(tls_config)
-    } else {
-        exporter
-    };
-    let mut meta_map = MetadataMap::new();
-    for kv in &report.metadata {
-        meta_map.append(
-            AsciiMetadataKey::from_str(kv.k.as_str())?,
-            kv.v.as_str().parse()?,
-        );
+        if let Some(capture) = &report.opts.from_capture {
+            replay::replay(&transport, Signal::Logs, capture, report.opts.framing, &report.opts.add_resource_attr).await?;
+            continue;
+        }
+        report_logs(&report, &transport)?;
     }
-    let exporter = exporter.with_metadata(meta_map);
-    let pipeline = pipeline.with_exporter(exporter);
-
-    let logger = pipeline.install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    report.transport.exports.finish()?;
+    Ok(())
+}
 
-    for _ in 0..report.batch {
+fn report_logs(report: &Report, transport: &TransportOpts) -> Result<(), Box<dyn error::Error>> {
+    let log_config = logs::config().with_resource(resource(&report.rtags, report.opts.schema_url.as_deref()));
+    let exporter = transport.exporter()?.build_log_exporter()?;
+    let exporter = CheckedLogExporter { inner: exporter, exports: transport.exports.clone() };
+    let provider = logs::LoggerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_config(log_config)
+        .build();
+    let logger = provider.versioned_logger(
+        INSTRUMENTATION_LIB_NAME,
+        None,
+        report.opts.schema_url.clone().map(Into::into),
+        None,
+    );
+    global::set_logger_provider(provider);
+    let emit = |line: Line| {
+        let extracted = report.extract.as_ref().map_or(vec![], |re| extract(re, &line.body));
         let mut log_builder = LogRecord::builder()
-            .with_timestamp(SystemTime::now())
-            .with_body(AnyValue::String(report.body.clone().into()));
-        for attr in &report.attrs {
-            log_builder = log_builder.with_attribute(attr.k.clone(), attr.v.clone());
+            .with_timestamp(line.time.unwrap_or_else(SystemTime::now))
+            .with_body(AnyValue::String(line.body.into()));
+        for attr in report.attrs.iter().chain(&line.attrs).chain(&extracted) {
+            log_builder = log_builder.with_attribute(attr.k.clone(), attr.value());
         }
-        log_builder = log_builder.with_severity_text(report.severity.clone());
+        log_builder = log_builder.with_severity_text(line.severity.unwrap_or_else(|| report.severity.clone()));
         let rec = log_builder.build();
         logger.emit(rec);
+    };
+    match &report.input {
+        Some(input) => {
+            for line in framing::open_input(input)?.lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    emit(Line::parse(report, line));
+                }
+            }
+        }
+        None => {
+            for _ in 0..report.batch {
+                emit(Line::plain(report.body.clone().unwrap_or_default()));
+            }
+        }
     }
     global::shutdown_logger_provider();
     Ok(())
 }
 
-async fn do_report_log_http(
-    pipeline: OtlpLogPipeline<NoExporterConfig>,
-    report: Report,
-    endpoint_base: String,
-) -> Result<(), Box<dyn error::Error>> {
-    if report.tls {
-        return Err(Box::new(OTKError::UnimplementedError(
-            "http does not support tls for now".into(),
-        )));
+/// a log to send, from --body or a line of --input
+struct Line {
+    body: String,
+    time: Option<SystemTime>,
+    severity: Option<String>,
+    attrs: Vec<KeyValue>,
+}
+
+impl Line {
+    fn plain(body: String) -> Line {
+        Line { body, time: None, severity: None, attrs: vec![] }
     }
-    if !report.metadata.is_empty() {
-        return Err(Box::new(OTKError::InvalidArgumentError(
-            "http can not set metadata for now".into(),
-        )));
+
+    /// a line of --input, taking json objects apart into body, timestamp,
+    /// severity and attributes
+    fn parse(report: &Report, text: String) -> Line {
+        let fields = match serde_json::from_str::<Json>(&text) {
+            Ok(Json::Object(fields)) if text.trim_start().starts_with('{') => fields,
+            _ => return Line::plain(text),
+        };
+        let body_key = first_field(&fields, &report.body_field);
+        let time_key = first_field(&fields, &report.time_field);
+        let severity_key = first_field(&fields, &report.severity_field);
+        // a line without a body field is sent whole as the body
+        let body = match body_key.map(|k| &fields[k.as_str()]) {
+            Some(Json::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => text.clone(),
+        };
+        let time = time_key.and_then(|k| json_time(&fields[k.as_str()]));
+        let severity = severity_key.map(|k| match &fields[k.as_str()] {
+            Json::String(s) => s.clone(),
+            other => other.to_string(),
+        });
+        let mut attrs = vec![];
+        let taken = [body_key, severity_key, time_key.filter(|_| time.is_some())];
+        let rest = fields.iter().filter(|(k, _)| !taken.iter().flatten().any(|t| t == k));
+        flatten_fields("", rest, &mut attrs);
+        Line { body, time, severity, attrs }
     }
+}
 
-    let exporter = opentelemetry_otlp::new_exporter()
-        .http()
-        .with_endpoint(endpoint_base)
-        .with_timeout(std::time::Duration::from_secs(report.timeout));
+/// the first of `names` set in `fields`
+fn first_field<'a>(fields: &Map<String, Json>, names: &'a [String]) -> Option<&'a String> {
+    names.iter().find(|n| fields.get(n.as_str()).is_some_and(|v| !v.is_null()))
+}
 
-    let pipeline = pipeline.with_exporter(exporter);
-    let logger = pipeline.install_batch(opentelemetry_sdk::runtime::Tokio)?;
-    for _ in 0..report.batch {
-        let mut log_builder = LogRecord::builder()
-            .with_body(AnyValue::String(report.body.clone().into()))
-            .with_timestamp(SystemTime::now());
-        for attr in &report.attrs {
-            log_builder = log_builder.with_attribute(attr.k.clone(), attr.v.clone());
-        }
-        log_builder = log_builder.with_severity_text(report.severity.clone());
-        let rec = log_builder.build();
-        logger.emit(rec);
+/// json fields as attributes, keys of nested objects joined with dots and
+/// arrays as json text
+fn flatten_fields<'a>(prefix: &str, fields: impl Iterator<Item = (&'a String, &'a Json)>, attrs: &mut Vec<KeyValue>) {
+    for (k, v) in fields {
+        let k = format!("{}{}", prefix, k);
+        let (v, ty) = match v {
+            Json::Null => continue,
+            Json::Object(nested) => {
+                flatten_fields(&format!("{}.", k), nested.iter(), attrs);
+                continue;
+            }
+            Json::String(s) => (s.clone(), ValueType::String),
+            Json::Bool(b) => (b.to_string(), ValueType::Bool),
+            Json::Number(n) if n.is_i64() => (n.to_string(), ValueType::Int),
+            Json::Number(n) => (n.to_string(), ValueType::Double),
+            Json::Array(_) => (v.to_string(), ValueType::String),
+        };
+        attrs.push(KeyValue { k, v, ty });
     }
-    global::shutdown_logger_provider();
-    Ok(())
+}
+
+/// an RFC3339 timestamp, or a unix one in a unit guessed from its size
+fn json_time(value: &Json) -> Option<SystemTime> {
+    let nanos = match value {
+        Json::String(s) => parse_time(s).ok()?,
+        // integers stay exact, fractions of seconds are fine as floats
+        Json::Number(n) => match (n.as_u64(), n.as_f64()?) {
+            (Some(n), _) if n < 100_000_000_000 => n * 1_000_000_000,
+            (Some(n), _) if n < 100_000_000_000_000 => n * 1_000_000,
+            (Some(n), _) if n < 100_000_000_000_000_000 => n * 1_000,
+            (Some(n), _) => n,
+            (None, f) if (0. ..1e11).contains(&f) => (f * 1e9) as u64,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+/// the named groups of --extract matching `body`, as attributes with the
+/// type their text parses as
+fn extract(re: &Regex, body: &str) -> Vec<KeyValue> {
+    let Some(captures) = re.captures(body) else {
+        return vec![];
+    };
+    re.capture_names()
+        .flatten()
+        .filter_map(|name| {
+            let v = captures.name(name)?.as_str().to_string();
+            let ty = if v.parse::<i64>().is_ok() {
+                ValueType::Int
+            } else if v.parse::<f64>().is_ok_and(f64::is_finite) {
+                ValueType::Double
+            } else if v == "true" || v == "false" {
+                ValueType::Bool
+            } else {
+                ValueType::String
+            };
+            Some(KeyValue { k: name.to_string(), v, ty })
+        })
+        .collect()
 }
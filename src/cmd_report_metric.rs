@@ -1,47 +1,35 @@
-use crate::common::{KeyValue, INSTRUMENTATION_LIB_NAME};
+use crate::cmd_search::Signal;
+use crate::common::{parse_duration, resource, resolve_run_id, run_id_attr, KeyValue, INSTRUMENTATION_LIB_NAME};
+use crate::export::{self, CheckedMetricsExporter, Exports};
 use crate::otk_error::OTKError;
+use crate::replay;
+use crate::report::ReportOpts;
+use crate::transport::TransportOpts;
 use clap::Parser;
 use opentelemetry::global;
-use opentelemetry::metrics::{Counter, Histogram, UpDownCounter};
-use opentelemetry::KeyValue as OTLPKeyValue;
-use opentelemetry_otlp::{ExportConfig, WithExportConfig};
+use opentelemetry::metrics::{Counter, Histogram, Unit, UpDownCounter};
+use opentelemetry::{InstrumentationLibrary, KeyValue as OTLPKeyValue};
+use opentelemetry_sdk::metrics::data::{Aggregation, DataPoint, Gauge, Metric, ResourceMetrics, ScopeMetrics, Sum, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, DefaultAggregationSelector, DefaultTemporalitySelector};
+use opentelemetry_sdk::metrics::{self, InstrumentKind, MeterProvider, PeriodicReader};
 use opentelemetry_sdk::runtime::Tokio;
-use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::AttributeSet;
+use rand::seq::SliceRandom;
 use std::error;
 use std::str::FromStr;
-use std::time::Duration;
-use strum_macros::{Display, EnumString};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::runtime::Runtime;
 
-#[derive(Debug, Clone, Display, EnumString)]
-enum Protocol {
-    #[strum(serialize = "grpc", serialize = "g")]
-    Grpc,
-    #[strum(serialize = "http", serialize = "h")]
-    Http,
-    #[strum(serialize = "http_json", serialize = "hj")]
-    HttpJson,
-}
-
-static DEFAULT_GRPC_PORT: u16 = 4317;
-static DEFAULT_HTTP_PORT: u16 = 55681;
-static DEFAULT_HTTP_JSON_PORT: u16 = 55681;
-
 /// report to otlp receiver
 #[derive(Parser, Debug)]
 pub struct Report {
-    /// protocol to use (grpc, http or http_json), currently
-    /// only grpc is supported
-    #[clap(long, default_value = "grpc")]
-    protocol: Protocol,
+    #[clap(flatten)]
+    transport: TransportOpts,
 
-    /// server host
-    #[clap(long, default_value = "localhost", env = "OTK_REPORT_HOST")]
-    host: String,
-
-    /// server port (default value depends on protocol)
-    #[clap(long, env = "OTK_REPORT_PORT")]
-    port: Option<u16>,
+    #[clap(flatten)]
+    opts: ReportOpts,
 
     /// tag used in resource
     #[clap(short, long, num_args = 0..)]
@@ -55,7 +43,8 @@ pub struct Report {
     #[clap(short, long, default_value = "f64")]
     dtype: String,
 
-    /// metrics type
+    /// metrics type: counter, up_down_counter, histogram, or gauge (sent as
+    /// explicit data points, see --out-of-order)
     #[clap(short, long, default_value = "counter")]
     mtype: String,
 
@@ -75,76 +64,114 @@ pub struct Report {
     #[clap(short, long, default_value = "1")]
     times: u32,
 
-    /// how many seconds to wait
-    #[clap(short, long, default_value = "0.15")]
-    wait_secs: f64,
+    /// how long to wait for the export (e.g. 150ms, 2s or 0.15 seconds)
+    #[clap(short, long, alias = "wait-secs", value_parser = parse_duration, default_value = "150ms")]
+    wait: Duration,
 
-    /// histograms buckets
+    /// upper bounds of the histogram buckets
     #[clap(long, default_values = &["10", "20", "30", "40", "50", "60", "70", "80", "90"], num_args = 0..)]
     histograms: Vec<f64>,
 
-    /// labels
+    /// after sending a histogram, print what the backend should show for it:
+    /// count, sum, min, max and the count of every bucket
+    #[clap(long, conflicts_with = "from_capture")]
+    expect: bool,
+
+    /// labels, `key=value` or typed like `count:int=5` (see report-trace --attrs)
     #[clap(short, long, num_args = 0..)]
     labels: Vec<KeyValue>,
 
+    /// send the values as data points of one counter or up_down_counter
+    /// series with explicit timestamps (--point-interval apart), shuffled out
+    /// of chronological order
+    #[clap(long)]
+    out_of_order: bool,
+
+    /// send the values as data points with explicit timestamps (see
+    /// --out-of-order) where every two consecutive points share a timestamp
+    #[clap(long)]
+    duplicate_timestamps: bool,
+
+    /// send the values as data points of a cumulative sum with this
+    /// monotonicity instead of the one of --mtype, e.g. false with counter for
+    /// a non-monotonic cumulative sum
+    #[clap(long)]
+    monotonic: Option<bool>,
+
+    /// time between explicit data point timestamps, the last one is now
+    #[clap(long, value_parser = parse_duration, default_value = "1s")]
+    point_interval: Duration,
+
     /// verbose
     #[clap(long)]
     verbose: bool,
 }
 
-pub fn do_report(report: Report) -> Result<(), Box<dyn error::Error>> {
+pub fn do_report(mut report: Report) -> Result<(), Box<dyn error::Error>> {
     if report.verbose {
         println!("{:?}", report);
     }
+    // checked before anything is sent
+    if report.expect && (report.mtype != "histogram" || report.out_of_order || report.duplicate_timestamps) {
+        return Err(Box::new(OTKError::InvalidArgumentError("--expect only applies to histograms".into())));
+    }
+    report.transport.dry_run = report.opts.dry_run;
+    report.transport.exports = Arc::new(Exports::new(report.opts.retries, "points"));
+    export::handle_other_errors();
+    resolve_run_id(&mut report.opts.run_id);
+    report.labels.extend(run_id_attr(&report.opts.run_id));
     Runtime::new().unwrap().block_on(do_report_metric(report))
 }
 
 async fn do_report_metric(report: Report) -> Result<(), Box<dyn error::Error>> {
-    let pipeline = opentelemetry_otlp::new_pipeline().metrics(Tokio);
-    let port = report.port.unwrap_or_else(|| match report.protocol {
-        Protocol::Grpc => DEFAULT_GRPC_PORT,
-        Protocol::Http => DEFAULT_HTTP_PORT,
-        Protocol::HttpJson => DEFAULT_HTTP_JSON_PORT,
-    });
-    let protocol = match report.protocol {
-        Protocol::Grpc => opentelemetry_otlp::Protocol::Grpc,
-        Protocol::Http => {
-            return Err(Box::new(OTKError::UnimplementedError(
-                "http not supported for now".into(),
-            )))
+    for transport in report.transport.sweep()? {
+        if report.verbose {
+            println!("metadata: {:?}", transport.metadata);
         }
-        Protocol::HttpJson => {
-            return Err(Box::new(OTKError::UnimplementedError(
-                "http json not supported for now".into(),
-            )))
+        if let Some(capture) = &report.opts.from_capture {
+            replay::replay(&transport, Signal::Metrics, capture, report.opts.framing, &report.opts.add_resource_attr).await?;
+            continue;
         }
-    };
-    let scheme = "http";
-    let endpoint_base = format!("{}://{}:{}", scheme, report.host, port);
-    let export_config = ExportConfig {
-        endpoint: endpoint_base,
-        protocol,
-        timeout: Duration::from_secs(10),
-    };
-    let resource = Resource::new(report.rtags.into_iter().map(|x| x.into()));
+        if report.out_of_order || report.duplicate_timestamps || report.monotonic.is_some() || report.mtype == "gauge" {
+            report_points(&report, &transport).await?;
+        } else {
+            report_metrics(&report, &transport)?;
+        }
+    }
+    if report.expect {
+        print_expected_histogram(&report)?;
+    }
+    report.transport.exports.finish()?;
+    Ok(())
+}
+
+fn report_metrics(report: &Report, transport: &TransportOpts) -> Result<(), Box<dyn error::Error>> {
+    let resource = resource(&report.rtags, report.opts.schema_url.as_deref());
     let labels = report
         .labels
-        .into_iter()
-        .map(|x| x.into())
+        .iter()
+        .map(|x| x.clone().into())
         .collect::<Vec<_>>();
     if report.verbose {
         println!("resource: {:?}", resource);
         println!("labels: {:?}", labels);
     }
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_export_config(export_config);
-    let _started = pipeline
-        .with_exporter(exporter)
-        .with_period(Duration::from_millis(100))
-        .with_resource(resource)
-        .build()?;
-    let meter = global::meter(report.library_name);
+    let boundaries = report.histograms.clone();
+    let exporter = transport.exporter()?.build_metrics_exporter(
+        Box::new(DefaultTemporalitySelector::new()),
+        Box::new(move |kind| match kind {
+            InstrumentKind::Histogram => metrics::Aggregation::ExplicitBucketHistogram {
+                boundaries: boundaries.clone(),
+                record_min_max: true,
+            },
+            kind => DefaultAggregationSelector::new().aggregation(kind),
+        }),
+    )?;
+    let exporter = CheckedMetricsExporter { inner: exporter, exports: transport.exports.clone() };
+    let reader = PeriodicReader::builder(exporter, Tokio).with_interval(Duration::from_millis(100)).build();
+    let provider = MeterProvider::builder().with_reader(reader).with_resource(resource).build();
+    global::set_meter_provider(provider.clone());
+    let meter = global::meter_with_version(report.library_name.clone(), None::<&str>, report.opts.schema_url.clone(), None);
     if report.verbose {
         println!("{} {}", report.dtype.as_str(), report.mtype.as_str());
     }
@@ -156,25 +183,25 @@ async fn do_report_metric(report: Report) -> Result<(), Box<dyn error::Error>> {
         .repeat(report.times as usize);
     match (report.dtype.as_str(), report.mtype.as_str()) {
         ("u64", "counter") => {
-            mk_counter_measurement(meter.u64_counter(report.name).init(), values, labels)?
+            mk_counter_measurement(meter.u64_counter(report.name.clone()).init(), values, labels)?
         }
         ("f64", "counter") => {
-            mk_counter_measurement(meter.f64_counter(report.name).init(), values, labels)?
+            mk_counter_measurement(meter.f64_counter(report.name.clone()).init(), values, labels)?
         }
         ("i64", "up_down_counter") => {
-            mk_updown_counter_measurement(meter.i64_up_down_counter(report.name).init(), values, labels)?
+            mk_updown_counter_measurement(meter.i64_up_down_counter(report.name.clone()).init(), values, labels)?
         }
         ("f64", "up_down_counter") => {
-            mk_updown_counter_measurement(meter.f64_up_down_counter(report.name).init(), values, labels)?
+            mk_updown_counter_measurement(meter.f64_up_down_counter(report.name.clone()).init(), values, labels)?
         }
         ("i64", "histogram") => {
-            mk_histogram_measurement(meter.i64_histogram(report.name).init(), values, labels)?
+            mk_histogram_measurement(meter.i64_histogram(report.name.clone()).init(), values, labels)?
         }
         ("u64", "histogram") => {
-            mk_histogram_measurement(meter.u64_histogram(report.name).init(), values, labels)?
+            mk_histogram_measurement(meter.u64_histogram(report.name.clone()).init(), values, labels)?
         }
         ("f64", "histogram") => {
-            mk_histogram_measurement(meter.f64_histogram(report.name).init(), values, labels)?
+            mk_histogram_measurement(meter.f64_histogram(report.name.clone()).init(), values, labels)?
         }
         _ => {
             return Err(Box::new(OTKError::InvalidArgumentError(
@@ -182,11 +209,119 @@ async fn do_report_metric(report: Report) -> Result<(), Box<dyn error::Error>> {
             )))
         }
     };
-    std::thread::sleep(Duration::from_millis((report.wait_secs * 1000.) as u64));
+    std::thread::sleep(report.wait);
+    // stop the periodic reader before the next sweep installs a new provider.
+    // the sdk marks the reader shut down before its final collect, so this
+    // always reports "reader is shut down" and is not worth surfacing
+    let _ = provider.shutdown();
+    Ok(())
+}
 
+/// send every value as its own data point with an explicit timestamp,
+/// bypassing the sdk aggregation
+async fn report_points(report: &Report, transport: &TransportOpts) -> Result<(), Box<dyn error::Error>> {
+    let values = report
+        .value
+        .iter()
+        .map(|x| x.as_str())
+        .collect::<Vec<_>>()
+        .repeat(report.times as usize);
+    let is_monotonic = match report.mtype.as_str() {
+        "counter" => true,
+        "up_down_counter" | "gauge" => false,
+        _ => {
+            return Err(Box::new(OTKError::InvalidArgumentError(
+                "explicit data points only support counter, up_down_counter and gauge".into(),
+            )))
+        }
+    };
+    let is_monotonic = match (report.mtype.as_str(), report.monotonic) {
+        ("gauge", Some(_)) => {
+            return Err(Box::new(OTKError::InvalidArgumentError(
+                "--monotonic only applies to sums".into(),
+            )))
+        }
+        (_, monotonic) => monotonic.unwrap_or(is_monotonic),
+    };
+    let gauge = report.mtype == "gauge";
+    let data: Box<dyn Aggregation> = match report.dtype.as_str() {
+        "u64" if gauge => Box::new(Gauge { data_points: data_points::<u64>(report, &values)? }),
+        "i64" if gauge => Box::new(Gauge { data_points: data_points::<i64>(report, &values)? }),
+        "f64" if gauge => Box::new(Gauge { data_points: data_points::<f64>(report, &values)? }),
+        "u64" => Box::new(sum_points::<u64>(report, &values, is_monotonic)?),
+        "i64" => Box::new(sum_points::<i64>(report, &values, is_monotonic)?),
+        "f64" => Box::new(sum_points::<f64>(report, &values, is_monotonic)?),
+        _ => {
+            return Err(Box::new(OTKError::InvalidArgumentError(
+                "invalid combination".into(),
+            )))
+        }
+    };
+    if report.verbose {
+        println!("{:?}", data);
+    }
+    let mut metrics = ResourceMetrics {
+        resource: resource(&report.rtags, report.opts.schema_url.as_deref()),
+        scope_metrics: vec![ScopeMetrics {
+            scope: InstrumentationLibrary::new(report.library_name.clone(), None::<&str>, report.opts.schema_url.clone(), None),
+            metrics: vec![Metric {
+                name: report.name.clone().into(),
+                description: "".into(),
+                unit: Unit::new(""),
+                data,
+            }],
+        }],
+    };
+    let exporter = transport.exporter()?.build_metrics_exporter(
+        Box::new(DefaultTemporalitySelector::new()),
+        Box::new(DefaultAggregationSelector::new()),
+    )?;
+    let exporter = CheckedMetricsExporter { inner: exporter, exports: transport.exports.clone() };
+    exporter.export(&mut metrics).await?;
+    exporter.shutdown()?;
     Ok(())
 }
 
+/// a cumulative sum of the data points of `values`
+fn sum_points<T: FromStr>(report: &Report, values: &[&str], is_monotonic: bool) -> Result<Sum<T>, Box<OTKError>> {
+    Ok(Sum {
+        data_points: data_points(report, values)?,
+        temporality: Temporality::Cumulative,
+        is_monotonic,
+    })
+}
+
+/// one data point per value ending now, optionally with pairs of points
+/// sharing a timestamp and shuffled
+fn data_points<T: FromStr>(report: &Report, values: &[&str]) -> Result<Vec<DataPoint<T>>, Box<OTKError>> {
+    let labels = report.labels.iter().map(|x| x.clone().into()).collect::<Vec<OTLPKeyValue>>();
+    let attributes = AttributeSet::from(labels.as_slice());
+    let per_timestamp = if report.duplicate_timestamps { 2 } else { 1 };
+    let steps = values.len().div_ceil(per_timestamp) as u32;
+    let start = SystemTime::now() - report.point_interval * steps;
+    let mut data_points = vec![];
+    for (i, val) in values.iter().enumerate() {
+        let value = val.parse().map_err(|_| {
+            Box::new(OTKError::InvalidArgumentError("parse metric value failed".into()))
+        })?;
+        data_points.push(DataPoint {
+            attributes: attributes.clone(),
+            start_time: Some(start),
+            time: Some(start + report.point_interval * (i / per_timestamp + 1) as u32),
+            value,
+            exemplars: vec![],
+        });
+    }
+    if report.out_of_order {
+        data_points.shuffle(&mut rand::thread_rng());
+        // a shuffle can come out in order, reversing it can not
+        if data_points.windows(2).all(|w| w[0].time <= w[1].time) {
+            data_points.reverse();
+        }
+    }
+    Ok(data_points)
+}
+
 fn mk_counter_measurement<T: FromStr>(
     counter: Counter<T>,
     values: Vec<&str>,
@@ -223,6 +358,50 @@ fn mk_updown_counter_measurement<T: FromStr>(
     Ok(())
 }
 
+/// the aggregated histogram of the values over the --histograms buckets,
+/// to compare with the backend
+fn print_expected_histogram(report: &Report) -> Result<(), Box<dyn error::Error>> {
+    let values = report
+        .value
+        .iter()
+        .map(|v| v.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?
+        .repeat(report.times as usize);
+    let counts = bucket_counts(&report.histograms, &values);
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    println!(
+        "expected {}: count={} sum={} min={} max={}",
+        report.name,
+        values.len(),
+        values.iter().sum::<f64>(),
+        min,
+        max
+    );
+    let mut lower = "-inf".to_string();
+    for (i, count) in counts.iter().enumerate() {
+        match report.histograms.get(i) {
+            Some(upper) => {
+                println!("  ({}, {}]: {}", lower, upper, count);
+                lower = upper.to_string();
+            }
+            None => println!("  ({}, +inf): {}", lower, count),
+        }
+    }
+    Ok(())
+}
+
+/// how many of `values` fall in each bucket of the upper `bounds`, and
+/// above the last one
+fn bucket_counts(bounds: &[f64], values: &[f64]) -> Vec<u64> {
+    let mut counts = vec![0; bounds.len() + 1];
+    for v in values {
+        // buckets include their upper bound
+        counts[bounds.iter().take_while(|b| *b < v).count()] += 1;
+    }
+    counts
+}
+
 fn mk_histogram_measurement<T: FromStr>(
     recorder: Histogram<T>,
     values: Vec<&str>,
@@ -240,3 +419,23 @@ fn mk_histogram_measurement<T: FromStr>(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_include_upper_bound() {
+        let bounds = [10., 20., 30.];
+        assert_eq!(bucket_counts(&bounds, &[-5., 10., 10.5, 20., 30., 30.01, 1e9]), vec![2, 2, 1, 2]);
+        assert_eq!(bucket_counts(&bounds, &[]), vec![0, 0, 0, 0]);
+        assert_eq!(bucket_counts(&[], &[1., 2.]), vec![2]);
+    }
+
+    #[test]
+    fn expect_only_histograms() {
+        let report = Report::try_parse_from(["report-metric", "--expect", "-m", "counter", "--url", "http://127.0.0.1:9"]).unwrap();
+        let err = do_report(report).unwrap_err().to_string();
+        assert!(err.contains("--expect only applies to histograms"), "{}", err);
+    }
+}
@@ -1,240 +1,1113 @@
-use crate::common::KeyValue;
+use crate::common::{
+    parse_duration, parse_rate, parse_ratio, parse_span_id, parse_time, parse_trace_id, parse_traceparent, curl_trace_headers, rate_period, resource, resolve_run_id, run_id_attr, split_unquoted,
+    KeyValue, Wordlist, INSTRUMENTATION_LIB_NAME, RUN_ID_KEY,
+};
+use crate::cmd_search::{Signal, SpanStatus};
+use crate::export::{self, CheckedSpanExporter, Exports};
 use crate::otk_error::OTKError;
+use crate::replay;
+use crate::report::ReportOpts;
+use crate::scenario::Scenario;
+use crate::transport::{Protocol, TransportOpts};
+use crate::verify::{self, parse_verify_url};
 use clap::Parser;
-use opentelemetry::trace::{Span as _, Status, Tracer};
-use opentelemetry::KeyValue as OTLP_KeyValue;
-use opentelemetry::{global, Key};
-use opentelemetry_otlp::{NoExporterConfig, OtlpTracePipeline, WithExportConfig};
-use opentelemetry_sdk::trace::RandomIdGenerator;
-use opentelemetry_sdk::{trace, Resource};
+use opentelemetry::trace::{
+    Link, Span as _, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer,
+    TracerProvider as _,
+};
+use opentelemetry::{Context, Key};
+use opentelemetry_sdk::export::trace::SpanExporter;
+use opentelemetry_sdk::trace::{IdGenerator, RandomIdGenerator};
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use opentelemetry_sdk::trace;
 use std::error;
-use std::fs::read_to_string;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use strum_macros::{Display, EnumString};
 use tokio::runtime::Runtime;
-use tonic::metadata::{AsciiMetadataKey, MetadataMap};
-use tonic::transport::{Certificate, ClientTlsConfig};
-
-#[derive(Debug, Clone, Display, EnumString)]
-enum Protocol {
-    #[strum(serialize = "grpc", serialize = "g")]
-    Grpc,
-    #[strum(serialize = "http", serialize = "h")]
-    Http,
-    #[strum(serialize = "http_json", serialize = "hj")]
-    HttpJson,
-}
-
-static DEFAULT_GRPC_PORT: u16 = 4317;
-static DEFAULT_HTTP_PORT: u16 = 4318;
-static DEFAULT_HTTP_JSON_PORT: u16 = 4318;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
 
 /// report to otlp receiver
 #[derive(Parser, Debug)]
+// the short flags these had before the transport options were shared
+#[clap(mut_arg("metadata", |a| a.short('m')), mut_arg("timeout", |a| a.short('t')))]
 pub struct Report {
-    /// protocol to use (grpc, http or http_json), currently
-    /// only grpc is supported
-    #[clap(long, default_value = "grpc")]
-    protocol: Protocol,
-
-    /// whether to use tls
-    #[clap(long)]
-    tls: bool,
-
-    /// CA cert path if tls is enabled
-    #[clap(long, requires = "tls")]
-    ca_cert: Option<String>,
+    #[clap(flatten)]
+    transport: TransportOpts,
 
-    /// server host name to verify
-    #[clap(long, requires = "tls")]
-    domain: Option<String>,
-
-    /// server host
-    #[clap(long, default_value = "localhost", env = "OTK_REPORT_HOST")]
-    host: String,
-
-    /// server port (default value depends on protocol)
-    #[clap(long, env = "OTK_REPORT_PORT")]
-    port: Option<u16>,
+    #[clap(flatten)]
+    opts: ReportOpts,
 
     /// tag used in resource
     #[clap(short, long, num_args = 0..)]
     rtags: Vec<KeyValue>,
 
-    /// metadata map value
-    #[clap(short, long, num_args = 0..)]
-    metadata: Vec<KeyValue>,
-
     /// span name
     #[clap(short, long, default_value = "otk_test_span")]
     name: String,
 
-    /// span attributes
+    /// instrumentation scope name of the spans
+    #[clap(long, default_value = INSTRUMENTATION_LIB_NAME)]
+    scope_name: String,
+
+    /// instrumentation scope version
+    #[clap(long)]
+    scope_version: Option<String>,
+
+    /// instrumentation scope attributes, `key=value` or typed like --attrs
+    #[clap(long, num_args = 0..)]
+    scope_attrs: Vec<KeyValue>,
+
+    /// span kind (server, client, producer, consumer or internal), the
+    /// children of --depth stay internal
+    #[clap(long, default_value = "internal")]
+    kind: Kind,
+
+    /// semantic convention of the root spans (http-server, http-client,
+    /// db-client, messaging-producer or rpc-server), setting their kind and
+    /// required attributes with placeholder values that --attrs override
+    #[clap(long, conflicts_with = "kind")]
+    preset: Option<Preset>,
+
+    /// span attributes, `key=value` or typed like `count:int=5`, `ok:bool=true`,
+    /// `ratio:double=0.5` or `tags:array=a,b,c`
     #[clap(short, long, num_args = 0..)]
     attrs: Vec<KeyValue>,
 
+    /// span events, `name[@+offset][,key=value...]` where offset (like 15ms)
+    /// is relative to the span start, can be repeated
+    #[clap(long, num_args = 0..)]
+    event: Vec<SpanEvent>,
+
+    /// span links, `traceid:spanid[:key=value...]`, can be repeated
+    #[clap(long, num_args = 0..)]
+    link: Vec<SpanLink>,
+
+    /// link each span of the batch to the span before it
+    #[clap(long)]
+    link_previous: bool,
+
     /// long length tag (for testing size limit), tag name is "ll",
     /// and for k=v will repeat string k, v times
     #[clap(long)]
     long_length_tag: Option<KeyValue>,
 
-    /// status message
+    /// status of the root spans: unset, ok or error
+    #[clap(long, default_value = "ok")]
+    status: SpanStatus,
+
+    /// description of the error status, only valid with --status error
     #[clap(long)]
     status_msg: Option<String>,
 
-    /// duration in milliseconds
-    #[clap(long, default_value = "0")]
-    duration: u64,
+    /// duration of every span (e.g. 200ms, 1m30s)
+    #[clap(long, value_parser = parse_duration, default_value = "0")]
+    duration: Duration,
+
+    /// vary the duration of the root spans by this much (e.g. 50ms), how
+    /// depends on --duration-dist
+    #[clap(long, value_parser = parse_duration, default_value = "0")]
+    duration_jitter: Duration,
+
+    /// distribution of the jittered durations: uniform within --duration ±
+    /// jitter, normal around --duration with jitter as standard deviation, or
+    /// pareto, a long tail above --duration adding jitter on average
+    #[clap(long, default_value = "uniform", requires = "duration_jitter")]
+    duration_dist: DurationDist,
+
+    /// start of the first span (RFC3339 or relative like -1h or +5m), the
+    /// spans of the batch follow each other. without it or --end-time the
+    /// last span ends now
+    #[clap(long, value_parser = parse_time, allow_hyphen_values = true)]
+    start_time: Option<u64>,
+
+    /// end of the last span (RFC3339 or relative like -1h or +5m). with
+    /// --start-time every span of the batch covers exactly that range
+    #[clap(long, value_parser = parse_time, allow_hyphen_values = true)]
+    end_time: Option<u64>,
+
+    /// trace id (32 hex chars) for the reported spans, random if not given
+    #[clap(long)]
+    trace_id: Option<String>,
+
+    /// span id (16 hex chars) for the first reported span, e.g. to pre-create
+    /// the parent of a traceparent printed by `otk inject`
+    #[clap(long)]
+    span_id: Option<String>,
+
+    /// w3c traceparent header (`00-<trace id>-<parent id>-<flags>`) of a
+    /// remote span that becomes the parent of the reported root spans, to
+    /// splice them into a real trace. its sampled flag is honoured
+    #[clap(long, value_parser = parse_traceparent, conflicts_with = "trace_id")]
+    traceparent: Option<SpanContext>,
+
+    /// give every root span of the batch, across all --workers, the same
+    /// trace and span id (--trace-id and --span-id if given), to reproduce id
+    /// collisions and test deduplication
+    #[clap(long)]
+    duplicate_ids: bool,
+
+    /// store the intended duration as an integer attribute "otk.duration_ns",
+    /// as ground truth for backend-computed latencies
+    #[clap(long)]
+    attach_duration_attr: bool,
+
+    /// share of spans (0 to 1) reported with a random parent span id that is
+    /// never sent, to simulate orphans of incomplete traces
+    #[clap(long, value_parser = parse_ratio, default_value = "0")]
+    orphan_rate: f64,
+
+    /// share of spans (0 to 1) held back and sent in a separate request after
+    /// --late-delay, to test late data merging of backends
+    #[clap(long, value_parser = parse_ratio, default_value = "0")]
+    late_fraction: f64,
+
+    /// how long late spans are held back
+    #[clap(long, value_parser = parse_duration, default_value = "30s")]
+    late_delay: Duration,
+
+    /// file with one word per line (e.g. your api's routes), each span takes
+    /// a random one as its name and "http.route" instead of --name
+    #[clap(long)]
+    wordlist: Option<String>,
+
+    /// string attributes whose value is a random word of --wordlist, can be
+    /// repeated
+    #[clap(long, num_args = 0.., requires = "wordlist")]
+    wordlist_attr: Vec<String>,
+
+    /// levels of each trace, counting the root: with 2 or more every span of
+    /// the batch gets --fanout children, which get their own children, and so on
+    #[clap(long, default_value = "1")]
+    depth: usize,
+
+    /// children of every span above the last level
+    #[clap(long, default_value = "2")]
+    fanout: u32,
+
+    /// duration of the spans on each level below the root (e.g. 50ms,10ms),
+    /// the last one repeats for deeper levels; default half the parent's
+    #[clap(long, value_parser = parse_duration, value_delimiter = ',')]
+    level_duration: Vec<Duration>,
 
     /// send a batch of spans
     #[clap(long, default_value = "1")]
     batch: u64,
 
+    /// sampler of the reported spans (always_on, always_off, traceidratio or
+    /// parentbased_traceidratio, as in OTEL_TRACES_SAMPLER), to see what it
+    /// actually lets through
+    #[clap(long, default_value = "always_on")]
+    sampler: SamplerKind,
+
+    /// ratio of the traceidratio samplers (0 to 1)
+    #[clap(long, value_parser = parse_ratio, default_value = "1")]
+    sampler_arg: f64,
+
+    /// keep sending root spans at this many per second, with real
+    /// timestamps, until --run-duration elapses or ctrl-c (instead of --batch)
+    #[clap(long, value_parser = parse_rate, conflicts_with_all = ["batch", "start_time", "end_time"])]
+    rate: Option<f64>,
+
+    /// concurrent tasks generating spans, each with its own exporter, to
+    /// saturate a collector. --batch and --rate are split between them
+    #[clap(long, default_value = "1")]
+    workers: usize,
+
+    /// how long --rate keeps sending (e.g. 90s, 2h), until ctrl-c if not given
+    #[clap(long, value_parser = parse_duration, requires = "rate")]
+    run_duration: Option<Duration>,
+
+    /// send --batch traces shaped like a yaml file instead of generated spans:
+    /// a `spans` list of `name`, `id`, `parent` (id of an earlier span),
+    /// `kind`, `offset` (after the parent's start), `duration`, `attributes`,
+    /// `events` (`name`, `offset`, `attributes`), `status` and `status_message`
+    #[clap(long, conflicts_with_all = ["from_capture", "rate", "depth", "wordlist"])]
+    scenario: Option<String>,
+
+    /// print the traceparent (and tracestate) header of every root span as
+    /// curl arguments, to continue its trace from other services
+    #[clap(long)]
+    print_traceparent: bool,
+
+    /// append the trace id and span id of every root span to this file, one
+    /// root per line, whatever the verbosity
+    #[clap(long)]
+    ids_out: Option<String>,
+
+    /// after sending, poll this url until the trace of every sampled root
+    /// answers (a 404 meaning not yet), `{trace_id}` being replaced by its id,
+    /// e.g. http://localhost:8080/traces/{trace_id} of conformance --api or
+    /// http://tempo:3200/api/traces/{trace_id}
+    #[clap(long, value_parser = parse_verify_url, conflicts_with_all = ["rate", "from_capture", "dry_run"])]
+    verify: Option<String>,
+
+    /// with --verify, fail unless every trace is queryable this long after
+    /// the export (default 30s)
+    #[clap(long, value_parser = parse_duration, requires = "verify")]
+    max_e2e_latency: Option<Duration>,
+
+    /// span processor: `batch` exports the spans in batches (see --bsp-*),
+    /// `simple` each span right away in a request of its own, to test how a
+    /// collector handles every request (auth, rate limiting...)
+    #[clap(long, default_value = "batch")]
+    processor: Processor,
+
+    /// max spans the batch span processor queues, spans beyond it are dropped
+    /// (default 2048, or OTEL_BSP_MAX_QUEUE_SIZE)
+    #[clap(long)]
+    bsp_max_queue_size: Option<usize>,
+
+    /// max spans per export request of the batch span processor (default 512,
+    /// or OTEL_BSP_MAX_EXPORT_BATCH_SIZE)
+    #[clap(long)]
+    bsp_max_export_batch_size: Option<usize>,
+
+    /// delay between exports of the batch span processor (default 5s, or
+    /// OTEL_BSP_SCHEDULE_DELAY)
+    #[clap(long, value_parser = parse_duration)]
+    bsp_schedule_delay: Option<Duration>,
+
     /// verbose
     #[clap(short, long)]
     verbose: bool,
-
-    /// send timeout in seconds (this is a general timeout and might be restricted by other
-    /// timeout, like batch processor timeout)
-    #[clap(short, long, default_value = "10")]
-    timeout: u64,
 }
 
-pub fn do_report(report: Report) -> Result<(), Box<dyn error::Error>> {
+pub fn do_report(mut report: Report) -> Result<(), Box<dyn error::Error>> {
     if report.verbose {
         println!("{:?}", report);
     }
+    report.transport.dry_run = report.opts.dry_run;
+    report.transport.exports = Arc::new(Exports::new(report.opts.retries, "spans"));
+    export::handle_other_errors();
+    if let Some(preset) = report.preset {
+        report.kind = preset.kind();
+        let mut attrs = preset.attrs().iter().map(|a| KeyValue::from_str(a)).collect::<Result<Vec<_>, _>>()?;
+        attrs.retain(|attr| report.attrs.iter().all(|given| given.k != attr.k));
+        attrs.append(&mut report.attrs);
+        report.attrs = attrs;
+    }
+    resolve_run_id(&mut report.opts.run_id);
+    report.attrs.extend(run_id_attr(&report.opts.run_id));
     Runtime::new().unwrap().block_on(do_report_trace(report))
 }
 
 async fn do_report_trace(report: Report) -> Result<(), Box<dyn error::Error>> {
-    let pipeline = opentelemetry_otlp::new_pipeline().tracing();
-    let port = report.port.unwrap_or_else(|| match report.protocol {
-        Protocol::Grpc => DEFAULT_GRPC_PORT,
-        Protocol::Http => DEFAULT_HTTP_PORT,
-        Protocol::HttpJson => DEFAULT_HTTP_JSON_PORT,
-    });
-    let scheme = if report.tls { "https" } else { "http" };
-    let endpoint_base = format!("{}://{}:{}", scheme, report.host, port);
-    let resource = Resource::new(report.rtags.iter().map(|x| x.clone().into()));
+    let scenario = report.scenario.as_deref().map(Scenario::load).transpose()?;
+    let ids_out = match &report.ids_out {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| {
+                OTKError::InvalidArgumentError(format!("open --ids-out {} failed: {}", path, e))
+            })?;
+            Some(Arc::new(Mutex::new(BufWriter::new(file))))
+        }
+        None => None,
+    };
+    let report = Arc::new(report);
+    let mut traces = vec![];
+    for transport in report.transport.sweep()? {
+        if report.verbose {
+            println!("metadata: {:?}", transport.metadata);
+        }
+        if let Some(capture) = &report.opts.from_capture {
+            replay::replay(&transport, Signal::Traces, capture, report.opts.framing, &report.opts.add_resource_attr).await?;
+            continue;
+        }
+        traces.extend(match &scenario {
+            Some(scenario) => report_scenario(&report, &transport, scenario, ids_out.as_ref()).await?,
+            None => report_spans(&report, &transport, ids_out.as_ref()).await?,
+        });
+    }
+    if let Some(ids_out) = ids_out {
+        ids_out.lock().unwrap().flush()?;
+    }
+    report.transport.exports.finish()?;
+    if let Some(url) = &report.verify {
+        let budget = report.max_e2e_latency.unwrap_or(verify::DEFAULT_BUDGET);
+        let latency = verify::verify(url, &traces, budget).await?;
+        println!("{} traces queryable after {:?} (budget {:?})", traces.len(), latency, budget);
+    }
+    Ok(())
+}
+
+/// the --ids-out file, shared by the workers
+type IdsOut = Arc<Mutex<BufWriter<File>>>;
+
+/// append the trace and span id of a root span to --ids-out
+fn write_ids(ids_out: &IdsOut, cx: &SpanContext) -> std::io::Result<()> {
+    writeln!(ids_out.lock().unwrap(), "{:032x} {:016x}", cx.trace_id(), cx.span_id())
+}
+
+/// send the generated spans, returning the traces of the sampled roots for
+/// --verify
+async fn report_spans(
+    report: &Arc<Report>,
+    transport: &TransportOpts,
+    ids_out: Option<&IdsOut>,
+) -> Result<Vec<TraceId>, Box<dyn error::Error>> {
+    if report.workers == 0 {
+        return Err(OTKError::InvalidArgumentError("--workers must be at least 1".into()).into());
+    }
+    if report.status_msg.is_some() && report.status != SpanStatus::Error {
+        return Err(OTKError::InvalidArgumentError("--status-msg is only valid with --status error".into()).into());
+    }
+    let wordlist = report.wordlist.as_deref().map(Wordlist::load).transpose()?.map(Arc::new);
+    let long_tag = match &report.long_length_tag {
+        Some(ll) => Some(ll.k.repeat(ll.v.parse::<u32>()? as usize)),
+        None => None,
+    };
+    let mut trace_id = report.trace_id.as_deref().map(parse_trace_id).transpose()?;
+    let mut span_id = report.span_id.as_deref().map(parse_span_id).transpose()?;
+    if report.duplicate_ids {
+        // one pair of ids for the spans of every worker
+        let ids = RandomIdGenerator::default();
+        trace_id = trace_id
+            .or(report.traceparent.as_ref().map(SpanContext::trace_id))
+            .or_else(|| Some(ids.new_trace_id()));
+        span_id = span_id.or_else(|| Some(ids.new_span_id()));
+    }
+    let (mut clock, duration) = span_times(report, report.duration)?;
+    // each worker exports through its own provider, so exports run in parallel
+    let providers = (0..report.workers)
+        .map(|_| span_provider(report, transport))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (stop_tx, stop) = watch::channel(false);
+    if report.rate.is_some() {
+        let run_duration = report.run_duration;
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = async {
+                    match run_duration {
+                        Some(d) => tokio::time::sleep(d).await,
+                        None => std::future::pending().await,
+                    }
+                } => {},
+            }
+            let _ = stop_tx.send(true);
+        });
+    }
+    let run_start = Instant::now();
+    let workers = report.workers as u64;
+    let handles = providers
+        .iter()
+        .enumerate()
+        .map(|(i, provider)| {
+            // each worker sends its share of the batch after the previous
+            // worker's share
+            let batch = report.batch / workers + ((i as u64) < report.batch % workers) as u64;
+            let start = clock;
+            if report.start_time.is_none() || report.end_time.is_none() {
+                clock += duration.mul_f64(batch as f64);
+            }
+            let worker = Worker {
+                report: report.clone(),
+                tracer: scope_tracer(report, provider),
+                wordlist: wordlist.clone(),
+                long_tag: long_tag.clone(),
+                ids_out: ids_out.cloned(),
+                trace_id,
+                // only the very first span takes --span-id, unless every span does
+                span_id: if report.duplicate_ids { span_id } else { span_id.take() },
+                batch,
+                rate: report.rate.map(|rate| rate / workers as f64),
+                clock: start,
+                duration,
+                stop: stop.clone(),
+            };
+            tokio::spawn(worker.run())
+        })
+        .collect::<Vec<_>>();
+    let (mut sent, mut sampled) = (0, 0);
+    let (mut late, mut traces) = (vec![], vec![]);
+    for handle in handles {
+        let worker = handle.await?;
+        sent += worker.roots;
+        sampled += worker.sampled;
+        late.extend(worker.late);
+        traces.extend(worker.traces);
+    }
+    if !late.is_empty() || report.rate.is_some() || report.workers > 1 {
+        // export the on-time spans first, so the late ones go in their own
+        // request (and the throughput counts the export). failed exports were
+        // already printed, they only matter before sending late spans
+        for provider in &providers {
+            let flushed = provider.force_flush().into_iter().collect::<Result<Vec<_>, _>>();
+            if !late.is_empty() {
+                flushed?;
+            }
+        }
+    }
+    if report.rate.is_some() || report.workers > 1 {
+        let elapsed = run_start.elapsed().as_secs_f64();
+        let mut line = format!("sent {} root spans", sent);
+        if report.workers > 1 {
+            line.push_str(&format!(" with {} workers", report.workers));
+        }
+        line.push_str(&format!(" in {:.1}s ({:.1}/s", elapsed, sent as f64 / elapsed));
+        if let Some(rate) = report.rate {
+            line.push_str(&format!(", target {}/s", rate));
+        }
+        println!("{})", line);
+    }
+    if report.sampler != SamplerKind::AlwaysOn {
+        let percent = if sent == 0 { 0. } else { sampled as f64 * 100. / sent as f64 };
+        println!("{} sampled {} of {} root spans ({:.1}%)", report.sampler, sampled, sent, percent);
+    }
+    if !late.is_empty() {
+        if report.verbose {
+            println!("sending {} late spans in {:?}", late.len(), report.late_delay);
+        }
+        tokio::time::sleep(report.late_delay).await;
+        for (mut span, end) in late {
+            span.end_with_timestamp(end);
+        }
+    }
+    // dropping the providers flushes and shuts down their exporters
+    drop(providers);
+    Ok(traces)
+}
+
+/// send --batch traces of the --scenario spans, back to back, returning the
+/// sampled ones for --verify
+async fn report_scenario(
+    report: &Report,
+    transport: &TransportOpts,
+    scenario: &Scenario,
+    ids_out: Option<&IdsOut>,
+) -> Result<Vec<TraceId>, Box<dyn error::Error>> {
+    let provider = span_provider(report, transport)?;
+    let tracer = scope_tracer(report, &provider);
+    let (mut clock, _) = span_times(report, scenario.length())?;
+    let mut traces = vec![];
+    for _ in 0..report.batch {
+        // every root of the scenario shares the trace of the first one
+        let mut trace_id = report.trace_id.as_deref().map(parse_trace_id).transpose()?;
+        let mut started: Vec<(SpanContext, SystemTime)> = vec![];
+        for s in &scenario.spans {
+            let (parent, parent_start) = match s.parent {
+                Some(i) => (Some(started[i].0.clone()), started[i].1),
+                None => (report.traceparent.clone(), clock),
+            };
+            let start = parent_start + s.offset;
+            let mut builder = tracer
+                .span_builder(s.name.clone())
+                .with_kind(s.kind.into())
+                .with_start_time(start)
+                .with_attributes(s.attributes.clone());
+            builder.trace_id = trace_id;
+            let cx = match parent {
+                Some(parent) => Context::new().with_remote_span_context(parent),
+                None => Context::new(),
+            };
+            let mut span = tracer.build_with_context(builder, &cx);
+            for event in &s.events {
+                span.add_event_with_timestamp(event.name.clone(), start + event.offset, event.attributes.clone());
+            }
+            if let Some(run_id) = &report.opts.run_id {
+                span.set_attribute(Key::new(RUN_ID_KEY).string(run_id.clone()));
+            }
+            span.set_status(s.status.clone());
+            if started.is_empty() {
+                if report.verbose {
+                    println!("{:032x}", span.span_context().trace_id());
+                }
+                if report.print_traceparent {
+                    println!("{}", curl_trace_headers(span.span_context()));
+                }
+                if let Some(ids_out) = ids_out {
+                    write_ids(ids_out, span.span_context())?;
+                }
+                if report.verify.is_some() && span.span_context().is_sampled() {
+                    traces.push(span.span_context().trace_id());
+                }
+            }
+            trace_id = Some(span.span_context().trace_id());
+            started.push((span.span_context().clone(), start));
+            span.end_with_timestamp(start + s.duration);
+        }
+        clock += scenario.length();
+    }
+    // dropping the provider flushes and shuts down its exporter
+    drop(provider);
+    Ok(traces)
+}
+
+fn span_provider(report: &Report, transport: &TransportOpts) -> Result<trace::TracerProvider, Box<dyn error::Error>> {
+    let resource = resource(&report.rtags, report.opts.schema_url.as_deref());
     let trace_config = trace::config()
-        .with_sampler(trace::Sampler::AlwaysOn)
+        .with_sampler(match report.sampler {
+            // keep the decision of the traceparent's sampled flag
+            SamplerKind::AlwaysOn if report.traceparent.is_some() => {
+                trace::Sampler::ParentBased(Box::new(trace::Sampler::AlwaysOn))
+            }
+            kind => kind.sampler(report.sampler_arg),
+        })
         .with_id_generator(RandomIdGenerator::default())
         .with_resource(resource);
-    let pipeline = pipeline.with_trace_config(trace_config);
-
-    match report.protocol {
-        Protocol::Grpc => do_report_trace_grpc(pipeline, report, endpoint_base).await,
-        Protocol::Http => do_report_trace_http(pipeline, report, endpoint_base).await,
-        _ => return Err(Box::new(OTKError::UnimplementedError("httpjson".into()))),
-    }
-}
-
-async fn do_report_trace_grpc(
-    pipeline: OtlpTracePipeline<NoExporterConfig>,
-    report: Report,
-    endpoint_base: String,
-) -> Result<(), Box<dyn error::Error>> {
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_endpoint(endpoint_base)
-        .with_timeout(std::time::Duration::from_secs(report.timeout));
-    let exporter = if report.tls {
-        let mut tls_config = ClientTlsConfig::new();
-        if report.ca_cert.is_some() {
-            let pem = read_to_string(report.ca_cert.unwrap()).expect("open cacert");
-            tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
-        };
-        if report.domain.is_some() {
-            tls_config = tls_config.domain_name(report.domain.unwrap());
-        }
-        exporter.with_tls_config(tls_config)
+    let builder = trace::TracerProvider::builder().with_config(trace_config);
+    let exporter: Box<dyn SpanExporter> = if transport.protocol == Protocol::HttpJson && transport.dry_run.is_none() {
+        Box::new(transport.json_span_exporter()?)
     } else {
-        exporter
+        Box::new(transport.exporter()?.build_span_exporter()?)
     };
-    let mut meta_map = MetadataMap::new();
-    for kv in &report.metadata {
-        meta_map.append(
-            AsciiMetadataKey::from_str(kv.k.as_str())?,
-            kv.v.as_str().parse()?,
-        );
+    let exporter = CheckedSpanExporter::new(exporter, transport.exports.clone());
+    let batch_config = batch_config(report)?;
+    let builder = match report.processor {
+        Processor::Simple => builder.with_simple_exporter(exporter),
+        Processor::Batch => builder.with_span_processor(
+            trace::BatchSpanProcessor::builder(exporter, opentelemetry_sdk::runtime::Tokio)
+                .with_batch_config(batch_config)
+                .build(),
+        ),
+    };
+    Ok(builder.build())
+}
+
+/// the sdk's batch span processor config (and OTEL_BSP_* variables) with the
+/// --bsp-* flags given, which --processor simple rejects
+fn batch_config(report: &Report) -> Result<trace::BatchConfig, OTKError> {
+    let invalid = |message: &str| OTKError::InvalidArgumentError(message.into());
+    let bsp = report.bsp_max_queue_size.is_some()
+        || report.bsp_max_export_batch_size.is_some()
+        || report.bsp_schedule_delay.is_some();
+    if report.processor == Processor::Simple && bsp {
+        return Err(invalid("the --bsp-* flags only apply to --processor batch"));
+    }
+    if report.bsp_max_queue_size == Some(0) || report.bsp_max_export_batch_size == Some(0) {
+        return Err(invalid("--bsp-max-queue-size and --bsp-max-export-batch-size must be at least 1"));
     }
-    let exporter = exporter.with_metadata(meta_map);
-    let pipeline = pipeline.with_exporter(exporter);
+    if let (Some(queue), Some(batch)) = (report.bsp_max_queue_size, report.bsp_max_export_batch_size) {
+        if batch > queue {
+            return Err(invalid("--bsp-max-export-batch-size can't be larger than --bsp-max-queue-size"));
+        }
+    }
+    let mut config = trace::BatchConfig::default();
+    if let Some(size) = report.bsp_max_queue_size {
+        config = config.with_max_queue_size(size);
+    }
+    if let Some(size) = report.bsp_max_export_batch_size {
+        config = config.with_max_export_batch_size(size);
+    }
+    if let Some(delay) = report.bsp_schedule_delay {
+        config = config.with_scheduled_delay(delay);
+    }
+    Ok(config)
+}
 
-    let tracer = pipeline.install_batch(opentelemetry_sdk::runtime::Tokio)?;
+/// tracer of --scope-name, --scope-version and --scope-attrs
+fn scope_tracer(report: &Report, provider: &trace::TracerProvider) -> trace::Tracer {
+    let attrs = report.scope_attrs.iter().map(|x| x.clone().into()).collect::<Vec<_>>();
+    provider.versioned_tracer(
+        report.scope_name.clone(),
+        report.scope_version.clone(),
+        report.opts.schema_url.clone(),
+        Some(attrs).filter(|attrs| !attrs.is_empty()),
+    )
+}
 
-    let span_builder = tracer.span_builder(report.name);
-    for _ in 0..report.batch {
-        let mut span = span_builder.clone().start(&tracer);
-        for attr in &report.attrs {
-            span.set_attribute(attr.clone().into())
-        }
-        if let Some(ll) = &report.long_length_tag {
-            let val = ll.k.repeat(ll.v.parse::<u32>()? as usize);
-            span.set_attribute(Key::new("ll").string(val));
+/// one of --workers, generating its share of the spans
+struct Worker {
+    report: Arc<Report>,
+    tracer: trace::Tracer,
+    wordlist: Option<Arc<Wordlist>>,
+    /// value of the "ll" attribute of --long-length-tag
+    long_tag: Option<String>,
+    ids_out: Option<IdsOut>,
+    trace_id: Option<TraceId>,
+    span_id: Option<SpanId>,
+    /// root spans to send without --rate
+    batch: u64,
+    /// root spans per second, until `stop`
+    rate: Option<f64>,
+    clock: SystemTime,
+    duration: Duration,
+    stop: watch::Receiver<bool>,
+}
+
+impl Worker {
+    /// send the spans, returning what was sent and the late spans still to
+    /// be ended
+    async fn run(mut self) -> Sent {
+        let report = &*self.report;
+        let tracer = &self.tracer;
+        let wordlist = self.wordlist.as_deref();
+        let duration = self.duration;
+        let mut span_builder = tracer.span_builder(report.name.clone()).with_kind(report.kind.into());
+        span_builder.trace_id = self.trace_id.or(report.traceparent.as_ref().map(SpanContext::trace_id));
+        let ids = RandomIdGenerator::default();
+        let mut sent = Sent::default();
+        let mut previous: Option<SpanContext> = None;
+        // skip missed ticks so a slow exporter shows up as a lower achieved rate
+        let mut ticker = self.rate.map(|rate| {
+            let mut ticker = tokio::time::interval(rate_period(rate));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            ticker
+        });
+        loop {
+            match &mut ticker {
+                Some(ticker) => {
+                    tokio::select! {
+                        _ = ticker.tick() => {},
+                        _ = self.stop.wait_for(|stop| *stop) => break,
+                    }
+                    self.clock = SystemTime::now();
+                }
+                None if sent.roots == self.batch => break,
+                None => {}
+            }
+            sent.roots += 1;
+            let mut builder = span_builder.clone();
+            let route = wordlist.map(|words| words.pick().to_string());
+            if let Some(route) = &route {
+                builder.name = route.clone().into();
+            }
+            let span_id = if report.duplicate_ids { self.span_id } else { self.span_id.take() };
+            if let Some(span_id) = span_id {
+                builder = builder.with_span_id(span_id);
+            }
+            let mut links: Vec<Link> = report.link.iter().map(SpanLink::to_link).collect();
+            if let Some(previous) = previous.take().filter(|_| report.link_previous) {
+                links.push(Link::new(previous, vec![]));
+            }
+            let duration = report.duration_dist.sample(duration, report.duration_jitter);
+            let start = self.clock;
+            if report.start_time.is_none() || report.end_time.is_none() {
+                self.clock += duration;
+            }
+            let builder = builder.with_start_time(start).with_links(links);
+            let orphan = rand::thread_rng().gen_bool(report.orphan_rate);
+            let mut span = if orphan {
+                let trace_id = builder.trace_id.unwrap_or_else(|| ids.new_trace_id());
+                let parent = SpanContext::new(trace_id, ids.new_span_id(), TraceFlags::SAMPLED, true, TraceState::default());
+                tracer.build_with_context(builder, &Context::new().with_remote_span_context(parent))
+            } else if let Some(parent) = &report.traceparent {
+                tracer.build_with_context(builder, &Context::new().with_remote_span_context(parent.clone()))
+            } else {
+                builder.start(tracer)
+            };
+            previous = Some(span.span_context().clone());
+            for attr in &report.attrs {
+                span.set_attribute(attr.clone().into())
+            }
+            for event in &report.event {
+                let attrs = event.attrs.iter().map(|x| x.clone().into()).collect();
+                match event.offset {
+                    Some(offset) => span.add_event_with_timestamp(event.name.clone(), start + offset, attrs),
+                    None => span.add_event(event.name.clone(), attrs),
+                }
+            }
+            if let (Some(words), Some(route)) = (wordlist, route) {
+                span.set_attribute(Key::new("http.route").string(route));
+                for key in &report.wordlist_attr {
+                    span.set_attribute(Key::new(key.clone()).string(words.pick().to_string()));
+                }
+            }
+            if let Some(ll) = &self.long_tag {
+                span.set_attribute(Key::new("ll").string(ll.clone()));
+            }
+            if report.attach_duration_attr {
+                span.set_attribute(Key::new("otk.duration_ns").i64(duration.as_nanos() as i64));
+            }
+            add_children(report, tracer, wordlist, span.span_context(), start, duration, 1);
+            match report.status {
+                SpanStatus::Unset => {}
+                SpanStatus::Ok => span.set_status(Status::Ok),
+                SpanStatus::Error => span.set_status(Status::error(report.status_msg.clone().unwrap_or_default())),
+            }
+            let is_late = rand::thread_rng().gen_bool(report.late_fraction);
+            if report.verbose {
+                let mut line = format!("{:032x}", span.span_context().trace_id());
+                if orphan {
+                    line.push_str(" (orphan)");
+                }
+                if is_late {
+                    line.push_str(" (late)");
+                }
+                if !span.span_context().is_sampled() {
+                    line.push_str(" (not sampled)");
+                }
+                println!("{}", line);
+            }
+            if report.print_traceparent {
+                println!("{}", curl_trace_headers(span.span_context()));
+            }
+            if let Some(ids_out) = &self.ids_out {
+                if let Err(e) = write_ids(ids_out, span.span_context()) {
+                    eprintln!("write --ids-out failed: {}", e);
+                }
+            }
+            sent.sampled += span.span_context().is_sampled() as u64;
+            if report.verify.is_some() && span.span_context().is_sampled() {
+                sent.traces.push(span.span_context().trace_id());
+            }
+            let end = start + duration;
+            if is_late {
+                sent.late.push((span, end));
+            } else {
+                span.end_with_timestamp(end);
+            }
+            // give the other workers and the exporters a turn between spans
+            if ticker.is_none() {
+                tokio::task::yield_now().await;
+            }
         }
-        std::thread::sleep(std::time::Duration::from_millis(report.duration));
-        if report.status_msg.is_none() {
-            span.set_status(Status::Ok);
-        } else {
-            span.set_status(Status::error(report.status_msg.clone().unwrap()));
+        sent
+    }
+}
+
+/// what a worker sent
+#[derive(Default)]
+struct Sent {
+    roots: u64,
+    /// root spans the sampler kept
+    sampled: u64,
+    /// spans held back by --late-fraction, with their end
+    late: Vec<(trace::Span, SystemTime)>,
+    /// traces of the sampled roots, with --verify
+    traces: Vec<TraceId>,
+}
+
+/// start of the first span and the duration of every span of the batch,
+/// from --start-time, --end-time and --duration, roots starting `step` apart
+fn span_times(report: &Report, step: Duration) -> Result<(SystemTime, Duration), OTKError> {
+    let at = |nanos| UNIX_EPOCH + Duration::from_nanos(nanos);
+    let duration = report.duration;
+    let length = step.mul_f64(report.batch as f64);
+    Ok(match (report.start_time, report.end_time) {
+        (Some(start), Some(end)) => {
+            if end < start {
+                return Err(OTKError::InvalidArgumentError("--end-time is before --start-time".into()));
+            }
+            if !report.duration.is_zero() || !report.duration_jitter.is_zero() {
+                return Err(OTKError::InvalidArgumentError(
+                    "--duration and --duration-jitter can't be combined with both --start-time and --end-time".into(),
+                ));
+            }
+            (at(start), Duration::from_nanos(end - start))
         }
-        span.end();
-        if report.verbose {
-            println!("{:x}", span.span_context().trace_id())
+        (Some(start), None) => (at(start), duration),
+        (None, Some(end)) => (at(end) - length, duration),
+        // spans in the future get clamped or dropped by backends
+        (None, None) => (SystemTime::now() - length, duration),
+    })
+}
+
+/// spans below `parent` down to --depth, spread evenly over the parent's
+/// duration
+fn add_children(
+    report: &Report,
+    tracer: &trace::Tracer,
+    wordlist: Option<&Wordlist>,
+    parent: &SpanContext,
+    start: SystemTime,
+    duration: Duration,
+    level: usize,
+) {
+    if level >= report.depth {
+        return;
+    }
+    let child_duration = match report.level_duration.get(level - 1).or(report.level_duration.last()) {
+        Some(d) => *d,
+        None => duration / 2,
+    };
+    let parent_cx = Context::new().with_remote_span_context(parent.clone());
+    for i in 0..report.fanout {
+        let child_start = start + duration.saturating_sub(child_duration) * i / report.fanout;
+        let name = wordlist.map_or(report.name.clone(), |words| words.pick().to_string());
+        let mut span = tracer
+            .span_builder(name)
+            .with_start_time(child_start)
+            .start_with_context(tracer, &parent_cx);
+        if let Some(run_id) = &report.opts.run_id {
+            span.set_attribute(Key::new(RUN_ID_KEY).string(run_id.clone()));
         }
+        add_children(report, tracer, wordlist, span.span_context(), child_start, child_duration, level + 1);
+        span.end_with_timestamp(child_start + child_duration);
     }
-    global::shutdown_tracer_provider();
-    Ok(())
 }
 
-async fn do_report_trace_http(
-    pipeline: OtlpTracePipeline<NoExporterConfig>,
-    report: Report,
-    endpoint_base: String,
-) -> Result<(), Box<dyn error::Error>> {
-    if report.tls {
-        return Err(Box::new(OTKError::UnimplementedError(
-            "http does not support tls for now".into(),
-        )));
+/// --sampler, named like the values of OTEL_TRACES_SAMPLER
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum SamplerKind {
+    #[strum(serialize = "always_on")]
+    AlwaysOn,
+    #[strum(serialize = "always_off")]
+    AlwaysOff,
+    #[strum(serialize = "traceidratio")]
+    TraceIdRatio,
+    #[strum(serialize = "parentbased_traceidratio")]
+    ParentBasedTraceIdRatio,
+}
+
+impl SamplerKind {
+    fn sampler(self, ratio: f64) -> trace::Sampler {
+        match self {
+            SamplerKind::AlwaysOn => trace::Sampler::AlwaysOn,
+            SamplerKind::AlwaysOff => trace::Sampler::AlwaysOff,
+            SamplerKind::TraceIdRatio => trace::Sampler::TraceIdRatioBased(ratio),
+            SamplerKind::ParentBasedTraceIdRatio => {
+                trace::Sampler::ParentBased(Box::new(trace::Sampler::TraceIdRatioBased(ratio)))
+            }
+        }
     }
-    if !report.metadata.is_empty() {
-        return Err(Box::new(OTKError::InvalidArgumentError(
-            "http can not set metadata for now".into(),
-        )));
+}
+
+/// --processor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Processor {
+    #[strum(serialize = "simple")]
+    Simple,
+    #[strum(serialize = "batch")]
+    Batch,
+}
+
+/// --duration-dist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum DurationDist {
+    #[strum(serialize = "uniform")]
+    Uniform,
+    #[strum(serialize = "normal")]
+    Normal,
+    #[strum(serialize = "pareto")]
+    Pareto,
+}
+
+impl DurationDist {
+    /// a duration around `duration`, never negative
+    fn sample(self, duration: Duration, jitter: Duration) -> Duration {
+        if jitter.is_zero() {
+            return duration;
+        }
+        let mut rng = rand::thread_rng();
+        let (base, jitter) = (duration.as_secs_f64(), jitter.as_secs_f64());
+        let sampled = match self {
+            DurationDist::Uniform => base + rng.gen_range(-jitter..=jitter),
+            DurationDist::Normal => {
+                // box-muller
+                let (u1, u2): (f64, f64) = (1. - rng.gen::<f64>(), rng.gen());
+                base + jitter * (-2. * u1.ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos()
+            }
+            // lomax (pareto shifted to start at 0) of shape 2, whose mean is
+            // its scale
+            DurationDist::Pareto => base + jitter * ((1. - rng.gen::<f64>()).powf(-0.5) - 1.),
+        };
+        Duration::from_secs_f64(sampled.max(0.))
     }
+}
 
-    let exporter = opentelemetry_otlp::new_exporter()
-        .http()
-        .with_endpoint(endpoint_base)
-        .with_timeout(std::time::Duration::from_secs(report.timeout));
+/// --preset
+#[derive(Debug, Clone, Copy, Display, EnumString)]
+pub enum Preset {
+    #[strum(serialize = "http-server")]
+    HttpServer,
+    #[strum(serialize = "http-client")]
+    HttpClient,
+    #[strum(serialize = "db-client")]
+    DbClient,
+    #[strum(serialize = "messaging-producer")]
+    MessagingProducer,
+    #[strum(serialize = "rpc-server")]
+    RpcServer,
+}
 
-    let tracer = pipeline
-        .with_exporter(exporter)
-        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+impl Preset {
+    fn kind(self) -> Kind {
+        match self {
+            Preset::HttpServer | Preset::RpcServer => Kind::Server,
+            Preset::HttpClient | Preset::DbClient => Kind::Client,
+            Preset::MessagingProducer => Kind::Producer,
+        }
+    }
 
-    let span_builder = tracer.span_builder(report.name);
-    for _ in 0..report.batch {
-        let mut span = span_builder.clone().start(&tracer);
-        for attr in &report.attrs {
-            span.set_attribute(OTLP_KeyValue::new(attr.k.clone(), attr.v.clone()))
+    /// the attributes the convention requires (or recommends where backends
+    /// rely on them), as --attrs
+    fn attrs(self) -> &'static [&'static str] {
+        match self {
+            Preset::HttpServer => &[
+                "http.request.method=GET",
+                "http.route=/api/items/{id}",
+                "url.path=/api/items/42",
+                "url.scheme=http",
+                "http.response.status_code:int=200",
+                "server.address=localhost",
+                "server.port:int=8080",
+                "network.protocol.version=1.1",
+            ],
+            Preset::HttpClient => &[
+                "http.request.method=GET",
+                "url.full=http://example.com:8080/api/items/42",
+                "http.response.status_code:int=200",
+                "server.address=example.com",
+                "server.port:int=8080",
+                "network.protocol.version=1.1",
+            ],
+            Preset::DbClient => &[
+                "db.system=postgresql",
+                "db.namespace=shop",
+                "db.operation.name=SELECT",
+                "db.query.text=SELECT * FROM orders WHERE id = $1",
+                "server.address=db.example.com",
+                "server.port:int=5432",
+            ],
+            Preset::MessagingProducer => &[
+                "messaging.system=kafka",
+                "messaging.operation=publish",
+                "messaging.destination.name=orders",
+                "messaging.message.id=0d7c6f3e-6b8e-4c55-9d2c-1f0a2b3c4d5e",
+                "server.address=kafka.example.com",
+                "server.port:int=9092",
+            ],
+            Preset::RpcServer => &[
+                "rpc.system=grpc",
+                "rpc.service=shop.OrderService",
+                "rpc.method=GetOrder",
+                "rpc.grpc.status_code:int=0",
+                "server.address=localhost",
+                "server.port:int=50051",
+            ],
         }
-        if let Some(ll) = &report.long_length_tag {
-            let val = ll.k.repeat(ll.v.parse::<u32>()? as usize);
-            span.set_attribute(Key::new("ll").string(val));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[strum(serialize = "server")]
+    Server,
+    #[strum(serialize = "client")]
+    Client,
+    #[strum(serialize = "producer")]
+    Producer,
+    #[strum(serialize = "consumer")]
+    Consumer,
+    #[strum(serialize = "internal")]
+    Internal,
+}
+
+impl From<Kind> for SpanKind {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::Server => SpanKind::Server,
+            Kind::Client => SpanKind::Client,
+            Kind::Producer => SpanKind::Producer,
+            Kind::Consumer => SpanKind::Consumer,
+            Kind::Internal => SpanKind::Internal,
         }
-        std::thread::sleep(std::time::Duration::from_millis(report.duration));
-        if report.status_msg.is_none() {
-            span.set_status(Status::Ok);
-        } else {
-            span.set_status(Status::error(report.status_msg.clone().unwrap()));
+    }
+}
+
+/// span event given on the command line
+#[derive(Debug, Clone)]
+pub struct SpanEvent {
+    name: String,
+    offset: Option<Duration>,
+    attrs: Vec<KeyValue>,
+}
+
+/// parse `name[@+offset][,key=value...]`, values may be quoted to contain commas
+impl FromStr for SpanEvent {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = split_unquoted(s, ',').into_iter();
+        let head = parts.next().unwrap_or_default();
+        let (name, offset) = match head.rsplit_once('@') {
+            Some((name, offset)) => {
+                let offset = offset.strip_prefix('+').unwrap_or(offset);
+                (name, Some(parse_duration(offset)?))
+            }
+            None => (head, None),
+        };
+        if name.is_empty() {
+            return Err(OTKError::ParseError(String::from("event name is empty")));
         }
-        span.end();
-        if report.verbose {
-            println!("{:x}", span.span_context().trace_id())
+        Ok(SpanEvent {
+            name: name.to_string(),
+            offset,
+            attrs: parts.map(KeyValue::from_str).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// link to another span given on the command line
+#[derive(Debug, Clone)]
+pub struct SpanLink {
+    context: SpanContext,
+    attrs: Vec<KeyValue>,
+}
+
+impl SpanLink {
+    fn to_link(&self) -> Link {
+        Link::new(self.context.clone(), self.attrs.iter().map(|x| x.clone().into()).collect())
+    }
+}
+
+/// parse `traceid:spanid[:key=value...]`, values may be quoted to contain colons
+impl FromStr for SpanLink {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = split_unquoted(s, ':').into_iter();
+        let (Some(trace_id), Some(span_id)) = (parts.next(), parts.next()) else {
+            return Err(OTKError::ParseError(String::from("invalid link (expect traceid:spanid)")));
+        };
+        let context = SpanContext::new(
+            parse_trace_id(trace_id)?,
+            parse_span_id(span_id)?,
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        Ok(SpanLink {
+            context,
+            attrs: parts.map(KeyValue::from_str).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_use_current_keys() {
+        let presets = [Preset::HttpServer, Preset::HttpClient, Preset::DbClient, Preset::MessagingProducer, Preset::RpcServer];
+        for preset in presets {
+            for attr in preset.attrs() {
+                let kv = KeyValue::from_str(attr).unwrap();
+                assert!(!crate::grade::DEPRECATED_KEYS.iter().any(|(old, _)| *old == kv.k), "{}: {}", preset, attr);
+            }
         }
     }
-    global::shutdown_tracer_provider();
-    Ok(())
+
+    fn report(args: &[&str]) -> Report {
+        Report::try_parse_from(["report-trace"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn batch_ends_now() {
+        let before = SystemTime::now();
+        let (start, duration) = span_times(&report(&["--batch", "100", "--duration", "1s"]), Duration::from_secs(1)).unwrap();
+        assert_eq!(duration, Duration::from_secs(1));
+        let end = start + Duration::from_secs(100);
+        assert!(before <= end && end <= SystemTime::now());
+    }
+
+    #[test]
+    fn batch_between_times() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let times = |args: &[&str]| span_times(&report(args), Duration::from_secs(2)).unwrap();
+        let start = "2023-01-02T15:04:05Z";
+        let end = "2023-01-02T15:05:05Z";
+        assert_eq!(times(&["--batch", "10", "--duration", "2s", "--start-time", start]), (at(1672671845), Duration::from_secs(2)));
+        assert_eq!(times(&["--batch", "10", "--duration", "2s", "--end-time", end]), (at(1672671885), Duration::from_secs(2)));
+        assert_eq!(times(&["--start-time", start, "--end-time", end]), (at(1672671845), Duration::from_secs(60)));
+        assert!(span_times(&report(&["--start-time", end, "--end-time", start]), Duration::ZERO).is_err());
+    }
 }
@@ -0,0 +1,26 @@
+use crate::scenario;
+use clap::Parser;
+use std::error;
+use strum_macros::EnumString;
+
+/// print the JSON Schema of an otk file format, for editors to validate and
+/// complete such files
+#[derive(Parser, Debug)]
+pub struct Schema {
+    /// format to describe (scenario: report-trace --scenario files)
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, EnumString)]
+enum Format {
+    #[strum(serialize = "scenario")]
+    Scenario,
+}
+
+pub fn do_schema(schema: Schema) -> Result<(), Box<dyn error::Error>> {
+    let schema = match schema.format {
+        Format::Scenario => scenario::schema(),
+    };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
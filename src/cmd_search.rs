@@ -4,18 +4,324 @@ use std::error;
 use std::io::{BufReader, BufRead};
 use std::fs::File;
 use crate::proto;
+use crate::proto::trace::v1::status::StatusCode;
+use crate::common::{parse_duration, parse_time, parse_trace_id};
+use crate::common::any_value_to_string;
+use crate::filter::{self, AttrFilter, Attributes, LogRef, PointRef, SpanRef};
+use crate::otk_error::OTKError;
+use crate::query::{kind_name, status_name, Query};
+use crate::hook::{self, Hook};
+use crate::index;
+use crate::mmap::Mmap;
+use crate::render::{Color, Renderer};
+use crate::framing::{self, Framing};
 use hex::ToHex;
+use regex::Regex;
+use serde_json::json;
+use std::fmt::Debug;
+use std::thread;
+use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
 
-/// search from trace (input is base64 encoded binary)
+/// match counts per --group-by value
+type Counts = HashMap<String, u64>;
+
+/// split point for parallel search, chunks end at the first line break after
+/// this many bytes
+const CHUNK_SIZE: usize = 8 << 20;
+
+/// what searching produces: the text to print and the match counts
+#[derive(Debug, Default)]
+struct Output {
+    text: String,
+    counts: Counts,
+    /// printed matches so far, stops at `limit`
+    matched: usize,
+    limit: Option<usize>,
+    /// where the text of each match not flushed yet ends
+    ends: Vec<usize>,
+    /// file and line (message number for binary framings) of the message
+    /// being searched, only tracked when searching several inputs
+    source: Option<(String, usize)>,
+}
+
+impl Output {
+    fn new(limit: Option<usize>) -> Self {
+        Output { limit, ..Default::default() }
+    }
+
+    /// note where the next matches come from, when searching several inputs
+    fn at(&mut self, search: &Search, input: &str, line: usize) {
+        if search.with_filename {
+            self.source = Some((framing::input_name(input).to_string(), line));
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.limit.is_some_and(|limit| self.matched >= limit)
+    }
+
+    /// add the text of one match, unless the limit is reached
+    fn push_match(&mut self, text: std::fmt::Arguments) {
+        if self.done() {
+            return;
+        }
+        let _ = self.text.write_fmt(text);
+        self.matched += 1;
+        self.ends.push(self.text.len());
+    }
+
+    /// drop the text after the first `n` matches
+    fn truncate(&mut self, n: usize) {
+        if n < self.ends.len() {
+            self.text.truncate(if n == 0 { 0 } else { self.ends[n - 1] });
+            self.matched -= self.ends.len() - n;
+            self.ends.truncate(n);
+        }
+    }
+
+    fn flush(&mut self) {
+        print!("{}", self.text);
+        self.text.clear();
+        self.ends.clear();
+    }
+
+    fn merge(&mut self, other: Output) {
+        self.text.push_str(&other.text);
+        self.matched += other.matched;
+        for (group, n) in other.counts {
+            *self.counts.entry(group).or_insert(0) += n;
+        }
+    }
+}
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Signal {
+    #[strum(serialize = "traces", serialize = "trace", serialize = "t")]
+    Traces,
+    #[strum(serialize = "logs", serialize = "log", serialize = "l")]
+    Logs,
+    #[strum(serialize = "metrics", serialize = "metric", serialize = "m")]
+    Metrics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum SpanStatus {
+    #[strum(serialize = "unset", serialize = "UNSET")]
+    Unset,
+    #[strum(serialize = "ok", serialize = "OK")]
+    Ok,
+    #[strum(serialize = "error", serialize = "ERROR")]
+    Error,
+}
+
+impl SpanStatus {
+    fn code(self) -> StatusCode {
+        match self {
+            SpanStatus::Unset => StatusCode::Unset,
+            SpanStatus::Ok => StatusCode::Ok,
+            SpanStatus::Error => StatusCode::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum ExtractFormat {
+    #[strum(serialize = "csv")]
+    Csv,
+    #[strum(serialize = "tsv")]
+    Tsv,
+}
+
+/// what --group-by breaks the match count down by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupBy {
+    Name,
+    Service,
+    Status,
+    Kind,
+    Severity,
+    Attr(String),
+}
+
+impl FromStr for GroupBy {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "" => return Err(OTKError::ParseError("empty group by field".into())),
+            "name" => GroupBy::Name,
+            "service" => GroupBy::Service,
+            "status" => GroupBy::Status,
+            "kind" => GroupBy::Kind,
+            "severity" => GroupBy::Severity,
+            key => GroupBy::Attr(key.to_string()),
+        })
+    }
+}
+
+/// search from trace, log or metric captures (input is base64 encoded binary)
 #[derive(Parser, Debug)]
 pub struct Search {
-    /// file to read (- for stdin)
-    input: String,
+    /// files to read (- for stdin), glob patterns are expanded. with several
+    /// files, matches are prefixed with file name and line number
+    #[clap(required = true)]
+    inputs: Vec<String>,
 
-    /// search trace id (in 16 byte lowercase)
+    /// prefix matches with their file, set when there are several inputs
+    #[clap(skip)]
+    with_filename: bool,
+
+    /// signal of the captured requests (traces, logs or metrics)
+    #[clap(long, default_value = "traces")]
+    signal: Signal,
+
+    /// message framing of the input (base64, raw, length-delimited or
+    /// length-prefixed)
+    #[clap(long, default_value = "base64")]
+    framing: Framing,
+
+    /// search trace id (32 hex chars), matched against spans, log records
+    /// and metric exemplars
     #[clap(long)]
     trace_id: Option<String>,
 
+    /// file with trace ids to search, the first word of each line (lines
+    /// starting with # are ignored), combined with --trace-id
+    #[clap(long)]
+    trace_id_file: Option<String>,
+
+    /// every searched trace id, from --trace-id and --trace-id-file
+    #[clap(skip)]
+    trace_ids: HashSet<String>,
+
+    /// attribute filter on span, scope or resource attributes
+    /// (key=value, key!=value or key~=regex), can be repeated
+    #[clap(long, num_args = 0..)]
+    attr: Vec<AttrFilter>,
+
+    /// boolean span filter, e.g.
+    /// `service.name == "checkout" && (duration > 500ms || status == ERROR)`
+    #[clap(long)]
+    query: Option<Query>,
+
+    /// resource attribute filter (key=value, key!=value or key~=regex), can
+    /// be repeated
+    #[clap(long, num_args = 0..)]
+    resource: Vec<AttrFilter>,
+
+    /// instrumentation scope name to match
+    #[clap(long)]
+    scope_name: Option<String>,
+
+    /// instrumentation scope version to match
+    #[clap(long)]
+    scope_version: Option<String>,
+
+    /// require attribute key to be present, can be repeated
+    #[clap(long, num_args = 0..)]
+    attr_exists: Vec<String>,
+
+    /// regex the span or metric name must match
+    #[clap(long)]
+    name_regex: Option<Regex>,
+
+    /// only spans starting (or log records and data points timestamped) at or
+    /// after this time (RFC3339 or relative like -1h)
+    #[clap(long, value_parser = parse_time, allow_hyphen_values = true)]
+    since: Option<u64>,
+
+    /// only spans ending (or log records and data points timestamped) at or
+    /// before this time (RFC3339 or relative like -1h)
+    #[clap(long, value_parser = parse_time, allow_hyphen_values = true)]
+    until: Option<u64>,
+
+    /// only spans lasting at least this long (e.g. 150ms, 2s)
+    #[clap(long, value_parser = parse_duration)]
+    min_duration: Option<Duration>,
+
+    /// only spans lasting at most this long (e.g. 150ms, 2s)
+    #[clap(long, value_parser = parse_duration)]
+    max_duration: Option<Duration>,
+
+    /// span status to match (ok, error or unset)
+    #[clap(long)]
+    status: Option<SpanStatus>,
+
+    /// regex the span status message must match
+    #[clap(long)]
+    status_message_regex: Option<Regex>,
+
+    /// log severity text to match (case insensitive)
+    #[clap(long)]
+    severity: Option<String>,
+
+    /// substring the log body must contain
+    #[clap(long)]
+    body_contains: Option<String>,
+
+    /// print only the matching spans, log records or metrics instead of the
+    /// whole request
+    #[clap(long, alias = "only-matches")]
+    only_matching: bool,
+
+    /// with --only-matching, keep the resource and scope around the matching
+    /// items (prints the request pruned to them)
+    #[clap(long, requires = "only_matching")]
+    with_context: bool,
+
+    /// print the number of matching spans, log records or data points
+    /// instead of the matches
+    #[clap(long, conflicts_with_all = &["only_matching", "follow"])]
+    count: bool,
+
+    /// count matches per name, service, status, kind, severity or any other
+    /// attribute key (implies --count)
+    #[clap(long, conflicts_with_all = &["only_matching", "follow"])]
+    group_by: Option<GroupBy>,
+
+    /// print these comma separated fields of each matching span as a
+    /// delimited row: trace_id, span_id, parent_span_id, name, duration (in
+    /// milliseconds), start, end (unix nanos), status, kind, service or any
+    /// attribute key
+    #[clap(long, value_delimiter = ',', conflicts_with_all = &["only_matching", "count", "group_by"])]
+    extract: Vec<String>,
+
+    /// row format of --extract (csv or tsv)
+    #[clap(long, default_value = "csv")]
+    extract_format: ExtractFormat,
+
+    /// stop reading the input after this many matches are printed
+    #[clap(long, conflicts_with_all = &["count", "group_by"])]
+    first: Option<usize>,
+
+    /// keep the input open and search new lines as they are appended (like
+    /// `tail -f`)
+    #[clap(short, long)]
+    follow: bool,
+
+    /// with --follow, hooks fired for every new request with matches:
+    /// `exec:<command>` runs the command with the event json on stdin (and
+    /// its kind in $OTK_EVENT), `webhook:<url>` POSTs it, can be repeated
+    #[clap(long, num_args = 0.., requires = "follow")]
+    on_match: Vec<Hook>,
+
+    /// number of threads searching a base64 capture file, defaults to the
+    /// number of cpus
+    #[clap(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// scan the whole capture even if an index exists
+    #[clap(long)]
+    no_index: bool,
+
     /// verbose
     #[clap(short, long)]
     verbose: bool,
@@ -23,47 +329,1052 @@ pub struct Search {
     /// pretty print
     #[clap(short, long)]
     pretty: bool,
+
+    /// color trace and span ids in printed matches, each id in a color of
+    /// its own (auto, always or never)
+    #[clap(long, default_value = "auto")]
+    color: Color,
+
+    #[clap(skip)]
+    renderer: Renderer,
+}
+
+pub fn do_search(mut search: Search) -> Result<(), Box<dyn error::Error>> {
+    search.inputs = framing::expand_inputs(&search.inputs)?;
+    search.renderer = Renderer::new(search.color);
+    search.with_filename = search.inputs.len() > 1;
+    check_filters(&search)?;
+    if let Some(trace_id) = search.trace_id.as_deref().map(parse_trace_id).transpose()? {
+        search.trace_ids.insert(format!("{:032x}", trace_id));
+    }
+    if let Some(path) = &search.trace_id_file {
+        search.trace_ids.extend(read_trace_ids(path)?);
+    }
+    let mut out = Output::new(search.first);
+    if !search.extract.is_empty() {
+        let mut header = search.extract.clone();
+        if search.with_filename {
+            header.splice(0..0, ["file".to_string(), "line".to_string()]);
+        }
+        println!("{}", format_row(&header, search.extract_format));
+    }
+    let threads = search
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    if search.follow && search.inputs[0] != "-" {
+        return follow(&search.inputs[0], &search);
+    }
+    for input in &search.inputs {
+        search_input(input, &search, threads, &mut out)?;
+        if out.done() {
+            break;
+        }
+    }
+    if search.counting() {
+        print_counts(out.counts, &search);
+    }
+    Ok(())
 }
 
-pub fn do_search(search: Search) -> Result<(), Box<dyn error::Error>> {
-    if search.input == "-" {
-        let stdin = std::io::stdin();
-        for line in stdin.lock().lines() {
-            process(line.unwrap(), &search)?;
+fn search_input(input: &str, search: &Search, threads: usize, out: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    if let Some(offsets) = indexed_offsets(input, search)? {
+        let numbers = if search.with_filename {
+            index::line_numbers(input, &offsets)?
+        } else {
+            vec![0; offsets.len()]
+        };
+        for (line, number) in index::read_lines_at(input, &offsets)?.into_iter().zip(numbers) {
+            out.at(search, input, number);
+            process(&base64::decode_config(line, base64::STANDARD)?, search, out)?;
+            out.flush();
+            if out.done() {
+                break;
+            }
         }
+    } else if threads > 1 && input != "-" && search.framing == Framing::Base64 {
+        search_parallel(input, search, threads, out)?;
     } else {
-        let file = File::open(&search.input)?;
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            process(line.unwrap(), &search)?;
+        let mut number = 0;
+        framing::for_each_message(input, search.framing, |bs| {
+            number += 1;
+            out.at(search, input, number);
+            process(bs, search, out)?;
+            out.flush();
+            Ok(if out.done() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) })
+        })?;
+    }
+    Ok(())
+}
+
+/// search the file from the start, then poll it for appended lines. a
+/// trailing line without newline is held back until it is complete, and the
+/// file is read again from the start if it gets truncated
+fn follow(input: &str, search: &Search) -> Result<(), Box<dyn error::Error>> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut out = Output::new(search.first);
+    let mut pos = 0u64;
+    let mut line = String::new();
+    loop {
+        let n = reader.read_line(&mut line)?;
+        if n > 0 {
+            pos += n as u64;
+            if line.ends_with('\n') {
+                let bs = base64::decode_config(line.trim_end(), base64::STANDARD)?;
+                let matched = out.matched;
+                process(&bs, search, &mut out)?;
+                if out.matched > matched && !search.on_match.is_empty() {
+                    let event = json!({
+                        "event": "match",
+                        "input": input,
+                        "signal": search.signal.to_string(),
+                        "matches": out.matched - matched,
+                        "text": out.text,
+                    });
+                    hook::fire_all(&search.on_match, &event);
+                }
+                out.flush();
+                if out.done() {
+                    return Ok(());
+                }
+                line.clear();
+            }
+            continue;
+        }
+        thread::sleep(FOLLOW_INTERVAL);
+        if std::fs::metadata(input)?.len() < pos {
+            eprintln!("{} truncated, reading from the start", input);
+            reader = BufReader::new(File::open(input)?);
+            pos = 0;
+            line.clear();
+        }
+    }
+}
+
+/// search a base64 capture with `threads` workers. the memory mapped file is
+/// split into chunks at line boundaries, the text of each chunk is printed in
+/// file order as soon as the chunks before it are done. with --first, every
+/// chunk stops after that many matches and printing stops once the total is
+/// reached
+fn search_parallel(input: &str, search: &Search, threads: usize, total: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    let map = Mmap::open(input)?;
+    let chunks = split_lines(&map, CHUNK_SIZE);
+    // line number each chunk starts at, only needed to prefix matches
+    let mut first_lines = vec![1; chunks.len()];
+    if search.with_filename {
+        for i in 1..chunks.len() {
+            first_lines[i] = first_lines[i - 1] + chunks[i - 1].iter().filter(|b| **b == b'\n').count();
         }
     }
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(chunks.len()) {
+            let (tx, next, chunks, first_lines) = (tx.clone(), &next, &chunks, &first_lines);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(chunk) = chunks.get(i) else { break };
+                // errors are not Send, pass them on as text
+                let out = search_chunk(chunk, input, first_lines[i], search).map_err(|e| e.to_string());
+                // the receiver is gone after an error, stop early then
+                if tx.send((i, out)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+        let mut pending = BTreeMap::new();
+        let mut printed = 0;
+        for (i, out) in rx {
+            pending.insert(i, out);
+            while let Some(out) = pending.remove(&printed) {
+                let mut out = out.map_err(OTKError::ParseError)?;
+                if let Some(first) = search.first {
+                    out.truncate(first - total.matched);
+                }
+                out.flush();
+                total.merge(out);
+                printed += 1;
+                // dropping the receiver stops the workers
+                if total.done() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok::<_, OTKError>(())
+    })?;
     Ok(())
 }
 
-fn process(payload: String, search: &Search) -> Result<(), Box<dyn error::Error>> {
-    let bs = base64::decode_config(payload, base64::STANDARD)?;
-    let body = proto::collector::trace::v1::ExportTraceServiceRequest::decode(&bs as &[u8])?;
-    if search.trace_id.is_some() {
-        let id = search.trace_id.as_ref().unwrap();
-        let found = body.resource_spans.iter().flat_map(|rs| {
-            rs.scope_spans.iter().flat_map(|ils| {
-                ils.spans.iter().map(|span| {
-                    let trace_id = span.trace_id.encode_hex::<String>();
-                    if search.verbose {
-                        println!("{}", trace_id);
-                    }
-                    trace_id == *id
-                })
-            })
-        }).any(|x| x);
-        if found {
-            if search.pretty {
-                println!("{:#?}", body);
-            } else {
-                println!("{:?}", body);
+/// split into chunks of about `size` bytes ending at line breaks
+fn split_lines(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = (start + size).min(data.len());
+        if let Some(newline) = data[end..].iter().position(|b| *b == b'\n') {
+            end += newline + 1;
+        } else {
+            end = data.len();
+        }
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+fn search_chunk(chunk: &[u8], input: &str, first_line: usize, search: &Search) -> Result<Output, Box<dyn error::Error>> {
+    let mut out = Output::new(search.first);
+    let chunk = chunk.strip_suffix(b"\n").unwrap_or(chunk);
+    for (i, line) in chunk.split(|b| *b == b'\n').enumerate() {
+        out.at(search, input, first_line + i);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        process(&base64::decode_config(line, base64::STANDARD)?, search, &mut out)?;
+        if out.done() {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// trace ids listed in a file, the first word of each line (so the trace
+/// and span id lines of report-trace --ids-out work), normalized to lowercase
+pub fn read_trace_ids(path: &str) -> Result<Vec<String>, Box<dyn error::Error>> {
+    let mut trace_ids = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        match line.split_whitespace().next() {
+            None => continue,
+            Some(word) if word.starts_with('#') => continue,
+            Some(word) => trace_ids.push(format!("{:032x}", parse_trace_id(word)?)),
+        }
+    }
+    Ok(trace_ids)
+}
+
+/// lines that may hold the searched trace ids and attributes according to
+/// the capture index
+fn indexed_offsets(input: &str, search: &Search) -> Result<Option<Vec<u64>>, Box<dyn error::Error>> {
+    let terms = search
+        .attr
+        .iter()
+        .chain(&search.resource)
+        .filter_map(AttrFilter::index_term)
+        .chain(search.attr_exists.iter().cloned())
+        .collect::<Vec<_>>();
+    // the index records offsets of base64 lines
+    if (search.trace_ids.is_empty() && terms.is_empty())
+        || search.signal != Signal::Traces
+        || search.framing != Framing::Base64
+        || search.no_index
+    {
+        return Ok(None);
+    }
+    let trace_ids = Some(&search.trace_ids).filter(|ids| !ids.is_empty());
+    let offsets = index::lookup(input, trace_ids, &terms)?;
+    if search.verbose {
+        if let Some(offsets) = &offsets {
+            eprintln!("index: {} candidate lines", offsets.len());
+        }
+    }
+    Ok(offsets)
+}
+
+fn check_filters(search: &Search) -> Result<(), OTKError> {
+    let log_only = search.severity.is_some() || search.body_contains.is_some();
+    if search.signal != Signal::Logs && log_only {
+        return Err(OTKError::InvalidArgumentError(
+            "--severity and --body-contains only apply to logs".into(),
+        ));
+    }
+    let span_only = search.min_duration.is_some()
+        || search.max_duration.is_some()
+        || search.query.is_some()
+        || search.status.is_some()
+        || search.status_message_regex.is_some();
+    if search.signal != Signal::Traces && span_only {
+        return Err(OTKError::InvalidArgumentError(
+            "--min-duration, --max-duration, --query, --status and --status-message-regex only apply to traces".into(),
+        ));
+    }
+    if search.signal == Signal::Logs && search.name_regex.is_some() {
+        return Err(OTKError::InvalidArgumentError(
+            "--name-regex does not apply to logs".into(),
+        ));
+    }
+    if search.follow && search.framing != Framing::Base64 {
+        return Err(OTKError::InvalidArgumentError(
+            "--follow only supports base64 input".into(),
+        ));
+    }
+    if search.follow && search.inputs.len() > 1 {
+        return Err(OTKError::InvalidArgumentError(
+            "--follow only supports a single input".into(),
+        ));
+    }
+    let unsupported = match (&search.group_by, search.signal) {
+        (Some(GroupBy::Name), Signal::Logs) => Some("name"),
+        (Some(GroupBy::Status), signal) if signal != Signal::Traces => Some("status"),
+        (Some(GroupBy::Kind), signal) if signal != Signal::Traces => Some("kind"),
+        (Some(GroupBy::Severity), signal) if signal != Signal::Logs => Some("severity"),
+        _ => None,
+    };
+    if search.signal != Signal::Traces && !search.extract.is_empty() {
+        return Err(OTKError::InvalidArgumentError(
+            "--extract only applies to traces".into(),
+        ));
+    }
+    if let Some(field) = unsupported {
+        return Err(OTKError::InvalidArgumentError(format!(
+            "--group-by {} does not apply to {}",
+            field, search.signal
+        )));
+    }
+    Ok(())
+}
+
+impl Search {
+    fn counting(&self) -> bool {
+        self.count || self.group_by.is_some()
+    }
+}
+
+fn process(bs: &[u8], search: &Search, out: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    match search.signal {
+        Signal::Traces => process_traces(bs, search, out),
+        Signal::Logs => process_logs(bs, search, out),
+        Signal::Metrics => process_metrics(bs, search, out),
+    }
+}
+
+fn process_traces(bs: &[u8], search: &Search, out: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    let body = proto::collector::trace::v1::ExportTraceServiceRequest::decode(bs)?;
+    if search.counting() {
+        for span in filter::spans(&body).filter(|span| matches_span(*span, search)) {
+            let group = match &search.group_by {
+                Some(GroupBy::Name) => span.span.name.clone(),
+                Some(GroupBy::Status) => status_name(span.span.status.as_ref().map_or(0, |s| s.code)).into(),
+                Some(GroupBy::Kind) => kind_name(span.span.kind).into(),
+                group_by => attr_group(&span, group_by),
+            };
+            *out.counts.entry(group).or_insert(0) += 1;
+        }
+        return Ok(());
+    }
+    if !search.extract.is_empty() {
+        for span in filter::spans(&body).filter(|span| matches_span(*span, search)) {
+            let mut row = search.extract.iter().map(|f| extract_field(&span, f)).collect::<Vec<_>>();
+            if let Some((file, line)) = &out.source {
+                row.splice(0..0, [file.clone(), line.to_string()]);
             }
+            out.push_match(format_args!("{}\n", format_row(&row, search.extract_format)));
         }
+        return Ok(());
+    }
+    if search.with_context {
+        let mut pruned = body.clone();
+        for rs in &mut pruned.resource_spans {
+            for ss in &mut rs.scope_spans {
+                let (resource, scope) = (rs.resource.as_ref(), ss.scope.as_ref());
+                ss.spans.retain(|span| matches_span(SpanRef { resource, scope, span }, search));
+            }
+            rs.scope_spans.retain(|ss| !ss.spans.is_empty());
+        }
+        pruned.resource_spans.retain(|rs| !rs.scope_spans.is_empty());
+        if !pruned.resource_spans.is_empty() {
+            print_item(out, &pruned, search);
+        }
+        return Ok(());
+    }
+    if search.only_matching {
+        for span in filter::spans(&body).filter(|span| matches_span(*span, search)) {
+            print_item(out, span.span, search);
+        }
+        return Ok(());
+    }
+    let found = filter::spans(&body).any(|span| {
+        if search.verbose {
+            let _ = writeln!(out.text, "{}", span.span.trace_id.encode_hex::<String>());
+        }
+        matches_span(span, search)
+    });
+    if found {
+        print_item(out, &body, search);
     }
     Ok(())
 }
+
+fn process_logs(bs: &[u8], search: &Search, out: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    let body = proto::collector::logs::v1::ExportLogsServiceRequest::decode(bs)?;
+    if search.counting() {
+        for log in filter::logs(&body).filter(|log| matches_log(*log, search)) {
+            let group = match &search.group_by {
+                Some(GroupBy::Severity) => log.log.severity_text.clone(),
+                group_by => attr_group(&log, group_by),
+            };
+            *out.counts.entry(group).or_insert(0) += 1;
+        }
+        return Ok(());
+    }
+    if search.with_context {
+        let mut pruned = body.clone();
+        for rl in &mut pruned.resource_logs {
+            for sl in &mut rl.scope_logs {
+                let (resource, scope) = (rl.resource.as_ref(), sl.scope.as_ref());
+                sl.log_records.retain(|log| matches_log(LogRef { resource, scope, log }, search));
+            }
+            rl.scope_logs.retain(|sl| !sl.log_records.is_empty());
+        }
+        pruned.resource_logs.retain(|rl| !rl.scope_logs.is_empty());
+        if !pruned.resource_logs.is_empty() {
+            print_item(out, &pruned, search);
+        }
+        return Ok(());
+    }
+    if search.only_matching {
+        for log in filter::logs(&body).filter(|log| matches_log(*log, search)) {
+            print_item(out, log.log, search);
+        }
+        return Ok(());
+    }
+    if filter::logs(&body).any(|log| matches_log(log, search)) {
+        print_item(out, &body, search);
+    }
+    Ok(())
+}
+
+fn process_metrics(bs: &[u8], search: &Search, out: &mut Output) -> Result<(), Box<dyn error::Error>> {
+    let body = proto::collector::metrics::v1::ExportMetricsServiceRequest::decode(bs)?;
+    if search.counting() {
+        for point in filter::points(&body).filter(|point| matches_point(*point, search)) {
+            let group = match &search.group_by {
+                Some(GroupBy::Name) => point.metric.name.clone(),
+                group_by => attr_group(&point, group_by),
+            };
+            *out.counts.entry(group).or_insert(0) += 1;
+        }
+        return Ok(());
+    }
+    if search.with_context {
+        // metrics are kept whole when any of their points match
+        let mut pruned = body.clone();
+        for rm in &mut pruned.resource_metrics {
+            for sm in &mut rm.scope_metrics {
+                let (resource, scope) = (rm.resource.as_ref(), sm.scope.as_ref());
+                sm.metrics.retain(|metric| {
+                    filter::metric_points(resource, scope, metric)
+                        .into_iter()
+                        .any(|point| matches_point(point, search))
+                });
+            }
+            rm.scope_metrics.retain(|sm| !sm.metrics.is_empty());
+        }
+        pruned.resource_metrics.retain(|rm| !rm.scope_metrics.is_empty());
+        if !pruned.resource_metrics.is_empty() {
+            print_item(out, &pruned, search);
+        }
+        return Ok(());
+    }
+    if search.only_matching {
+        // print each metric once, however many of its points match
+        let mut last: Option<&proto::metrics::v1::Metric> = None;
+        for point in filter::points(&body).filter(|point| matches_point(*point, search)) {
+            if !last.is_some_and(|m| std::ptr::eq(m, point.metric)) {
+                print_item(out, point.metric, search);
+                last = Some(point.metric);
+            }
+        }
+        return Ok(());
+    }
+    if filter::points(&body).any(|point| matches_point(point, search)) {
+        print_item(out, &body, search);
+    }
+    Ok(())
+}
+
+/// group of an item for the fields shared by all signals, `-` when the
+/// attribute is missing
+fn attr_group<'a>(item: &impl Attributes<'a>, group_by: &Option<GroupBy>) -> String {
+    let key = match group_by {
+        None => return String::new(),
+        Some(GroupBy::Service) => "service.name",
+        Some(GroupBy::Attr(key)) => key,
+        Some(_) => unreachable!("rejected by check_filters"),
+    };
+    item.attr(key).map(any_value_to_string).unwrap_or_else(|| "-".into())
+}
+
+/// print the total, or the counts per group sorted by count
+fn print_counts(counts: Counts, search: &Search) {
+    let total: u64 = counts.values().sum();
+    if search.group_by.is_none() {
+        println!("{}", total);
+        return;
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (group, n) in counts {
+        println!("{:>10}  {}", n, group);
+    }
+    println!("{:>10}  total", total);
+}
+
+fn extract_field(span: &SpanRef, field: &str) -> String {
+    let s = span.span;
+    match field {
+        "trace_id" => s.trace_id.encode_hex(),
+        "span_id" => s.span_id.encode_hex(),
+        "parent_span_id" => s.parent_span_id.encode_hex(),
+        "name" => s.name.clone(),
+        "duration" => format!("{:.3}", s.end_time_unix_nano.saturating_sub(s.start_time_unix_nano) as f64 / 1e6),
+        "start" => s.start_time_unix_nano.to_string(),
+        "end" => s.end_time_unix_nano.to_string(),
+        "status" => status_name(s.status.as_ref().map_or(0, |s| s.code)).into(),
+        "kind" => kind_name(s.kind).into(),
+        "service" => span.attr("service.name").map(any_value_to_string).unwrap_or_default(),
+        key => span.attr(key).map(any_value_to_string).unwrap_or_default(),
+    }
+}
+
+/// format a csv row (quoted as in RFC 4180 when needed) or a tsv row (tabs
+/// and newlines escaped)
+fn format_row(row: &[String], format: ExtractFormat) -> String {
+    let cells = row.iter().map(|cell| match format {
+        ExtractFormat::Csv if cell.contains(&[',', '"', '\n', '\r'][..]) => {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        }
+        ExtractFormat::Csv => cell.clone(),
+        ExtractFormat::Tsv => cell.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"),
+    });
+    let sep = match format {
+        ExtractFormat::Csv => ",",
+        ExtractFormat::Tsv => "\t",
+    };
+    cells.collect::<Vec<_>>().join(sep)
+}
+
+fn print_item(out: &mut Output, item: &impl Debug, search: &Search) {
+    let prefix = out.source.as_ref().map(|(file, line)| format!("{}:{}:", file, line)).unwrap_or_default();
+    let text = match search.pretty {
+        true => format!("{:#?}", item),
+        false => format!("{:?}", item),
+    };
+    out.push_match(format_args!("{}{}\n", prefix, search.renderer.ids(&text)));
+}
+
+fn matches_span(span: SpanRef, search: &Search) -> bool {
+    if !search.trace_ids.is_empty() && !search.trace_ids.contains(&span.span.trace_id.encode_hex::<String>()) {
+        return false;
+    }
+    if search.since.is_some_and(|t| span.span.start_time_unix_nano < t)
+        || search.until.is_some_and(|t| span.span.end_time_unix_nano > t)
+    {
+        return false;
+    }
+    let duration = Duration::from_nanos(
+        span.span
+            .end_time_unix_nano
+            .saturating_sub(span.span.start_time_unix_nano),
+    );
+    if search.min_duration.is_some_and(|d| duration < d)
+        || search.max_duration.is_some_and(|d| duration > d)
+    {
+        return false;
+    }
+    if let Some(re) = &search.name_regex {
+        if !re.is_match(&span.span.name) {
+            return false;
+        }
+    }
+    let (code, message) = span.span.status.as_ref().map_or((0, ""), |s| (s.code, s.message.as_str()));
+    if search.status.is_some_and(|s| code != s.code() as i32) {
+        return false;
+    }
+    if let Some(re) = &search.status_message_regex {
+        if !re.is_match(message) {
+            return false;
+        }
+    }
+    if search.query.as_ref().is_some_and(|q| !q.matches(&span)) {
+        return false;
+    }
+    matches_scope(&span, search) && matches_attrs(&span, search)
+}
+
+fn matches_log(log: LogRef, search: &Search) -> bool {
+    if !search.trace_ids.is_empty() && !search.trace_ids.contains(&log.log.trace_id.encode_hex::<String>()) {
+        return false;
+    }
+    let time = if log.log.time_unix_nano != 0 {
+        log.log.time_unix_nano
+    } else {
+        log.log.observed_time_unix_nano
+    };
+    if !in_time_range(time, search) {
+        return false;
+    }
+    if let Some(severity) = &search.severity {
+        if !log.log.severity_text.eq_ignore_ascii_case(severity) {
+            return false;
+        }
+    }
+    if let Some(needle) = &search.body_contains {
+        let body = log.log.body.as_ref().map(any_value_to_string).unwrap_or_default();
+        if !body.contains(needle.as_str()) {
+            return false;
+        }
+    }
+    matches_scope(&log, search) && matches_attrs(&log, search)
+}
+
+fn matches_point(point: PointRef, search: &Search) -> bool {
+    if !search.trace_ids.is_empty()
+        && !point
+            .exemplars
+            .iter()
+            .any(|e| search.trace_ids.contains(&e.trace_id.encode_hex::<String>()))
+    {
+        return false;
+    }
+    if !in_time_range(point.time_unix_nano, search) {
+        return false;
+    }
+    if let Some(re) = &search.name_regex {
+        if !re.is_match(&point.metric.name) {
+            return false;
+        }
+    }
+    matches_scope(&point, search) && matches_attrs(&point, search)
+}
+
+fn in_time_range(time: u64, search: &Search) -> bool {
+    search.since.is_none_or(|t| time >= t) && search.until.is_none_or(|t| time <= t)
+}
+
+fn matches_scope<'a>(item: &impl Attributes<'a>, search: &Search) -> bool {
+    let scope = item.scope();
+    search.scope_name.as_ref().is_none_or(|name| scope.is_some_and(|s| s.name == *name))
+        && search
+            .scope_version
+            .as_ref()
+            .is_none_or(|version| scope.is_some_and(|s| s.version == *version))
+}
+
+fn matches_attrs<'a>(item: &impl Attributes<'a>, search: &Search) -> bool {
+    search.resource.iter().all(|f| f.matches_resource(item))
+        && search.attr.iter().all(|f| f.matches_attrs(item))
+        && search
+            .attr_exists
+            .iter()
+            .all(|k| AttrFilter::exists(k).matches_attrs(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::proto::common::v1::{any_value, AnyValue, KeyValue};
+    use crate::proto::resource::v1::Resource;
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: Some(AnyValue { value: Some(any_value::Value::StringValue(value.into())) }),
+        }
+    }
+
+    /// a span of 100ms starting at 1s
+    fn span(name: &str, attributes: &[(&str, &str)]) -> Span {
+        Span {
+            trace_id: vec![1; 16],
+            span_id: vec![2; 8],
+            name: name.into(),
+            start_time_unix_nano: 1_000_000_000,
+            end_time_unix_nano: 1_100_000_000,
+            attributes: attributes.iter().map(|(k, v)| kv(k, v)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn traces(service: &str, spans: Vec<Span>) -> ExportTraceServiceRequest {
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource { attributes: vec![kv("service.name", service)], ..Default::default() }),
+                scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn search(args: &[&str]) -> Search {
+        Search::try_parse_from(["search", "-"].iter().chain(args)).unwrap()
+    }
+
+    /// names of the spans the search matches
+    fn matching(search: &Search, request: &ExportTraceServiceRequest) -> Vec<String> {
+        filter::spans(request).filter(|s| matches_span(*s, search)).map(|s| s.span.name.clone()).collect()
+    }
+
+    fn output(search: &Search, message: &[u8]) -> Output {
+        let mut out = Output::new(search.first);
+        process(message, search, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn attribute_filters() {
+        let request = traces(
+            "checkout",
+            vec![
+                span("GET /cart", &[("http.method", "GET"), ("http.route", "/cart")]),
+                span("POST /pay", &[("http.method", "POST")]),
+            ],
+        );
+        assert_eq!(matching(&search(&["--attr", "http.method=GET"]), &request), ["GET /cart"]);
+        assert_eq!(matching(&search(&["--attr", "http.method!=GET"]), &request), ["POST /pay"]);
+        assert_eq!(matching(&search(&["--attr", "http.method~=^P"]), &request), ["POST /pay"]);
+        assert_eq!(matching(&search(&["--attr-exists", "http.route"]), &request), ["GET /cart"]);
+        // resource attributes are looked up too, and the filters all apply
+        assert_eq!(matching(&search(&["--attr", "service.name=checkout"]), &request).len(), 2);
+        assert!(matching(&search(&["--attr", "http.method=GET", "--attr", "http.route=/pay"]), &request).is_empty());
+    }
+
+    #[test]
+    fn prints_matching_requests() {
+        let message = traces("checkout", vec![span("GET /cart", &[("http.method", "GET")])]).encode_to_vec();
+        let out = output(&search(&["--attr", "http.method=GET"]), &message);
+        assert_eq!(out.matched, 1);
+        assert!(out.text.starts_with("ExportTraceServiceRequest {") && out.text.contains("GET /cart"), "{}", out.text);
+        assert!(output(&search(&["--attr", "http.method=POST"]), &message).text.is_empty());
+    }
+
+    #[test]
+    fn name_regex() {
+        let request = traces("checkout", vec![span("GET /cart", &[]), span("SELECT carts", &[])]);
+        assert_eq!(matching(&search(&["--name-regex", "^GET "]), &request), ["GET /cart"]);
+        assert_eq!(matching(&search(&["--name-regex", "cart"]), &request).len(), 2);
+        assert!(Search::try_parse_from(["search", "-", "--name-regex", "("]).is_err());
+    }
+
+
+    /// a span from `start` to `end` seconds after the epoch
+    fn timed(name: &str, start: u64, end: u64) -> Span {
+        Span { start_time_unix_nano: start * 1_000_000_000, end_time_unix_nano: end * 1_000_000_000, ..span(name, &[]) }
+    }
+
+    #[test]
+    fn time_range() {
+        let request = traces("checkout", vec![timed("early", 10, 20), timed("late", 30, 40)]);
+        let since = |t| search(&["--since", t]);
+        assert_eq!(matching(&since("1970-01-01T00:00:25Z"), &request), ["late"]);
+        // spans must start at or after --since and end at or before --until
+        assert_eq!(matching(&since("1970-01-01T00:00:10Z"), &request).len(), 2);
+        assert_eq!(matching(&search(&["--until", "1970-01-01T00:00:35Z"]), &request), ["early"]);
+        let both = search(&["--since", "1970-01-01T00:00:15Z", "--until", "1970-01-01T00:00:45Z"]);
+        assert_eq!(matching(&both, &request), ["late"]);
+        assert!(matching(&search(&["--since", "-1h"]), &request).is_empty());
+    }
+
+
+    fn logs() -> Vec<u8> {
+        use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+        use crate::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+        let log = |severity: &str, body: &str, time: u64| LogRecord {
+            severity_text: severity.into(),
+            body: Some(AnyValue { value: Some(any_value::Value::StringValue(body.into())) }),
+            observed_time_unix_nano: time * 1_000_000_000,
+            ..Default::default()
+        };
+        let log_records = vec![log("INFO", "cart loaded", 10), log("ERROR", "payment declined", 20)];
+        ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                scope_logs: vec![ScopeLogs { log_records, ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    fn metrics() -> Vec<u8> {
+        use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+        use crate::proto::metrics::v1::{metric::Data, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics};
+        let gauge = |name: &str, time: u64| Metric {
+            name: name.into(),
+            data: Some(Data::Gauge(Gauge {
+                data_points: vec![NumberDataPoint { time_unix_nano: time * 1_000_000_000, ..Default::default() }],
+            })),
+            ..Default::default()
+        };
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics { metrics: vec![gauge("cpu", 10), gauge("memory", 20)], ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn logs_and_metrics() {
+        let count = |args: &[&str], message: &[u8]| {
+            let out = output(&search(&[&["--count"], args].concat()), message);
+            out.counts.values().sum::<u64>()
+        };
+        assert_eq!(count(&["--signal", "logs", "--severity", "error"], &logs()), 1);
+        assert_eq!(count(&["--signal", "logs", "--body-contains", "cart"], &logs()), 1);
+        assert_eq!(count(&["--signal", "logs", "--since", "1970-01-01T00:00:15Z"], &logs()), 1);
+        assert_eq!(count(&["--signal", "metrics", "--name-regex", "^mem"], &metrics()), 1);
+        assert_eq!(count(&["--signal", "metrics", "--until", "1970-01-01T00:00:15Z"], &metrics()), 1);
+        // filters of another signal are rejected
+        assert!(check_filters(&search(&["--severity", "error"])).is_err());
+        assert!(check_filters(&search(&["--signal", "logs", "--name-regex", "cart"])).is_err());
+        assert!(check_filters(&search(&["--signal", "metrics", "--status", "error"])).is_err());
+    }
+
+
+    #[test]
+    fn only_matching() {
+        let message = traces("checkout", vec![span("GET /cart", &[]), span("POST /pay", &[])]).encode_to_vec();
+        let out = output(&search(&["--only-matches", "--name-regex", "pay"]), &message);
+        assert_eq!(out.matched, 1);
+        assert!(out.text.starts_with("Span {") && !out.text.contains("GET /cart"), "{}", out.text);
+        // with the context, the request pruned to the matching span
+        let out = output(&search(&["--only-matching", "--with-context", "--name-regex", "pay"]), &message);
+        assert!(out.text.starts_with("ExportTraceServiceRequest {"), "{}", out.text);
+        assert!(out.text.contains("POST /pay") && out.text.contains("checkout") && !out.text.contains("GET /cart"));
+        assert!(output(&search(&["--only-matching", "--with-context", "--name-regex", "x"]), &message).text.is_empty());
+        assert!(Search::try_parse_from(["search", "-", "--with-context"]).is_err());
+    }
+
+
+    #[test]
+    fn durations() {
+        let request = traces("checkout", vec![timed("fast", 10, 10), timed("slow", 10, 12)]);
+        assert_eq!(matching(&search(&["--min-duration", "1s"]), &request), ["slow"]);
+        assert_eq!(matching(&search(&["--max-duration", "150ms"]), &request), ["fast"]);
+        // both bounds are inclusive
+        assert_eq!(matching(&search(&["--min-duration", "2s", "--max-duration", "2s"]), &request), ["slow"]);
+    }
+
+
+    #[test]
+    fn resource_filters() {
+        let request = traces("checkout", vec![span("GET /cart", &[("tier", "web")])]);
+        assert_eq!(matching(&search(&["--resource", "service.name=checkout"]), &request), ["GET /cart"]);
+        assert!(matching(&search(&["--resource", "service.name~=^pay"]), &request).is_empty());
+        // unlike --attr, span attributes don't count
+        assert!(matching(&search(&["--resource", "tier=web"]), &request).is_empty());
+        assert_eq!(matching(&search(&["--attr", "tier=web"]), &request), ["GET /cart"]);
+    }
+
+
+    #[test]
+    fn query() {
+        use crate::proto::trace::v1::Status;
+        let failed = Span { status: Some(Status { code: StatusCode::Error as i32, ..Default::default() }), ..span("pay", &[]) };
+        let request = traces("checkout", vec![timed("fast", 10, 10), timed("slow", 10, 12), failed]);
+        let query = |q| matching(&search(&["--query", q]), &request);
+        assert_eq!(query(r#"service.name == "checkout" && (duration > 500ms || status == ERROR)"#), ["slow", "pay"]);
+        assert_eq!(query(r#"!(name =~ "^(fast|slow)$")"#), ["pay"]);
+        assert!(query(r#"service.name != "checkout""#).is_empty());
+        assert!(Search::try_parse_from(["search", "-", "--query", "duration =~ \"1s\""]).is_err());
+    }
+
+
+    #[test]
+    fn follows_appended_lines() {
+        use std::io::Write as _;
+        let path = std::env::temp_dir().join(format!("otk-follow-{}.b64", std::process::id()));
+        let line = |name| format!("{}\n", base64::encode(traces("checkout", vec![span(name, &[])]).encode_to_vec()));
+        std::fs::write(&path, line("first")).unwrap();
+        let appender = {
+            let (path, second) = (path.clone(), line("second"));
+            thread::spawn(move || {
+                thread::sleep(FOLLOW_INTERVAL * 2);
+                let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+                // a line is only searched once complete
+                file.write_all(&second.as_bytes()[..10]).unwrap();
+                thread::sleep(FOLLOW_INTERVAL * 2);
+                file.write_all(&second.as_bytes()[10..]).unwrap();
+            })
+        };
+        let input = path.to_str().unwrap();
+        // returns once --first is reached, so only after the appended line
+        follow(input, &Search::try_parse_from(["search", input, "--follow", "--first", "2"]).unwrap()).unwrap();
+        appender.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(check_filters(&search(&["--follow", "--framing", "raw"])).is_err());
+    }
+
+
+    #[test]
+    fn counts() {
+        let message = traces(
+            "checkout",
+            vec![span("GET /cart", &[("tier", "web")]), span("GET /cart", &[]), span("POST /pay", &[("tier", "web")])],
+        )
+        .encode_to_vec();
+        let counts = |args: &[&str]| {
+            let mut counts = output(&search(args), &message).counts.into_iter().collect::<Vec<_>>();
+            counts.sort();
+            counts
+        };
+        assert_eq!(counts(&["--count"]), [(String::new(), 3)]);
+        assert_eq!(counts(&["--count", "--name-regex", "GET"]), [(String::new(), 2)]);
+        assert_eq!(counts(&["--group-by", "name"]), [("GET /cart".into(), 2), ("POST /pay".into(), 1)]);
+        assert_eq!(counts(&["--group-by", "service"]), [("checkout".into(), 3)]);
+        assert_eq!(counts(&["--group-by", "kind"]), [("UNSPECIFIED".into(), 3)]);
+        // spans without the attribute are grouped under -
+        assert_eq!(counts(&["--group-by", "tier"]), [("-".into(), 1), ("web".into(), 2)]);
+        assert!(check_filters(&search(&["--signal", "logs", "--group-by", "status"])).is_err());
+        assert!(check_filters(&search(&["--signal", "traces", "--group-by", "severity"])).is_err());
+        assert!(Search::try_parse_from(["search", "-", "--count", "--only-matching"]).is_err());
+    }
+
+
+    #[test]
+    fn extract() {
+        let message = traces("checkout", vec![span("GET /cart", &[("note", "a,\"b\"")])]).encode_to_vec();
+        let out = output(&search(&["--extract", "trace_id,name,duration,kind,status,service,note,missing"]), &message);
+        assert_eq!(
+            out.text,
+            "01010101010101010101010101010101,GET /cart,100.000,UNSPECIFIED,UNSET,checkout,\"a,\"\"b\"\"\",\n"
+        );
+        assert_eq!(out.matched, 1);
+        let out = output(&search(&["--extract", "span_id,start,end", "--extract-format", "tsv"]), &message);
+        assert_eq!(out.text, "0202020202020202\t1000000000\t1100000000\n");
+    }
+
+    #[test]
+    fn row_formats() {
+        let row = ["plain".to_string(), "tab\there".into(), "line\nbreak".into(), "back\\slash".into()];
+        assert_eq!(format_row(&row, ExtractFormat::Csv), "plain,tab\there,\"line\nbreak\",back\\slash");
+        assert_eq!(format_row(&row, ExtractFormat::Tsv), "plain\ttab\\there\tline\\nbreak\tback\\\\slash");
+        assert!(check_filters(&search(&["--signal", "logs", "--extract", "name"])).is_err());
+    }
+
+
+    #[test]
+    fn trace_id_file() {
+        let path = std::env::temp_dir().join(format!("otk-ids-{}.txt", std::process::id()));
+        let id = "0101010101010101010101010101010A";
+        std::fs::write(&path, format!("# trace span\n\n{} 0202020202020202\n{}\n", id, "f".repeat(32))).unwrap();
+        let ids = read_trace_ids(path.to_str().unwrap()).unwrap();
+        assert_eq!(ids, [id.to_lowercase(), "f".repeat(32)]);
+        std::fs::write(&path, "not-an-id\n").unwrap();
+        assert!(read_trace_ids(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let other = Span { trace_id: vec![0xff; 16], ..span("other", &[]) };
+        let request = traces("checkout", vec![span("ours", &[]), other]);
+        let mut search = search(&[]);
+        search.trace_ids.insert("01".repeat(16));
+        assert_eq!(matching(&search, &request), ["ours"]);
+    }
+
+
+    #[test]
+    fn splits_at_line_breaks() {
+        let data = b"aaaa\nbb\ncccccc\nd";
+        assert_eq!(split_lines(data, 3), [&b"aaaa\n"[..], b"bb\ncccccc\n", b"d"]);
+        assert_eq!(split_lines(data, 6), [&b"aaaa\nbb\n"[..], b"cccccc\n", b"d"]);
+        assert_eq!(split_lines(data, 100), [&data[..]]);
+        assert!(split_lines(b"", 3).is_empty());
+    }
+
+    #[test]
+    fn searches_in_parallel() {
+        let names = (0..50).map(|i| format!("span {}", i)).collect::<Vec<_>>();
+        let lines = names
+            .iter()
+            .map(|name| base64::encode(traces("checkout", vec![span(name, &[])]).encode_to_vec()) + "\r\n")
+            .collect::<String>();
+        let path = std::env::temp_dir().join(format!("otk-parallel-{}.b64", std::process::id()));
+        std::fs::write(&path, &lines).unwrap();
+        let input = path.to_str().unwrap();
+        let search = Search::try_parse_from(["search", input, "--extract", "name", "--name-regex", "[05]$"]).unwrap();
+        let mut out = Output::new(None);
+        search_parallel(input, &search, 4, &mut out).unwrap();
+        assert_eq!(out.matched, 10);
+
+        // chunks keep their line numbers
+        let mut search = search;
+        search.with_filename = true;
+        let end = lines.match_indices('\n').nth(20).unwrap().0 + 1;
+        let chunk = search_chunk(&lines.as_bytes()[..end], input, 1, &search).unwrap();
+        assert!(chunk.text.ends_with(&format!("{},21,span 20\n", input)), "{}", chunk.text);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    #[test]
+    fn status_filters() {
+        use crate::proto::trace::v1::Status;
+        let with_status = |name, code: StatusCode, message: &str| Span {
+            status: Some(Status { code: code as i32, message: message.into() }),
+            ..span(name, &[])
+        };
+        let request = traces(
+            "checkout",
+            vec![span("unset", &[]), with_status("ok", StatusCode::Ok, ""), with_status("failed", StatusCode::Error, "card declined")],
+        );
+        assert_eq!(matching(&search(&["--status", "error"]), &request), ["failed"]);
+        assert_eq!(matching(&search(&["--status", "OK"]), &request), ["ok"]);
+        // a span without status is unset
+        assert_eq!(matching(&search(&["--status", "unset"]), &request), ["unset"]);
+        assert_eq!(matching(&search(&["--status-message-regex", "declined$"]), &request), ["failed"]);
+        assert!(Search::try_parse_from(["search", "-", "--status", "failed"]).is_err());
+    }
+
+
+    #[test]
+    fn scope_filters() {
+        use crate::proto::common::v1::InstrumentationScope;
+        let mut request = traces("checkout", vec![span("GET /cart", &[])]);
+        let scope = InstrumentationScope { name: "http".into(), version: "1.2".into(), ..Default::default() };
+        request.resource_spans[0].scope_spans[0].scope = Some(scope);
+        request.resource_spans[0].scope_spans.push(ScopeSpans { spans: vec![span("unscoped", &[])], ..Default::default() });
+        assert_eq!(matching(&search(&["--scope-name", "http"]), &request), ["GET /cart"]);
+        assert_eq!(matching(&search(&["--scope-name", "http", "--scope-version", "1.2"]), &request), ["GET /cart"]);
+        assert!(matching(&search(&["--scope-version", "1.3"]), &request).is_empty());
+    }
+
+
+    #[test]
+    fn first_matches() {
+        let mut out = Output::new(Some(2));
+        for i in 0..3 {
+            out.push_match(format_args!("{}\n", i));
+        }
+        assert!(out.done());
+        assert_eq!((out.text.as_str(), out.matched), ("0\n1\n", 2));
+        out.truncate(1);
+        assert_eq!((out.text.as_str(), out.matched), ("0\n", 1));
+
+        let message = traces("checkout", vec![span("a", &[]), span("b", &[]), span("c", &[])]).encode_to_vec();
+        let out = output(&search(&["--only-matching", "--first", "2"]), &message);
+        assert_eq!(out.matched, 2);
+        assert!(!out.text.contains("\"c\""), "{}", out.text);
+        assert!(Search::try_parse_from(["search", "-", "--first", "1", "--count"]).is_err());
+    }
+
+
+    #[test]
+    fn prefixes_file_and_line() {
+        let message = traces("checkout", vec![span("GET /cart", &[])]).encode_to_vec();
+        let mut search = search(&["--only-matching"]);
+        let mut out = Output::new(None);
+        out.at(&search, "a.b64", 3);
+        process(&message, &search, &mut out).unwrap();
+        assert!(out.text.starts_with("Span {"), "{}", out.text);
+
+        search.with_filename = true;
+        out.at(&search, "a.b64", 3);
+        process(&message, &search, &mut out).unwrap();
+        assert!(out.text.contains("\na.b64:3:Span {"), "{}", out.text);
+        out.at(&search, "-", 4);
+        out.text.clear();
+        process(&message, &search, &mut out).unwrap();
+        assert!(out.text.starts_with("(standard input):4:Span {"), "{}", out.text);
+    }
+}
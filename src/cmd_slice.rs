@@ -0,0 +1,240 @@
+use crate::cmd_search::{read_trace_ids, Signal};
+use crate::framing::{self, Framing};
+use crate::index;
+use crate::otk_error::OTKError;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use clap::Parser;
+use hex::ToHex;
+use prost::Message;
+use std::collections::HashSet;
+use std::error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::ControlFlow;
+
+/// cut the spans (or log records) of some traces out of a capture into a
+/// smaller capture, e.g. a minimal reproducer for a bug report
+#[derive(Parser, Debug)]
+pub struct Slice {
+    /// capture to read (- for stdin)
+    input: String,
+
+    /// file with the trace ids to keep, the first word of each line (lines
+    /// starting with # are ignored), like report-trace --ids-out writes
+    #[clap(long)]
+    trace_ids: String,
+
+    /// capture to write, one base64 encoded request per line, stdout if not
+    /// given
+    #[clap(long)]
+    out: Option<String>,
+
+    /// signal of the captured requests (traces or logs)
+    #[clap(long, default_value = "traces")]
+    signal: Signal,
+
+    /// message framing of the input (base64, raw, length-delimited or
+    /// length-prefixed)
+    #[clap(long, default_value = "base64")]
+    framing: Framing,
+
+    /// scan the whole capture even if an index exists
+    #[clap(long)]
+    no_index: bool,
+}
+
+/// what was kept of the capture
+#[derive(Debug, Default)]
+struct Sliced {
+    requests: usize,
+    items: usize,
+    found: HashSet<String>,
+}
+
+pub fn do_slice(slice: Slice) -> Result<(), Box<dyn error::Error>> {
+    if slice.signal == Signal::Metrics {
+        return Err(Box::new(OTKError::UnimplementedError(
+            "metrics can't be sliced by trace id, only traces and logs".into(),
+        )));
+    }
+    let trace_ids = read_trace_ids(&slice.trace_ids)?.into_iter().collect::<HashSet<_>>();
+    if trace_ids.is_empty() {
+        return Err(Box::new(OTKError::InvalidArgumentError(format!("no trace ids in {}", slice.trace_ids))));
+    }
+    let mut out: Box<dyn Write> = match &slice.out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let mut sliced = Sliced::default();
+    let mut keep = |bs: &[u8]| -> Result<ControlFlow<()>, Box<dyn error::Error>> {
+        if let Some(message) = slice_message(slice.signal, bs, &trace_ids, &mut sliced)? {
+            writeln!(out, "{}", base64::encode(message))?;
+        }
+        Ok(ControlFlow::Continue(()))
+    };
+    // only the lines holding the traces when the capture is indexed
+    let indexed = match slice.signal == Signal::Traces && slice.framing == Framing::Base64 && slice.input != "-" && !slice.no_index {
+        true => index::lookup(&slice.input, Some(&trace_ids), &[])?,
+        false => None,
+    };
+    match indexed {
+        Some(offsets) => {
+            for line in index::read_lines_at(&slice.input, &offsets)? {
+                let _ = keep(&base64::decode_config(line, base64::STANDARD)?)?;
+            }
+        }
+        None => framing::for_each_message(&slice.input, slice.framing, keep)?,
+    }
+    out.flush()?;
+    eprintln!(
+        "kept {} {} of {} traces in {} requests",
+        sliced.items,
+        match slice.signal {
+            Signal::Logs => "log records",
+            _ => "spans",
+        },
+        sliced.found.len(),
+        sliced.requests
+    );
+    let missing = trace_ids.difference(&sliced.found).collect::<Vec<_>>();
+    if !missing.is_empty() {
+        eprintln!("{} trace ids not found, e.g. {}", missing.len(), missing[0]);
+    }
+    Ok(())
+}
+
+/// the request pruned to the items of `trace_ids` (keeping their resource
+/// and scope), none if it has no such item
+fn slice_message(
+    signal: Signal,
+    bs: &[u8],
+    trace_ids: &HashSet<String>,
+    sliced: &mut Sliced,
+) -> Result<Option<Vec<u8>>, prost::DecodeError> {
+    let mut keep = |trace_id: &[u8]| {
+        let id = trace_id.encode_hex::<String>();
+        let kept = trace_ids.contains(&id);
+        if kept {
+            sliced.items += 1;
+            sliced.found.insert(id);
+        }
+        kept
+    };
+    let (message, empty) = match signal {
+        Signal::Logs => {
+            let mut req = ExportLogsServiceRequest::decode(bs)?;
+            for rl in &mut req.resource_logs {
+                for sl in &mut rl.scope_logs {
+                    sl.log_records.retain(|log| keep(&log.trace_id));
+                }
+                rl.scope_logs.retain(|sl| !sl.log_records.is_empty());
+            }
+            req.resource_logs.retain(|rl| !rl.scope_logs.is_empty());
+            (req.encode_to_vec(), req.resource_logs.is_empty())
+        }
+        _ => {
+            let mut req = ExportTraceServiceRequest::decode(bs)?;
+            for rs in &mut req.resource_spans {
+                for ss in &mut rs.scope_spans {
+                    ss.spans.retain(|span| keep(&span.trace_id));
+                }
+                rs.scope_spans.retain(|ss| !ss.spans.is_empty());
+            }
+            req.resource_spans.retain(|rs| !rs.scope_spans.is_empty());
+            (req.encode_to_vec(), req.resource_spans.is_empty())
+        }
+    };
+    if empty {
+        return Ok(None);
+    }
+    sliced.requests += 1;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+    fn span(trace: u8) -> Span {
+        Span { trace_id: vec![trace; 16], span_id: vec![trace; 8], ..Default::default() }
+    }
+
+    /// two resources, the first with the spans of traces 1 and 2, the second
+    /// with one of trace 2
+    fn traces() -> ExportTraceServiceRequest {
+        let resource = |spans| ResourceSpans {
+            scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+            ..Default::default()
+        };
+        ExportTraceServiceRequest { resource_spans: vec![resource(vec![span(1), span(2)]), resource(vec![span(2)])] }
+    }
+
+    fn ids(traces: &[u8]) -> HashSet<String> {
+        traces.iter().map(|t| vec![*t; 16].encode_hex()).collect()
+    }
+
+    #[test]
+    fn prunes_requests() {
+        let mut sliced = Sliced::default();
+        let bs = traces().encode_to_vec();
+        let message = slice_message(Signal::Traces, &bs, &ids(&[1, 3]), &mut sliced).unwrap().unwrap();
+        let req = ExportTraceServiceRequest::decode(message.as_slice()).unwrap();
+        // the resource without the traces goes
+        assert_eq!(req.resource_spans.len(), 1);
+        assert_eq!(req.resource_spans[0].scope_spans[0].spans, [span(1)]);
+        assert_eq!((sliced.requests, sliced.items), (1, 1));
+        assert_eq!(slice_message(Signal::Traces, &bs, &ids(&[3]), &mut sliced).unwrap(), None);
+        let message = slice_message(Signal::Traces, &bs, &ids(&[2]), &mut sliced).unwrap().unwrap();
+        assert_eq!(ExportTraceServiceRequest::decode(message.as_slice()).unwrap().resource_spans.len(), 2);
+        assert_eq!((sliced.requests, sliced.items), (2, 3));
+        assert_eq!(sliced.found, ids(&[1, 2]));
+    }
+
+    #[test]
+    fn prunes_logs() {
+        let log = |trace: &[u8]| LogRecord { trace_id: trace.to_vec(), ..Default::default() };
+        let req = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                scope_logs: vec![ScopeLogs { log_records: vec![log(&[1; 16]), log(&[]), log(&[2; 16])], ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+        let mut sliced = Sliced::default();
+        let message = slice_message(Signal::Logs, &req.encode_to_vec(), &ids(&[2]), &mut sliced).unwrap().unwrap();
+        let req = ExportLogsServiceRequest::decode(message.as_slice()).unwrap();
+        assert_eq!(req.resource_logs[0].scope_logs[0].log_records, [log(&[2; 16])]);
+    }
+
+    #[test]
+    fn slices_captures() {
+        let dir = std::env::temp_dir().join(format!("otk-slice-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let capture = format!("{}\n{}\n", base64::encode(traces().encode_to_vec()), base64::encode(traces().encode_to_vec()));
+        std::fs::write(path("capture.b64"), capture).unwrap();
+        std::fs::write(path("ids"), format!("# wanted\n{} checkout\n", "01".repeat(16))).unwrap();
+        let slice = Slice::try_parse_from([
+            "slice",
+            &path("capture.b64"),
+            "--trace-ids",
+            &path("ids"),
+            "--out",
+            &path("out.b64"),
+        ])
+        .unwrap();
+        do_slice(slice).unwrap();
+        let out = std::fs::read_to_string(path("out.b64")).unwrap();
+        let metrics = Slice::try_parse_from(["slice", "-", "--trace-ids", &path("ids"), "--signal", "metrics"]).unwrap();
+        let err = do_slice(metrics).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        for line in out.lines() {
+            let req = ExportTraceServiceRequest::decode(base64::decode(line).unwrap().as_slice()).unwrap();
+            assert_eq!(req.resource_spans[0].scope_spans[0].spans, [span(1)]);
+        }
+        assert!(err.to_string().contains("metrics can't be sliced"), "{}", err);
+    }
+}
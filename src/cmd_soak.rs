@@ -0,0 +1,293 @@
+use crate::common::{parse_duration, parse_rate, rate_period, KeyValue, INSTRUMENTATION_LIB_NAME};
+use crate::openmetrics::OpenMetrics;
+use crate::otk_error::OTKError;
+use crate::transport::TransportOpts;
+use clap::Parser;
+use opentelemetry::trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceState};
+use opentelemetry::{InstrumentationLibrary, Key};
+use opentelemetry_sdk::export::trace::{SpanData, SpanExporter};
+use opentelemetry_sdk::trace::{EvictedQueue, IdGenerator, RandomIdGenerator};
+use opentelemetry_sdk::Resource;
+use rand::Rng;
+use std::borrow::Cow;
+use std::error;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::runtime::Runtime;
+use tokio::time::MissedTickBehavior;
+
+/// run a long steady span load and periodically report health
+#[derive(Parser, Debug)]
+pub struct Soak {
+    #[clap(flatten)]
+    transport: TransportOpts,
+
+    /// tag used in resource
+    #[clap(short, long, num_args = 0..)]
+    rtags: Vec<KeyValue>,
+
+    /// span name
+    #[clap(short, long, default_value = "otk_soak_span")]
+    name: String,
+
+    /// how long to run (e.g. 30m, 12h). ctrl-c stops early, still printing
+    /// (and writing) the health so far
+    #[clap(long, value_parser = parse_duration)]
+    duration: Duration,
+
+    /// target export requests per second
+    #[clap(long, default_value = "10", value_parser = parse_rate)]
+    rps: f64,
+
+    /// spans per export request
+    #[clap(long, default_value = "1")]
+    batch: u64,
+
+    /// interval between health lines
+    #[clap(long, default_value = "10s", value_parser = parse_duration)]
+    report_every: Duration,
+
+    /// also export each health line as an "otk.soak.health" span
+    #[clap(long)]
+    export_health: bool,
+
+    /// write the latest health summary in OpenMetrics text format to this
+    /// file (rewritten on every health line)
+    #[clap(long)]
+    openmetrics: Option<String>,
+
+    /// randomly vary batch size, trace shape, padding attribute size and gaps
+    /// between requests within the --chaos-* bounds
+    #[clap(long)]
+    chaos: bool,
+
+    /// max spans per request in chaos mode
+    #[clap(long, default_value = "100", requires = "chaos")]
+    chaos_max_batch: u64,
+
+    /// max size of the "otk.chaos.pad" attribute in chaos mode
+    #[clap(long, default_value = "4096", requires = "chaos")]
+    chaos_max_attr_bytes: usize,
+
+    /// max gap between requests in chaos mode (default 2/rps, so the mean rate
+    /// stays at rps)
+    #[clap(long, value_parser = parse_duration, requires = "chaos")]
+    chaos_max_gap: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    sent: u64,
+    errors: u64,
+    window_sent: u64,
+    last_error: Option<String>,
+}
+
+pub fn do_soak(mut soak: Soak) -> Result<(), Box<dyn error::Error>> {
+    if soak.report_every.is_zero() {
+        return Err(Box::new(OTKError::InvalidArgumentError("--report-every should be positive".into())));
+    }
+    soak.transport = soak.transport.preset()?;
+    Runtime::new().unwrap().block_on(run_soak(soak))
+}
+
+async fn run_soak(soak: Soak) -> Result<(), Box<dyn error::Error>> {
+    let mut exporter = soak.transport.exporter()?.build_span_exporter()?;
+    let resource = Resource::new(soak.rtags.iter().map(|x| x.clone().into()));
+    let ids = RandomIdGenerator::default();
+
+    // skip missed ticks so a slow receiver shows up as rps drift
+    let mut ticker = tokio::time::interval(rate_period(soak.rps));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut health = Health::default();
+    let start = Instant::now();
+    let mut window_start = start;
+    let max_gap = soak
+        .chaos_max_gap
+        .unwrap_or_else(|| rate_period(soak.rps / 2.));
+    let mut rng = rand::thread_rng();
+    // long runs often get cut short, keep what was measured so far
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut interrupted = false;
+    while start.elapsed() < soak.duration {
+        let round = async {
+            let batch = if soak.chaos {
+                tokio::time::sleep(max_gap.mul_f64(rng.gen())).await;
+                chaos_batch(&soak, &ids, &resource, &mut rng)
+            } else {
+                ticker.tick().await;
+                (0..soak.batch)
+                    .map(|_| make_span(&ids, &soak.name, &resource, vec![]))
+                    .collect()
+            };
+            exporter.export(batch).await
+        };
+        let result = tokio::select! {
+            result = round => result,
+            _ = &mut ctrl_c => {
+                interrupted = true;
+                break;
+            }
+        };
+        match result {
+            Ok(()) => health.sent += 1,
+            Err(e) => {
+                health.errors += 1;
+                health.last_error = Some(e.to_string());
+            }
+        }
+        health.window_sent += 1;
+        if window_start.elapsed() >= soak.report_every {
+            report_health(&soak, &mut health, start, window_start, &mut exporter, &ids, &resource).await;
+            window_start = Instant::now();
+        }
+    }
+    if interrupted {
+        println!("interrupted after {}s, health so far:", start.elapsed().as_secs());
+    }
+    if health.window_sent > 0 || interrupted {
+        report_health(&soak, &mut health, start, window_start, &mut exporter, &ids, &resource).await;
+    }
+    exporter.shutdown();
+    Ok(())
+}
+
+async fn report_health(
+    soak: &Soak,
+    health: &mut Health,
+    start: Instant,
+    window_start: Instant,
+    exporter: &mut opentelemetry_otlp::SpanExporter,
+    ids: &RandomIdGenerator,
+    resource: &Resource,
+) {
+    let rps = health.window_sent as f64 / window_start.elapsed().as_secs_f64();
+    let drift = (rps - soak.rps) * 100. / soak.rps;
+    let rss = rss_bytes();
+    println!(
+        "elapsed={}s sent={} errors={} rps={:.1} drift={:+.2}% rss={}{}",
+        start.elapsed().as_secs(),
+        health.sent,
+        health.errors,
+        rps,
+        drift,
+        rss.map(|b| format!("{:.1}MiB", b as f64 / 1048576.)).unwrap_or_else(|| "n/a".into()),
+        health.last_error.take().map(|e| format!(" last_error={:?}", e)).unwrap_or_default(),
+    );
+    health.window_sent = 0;
+
+    if let Some(path) = &soak.openmetrics {
+        if let Err(e) = write_openmetrics(path, health, start, rps, soak.rps, rss) {
+            eprintln!("write openmetrics failed: {}", e);
+        }
+    }
+
+    if soak.export_health {
+        let mut attrs = vec![
+            Key::new("otk.soak.sent").i64(health.sent as i64),
+            Key::new("otk.soak.errors").i64(health.errors as i64),
+            Key::new("otk.soak.rps").f64(rps),
+            Key::new("otk.soak.drift_pct").f64(drift),
+        ];
+        if let Some(rss) = rss {
+            attrs.push(Key::new("otk.soak.rss_bytes").i64(rss as i64));
+        }
+        let span = make_span(ids, "otk.soak.health", resource, attrs);
+        if let Err(e) = exporter.export(vec![span]).await {
+            eprintln!("export health failed: {}", e);
+        }
+    }
+}
+
+/// a batch of random size whose spans either start a new trace or continue
+/// the previous span's trace as its child, each padded with a random sized
+/// attribute
+fn chaos_batch(soak: &Soak, ids: &RandomIdGenerator, resource: &Resource, rng: &mut impl Rng) -> Vec<SpanData> {
+    let mut spans: Vec<SpanData> = vec![];
+    for _ in 0..rng.gen_range(1..=soak.chaos_max_batch.max(1)) {
+        let pad = "x".repeat(rng.gen_range(0..=soak.chaos_max_attr_bytes));
+        let mut span = make_span(ids, &soak.name, resource, vec![Key::new("otk.chaos.pad").string(pad)]);
+        if let Some(prev) = spans.last().filter(|_| rng.gen_bool(0.5)) {
+            span.parent_span_id = prev.span_context.span_id();
+            span.span_context = SpanContext::new(
+                prev.span_context.trace_id(),
+                span.span_context.span_id(),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            );
+        }
+        spans.push(span);
+    }
+    spans
+}
+
+fn write_openmetrics(
+    path: &str,
+    health: &Health,
+    start: Instant,
+    rps: f64,
+    target_rps: f64,
+    rss: Option<u64>,
+) -> std::io::Result<()> {
+    let mut om = OpenMetrics::default();
+    om.family("otk_soak_requests", "counter", None, "successful export requests");
+    om.sample("otk_soak_requests_total", &[], health.sent as f64);
+    om.family("otk_soak_errors", "counter", None, "failed export requests");
+    om.sample("otk_soak_errors_total", &[], health.errors as f64);
+    om.family("otk_soak_elapsed_seconds", "gauge", Some("seconds"), "time since the soak started");
+    om.sample("otk_soak_elapsed_seconds", &[], start.elapsed().as_secs_f64());
+    om.family("otk_soak_rps", "gauge", None, "export requests per second over the last health window");
+    om.sample("otk_soak_rps", &[], rps);
+    om.family("otk_soak_target_rps", "gauge", None, "target export requests per second");
+    om.sample("otk_soak_target_rps", &[], target_rps);
+    if let Some(rss) = rss {
+        om.family("otk_soak_rss_bytes", "gauge", Some("bytes"), "resident set size of otk");
+        om.sample("otk_soak_rss_bytes", &[], rss as f64);
+    }
+    om.write_to(path)
+}
+
+pub fn make_span(
+    ids: &RandomIdGenerator,
+    name: &str,
+    resource: &Resource,
+    attributes: Vec<opentelemetry::KeyValue>,
+) -> SpanData {
+    let now = SystemTime::now();
+    SpanData {
+        span_context: SpanContext::new(
+            ids.new_trace_id(),
+            ids.new_span_id(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        ),
+        parent_span_id: SpanId::INVALID,
+        span_kind: SpanKind::Internal,
+        name: Cow::Owned(name.to_string()),
+        start_time: now,
+        end_time: now,
+        attributes,
+        dropped_attributes_count: 0,
+        events: EvictedQueue::new(0),
+        links: EvictedQueue::new(0),
+        status: Status::Ok,
+        resource: Cow::Owned(resource.clone()),
+        instrumentation_lib: InstrumentationLibrary::new(
+            INSTRUMENTATION_LIB_NAME,
+            None::<&'static str>,
+            None::<&'static str>,
+            None,
+        ),
+    }
+}
+
+/// resident set size of this process, where the platform exposes it
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
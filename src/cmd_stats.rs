@@ -0,0 +1,515 @@
+use crate::common::any_value_to_string;
+use crate::filter;
+use crate::openmetrics::OpenMetrics;
+use crate::otk_error::OTKError;
+use crate::proto;
+use crate::proto::trace::v1::status::StatusCode;
+use clap::Parser;
+use prost::Message;
+use std::collections::{BTreeMap, HashSet};
+use std::error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// summarize trace captures (input is base64 encoded binary)
+#[derive(Parser, Debug)]
+pub struct Stats {
+    /// file to read (- for stdin)
+    #[clap(required_unless_present = "compare")]
+    input: Option<String>,
+
+    /// compare two captures side by side (before and after)
+    #[clap(long, num_args = 2, value_names = &["BEFORE", "AFTER"], conflicts_with = "input")]
+    compare: Vec<String>,
+
+    /// also write the summary in OpenMetrics text format to this file
+    #[clap(long)]
+    openmetrics: Option<String>,
+
+    /// print the distribution of the values of this span (or resource)
+    /// attribute, e.g. http.status_code. can be repeated
+    #[clap(long)]
+    attr_dist: Vec<String>,
+}
+
+/// rows of a distribution before the rest is bucketed (numbers) or summed up
+/// (other values)
+const DIST_ROWS: usize = 20;
+const DIST_BUCKETS: usize = 10;
+const DIST_BAR: usize = 40;
+const UNSET: &str = "(unset)";
+
+/// volume, errors, latency and attribute cardinality of a capture
+#[derive(Debug, Default)]
+pub struct Summary {
+    requests: u64,
+    spans: u64,
+    errors: u64,
+    durations: Vec<u64>,
+    cardinality: BTreeMap<String, HashSet<String>>,
+    /// spans per value of the --attr-dist keys
+    dists: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl Summary {
+    pub fn read(input: &str, dist_keys: &[String]) -> Result<Self, Box<dyn error::Error>> {
+        let mut summary = Summary {
+            dists: dist_keys.iter().map(|k| (k.clone(), BTreeMap::new())).collect(),
+            ..Default::default()
+        };
+        if input == "-" {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines() {
+                summary.add_b64(&line?)?;
+            }
+        } else {
+            let file = File::open(input)?;
+            let reader = BufReader::new(file);
+            for line in reader.lines() {
+                summary.add_b64(&line?)?;
+            }
+        }
+        summary.durations.sort_unstable();
+        Ok(summary)
+    }
+
+    fn add_b64(&mut self, payload: &str) -> Result<(), Box<dyn error::Error>> {
+        let bs = base64::decode_config(payload, base64::STANDARD)?;
+        let req = proto::collector::trace::v1::ExportTraceServiceRequest::decode(&bs as &[u8])?;
+        self.requests += 1;
+        for span in filter::spans(&req) {
+            self.spans += 1;
+            if span.span.status.as_ref().is_some_and(|s| s.code == StatusCode::Error as i32) {
+                self.errors += 1;
+            }
+            self.durations
+                .push(span.span.end_time_unix_nano.saturating_sub(span.span.start_time_unix_nano));
+            let resource_attrs = span.resource.map(|r| r.attributes.as_slice()).unwrap_or_default();
+            for kv in span.span.attributes.iter().chain(resource_attrs) {
+                let value = kv.value.as_ref().map(any_value_to_string).unwrap_or_default();
+                self.cardinality.entry(kv.key.clone()).or_default().insert(value);
+            }
+            for (key, counts) in &mut self.dists {
+                // the span's own attribute wins over the resource's
+                let value = span.span.attributes.iter().chain(resource_attrs).find(|kv| kv.key == *key);
+                let value = value.and_then(|kv| kv.value.as_ref()).map_or(UNSET.to_string(), any_value_to_string);
+                *counts.entry(value).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn error_rate(&self) -> Option<f64> {
+        if self.spans == 0 {
+            return None;
+        }
+        Some(self.errors as f64 * 100. / self.spans as f64)
+    }
+
+    /// nearest-rank percentile of span durations in milliseconds
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.durations.is_empty() {
+            return None;
+        }
+        let rank = ((p / 100. * self.durations.len() as f64).ceil() as usize).max(1);
+        Some(self.durations[rank - 1] as f64 / 1e6)
+    }
+
+    /// labelled rows of the summary, values are `None` when undefined
+    pub fn rows(&self) -> Vec<(String, Unit, Option<f64>)> {
+        let mut rows = vec![
+            ("requests".to_string(), Unit::Count, Some(self.requests as f64)),
+            ("spans".to_string(), Unit::Count, Some(self.spans as f64)),
+            ("errors".to_string(), Unit::Count, Some(self.errors as f64)),
+            ("error rate".to_string(), Unit::Percent, self.error_rate()),
+            ("p50".to_string(), Unit::Millis, self.percentile(50.)),
+            ("p95".to_string(), Unit::Millis, self.percentile(95.)),
+            ("p99".to_string(), Unit::Millis, self.percentile(99.)),
+        ];
+        for (key, values) in &self.cardinality {
+            rows.push((format!("cardinality {}", key), Unit::Count, Some(values.len() as f64)));
+        }
+        rows
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Count,
+    Percent,
+    Millis,
+}
+
+fn format_value(unit: Unit, value: Option<f64>) -> String {
+    match (unit, value) {
+        (_, None) => "-".to_string(),
+        (Unit::Count, Some(v)) => format!("{}", v),
+        (Unit::Percent, Some(v)) => format!("{:.2}%", v),
+        (Unit::Millis, Some(v)) => format!("{:.3}ms", v),
+    }
+}
+
+fn format_delta(unit: Unit, before: Option<f64>, after: Option<f64>) -> String {
+    let (before, after) = match (before, after) {
+        (Some(b), Some(a)) => (b, a),
+        _ => return "-".to_string(),
+    };
+    let delta = after - before;
+    match unit {
+        Unit::Count => format!("{:+}", delta),
+        Unit::Percent => format!("{:+.2}pp", delta),
+        Unit::Millis => format!("{:+.3}ms", delta),
+    }
+}
+
+pub fn do_stats(stats: Stats) -> Result<(), Box<dyn error::Error>> {
+    let summaries = if let [before, after] = stats.compare.as_slice() {
+        let summaries = vec![
+            (before.as_str(), Summary::read(before, &stats.attr_dist)?),
+            (after.as_str(), Summary::read(after, &stats.attr_dist)?),
+        ];
+        print_compare(&summaries[0].1, &summaries[1].1);
+        summaries
+    } else if let Some(input) = &stats.input {
+        let summary = Summary::read(input, &stats.attr_dist)?;
+        for (label, unit, value) in summary.rows() {
+            println!("{:<40} {:>14}", label, format_value(unit, value));
+        }
+        vec![(input.as_str(), summary)]
+    } else {
+        return Err(Box::new(OTKError::InvalidArgumentError("no input".into())));
+    };
+    for key in &stats.attr_dist {
+        println!();
+        print_dist(key, &summaries.iter().map(|(_, s)| &s.dists[key]).collect::<Vec<_>>());
+    }
+    if let Some(path) = &stats.openmetrics {
+        write_openmetrics(&summaries, path)?;
+    }
+    Ok(())
+}
+
+/// spans per value of an attribute, with a bar of the share for one capture
+/// and before, after and delta columns for two
+fn print_dist(key: &str, dists: &[&BTreeMap<String, u64>]) {
+    let rows = dist_rows(dists);
+    match dists {
+        [dist] => {
+            let total = dist.values().sum::<u64>().max(1) as f64;
+            println!("{:<40} {:>14} {:>8}", key, "spans", "share");
+            for (label, counts) in rows {
+                let share = counts[0] as f64 / total;
+                let bar = "#".repeat((share * DIST_BAR as f64).round() as usize);
+                println!("{:<40} {:>14} {:>7.2}% {}", label, counts[0], share * 100., bar);
+            }
+        }
+        _ => {
+            println!("{:<40} {:>14} {:>14} {:>14}", key, "before", "after", "delta");
+            for (label, counts) in rows {
+                let delta = counts[1] as f64 - counts[0] as f64;
+                println!("{:<40} {:>14} {:>14} {:>14}", label, counts[0], counts[1], format!("{:+}", delta));
+            }
+        }
+    }
+}
+
+/// the values and their counts in every capture. numbers are listed in
+/// order, or in equal-width buckets when there are too many distinct ones;
+/// other values by count, the least frequent summed up
+fn dist_rows(dists: &[&BTreeMap<String, u64>]) -> Vec<(String, Vec<u64>)> {
+    let mut values: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for (i, dist) in dists.iter().enumerate() {
+        for (value, count) in dist.iter() {
+            values.entry(value).or_insert_with(|| vec![0; dists.len()])[i] = *count;
+        }
+    }
+    let unset = values.remove(UNSET);
+    let numbers = values.keys().map(|v| v.parse::<f64>().ok()).collect::<Option<Vec<_>>>();
+    let mut rows = match numbers {
+        Some(numbers) if !numbers.is_empty() => {
+            let mut numbered = numbers.into_iter().zip(values.into_values()).collect::<Vec<_>>();
+            numbered.sort_by(|a, b| a.0.total_cmp(&b.0));
+            if numbered.len() <= DIST_ROWS {
+                numbered.into_iter().map(|(n, counts)| (n.to_string(), counts)).collect()
+            } else {
+                buckets(&numbered, dists.len())
+            }
+        }
+        _ => {
+            let mut counted = values.into_iter().collect::<Vec<_>>();
+            counted.sort_by(|a, b| b.1.iter().sum::<u64>().cmp(&a.1.iter().sum()).then(a.0.cmp(b.0)));
+            let mut rows = counted
+                .iter()
+                .take(DIST_ROWS)
+                .map(|(v, counts)| (v.to_string(), counts.clone()))
+                .collect::<Vec<_>>();
+            if counted.len() > DIST_ROWS {
+                let mut other = vec![0; dists.len()];
+                for (_, counts) in &counted[DIST_ROWS..] {
+                    other.iter_mut().zip(counts).for_each(|(o, c)| *o += c);
+                }
+                rows.push((format!("({} other values)", counted.len() - DIST_ROWS), other));
+            }
+            rows
+        }
+    };
+    rows.extend(unset.map(|counts| (UNSET.to_string(), counts)));
+    rows
+}
+
+/// sorted numbers and their counts summed into equal-width [lo, hi) buckets,
+/// the last one closed
+fn buckets(numbered: &[(f64, Vec<u64>)], captures: usize) -> Vec<(String, Vec<u64>)> {
+    let (min, max) = (numbered[0].0, numbered[numbered.len() - 1].0);
+    let width = (max - min) / DIST_BUCKETS as f64;
+    let bound = |i: usize| {
+        let bound = format!("{:.3}", min + width * i as f64);
+        bound.trim_end_matches('0').trim_end_matches('.').to_string()
+    };
+    let mut rows = (0..DIST_BUCKETS)
+        .map(|i| {
+            let close = if i == DIST_BUCKETS - 1 { "]" } else { ")" };
+            (format!("[{}, {}{}", bound(i), bound(i + 1), close), vec![0; captures])
+        })
+        .collect::<Vec<_>>();
+    for (n, counts) in numbered {
+        let i = (((n - min) / width) as usize).min(DIST_BUCKETS - 1);
+        rows[i].1.iter_mut().zip(counts).for_each(|(r, c)| *r += c);
+    }
+    rows
+}
+
+/// one sample per capture for every family, labelled with the capture path
+fn write_openmetrics(summaries: &[(&str, Summary)], path: &str) -> std::io::Result<()> {
+    let mut om = OpenMetrics::default();
+    let requests = |s: &Summary| s.requests;
+    let counts = [
+        ("otk_stats_requests", "export requests in the capture", requests as fn(&Summary) -> u64),
+        ("otk_stats_spans", "spans in the capture", |s| s.spans),
+        ("otk_stats_error_spans", "spans with error status", |s| s.errors),
+    ];
+    for (name, help, get) in counts {
+        om.family(name, "gauge", None, help);
+        for (capture, summary) in summaries {
+            om.sample(name, &[("capture", capture)], get(summary) as f64);
+        }
+    }
+    om.family("otk_stats_error_ratio", "gauge", Some("ratio"), "share of spans with error status");
+    for (capture, summary) in summaries {
+        if let Some(rate) = summary.error_rate() {
+            om.sample("otk_stats_error_ratio", &[("capture", capture)], rate / 100.);
+        }
+    }
+    let name = "otk_stats_span_duration_seconds";
+    om.family(name, "gauge", Some("seconds"), "span duration percentiles");
+    for (capture, summary) in summaries {
+        for (quantile, p) in [("0.5", 50.), ("0.95", 95.), ("0.99", 99.)] {
+            if let Some(ms) = summary.percentile(p) {
+                om.sample(name, &[("capture", capture), ("quantile", quantile)], ms / 1e3);
+            }
+        }
+    }
+    let name = "otk_stats_attribute_cardinality";
+    om.family(name, "gauge", None, "distinct values per span or resource attribute key");
+    for (capture, summary) in summaries {
+        for (key, values) in &summary.cardinality {
+            om.sample(name, &[("capture", capture), ("key", key)], values.len() as f64);
+        }
+    }
+    om.write_to(path)
+}
+
+fn print_compare(before: &Summary, after: &Summary) {
+    let mut rows: BTreeMap<String, (Unit, Option<f64>, Option<f64>)> = BTreeMap::new();
+    let mut order = vec![];
+    for (label, unit, value) in before.rows() {
+        order.push(label.clone());
+        rows.insert(label, (unit, value, None));
+    }
+    for (label, unit, value) in after.rows() {
+        let row = rows.entry(label.clone()).or_insert_with(|| {
+            order.push(label);
+            (unit, None, None)
+        });
+        row.2 = value;
+    }
+    // attribute keys only seen after the change land after the fixed rows;
+    // keep cardinality rows sorted by key
+    let (fixed, mut attrs): (Vec<_>, Vec<_>) = order.into_iter().partition(|l| !l.starts_with("cardinality "));
+    attrs.sort();
+
+    println!("{:<40} {:>14} {:>14} {:>14}", "", "before", "after", "delta");
+    for label in fixed.into_iter().chain(attrs) {
+        let (unit, b, a) = rows[&label];
+        // a key missing on one side has zero distinct values there
+        let (b, a) = if label.starts_with("cardinality ") {
+            (b.or(Some(0.)), a.or(Some(0.)))
+        } else {
+            (b, a)
+        };
+        println!(
+            "{:<40} {:>14} {:>14} {:>14}",
+            label,
+            format_value(unit, b),
+            format_value(unit, a),
+            format_delta(unit, b, a)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::proto::common::v1::any_value::Value;
+    use crate::proto::common::v1::{AnyValue, KeyValue};
+    use crate::proto::resource::v1::Resource;
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span, Status};
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(Value::StringValue(value.into())) }) }
+    }
+
+    /// a span of `ms` milliseconds, failed or not
+    fn span(ms: u64, error: bool, attributes: Vec<KeyValue>) -> Span {
+        let code = if error { StatusCode::Error } else { StatusCode::Ok };
+        Span {
+            start_time_unix_nano: 1_000_000_000,
+            end_time_unix_nano: 1_000_000_000 + ms * 1_000_000,
+            status: Some(Status { code: code as i32, ..Default::default() }),
+            attributes,
+            ..Default::default()
+        }
+    }
+
+    /// the summary of one request per service
+    fn summary(requests: Vec<(&str, Vec<Span>)>, dist_keys: &[&str]) -> Summary {
+        let mut summary = Summary {
+            dists: dist_keys.iter().map(|k| (k.to_string(), BTreeMap::new())).collect(),
+            ..Default::default()
+        };
+        for (service, spans) in requests {
+            let req = ExportTraceServiceRequest {
+                resource_spans: vec![ResourceSpans {
+                    resource: Some(Resource { attributes: vec![kv("service.name", service)], ..Default::default() }),
+                    scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+                    ..Default::default()
+                }],
+            };
+            summary.add_b64(&base64::encode(req.encode_to_vec())).unwrap();
+        }
+        summary.durations.sort_unstable();
+        summary
+    }
+
+    #[test]
+    fn summarizes() {
+        let spans = (1..=100).map(|ms| span(ms, ms % 10 == 0, vec![kv("http.route", &format!("/{}", ms % 3))]));
+        let summary = summary(vec![("checkout", spans.collect()), ("cart", vec![])], &[]);
+        let rows = summary.rows().into_iter().map(|(label, unit, value)| (label, format_value(unit, value))).collect::<Vec<_>>();
+        let expected = [
+            ("requests", "2"),
+            ("spans", "100"),
+            ("errors", "10"),
+            ("error rate", "10.00%"),
+            ("p50", "50.000ms"),
+            ("p95", "95.000ms"),
+            ("p99", "99.000ms"),
+            ("cardinality http.route", "3"),
+            // the resource of a request without spans isn't counted
+            ("cardinality service.name", "1"),
+        ];
+        assert_eq!(rows, expected.map(|(l, v)| (l.to_string(), v.to_string())));
+    }
+
+    #[test]
+    fn empty_captures() {
+        let summary = summary(vec![], &[]);
+        assert_eq!((summary.error_rate(), summary.percentile(50.)), (None, None));
+        assert_eq!(format_value(Unit::Millis, summary.percentile(50.)), "-");
+    }
+
+    #[test]
+    fn deltas() {
+        assert_eq!(format_delta(Unit::Count, Some(3.), Some(1.)), "-2");
+        assert_eq!(format_delta(Unit::Percent, Some(1.), Some(2.5)), "+1.50pp");
+        assert_eq!(format_delta(Unit::Millis, Some(1.), Some(1.25)), "+0.250ms");
+        assert_eq!(format_delta(Unit::Millis, None, Some(1.)), "-");
+    }
+
+
+    #[test]
+    fn writes_openmetrics() {
+        let path = std::env::temp_dir().join(format!("otk-stats-{}.om", std::process::id()));
+        let before = summary(vec![("checkout", vec![span(10, true, vec![])])], &[]);
+        let after = summary(vec![], &[]);
+        write_openmetrics(&[("before.b64", before), ("after.b64", after)], path.to_str().unwrap()).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let samples = written.lines().filter(|l| !l.starts_with('#')).collect::<Vec<_>>();
+        // captures without spans have no error ratio or percentiles
+        assert_eq!(
+            samples,
+            [
+                "otk_stats_requests{capture=\"before.b64\"} 1",
+                "otk_stats_requests{capture=\"after.b64\"} 0",
+                "otk_stats_spans{capture=\"before.b64\"} 1",
+                "otk_stats_spans{capture=\"after.b64\"} 0",
+                "otk_stats_error_spans{capture=\"before.b64\"} 1",
+                "otk_stats_error_spans{capture=\"after.b64\"} 0",
+                "otk_stats_error_ratio{capture=\"before.b64\"} 1",
+                "otk_stats_span_duration_seconds{capture=\"before.b64\",quantile=\"0.5\"} 0.01",
+                "otk_stats_span_duration_seconds{capture=\"before.b64\",quantile=\"0.95\"} 0.01",
+                "otk_stats_span_duration_seconds{capture=\"before.b64\",quantile=\"0.99\"} 0.01",
+                "otk_stats_attribute_cardinality{capture=\"before.b64\",key=\"service.name\"} 1",
+            ]
+        );
+        assert!(written.ends_with("# EOF\n"));
+    }
+
+
+    fn dist(counts: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        counts.iter().map(|(v, n)| (v.to_string(), *n)).collect()
+    }
+
+    #[test]
+    fn counts_attribute_values() {
+        let spans = vec![
+            span(1, false, vec![kv("http.route", "/cart")]),
+            span(1, false, vec![kv("http.route", "/cart")]),
+            span(1, false, vec![]),
+        ];
+        let summary = summary(vec![("checkout", spans)], &["http.route", "service.name"]);
+        assert_eq!(summary.dists["http.route"], dist(&[("/cart", 2), (UNSET, 1)]));
+        // resource attributes count too
+        assert_eq!(summary.dists["service.name"], dist(&[("checkout", 3)]));
+    }
+
+    #[test]
+    fn distribution_rows() {
+        let row = |label: &str, counts: &[u64]| (label.to_string(), counts.to_vec());
+        // numbers in order, unset last
+        let before = dist(&[("500", 1), ("200", 5), ("404", 2), (UNSET, 3)]);
+        let after = dist(&[("200", 6), ("503", 1)]);
+        assert_eq!(
+            dist_rows(&[&before, &after]),
+            [row("200", &[5, 6]), row("404", &[2, 0]), row("500", &[1, 0]), row("503", &[0, 1]), row(UNSET, &[3, 0])]
+        );
+
+        // other values by count, the rare ones summed up
+        let values = (0..25).map(|i| (format!("v{:02}", i), 30 - i as u64)).collect::<Vec<_>>();
+        let values = values.iter().map(|(v, n)| (v.as_str(), *n)).collect::<Vec<_>>();
+        let rows = dist_rows(&[&dist(&values)]);
+        assert_eq!(rows.len(), DIST_ROWS + 1);
+        assert_eq!(rows[0], row("v00", &[30]));
+        assert_eq!(rows[DIST_ROWS], row("(5 other values)", &[10 + 9 + 8 + 7 + 6]));
+
+        // too many distinct numbers are bucketed
+        let values = (0..=100).map(|i| (i.to_string(), 1)).collect::<Vec<_>>();
+        let values = values.iter().map(|(v, n)| (v.as_str(), *n)).collect::<Vec<_>>();
+        let rows = dist_rows(&[&dist(&values)]);
+        assert_eq!(rows.len(), DIST_BUCKETS);
+        assert_eq!(rows[0], row("[0, 10)", &[10]));
+        assert_eq!(rows[9], row("[90, 100]", &[11]));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cmd_search::Signal;
+use crate::common::any_value_to_string;
+use crate::filter::{self, Attributes};
+use crate::proto;
+use crate::proto::common::v1::AnyValue;
+use clap::Parser;
+use prost::Message;
+use std::collections::HashMap;
+use std::error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// list distinct values of an attribute key with counts (input is base64
+/// encoded binary)
+#[derive(Parser, Debug)]
+pub struct Values {
+    /// file to read (- for stdin)
+    input: String,
+
+    /// attribute key, looked up on the item, then its scope, then its resource
+    #[clap(short, long)]
+    key: String,
+
+    /// signal of the captured requests (traces, logs or metrics)
+    #[clap(long, default_value = "traces")]
+    signal: Signal,
+
+    /// only print the most frequent values
+    #[clap(long)]
+    top: Option<usize>,
+}
+
+pub fn do_values(values: Values) -> Result<(), Box<dyn error::Error>> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    if values.input == "-" {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            count(&line?, &values, &mut counts)?;
+        }
+    } else {
+        let file = File::open(&values.input)?;
+        let reader = BufReader::new(file);
+        for line in reader.lines() {
+            count(&line?, &values, &mut counts)?;
+        }
+    }
+
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let distinct = counts.len();
+    for (value, n) in counts.into_iter().take(values.top.unwrap_or(usize::MAX)) {
+        println!("{:>10}  {}", n, value);
+    }
+    println!("{} distinct values of {}", distinct, values.key);
+    Ok(())
+}
+
+fn count(payload: &str, values: &Values, counts: &mut HashMap<String, u64>) -> Result<(), Box<dyn error::Error>> {
+    let bs = base64::decode_config(payload, base64::STANDARD)?;
+    let key = values.key.as_str();
+    match values.signal {
+        Signal::Traces => {
+            let req = proto::collector::trace::v1::ExportTraceServiceRequest::decode(&bs as &[u8])?;
+            tally(counts, filter::spans(&req).filter_map(|span| span.attr(key)));
+        }
+        Signal::Logs => {
+            let req = proto::collector::logs::v1::ExportLogsServiceRequest::decode(&bs as &[u8])?;
+            tally(counts, filter::logs(&req).filter_map(|log| log.attr(key)));
+        }
+        Signal::Metrics => {
+            let req = proto::collector::metrics::v1::ExportMetricsServiceRequest::decode(&bs as &[u8])?;
+            tally(counts, filter::points(&req).filter_map(|point| point.attr(key)));
+        }
+    }
+    Ok(())
+}
+
+fn tally<'a>(counts: &mut HashMap<String, u64>, found: impl Iterator<Item = &'a AnyValue>) {
+    for value in found {
+        *counts.entry(any_value_to_string(value)).or_insert(0) += 1;
+    }
+}
@@ -0,0 +1,239 @@
+use crate::cmd_search::Signal;
+use crate::cmd_soak::make_span;
+use crate::common::{parse_duration, KeyValue, INSTRUMENTATION_LIB_NAME};
+use crate::hook::{self, Hook};
+use crate::otk_error::OTKError;
+use crate::transport::{MetricsExporter, Protocol, TransportOpts};
+use clap::Parser;
+use opentelemetry::logs::{AnyValue, LogRecord};
+use opentelemetry::metrics::Unit;
+use opentelemetry::{InstrumentationLibrary, Key};
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use opentelemetry_sdk::export::trace::SpanExporter;
+use opentelemetry_sdk::metrics::data::{DataPoint, Gauge, Metric, ResourceMetrics, ScopeMetrics};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{DefaultAggregationSelector, DefaultTemporalitySelector};
+use opentelemetry_sdk::trace::RandomIdGenerator;
+use opentelemetry_sdk::{AttributeSet, Resource};
+use rand::Rng;
+use serde_json::json;
+use std::borrow::Cow;
+use std::error;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Runtime;
+use tokio::time::MissedTickBehavior;
+
+/// periodically send a canary span, log record and metric with unique markers
+/// and alert when sending keeps failing, as a simple pipeline liveness probe
+#[derive(Parser, Debug)]
+pub struct Watchdog {
+    #[clap(flatten)]
+    transport: TransportOpts,
+
+    /// tag used in resource
+    #[clap(short, long, num_args = 0..)]
+    rtags: Vec<KeyValue>,
+
+    /// time between canaries
+    #[clap(long, default_value = "60s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// comma separated signals to send canaries of (traces, logs, metrics)
+    #[clap(long, value_delimiter = ',', default_value = "traces,logs,metrics")]
+    signals: Vec<Signal>,
+
+    /// consecutive failed rounds (any canary not sent) before alerting
+    #[clap(long, default_value = "3")]
+    failures: u32,
+
+    /// hooks fired on alert and again on recovery (`exec:<command>` or
+    /// `webhook:<url>`, see search --on-match), can be repeated
+    #[clap(long, num_args = 0..)]
+    on_error: Vec<Hook>,
+
+    /// exit with a failure status on alert instead of waiting for recovery
+    #[clap(long)]
+    exit_on_alert: bool,
+
+    /// stop after this many rounds, 0 runs until interrupted
+    #[clap(long, default_value = "0")]
+    rounds: u64,
+}
+
+/// exporters of the canaries, and the run id marking every canary of this
+/// watchdog so they can be found in the backend
+struct Canary {
+    run: String,
+    resource: Resource,
+    spans: Option<Box<dyn SpanExporter>>,
+    logs: Option<opentelemetry_otlp::LogExporter>,
+    metrics: Option<MetricsExporter>,
+}
+
+pub fn do_watchdog(mut watchdog: Watchdog) -> Result<(), Box<dyn error::Error>> {
+    if watchdog.failures == 0 {
+        return Err(Box::new(OTKError::InvalidArgumentError("--failures should be positive".into())));
+    }
+    if watchdog.interval.is_zero() {
+        return Err(Box::new(OTKError::InvalidArgumentError("--interval should be positive".into())));
+    }
+    watchdog.transport = watchdog.transport.preset()?;
+    Runtime::new().unwrap().block_on(run_watchdog(watchdog))
+}
+
+async fn run_watchdog(watchdog: Watchdog) -> Result<(), Box<dyn error::Error>> {
+    let mut canary = Canary::new(&watchdog)?;
+    println!("run={} endpoint={}", canary.run, watchdog.transport.endpoint());
+    let mut ticker = tokio::time::interval(watchdog.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut failed = 0;
+    let mut alerted = false;
+    let mut seq = 0;
+    while watchdog.rounds == 0 || seq < watchdog.rounds {
+        ticker.tick().await;
+        seq += 1;
+        let results = canary.send(seq).await;
+        let status = results
+            .iter()
+            .map(|(signal, r)| format!("{}={}", signal, if r.is_ok() { "ok" } else { "failed" }))
+            .collect::<Vec<_>>();
+        let errors = results
+            .iter()
+            .filter_map(|(signal, r)| r.as_ref().err().map(|e| format!("{}: {}", signal, e)))
+            .collect::<Vec<_>>();
+        failed = if errors.is_empty() { 0 } else { failed + 1 };
+        println!(
+            "seq={} {} failures={}{}",
+            seq,
+            status.join(" "),
+            failed,
+            if errors.is_empty() { String::new() } else { format!(" error={:?}", errors.join("; ")) },
+        );
+        if failed == 0 && alerted {
+            alerted = false;
+            eprintln!("recovered: canary {} sent", seq);
+            notify(&watchdog, &canary, json!({"event": "recovered", "seq": seq}));
+        } else if failed >= watchdog.failures && !alerted {
+            alerted = true;
+            let message = format!("{} consecutive rounds failed, last: {}", failed, errors.join("; "));
+            eprintln!("alert: {}", message);
+            notify(&watchdog, &canary, json!({"event": "alert", "seq": seq, "failures": failed, "error": message}));
+            if watchdog.exit_on_alert {
+                canary.shutdown();
+                return Err(Box::new(OTKError::CheckError(message)));
+            }
+        }
+    }
+    canary.shutdown();
+    Ok(())
+}
+
+/// fire the --on-error hooks with an event of this watchdog
+fn notify(watchdog: &Watchdog, canary: &Canary, mut event: serde_json::Value) {
+    event["run"] = json!(canary.run);
+    event["endpoint"] = json!(watchdog.transport.endpoint());
+    hook::fire_all(&watchdog.on_error, &event);
+}
+
+impl Canary {
+    fn new(watchdog: &Watchdog) -> Result<Canary, Box<dyn error::Error>> {
+        let transport = &watchdog.transport;
+        let wants = |signal| watchdog.signals.contains(&signal);
+        let spans: Option<Box<dyn SpanExporter>> = match wants(Signal::Traces) {
+            false => None,
+            true if transport.protocol == Protocol::HttpJson => Some(Box::new(transport.json_span_exporter()?)),
+            true => Some(Box::new(transport.exporter()?.build_span_exporter()?)),
+        };
+        let logs = match wants(Signal::Logs) {
+            true => Some(transport.exporter()?.build_log_exporter()?),
+            false => None,
+        };
+        let metrics = match wants(Signal::Metrics) {
+            true => Some(transport.exporter()?.build_metrics_exporter(
+                Box::new(DefaultTemporalitySelector::new()),
+                Box::new(DefaultAggregationSelector::new()),
+            )?),
+            false => None,
+        };
+        Ok(Canary {
+            run: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+            resource: Resource::new(watchdog.rtags.iter().map(|x| x.clone().into())),
+            spans,
+            logs,
+            metrics,
+        })
+    }
+
+    /// send canary `seq` of every signal, each marked with the run id and
+    /// `seq` ("otk.watchdog.run" and "otk.watchdog.seq" attributes)
+    async fn send(&mut self, seq: u64) -> Vec<(Signal, Result<(), String>)> {
+        let markers = vec![
+            Key::new("otk.watchdog.run").string(self.run.clone()),
+            Key::new("otk.watchdog.seq").i64(seq as i64),
+        ];
+        let mut results = vec![];
+        if let Some(exporter) = &mut self.spans {
+            let span = make_span(&RandomIdGenerator::default(), "otk.watchdog.canary", &self.resource, markers.clone());
+            results.push((Signal::Traces, exporter.export(vec![span]).await.map_err(|e| e.to_string())));
+        }
+        if let Some(exporter) = &mut self.logs {
+            let now = SystemTime::now();
+            let mut record = LogRecord::builder()
+                .with_timestamp(now)
+                .with_observed_timestamp(now)
+                .with_severity_text("INFO")
+                .with_body(AnyValue::String(format!("otk watchdog canary {} {}", self.run, seq).into()));
+            for kv in &markers {
+                record = record.with_attribute(kv.key.clone(), kv.value.clone());
+            }
+            let log = LogData {
+                record: record.build(),
+                resource: Cow::Owned(self.resource.clone()),
+                instrumentation: library(),
+            };
+            results.push((Signal::Logs, exporter.export(vec![log]).await.map_err(|e| e.to_string())));
+        }
+        if let Some(exporter) = &self.metrics {
+            // the run id only, a seq attribute would start a new series every round
+            let now = SystemTime::now();
+            let mut metrics = ResourceMetrics {
+                resource: self.resource.clone(),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: library(),
+                    metrics: vec![Metric {
+                        name: "otk.watchdog.canary".into(),
+                        description: "sequence number of the latest watchdog canary".into(),
+                        unit: Unit::new(""),
+                        data: Box::new(Gauge {
+                            data_points: vec![DataPoint {
+                                attributes: AttributeSet::from(&markers[..1]),
+                                start_time: Some(now),
+                                time: Some(now),
+                                value: seq as i64,
+                                exemplars: vec![],
+                            }],
+                        }),
+                    }],
+                }],
+            };
+            results.push((Signal::Metrics, exporter.export(&mut metrics).await.map_err(|e| e.to_string())));
+        }
+        results
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(exporter) = &mut self.spans {
+            exporter.shutdown();
+        }
+        if let Some(exporter) = &mut self.logs {
+            exporter.shutdown();
+        }
+        if let Some(exporter) = &self.metrics {
+            let _ = exporter.shutdown();
+        }
+    }
+}
+
+fn library() -> InstrumentationLibrary {
+    InstrumentationLibrary::new(INSTRUMENTATION_LIB_NAME, None::<&str>, None::<&str>, None)
+}
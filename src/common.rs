@@ -1,37 +1,558 @@
-use opentelemetry::KeyValue as OTLP_KeyValue;
+use opentelemetry::{Array, KeyValue as OTLP_KeyValue, Value as OTLP_Value};
+use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+use opentelemetry_sdk::Resource;
+use std::error;
 use std::str::FromStr;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hex::ToHex;
+use rand::seq::SliceRandom;
+use strum_macros::EnumString;
 use crate::otk_error::OTKError;
+use crate::proto::common::v1::any_value::Value;
+use crate::proto::common::v1::{AnyValue, KeyValue as ProtoKeyValue};
 
 pub const INSTRUMENTATION_LIB_NAME: &str = "otk.kto";
 
+/// attribute of --run-id
+pub const RUN_ID_KEY: &str = "otk.run_id";
+
 #[derive(Debug, Clone)]
 pub struct KeyValue {
     pub k: String,
     pub v: String,
+    /// how `v` is sent as an attribute value
+    pub ty: ValueType,
+}
+
+/// resource of --rtags, with the schema url of --schema-url
+pub fn resource(rtags: &[KeyValue], schema_url: Option<&str>) -> Resource {
+    let attrs = rtags.iter().map(|x| x.clone().into());
+    match schema_url {
+        Some(url) => Resource::from_schema_url(attrs, url.to_string()),
+        None => Resource::new(attrs),
+    }
+}
+
+/// resolve --run-id, `auto` becoming a random id, and print it so the data
+/// of this invocation can be found in the backend
+pub fn resolve_run_id(run_id: &mut Option<String>) {
+    if let Some(id) = run_id {
+        if id == "auto" {
+            *id = format!("{:016x}", rand::random::<u64>());
+        }
+        println!("run id: {}", id);
+    }
+}
+
+/// the "otk.run_id" attribute of a resolved --run-id
+pub fn run_id_attr(run_id: &Option<String>) -> Option<KeyValue> {
+    run_id.as_ref().map(|id| KeyValue { k: RUN_ID_KEY.into(), v: id.clone(), ty: ValueType::String })
+}
+
+/// type hint of a `key:type=value` attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+pub enum ValueType {
+    #[strum(serialize = "string", serialize = "str")]
+    String,
+    #[strum(serialize = "int", serialize = "i64")]
+    Int,
+    #[strum(serialize = "bool")]
+    Bool,
+    #[strum(serialize = "double", serialize = "f64")]
+    Double,
+    /// comma separated strings
+    #[strum(serialize = "array")]
+    Array,
+}
+
+impl KeyValue {
+    /// `v` as an attribute value of type `ty`, checked when parsing
+    pub fn value(&self) -> OTLP_Value {
+        typed_value(&self.k, &self.v, self.ty).unwrap_or_else(|_| self.v.clone().into())
+    }
+}
+
+fn typed_value(k: &str, v: &str, ty: ValueType) -> Result<OTLP_Value, OTKError> {
+    let invalid = |what| OTKError::ParseError(format!("invalid {} value {} of {}", what, v, k));
+    Ok(match ty {
+        ValueType::String => v.to_string().into(),
+        ValueType::Int => v.parse::<i64>().map_err(|_| invalid("int"))?.into(),
+        ValueType::Bool => v.parse::<bool>().map_err(|_| invalid("bool"))?.into(),
+        ValueType::Double => v.parse::<f64>().map_err(|_| invalid("double"))?.into(),
+        ValueType::Array => {
+            OTLP_Value::Array(Array::String(v.split(',').map(|s| s.to_string().into()).collect()))
+        }
+    })
 }
 
+/// parse `key=value`. the value may be wrapped in single or double quotes
+/// (`k="a=b,c d"`). `\` escapes a backslash, quote, `,`, `:` or `=` in both
+/// key and value, and in quotes `\n`, `\t`, `\r` and `\0` are translated. a
+/// backslash before anything else is kept, so `path=C:\temp` and `k=^\d+$`
+/// are taken as typed. `key:type=value` gives the
+/// attribute a type (string, int, bool, double or array, which splits the
+/// value on commas), plain `key=value` is a string
 impl FromStr for KeyValue {
     type Err = OTKError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut splits = s.split('=');
-        let fst = splits
-            .next()
-            .ok_or_else(|| OTKError::ParseError(String::from("")))?;
-        let snd = splits.remainder();
-        if snd.is_none() {
-            return Err(OTKError::ParseError(String::from(
-                "invalid format (expect key=value)",
-            )));
+        let mut chars = s.chars();
+        let mut k = String::new();
+        // an escaped `\:` is part of the key
+        let mut hint_at = None;
+        loop {
+            match chars.next() {
+                Some('\\') => k.push_str(&unescape(chars.next(), None)?),
+                Some(':') => {
+                    hint_at = Some(k.len());
+                    k.push(':');
+                }
+                Some('=') => break,
+                Some(c) => k.push(c),
+                None => {
+                    return Err(OTKError::ParseError(String::from(
+                        "invalid format (expect key=value)",
+                    )))
+                }
+            }
+        }
+        let v = parse_value(chars.as_str())?;
+        let hint = hint_at.and_then(|i| Some((i, ValueType::from_str(&k[i + 1..]).ok()?)));
+        let ty = match hint {
+            Some((i, ty)) => {
+                k.truncate(i);
+                typed_value(&k, &v, ty)?;
+                ty
+            }
+            None => ValueType::String,
+        };
+        Ok(KeyValue { k, v, ty })
+    }
+}
+
+fn parse_value(s: &str) -> Result<String, OTKError> {
+    let mut chars = s.chars();
+    let quote = match s.chars().next() {
+        Some(q @ ('"' | '\'')) => {
+            chars.next();
+            Some(q)
+        }
+        _ => None,
+    };
+    let mut v = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => v.push_str(&unescape(chars.next(), quote)?),
+            Some(c) if Some(c) == quote => {
+                if !chars.as_str().is_empty() {
+                    return Err(OTKError::ParseError(format!(
+                        "unexpected characters after closing quote: {}",
+                        chars.as_str()
+                    )));
+                }
+                return Ok(v);
+            }
+            Some(c) => v.push(c),
+            None if quote.is_some() => {
+                return Err(OTKError::ParseError(String::from("unterminated quote")))
+            }
+            None => return Ok(v),
         }
-        Ok(KeyValue {
-            k: String::from(fst),
-            v: String::from(snd.unwrap()),
-        })
     }
 }
 
+/// split on `sep` outside of quotes, keeping quotes and escapes in the parts
+/// so they can be handed to `KeyValue::from_str`
+pub fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut quote = None;
+    let mut escaped = false;
+    let mut begin = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ if Some(c) == quote => quote = None,
+            _ if c == sep && quote.is_none() => {
+                parts.push(&s[begin..i]);
+                begin = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[begin..]);
+    parts
+}
+
+/// the text of the escape sequence `\c`, in the value quoted by `quote`
+fn unescape(c: Option<char>, quote: Option<char>) -> Result<String, OTKError> {
+    let translated = match (c, quote) {
+        (Some('n'), Some(_)) => '\n',
+        (Some('t'), Some(_)) => '\t',
+        (Some('r'), Some(_)) => '\r',
+        (Some('0'), Some(_)) => '\0',
+        (Some(c @ ('\\' | '"' | '\'' | ',' | ':' | '=')), _) => c,
+        (Some(c), _) => return Ok(format!("\\{}", c)),
+        (None, _) => return Err(OTKError::ParseError(String::from("dangling escape at end of input"))),
+    };
+    Ok(translated.to_string())
+}
+
 impl From<KeyValue> for OTLP_KeyValue {
     fn from(kv: KeyValue) -> Self {
-        OTLP_KeyValue::new(kv.k, kv.v)
+        let value = kv.value();
+        OTLP_KeyValue::new(kv.k, value)
+    }
+}
+
+/// render an AnyValue as plain text (for labels and matching)
+pub fn any_value_to_string(value: &AnyValue) -> String {
+    match &value.value {
+        Some(Value::StringValue(s)) => s.clone(),
+        Some(Value::BoolValue(b)) => b.to_string(),
+        Some(Value::IntValue(i)) => i.to_string(),
+        Some(Value::DoubleValue(d)) => d.to_string(),
+        Some(Value::BytesValue(bs)) => bs.encode_hex::<String>(),
+        Some(Value::ArrayValue(arr)) => format!(
+            "[{}]",
+            arr.values
+                .iter()
+                .map(any_value_to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        Some(Value::KvlistValue(kvs)) => format!(
+            "{{{}}}",
+            kvs.values
+                .iter()
+                .map(|kv| format!("{}={}", kv.key, attr_value(kv)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        None => String::new(),
+    }
+}
+
+/// an AnyValue as plain json, bytes as hex (not the otlp json encoding)
+pub fn any_value_to_json(value: &AnyValue) -> serde_json::Value {
+    match &value.value {
+        Some(Value::StringValue(s)) => s.clone().into(),
+        Some(Value::BoolValue(b)) => (*b).into(),
+        Some(Value::IntValue(i)) => (*i).into(),
+        Some(Value::DoubleValue(d)) => (*d).into(),
+        Some(Value::BytesValue(bs)) => bs.encode_hex::<String>().into(),
+        Some(Value::ArrayValue(arr)) => arr.values.iter().map(any_value_to_json).collect(),
+        Some(Value::KvlistValue(kvs)) => attributes_to_json(&kvs.values),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// attributes as a json object of plain values
+pub fn attributes_to_json(attributes: &[ProtoKeyValue]) -> serde_json::Value {
+    attributes
+        .iter()
+        .map(|kv| (kv.key.clone(), kv.value.as_ref().map_or(serde_json::Value::Null, any_value_to_json)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// value of a proto KeyValue as plain text
+pub fn attr_value(kv: &ProtoKeyValue) -> String {
+    kv.value.as_ref().map(any_value_to_string).unwrap_or_default()
+}
+
+/// parse a 32 character hex trace id, rejecting the all-zero id
+pub fn parse_trace_id(s: &str) -> Result<TraceId, OTKError> {
+    match TraceId::from_hex(s) {
+        Ok(id) if s.len() == 32 && id != TraceId::INVALID => Ok(id),
+        _ => Err(OTKError::ParseError(format!("invalid trace id {} (expect 32 hex chars)", s))),
+    }
+}
+
+/// parse a 16 character hex span id, rejecting the all-zero id
+pub fn parse_span_id(s: &str) -> Result<SpanId, OTKError> {
+    match SpanId::from_hex(s) {
+        Ok(id) if s.len() == 16 && id != SpanId::INVALID => Ok(id),
+        _ => Err(OTKError::ParseError(format!("invalid span id {} (expect 16 hex chars)", s))),
+    }
+}
+
+/// w3c trace context headers of a span as curl arguments, tracestate only
+/// when it has entries
+pub fn curl_trace_headers(context: &SpanContext) -> String {
+    let mut args = format!(
+        "-H 'traceparent: 00-{:032x}-{:016x}-{:02x}'",
+        context.trace_id(),
+        context.span_id(),
+        context.trace_flags().to_u8()
+    );
+    let state = context.trace_state().header();
+    if !state.is_empty() {
+        args.push_str(&format!(" -H 'tracestate: {}'", state));
+    }
+    args
+}
+
+/// parse a w3c `traceparent` header value (`00-<trace id>-<parent id>-<flags>`),
+/// optionally still prefixed with the header name, into the remote context it
+/// carries
+pub fn parse_traceparent(s: &str) -> Result<SpanContext, OTKError> {
+    let err = |why: &str| OTKError::ParseError(format!("invalid traceparent {} ({})", s, why));
+    let value = s.trim();
+    let value = value
+        .split_once(':')
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("traceparent"))
+        .map_or(value, |(_, v)| v.trim());
+    let parts = value.split('-').collect::<Vec<_>>();
+    let [version, trace_id, span_id, flags, rest @ ..] = parts.as_slice() else {
+        return Err(err("expect version-traceid-parentid-flags"));
+    };
+    if version.len() != 2 || !version.chars().all(|c| c.is_ascii_hexdigit()) || *version == "ff" {
+        return Err(err("bad version"));
+    }
+    // later versions may append fields, version 00 may not
+    if *version == "00" && !rest.is_empty() {
+        return Err(err("version 00 has 4 fields"));
+    }
+    let flags = match u8::from_str_radix(flags, 16) {
+        Ok(bits) if flags.len() == 2 => bits,
+        _ => return Err(err("bad flags")),
+    };
+    Ok(SpanContext::new(
+        parse_trace_id(trace_id)?,
+        parse_span_id(span_id)?,
+        TraceFlags::new(flags),
+        true,
+        TraceState::default(),
+    ))
+}
+
+/// words to build generated data from, one per line (blank lines and lines
+/// starting with `#` are skipped)
+#[derive(Debug, Clone)]
+pub struct Wordlist(Vec<String>);
+
+impl Wordlist {
+    pub fn load(path: &str) -> Result<Wordlist, Box<dyn error::Error>> {
+        let words: Vec<String> = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(String::from)
+            .collect();
+        if words.is_empty() {
+            return Err(Box::new(OTKError::InvalidArgumentError(format!("wordlist {} has no words", path))));
+        }
+        Ok(Wordlist(words))
+    }
+
+    /// a random word
+    pub fn pick(&self) -> &str {
+        self.0.choose(&mut rand::thread_rng()).unwrap()
+    }
+}
+
+/// parse a probability between 0 and 1 (`0.02`)
+pub fn parse_ratio(s: &str) -> Result<f64, OTKError> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(OTKError::ParseError(format!("invalid ratio {} (expect a number between 0 and 1)", s))),
+    }
+}
+
+/// parse a rate per second, a finite positive number (`10`, `0.5`, `2e3`)
+pub fn parse_rate(s: &str) -> Result<f64, OTKError> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0. && rate.is_finite() => Ok(rate),
+        _ => Err(OTKError::ParseError(format!("invalid rate {} (expect a positive number)", s))),
+    }
+}
+
+/// the time between ticks at `rate` per second, at least 1ns since a zero
+/// period panics `tokio::time::interval`
+pub fn rate_period(rate: f64) -> Duration {
+    Duration::try_from_secs_f64(1. / rate)
+        .unwrap_or(Duration::MAX)
+        .max(Duration::from_nanos(1))
+}
+
+/// parse a count, optionally with a `k` or `M` suffix (`500`, `100k`, `1M`)
+pub fn parse_count(s: &str) -> Result<u64, OTKError> {
+    let (num, scale) = match s.strip_suffix(['k', 'K']) {
+        Some(num) => (num, 1_000),
+        None => match s.strip_suffix('M') {
+            Some(num) => (num, 1_000_000),
+            None => (s, 1),
+        },
+    };
+    match num.parse::<f64>() {
+        Ok(n) if n >= 0. && (n * scale as f64).fract() == 0. => Ok((n * scale as f64) as u64),
+        _ => Err(OTKError::ParseError(format!("invalid count {} (expect a whole number like 500, 100k or 1M)", s))),
+    }
+}
+
+/// parse a duration with a unit: `ns`, `us`, `ms`, `s`, `m`, `h` or `d`
+/// (`150ms`, `1.5s`, `12h`), several of them added up (`1m30s`), or a bare
+/// number of seconds (`5`, `0.15`)
+pub fn parse_duration(s: &str) -> Result<Duration, OTKError> {
+    let err = || OTKError::ParseError(format!("invalid duration {} (expect seconds or number with unit, like 150ms, 2s or 1m30s)", s));
+    if s.is_empty() {
+        return Err(err());
+    }
+    if s.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        let secs: f64 = s.parse().map_err(|_| err())?;
+        return Duration::try_from_secs_f64(secs).map_err(|_| err());
+    }
+    let mut rest = s;
+    let mut secs = 0.;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).ok_or_else(err)?;
+        let (num, tail) = rest.split_at(split);
+        let unit_len = tail.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        let num: f64 = num.parse().map_err(|_| err())?;
+        secs += num * match unit {
+            "ns" => 1e-9,
+            "us" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.,
+            "m" => 60.,
+            "h" => 3600.,
+            "d" => 86400.,
+            _ => return Err(err()),
+        };
+        rest = tail;
+    }
+    Duration::try_from_secs_f64(secs).map_err(|_| err())
+}
+
+/// parse a point in time into unix nanoseconds. accepts RFC3339
+/// (`2023-01-02T15:04:05.123Z`, `2023-01-02T15:04:05+08:00`) or a duration
+/// relative to now (`-1h`, `-30m`, `-10s`, `-2d`, or `+5m` in the future)
+pub fn parse_time(s: &str) -> Result<u64, OTKError> {
+    let err = || OTKError::ParseError(format!("invalid time {} (expect RFC3339 or relative like -1h)", s));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    if let Some(rel) = s.strip_prefix('-') {
+        let ago = parse_duration(rel).map_err(|_| err())?;
+        return Ok(now.saturating_sub(ago).as_nanos() as u64);
+    }
+    if let Some(rel) = s.strip_prefix('+') {
+        let ahead = parse_duration(rel).map_err(|_| err())?;
+        return Ok((now + ahead).as_nanos() as u64);
+    }
+    parse_rfc3339(s).ok_or_else(err)
+}
+
+fn parse_rfc3339(s: &str) -> Option<u64> {
+    let time = chrono::DateTime::parse_from_rfc3339(s).ok()?;
+    u64::try_from(time.timestamp_nanos_opt()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kv(s: &str) -> (String, String) {
+        let kv = KeyValue::from_str(s).unwrap();
+        (kv.k, kv.v)
+    }
+
+    #[test]
+    fn key_value_plain() {
+        assert_eq!(kv("k=v"), ("k".into(), "v".into()));
+        assert_eq!(kv("k="), ("k".into(), "".into()));
+        assert_eq!(kv("k=a=b"), ("k".into(), "a=b".into()));
+        assert!(KeyValue::from_str("k").is_err());
+    }
+
+    #[test]
+    fn key_value_keeps_unknown_escapes() {
+        assert_eq!(kv(r"path=C:\temp"), ("path".into(), r"C:\temp".into()));
+        assert_eq!(kv(r"k=^\d+\.\d+$"), ("k".into(), r"^\d+\.\d+$".into()));
+        assert_eq!(kv(r"k=a\nb"), ("k".into(), r"a\nb".into()));
+        assert_eq!(kv(r"k='^\d+$'"), ("k".into(), r"^\d+$".into()));
+    }
+
+    #[test]
+    fn key_value_escapes() {
+        assert_eq!(kv(r"k=a\,b\\c"), ("k".into(), r"a,b\c".into()));
+        assert_eq!(kv(r"a\=b\:c=v"), ("a=b:c".into(), "v".into()));
+        assert_eq!(kv(r#"k="a\"b\nc""#), ("k".into(), "a\"b\nc".into()));
+        assert!(KeyValue::from_str(r"k=a\").is_err());
+    }
+
+    #[test]
+    fn key_value_quotes() {
+        assert_eq!(kv(r#"k="a=b,c d""#), ("k".into(), "a=b,c d".into()));
+        assert_eq!(kv("k='x'"), ("k".into(), "x".into()));
+        assert!(KeyValue::from_str(r#"k="open"#).is_err());
+        assert!(KeyValue::from_str(r#"k="a"b"#).is_err());
+    }
+
+    #[test]
+    fn key_value_types() {
+        let typed = KeyValue::from_str("n:int=5").unwrap();
+        assert_eq!((typed.k.as_str(), typed.ty), ("n", ValueType::Int));
+        assert!(KeyValue::from_str("n:int=five").is_err());
+        // an unknown type is part of the key
+        assert_eq!(kv("http:url=x"), ("http:url".into(), "x".into()));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("150ms").unwrap(), Duration::from_millis(150));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h2m3s").unwrap(), Duration::from_secs(3723));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(172800));
+        assert_eq!(parse_duration("1s500ms").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("10us").unwrap(), Duration::from_micros(10));
+        assert_eq!(parse_duration("7ns").unwrap(), Duration::from_nanos(7));
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("5").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse_duration("0.15").unwrap(), Duration::from_millis(150));
+        for bad in ["", "s", "1x", "1m30", "-1s", "-1", "1..5s", "1e3s", "inf", "NaN", "1.2.3"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rfc3339_times() {
+        let base = 1_672_671_845_000_000_000;
+        assert_eq!(parse_time("2023-01-02T15:04:05Z").unwrap(), base);
+        assert_eq!(parse_time("2023-01-02t15:04:05z").unwrap(), base);
+        assert_eq!(parse_time("2023-01-02T15:04:05.123Z").unwrap(), base + 123_000_000);
+        assert_eq!(parse_time("2023-01-02T15:04:05.000000007Z").unwrap(), base + 7);
+        assert_eq!(parse_time("2023-01-02T23:04:05+08:00").unwrap(), base);
+        assert_eq!(parse_time("2023-01-02T12:34:05.5-02:30").unwrap(), base + 500_000_000);
+        for bad in ["2023-01-02", "2023-01-02T15:04:05", "2023-13-02T15:04:05Z", "2023-01-02T15:04:05.Z", "1969-12-31T23:59:59Z"] {
+            assert!(parse_time(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn relative_times() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        let ago = parse_time("-1h").unwrap();
+        assert!(now - 3_600_000_000_000 <= ago && ago < now);
+        assert!(parse_time("+5m").unwrap() > now + 299_000_000_000);
+        assert!(parse_time("-1x").is_err());
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("2e3").unwrap(), 2000.);
+        for bad in ["0", "-1", "inf", "NaN", "x"] {
+            assert!(parse_rate(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(rate_period(4.), Duration::from_millis(250));
+        assert_eq!(rate_period(1e10), Duration::from_nanos(1));
+        assert_eq!(rate_period(f64::MIN_POSITIVE), Duration::MAX);
+    }
+
+    #[test]
+    fn split_unquoted_keeps_quoted_separators() {
+        assert_eq!(split_unquoted(r#"a,k="b,c",d\,e"#, ','), vec!["a", r#"k="b,c""#, r"d\,e"]);
     }
 }
@@ -0,0 +1,129 @@
+use crate::otk_error::OTKError;
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hyper::header::{HeaderValue, CONTENT_ENCODING};
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+use std::io::{Read, Write};
+use strum_macros::{Display, EnumString};
+
+/// request compression of the exporters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Compression {
+    #[strum(serialize = "none")]
+    None,
+    #[strum(serialize = "gzip")]
+    Gzip,
+    #[strum(serialize = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// the compression of a content-encoding (grpc-encoding over grpc), if
+    /// supported
+    pub fn from_encoding(encoding: &str) -> Option<Compression> {
+        match encoding {
+            "" | "identity" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// content-encoding (grpc-encoding over grpc)
+    pub fn encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                // writing to a vec can't fail
+                encoder.write_all(data).and_then(|_| encoder.finish()).expect("gzip into memory")
+            }
+            Compression::Zstd => zstd::bulk::compress(data, 0).expect("zstd into memory"),
+        }
+    }
+
+    /// decompress a body, failing once it grows past `limit` bytes rather
+    /// than exhausting the memory on a compression bomb
+    pub fn decompress(self, data: &[u8], limit: usize) -> Result<Vec<u8>, OTKError> {
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::None => Box::new(data),
+            Compression::Gzip => Box::new(GzDecoder::new(data)),
+            Compression::Zstd => Box::new(
+                zstd::stream::read::Decoder::new(data).map_err(|e| OTKError::ParseError(format!("invalid zstd body: {}", e)))?,
+            ),
+        };
+        let mut out = vec![];
+        reader
+            .take(limit as u64 + 1)
+            .read_to_end(&mut out)
+            .map_err(|e| OTKError::ParseError(format!("invalid {} body: {}", self, e)))?;
+        if out.len() > limit {
+            return Err(OTKError::InvalidArgumentError(format!("{} body larger than {} bytes once decompressed", self, limit)));
+        }
+        Ok(out)
+    }
+}
+
+/// http client compressing request bodies, which the otlp/http exporters
+/// don't do themselves
+#[derive(Debug)]
+pub struct CompressingClient<C> {
+    pub inner: C,
+    pub compression: Compression,
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for CompressingClient<C> {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let (mut parts, body) = request.into_parts();
+        if let Some(encoding) = self.compression.encoding() {
+            parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        let body = self.compression.compress(&body);
+        self.inner.send(Request::from_parts(parts, body)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let data = b"otk otk otk otk otk otk otk otk".repeat(100);
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&data);
+            assert_eq!(compression.decompress(&compressed, data.len()).unwrap(), data, "{}", compression);
+        }
+        assert!(Compression::Gzip.compress(&data).len() < data.len() / 10);
+    }
+
+    #[test]
+    fn limits_decompressed_size() {
+        // 64 MiB of zeros compress to a few KiB
+        let bomb = vec![0; 64 << 20];
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let err = compression.decompress(&compression.compress(&bomb), 1 << 20).unwrap_err();
+            assert!(err.to_string().contains("larger than 1048576 bytes"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_bodies() {
+        assert!(Compression::Gzip.decompress(b"not gzip", 1024).is_err());
+        assert!(Compression::Zstd.decompress(b"not zstd", 1024).is_err());
+        let mut truncated = Compression::Gzip.compress(b"hello world");
+        truncated.truncate(truncated.len() - 4);
+        assert!(Compression::Gzip.decompress(&truncated, 1024).is_err());
+    }
+}
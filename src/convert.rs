@@ -0,0 +1,409 @@
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::{any_value::Value, AnyValue, ArrayValue, InstrumentationScope, KeyValue};
+use crate::proto::jaeger::api_v2 as jaeger;
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::{span, status, ResourceSpans, ScopeSpans, Span, Status};
+use crate::proto::zipkin::proto3 as zipkin;
+use opentelemetry::trace::{SpanKind, Status as SdkStatus};
+use opentelemetry::{Array, InstrumentationLibrary, Value as SdkValue};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::Resource as SdkResource;
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// convert a single jaeger span (resource taken from its own process)
+pub fn jaeger_span_to_otlp(span: &jaeger::Span) -> ExportTraceServiceRequest {
+    jaeger_spans_to_otlp(std::iter::once((span, span.process.as_ref())))
+}
+
+/// convert a jaeger batch, spans without their own process use the batch process
+pub fn jaeger_batch_to_otlp(batch: &jaeger::Batch) -> ExportTraceServiceRequest {
+    jaeger_spans_to_otlp(
+        batch
+            .spans
+            .iter()
+            .map(|s| (s, s.process.as_ref().or(batch.process.as_ref()))),
+    )
+}
+
+/// convert zipkin spans, grouped into resources by local endpoint
+pub fn zipkin_spans_to_otlp(spans: &[zipkin::Span]) -> ExportTraceServiceRequest {
+    let mut groups: Vec<(Option<&zipkin::Endpoint>, Vec<Span>)> = vec![];
+    for s in spans {
+        let local = s.local_endpoint.as_ref();
+        let converted = zipkin_span(s);
+        match groups.iter_mut().find(|(ep, _)| *ep == local) {
+            Some((_, spans)) => spans.push(converted),
+            None => groups.push((local, vec![converted])),
+        }
+    }
+    ExportTraceServiceRequest {
+        resource_spans: groups
+            .into_iter()
+            .map(|(ep, spans)| resource_spans(zipkin_resource(ep), spans))
+            .collect(),
+    }
+}
+
+/// spans of one instrumentation scope
+type ScopeGroup<'a> = (&'a InstrumentationLibrary, Vec<Span>);
+
+/// convert spans finished by the opentelemetry sdk, grouped by resource and
+/// instrumentation scope
+pub fn span_data_to_otlp(batch: &[SpanData]) -> ExportTraceServiceRequest {
+    let mut groups: Vec<(&SdkResource, Vec<ScopeGroup>)> = vec![];
+    for s in batch {
+        let resource = s.resource.as_ref();
+        let scopes = match groups.iter_mut().find(|(r, _)| *r == resource) {
+            Some((_, scopes)) => scopes,
+            None => {
+                groups.push((resource, vec![]));
+                &mut groups.last_mut().unwrap().1
+            }
+        };
+        let converted = sdk_span(s);
+        match scopes.iter_mut().find(|(lib, _)| **lib == s.instrumentation_lib) {
+            Some((_, spans)) => spans.push(converted),
+            None => scopes.push((&s.instrumentation_lib, vec![converted])),
+        }
+    }
+    ExportTraceServiceRequest {
+        resource_spans: groups
+            .into_iter()
+            .map(|(resource, scopes)| ResourceSpans {
+                resource: Some(Resource {
+                    attributes: resource.iter().map(|(k, v)| kv(k.as_str(), sdk_value(v))).collect(),
+                    ..Default::default()
+                }),
+                scope_spans: scopes
+                    .into_iter()
+                    .map(|(lib, spans)| ScopeSpans {
+                        scope: Some(InstrumentationScope {
+                            name: lib.name.to_string(),
+                            version: lib.version.as_deref().unwrap_or_default().to_string(),
+                            attributes: lib.attributes.iter().map(|a| kv(a.key.as_str(), sdk_value(&a.value))).collect(),
+                            ..Default::default()
+                        }),
+                        spans,
+                        schema_url: lib.schema_url.as_deref().unwrap_or_default().to_string(),
+                    })
+                    .collect(),
+                schema_url: resource.schema_url().unwrap_or_default().to_string(),
+            })
+            .collect(),
+    }
+}
+
+fn jaeger_spans_to_otlp<'a>(
+    spans: impl Iterator<Item = (&'a jaeger::Span, Option<&'a jaeger::Process>)>,
+) -> ExportTraceServiceRequest {
+    let mut groups: Vec<(Option<&jaeger::Process>, Vec<Span>)> = vec![];
+    for (s, process) in spans {
+        let converted = jaeger_span(s);
+        match groups.iter_mut().find(|(p, _)| *p == process) {
+            Some((_, spans)) => spans.push(converted),
+            None => groups.push((process, vec![converted])),
+        }
+    }
+    ExportTraceServiceRequest {
+        resource_spans: groups
+            .into_iter()
+            .map(|(p, spans)| resource_spans(jaeger_resource(p), spans))
+            .collect(),
+    }
+}
+
+fn resource_spans(resource: Resource, spans: Vec<Span>) -> ResourceSpans {
+    ResourceSpans {
+        resource: Some(resource),
+        scope_spans: vec![ScopeSpans {
+            spans,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+fn kv(key: &str, value: Value) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn error_status(message: String) -> Option<Status> {
+    Some(Status {
+        message,
+        code: status::StatusCode::Error as i32,
+    })
+}
+
+fn unix_nanos(seconds: i64, nanos: i32) -> u64 {
+    seconds.max(0) as u64 * 1_000_000_000 + nanos.max(0) as u64
+}
+
+fn sdk_value(value: &SdkValue) -> Value {
+    let array = |values: Vec<Value>| {
+        Value::ArrayValue(ArrayValue {
+            values: values.into_iter().map(|v| AnyValue { value: Some(v) }).collect(),
+        })
+    };
+    match value {
+        SdkValue::Bool(b) => Value::BoolValue(*b),
+        SdkValue::I64(i) => Value::IntValue(*i),
+        SdkValue::F64(f) => Value::DoubleValue(*f),
+        SdkValue::String(s) => Value::StringValue(s.to_string()),
+        SdkValue::Array(Array::Bool(bs)) => array(bs.iter().map(|b| Value::BoolValue(*b)).collect()),
+        SdkValue::Array(Array::I64(is)) => array(is.iter().map(|i| Value::IntValue(*i)).collect()),
+        SdkValue::Array(Array::F64(fs)) => array(fs.iter().map(|f| Value::DoubleValue(*f)).collect()),
+        SdkValue::Array(Array::String(ss)) => array(ss.iter().map(|s| Value::StringValue(s.to_string())).collect()),
+    }
+}
+
+fn sdk_nanos(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn sdk_span(s: &SpanData) -> Span {
+    let attributes = |attrs: &[opentelemetry::KeyValue]| {
+        attrs.iter().map(|a| kv(a.key.as_str(), sdk_value(&a.value))).collect::<Vec<_>>()
+    };
+    let parent_span_id = if s.parent_span_id == opentelemetry::trace::SpanId::INVALID {
+        vec![]
+    } else {
+        s.parent_span_id.to_bytes().to_vec()
+    };
+    Span {
+        trace_id: s.span_context.trace_id().to_bytes().to_vec(),
+        span_id: s.span_context.span_id().to_bytes().to_vec(),
+        trace_state: s.span_context.trace_state().header(),
+        parent_span_id,
+        name: s.name.to_string(),
+        kind: match s.span_kind {
+            SpanKind::Client => span::SpanKind::Client,
+            SpanKind::Server => span::SpanKind::Server,
+            SpanKind::Producer => span::SpanKind::Producer,
+            SpanKind::Consumer => span::SpanKind::Consumer,
+            SpanKind::Internal => span::SpanKind::Internal,
+        } as i32,
+        start_time_unix_nano: sdk_nanos(s.start_time),
+        end_time_unix_nano: sdk_nanos(s.end_time),
+        attributes: attributes(&s.attributes),
+        dropped_attributes_count: s.dropped_attributes_count,
+        events: s
+            .events
+            .iter()
+            .map(|e| span::Event {
+                time_unix_nano: sdk_nanos(e.timestamp),
+                name: e.name.to_string(),
+                attributes: attributes(&e.attributes),
+                dropped_attributes_count: e.dropped_attributes_count,
+            })
+            .collect(),
+        dropped_events_count: s.events.dropped_count(),
+        links: s
+            .links
+            .iter()
+            .map(|l| span::Link {
+                trace_id: l.span_context.trace_id().to_bytes().to_vec(),
+                span_id: l.span_context.span_id().to_bytes().to_vec(),
+                trace_state: l.span_context.trace_state().header(),
+                attributes: attributes(&l.attributes),
+                dropped_attributes_count: l.dropped_attributes_count,
+            })
+            .collect(),
+        dropped_links_count: s.links.dropped_count(),
+        status: match &s.status {
+            SdkStatus::Unset => None,
+            SdkStatus::Ok => Some(Status {
+                code: status::StatusCode::Ok as i32,
+                ..Default::default()
+            }),
+            SdkStatus::Error { description } => error_status(description.to_string()),
+        },
+    }
+}
+
+fn jaeger_resource(process: Option<&jaeger::Process>) -> Resource {
+    let mut attributes = vec![];
+    if let Some(process) = process {
+        attributes.push(kv("service.name", Value::StringValue(process.service_name.clone())));
+        attributes.extend(process.tags.iter().map(|t| kv(&t.key, jaeger_value(t))));
+    }
+    Resource {
+        attributes,
+        ..Default::default()
+    }
+}
+
+fn jaeger_value(tag: &jaeger::KeyValue) -> Value {
+    match jaeger::ValueType::from_i32(tag.v_type) {
+        Some(jaeger::ValueType::Bool) => Value::BoolValue(tag.v_bool),
+        Some(jaeger::ValueType::Int64) => Value::IntValue(tag.v_int64),
+        Some(jaeger::ValueType::Float64) => Value::DoubleValue(tag.v_float64),
+        Some(jaeger::ValueType::Binary) => Value::BytesValue(tag.v_binary.clone()),
+        _ => Value::StringValue(tag.v_str.clone()),
+    }
+}
+
+fn span_kind(kind: &str) -> span::SpanKind {
+    match kind {
+        "server" => span::SpanKind::Server,
+        "client" => span::SpanKind::Client,
+        "producer" => span::SpanKind::Producer,
+        "consumer" => span::SpanKind::Consumer,
+        "internal" => span::SpanKind::Internal,
+        _ => span::SpanKind::Unspecified,
+    }
+}
+
+fn jaeger_span(s: &jaeger::Span) -> Span {
+    let start = s.start_time.as_ref().map(|t| unix_nanos(t.seconds, t.nanos)).unwrap_or(0);
+    let duration = s.duration.as_ref().map(|d| unix_nanos(d.seconds, d.nanos)).unwrap_or(0);
+
+    let mut parent_span_id = vec![];
+    let mut links = vec![];
+    for r in &s.references {
+        let child_of = r.ref_type == jaeger::SpanRefType::ChildOf as i32;
+        if parent_span_id.is_empty() && child_of && r.trace_id == s.trace_id {
+            parent_span_id = r.span_id.clone();
+        } else {
+            links.push(span::Link {
+                trace_id: r.trace_id.clone(),
+                span_id: r.span_id.clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    let mut kind = span::SpanKind::Unspecified;
+    let mut status = None;
+    let mut attributes = vec![];
+    for tag in &s.tags {
+        match (tag.key.as_str(), jaeger_value(tag)) {
+            ("span.kind", Value::StringValue(k)) => kind = span_kind(&k),
+            ("error", Value::BoolValue(true)) => status = error_status(String::new()),
+            ("error", Value::StringValue(v)) if v == "true" => status = error_status(String::new()),
+            (key, value) => attributes.push(kv(key, value)),
+        }
+    }
+
+    let events = s
+        .logs
+        .iter()
+        .map(|log| {
+            let mut name = String::from("log");
+            let mut attributes = vec![];
+            for field in &log.fields {
+                match (field.key.as_str(), jaeger_value(field)) {
+                    ("event", Value::StringValue(v)) => name = v,
+                    (key, value) => attributes.push(kv(key, value)),
+                }
+            }
+            span::Event {
+                time_unix_nano: log
+                    .timestamp
+                    .as_ref()
+                    .map(|t| unix_nanos(t.seconds, t.nanos))
+                    .unwrap_or(0),
+                name,
+                attributes,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Span {
+        trace_id: s.trace_id.clone(),
+        span_id: s.span_id.clone(),
+        parent_span_id,
+        name: s.operation_name.clone(),
+        kind: kind as i32,
+        start_time_unix_nano: start,
+        end_time_unix_nano: start + duration,
+        attributes,
+        events,
+        links,
+        status,
+        ..Default::default()
+    }
+}
+
+fn zipkin_resource(endpoint: Option<&zipkin::Endpoint>) -> Resource {
+    let attributes = match endpoint {
+        Some(ep) if !ep.service_name.is_empty() => {
+            vec![kv("service.name", Value::StringValue(ep.service_name.clone()))]
+        }
+        _ => vec![],
+    };
+    Resource {
+        attributes,
+        ..Default::default()
+    }
+}
+
+fn zipkin_span(s: &zipkin::Span) -> Span {
+    // zipkin allows 64-bit trace ids, otlp requires 128-bit
+    let mut trace_id = s.trace_id.clone();
+    if trace_id.len() < 16 {
+        let mut padded = vec![0; 16 - trace_id.len()];
+        padded.append(&mut trace_id);
+        trace_id = padded;
+    }
+    let kind = match zipkin::span::Kind::from_i32(s.kind) {
+        Some(zipkin::span::Kind::Client) => span::SpanKind::Client,
+        Some(zipkin::span::Kind::Server) => span::SpanKind::Server,
+        Some(zipkin::span::Kind::Producer) => span::SpanKind::Producer,
+        Some(zipkin::span::Kind::Consumer) => span::SpanKind::Consumer,
+        _ => span::SpanKind::Internal,
+    };
+
+    let mut tags = s.tags.iter().collect::<Vec<_>>();
+    tags.sort();
+    let mut status = None;
+    let mut attributes = vec![];
+    for (k, v) in tags {
+        if k == "error" {
+            status = error_status(v.clone());
+        } else {
+            attributes.push(kv(k, Value::StringValue(v.clone())));
+        }
+    }
+    if let Some(remote) = &s.remote_endpoint {
+        if !remote.service_name.is_empty() {
+            attributes.push(kv("peer.service", Value::StringValue(remote.service_name.clone())));
+        }
+        if let Ok(ip) = <[u8; 4]>::try_from(remote.ipv4.as_slice()) {
+            attributes.push(kv("net.peer.ip", Value::StringValue(Ipv4Addr::from(ip).to_string())));
+        } else if let Ok(ip) = <[u8; 16]>::try_from(remote.ipv6.as_slice()) {
+            attributes.push(kv("net.peer.ip", Value::StringValue(Ipv6Addr::from(ip).to_string())));
+        }
+        if remote.port != 0 {
+            attributes.push(kv("net.peer.port", Value::IntValue(remote.port as i64)));
+        }
+    }
+
+    let events = s
+        .annotations
+        .iter()
+        .map(|a| span::Event {
+            time_unix_nano: a.timestamp * 1000,
+            name: a.value.clone(),
+            ..Default::default()
+        })
+        .collect();
+
+    Span {
+        trace_id,
+        span_id: s.id.clone(),
+        parent_span_id: s.parent_id.clone(),
+        name: s.name.clone(),
+        kind: kind as i32,
+        start_time_unix_nano: s.timestamp * 1000,
+        end_time_unix_nano: (s.timestamp + s.duration) * 1000,
+        attributes,
+        events,
+        status,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,50 @@
+use crate::otlp_json;
+use async_trait::async_trait;
+use bytes::Bytes;
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+use std::io::Write;
+use strum_macros::{Display, EnumString};
+
+/// how --dry-run prints the export requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum DryRun {
+    /// OTLP/JSON, one request per line
+    #[strum(serialize = "json")]
+    Json,
+    /// one base64 encoded request per line, as read by decode -b and
+    /// --from-capture
+    #[strum(serialize = "base64", serialize = "b64")]
+    Base64,
+    /// the protobuf bytes as they are, only decodable for a single request
+    #[strum(serialize = "raw", serialize = "proto")]
+    Raw,
+}
+
+/// http client of the http exporter printing the (protobuf) request bodies
+/// instead of sending them, and answering every request with an empty 200
+#[derive(Debug)]
+pub struct DryRunClient {
+    pub format: DryRun,
+}
+
+#[async_trait]
+impl HttpClient for DryRunClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let body = request.body();
+        let mut stdout = std::io::stdout().lock();
+        match self.format {
+            DryRun::Json => {
+                let name = match request.uri().path() {
+                    p if p.ends_with("/logs") => "opentelemetry.proto.collector.logs.v1.ExportLogsServiceRequest",
+                    p if p.ends_with("/metrics") => "opentelemetry.proto.collector.metrics.v1.ExportMetricsServiceRequest",
+                    _ => "opentelemetry.proto.collector.trace.v1.ExportTraceServiceRequest",
+                };
+                writeln!(stdout, "{}", otlp_json::to_json(name, body)?)?;
+            }
+            DryRun::Base64 => writeln!(stdout, "{}", base64::encode(body))?,
+            DryRun::Raw => stdout.write_all(body)?,
+        }
+        stdout.flush()?;
+        Ok(Response::builder().status(200).body(Bytes::new())?)
+    }
+}
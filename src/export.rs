@@ -0,0 +1,549 @@
+use crate::otk_error::OTKError;
+use crate::token;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use opentelemetry::global;
+use opentelemetry::logs::LogError;
+use opentelemetry::metrics::{MetricsError, Result as MetricsResult};
+use opentelemetry::trace::TraceError;
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+// the requests the otlp exporters encode, for their size
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, TemporalitySelector};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use prost_otlp::Message;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::Code;
+
+/// backoff before the first retry, doubled for every further one
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+/// longest backoff between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// retries of the exports of a report command (--retries) and how they
+/// ended, shared by all its exporters
+#[derive(Debug, Default)]
+pub struct Exports {
+    retries: u32,
+    /// what the exports carry (spans, log records or points)
+    items_name: &'static str,
+    started: AtomicU64,
+    succeeded: AtomicU64,
+    retried: AtomicU64,
+    items: AtomicU64,
+    /// attempts, each a request
+    requests: AtomicU64,
+    /// protobuf encoded size of the requests, before compression
+    bytes: AtomicU64,
+    latencies: Mutex<Vec<Duration>>,
+}
+
+impl Exports {
+    pub fn new(retries: u32, items_name: &'static str) -> Self {
+        Exports { retries, items_name, ..Default::default() }
+    }
+
+    /// count an export of `items` about to be attempted
+    pub fn start(&self, items: usize) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        self.items.fetch_add(items as u64, Ordering::Relaxed);
+    }
+
+    /// the result of attempt `retry` (counting from 0) of an export of
+    /// `bytes`, which took `latency`: the final result, or nothing after
+    /// waiting for the next attempt
+    pub async fn attempted<E: ExportFailure>(
+        &self,
+        retry: u32,
+        bytes: usize,
+        latency: Duration,
+        result: Result<(), E>,
+    ) -> Option<Result<(), E>> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.latencies.lock().unwrap().push(latency);
+        let err = match result {
+            Ok(()) => {
+                self.succeeded.fetch_add(1, Ordering::Relaxed);
+                return Some(Ok(()));
+            }
+            Err(err) => err,
+        };
+        let failure = err.failure();
+        // the interceptor setting the token can't see the answer, drop the
+        // token here and try once more with a new one
+        if failure.unauthenticated && retry == 0 && token::invalidate_all() {
+            eprintln!("export failed: {}, retrying with a new token", failure.message);
+            self.retried.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        if !failure.transient || retry >= self.retries {
+            eprintln!("export failed: {}", failure.message);
+            return Some(Err(err));
+        }
+        let backoff = backoff(retry);
+        eprintln!("export failed: {}, retrying in {:?}", failure.message, backoff);
+        self.retried.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(backoff).await;
+        None
+    }
+
+    /// print what was exported (on stderr, stdout may carry --dry-run
+    /// requests), then fail when any export failed, exports cut short by the
+    /// sdk's timeout included
+    pub fn finish(&self) -> Result<(), OTKError> {
+        let started = self.started.load(Ordering::Relaxed);
+        let succeeded = self.succeeded.load(Ordering::Relaxed);
+        let failed = started - succeeded;
+        if started > 0 {
+            eprintln!(
+                "exported {} {} in {} requests ({} retries), {} bytes: {} succeeded, {} failed",
+                self.items.load(Ordering::Relaxed),
+                self.items_name,
+                self.requests.load(Ordering::Relaxed),
+                self.retried.load(Ordering::Relaxed),
+                self.bytes.load(Ordering::Relaxed),
+                succeeded,
+                failed
+            );
+        }
+        let mut latencies = self.latencies.lock().unwrap();
+        if !latencies.is_empty() {
+            latencies.sort_unstable();
+            let avg = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+            // nearest rank
+            let p99 = latencies[(latencies.len() * 99).div_ceil(100).max(1) - 1];
+            let ms = |d: Duration| d.as_secs_f64() * 1e3;
+            eprintln!("export latency min {:.3}ms avg {:.3}ms p99 {:.3}ms", ms(latencies[0]), ms(avg), ms(p99));
+        }
+        if failed == 0 {
+            return Ok(());
+        }
+        Err(OTKError::CheckError(format!(
+            "{} of {} exports failed ({} retries)",
+            failed,
+            started,
+            self.retried.load(Ordering::Relaxed)
+        )))
+    }
+}
+
+/// wait before attempt `retry` + 1
+fn backoff(retry: u32) -> Duration {
+    FIRST_BACKOFF.saturating_mul(2u32.saturating_pow(retry)).min(MAX_BACKOFF)
+}
+
+/// what went wrong with an export, and whether trying again may help
+pub struct Failure {
+    message: String,
+    transient: bool,
+    /// a grpc UNAUTHENTICATED, worth a retry with a new --token-command token
+    unauthenticated: bool,
+}
+
+impl Failure {
+    fn new(message: String, transient: bool) -> Self {
+        Failure { message, transient, unauthenticated: false }
+    }
+
+    fn permanent(err: impl fmt::Display) -> Self {
+        Failure::new(err.to_string(), false)
+    }
+}
+
+/// the failures the sdk's exporter traits return
+pub trait ExportFailure {
+    fn failure(&self) -> Failure;
+}
+
+impl ExportFailure for TraceError {
+    fn failure(&self) -> Failure {
+        match self {
+            TraceError::ExportFailed(e) => cause(&**e).unwrap_or_else(|| Failure::permanent(e)),
+            TraceError::ExportTimedOut(_) => Failure::new(self.to_string(), true),
+            TraceError::Other(e) => cause(&**e).unwrap_or_else(|| Failure::permanent(e)),
+            _ => Failure::permanent(self),
+        }
+    }
+}
+
+impl ExportFailure for LogError {
+    fn failure(&self) -> Failure {
+        match self {
+            LogError::ExportFailed(e) => cause(&**e).unwrap_or_else(|| Failure::permanent(e)),
+            LogError::ExportTimedOut(_) => Failure::new(self.to_string(), true),
+            LogError::Other(e) => cause(&**e).unwrap_or_else(|| Failure::permanent(e)),
+            _ => Failure::permanent(self),
+        }
+    }
+}
+
+/// the failures of requests otk sends itself (replayed captures)
+impl ExportFailure for Box<dyn Error + Send + Sync> {
+    fn failure(&self) -> Failure {
+        cause(&**self).unwrap_or_else(|| Failure::permanent(self))
+    }
+}
+
+impl ExportFailure for MetricsError {
+    fn failure(&self) -> Failure {
+        match self {
+            MetricsError::ExportErr(e) => cause(&**e).unwrap_or_else(|| Failure::permanent(e)),
+            _ => Failure::permanent(self),
+        }
+    }
+}
+
+/// grpc statuses and http statuses the otlp spec lets exporters retry, and
+/// connections that could not be made. none for errors not of an exporter
+fn cause(err: &(dyn Error + 'static)) -> Option<Failure> {
+    if let Some(e) = err.downcast_ref::<opentelemetry_otlp::Error>() {
+        return Some(match e {
+            opentelemetry_otlp::Error::Status { code, message } => Failure {
+                message: format!(
+                    "grpc-status {} ({:?}){}",
+                    *code as i32,
+                    code,
+                    message.replacen(", detailed error message: ", ": ", 1)
+                ),
+                transient: matches!(
+                    code,
+                    Code::Cancelled | Code::DeadlineExceeded | Code::Aborted | Code::OutOfRange | Code::Unavailable | Code::DataLoss
+                ),
+                unauthenticated: *code == Code::Unauthenticated,
+            },
+            opentelemetry_otlp::Error::Transport(_) => Failure::new(e.to_string(), true),
+            opentelemetry_otlp::Error::RequestFailed(inner) => cause(&**inner).unwrap_or_else(|| Failure::permanent(e)),
+            _ => Failure::permanent(e),
+        });
+    }
+    if let Some(e) = err.downcast_ref::<HttpStatusError>() {
+        return Some(Failure::new(e.to_string(), matches!(e.status, 429 | 502 | 503 | 504)));
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return Some(Failure::new(e.to_string(), e.is_connect() || e.is_timeout()));
+    }
+    if let Some(e) = err.downcast_ref::<tokio::time::error::Elapsed>() {
+        return Some(Failure::new(format!("request {}", e), true));
+    }
+    if let Some(e) = err.downcast_ref::<hyper::Error>() {
+        return Some(Failure::new(e.to_string(), e.is_connect()));
+    }
+    None
+}
+
+/// print the errors of the sdk like its default handler, except the failed
+/// exports already reported by the checked exporters
+pub fn handle_other_errors() {
+    let handled = global::set_error_handler(|err| match err {
+        global::Error::Trace(TraceError::ExportFailed(_)) => {}
+        global::Error::Trace(TraceError::Other(e)) if cause(&*e).is_some() => {}
+        global::Error::Trace(err) => eprintln!("OpenTelemetry trace error occurred. {}", err),
+        global::Error::Log(LogError::ExportFailed(_)) => {}
+        global::Error::Log(LogError::Other(e)) if cause(&*e).is_some() => {}
+        global::Error::Log(err) => eprintln!("OpenTelemetry log error occurred. {}", err),
+        global::Error::Metric(MetricsError::ExportErr(_)) => {}
+        global::Error::Metric(err) => eprintln!("OpenTelemetry metrics error occurred. {}", err),
+        err => eprintln!("OpenTelemetry error occurred. {}", err),
+    });
+    if let Err(e) = handled {
+        eprintln!("set error handler failed: {}", e);
+    }
+}
+
+/// a non-2xx answer of an otlp/http receiver
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub body: String,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {}: {}", self.status, self.body)
+    }
+}
+
+impl Error for HttpStatusError {}
+
+/// http client failing requests the receiver didn't accept, which the http
+/// exporters would count as sent
+#[derive(Debug)]
+pub struct StatusClient<C> {
+    pub inner: C,
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for StatusClient<C> {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let response = self.inner.send(request).await?;
+        if !response.status().is_success() {
+            let body = String::from_utf8_lossy(response.body()).into_owned();
+            return Err(Box::new(HttpStatusError { status: response.status().as_u16(), body }));
+        }
+        Ok(response)
+    }
+}
+
+/// span exporter retrying the exports of its inner exporter
+#[derive(Debug)]
+pub struct CheckedSpanExporter {
+    inner: Arc<tokio::sync::Mutex<Box<dyn SpanExporter>>>,
+    exports: Arc<Exports>,
+    /// exports run on the runtime the exporter is created in, the simple span
+    /// processor calls it from a thread of its own
+    runtime: tokio::runtime::Handle,
+}
+
+impl CheckedSpanExporter {
+    /// must be called within a tokio runtime
+    pub fn new(inner: Box<dyn SpanExporter>, exports: Arc<Exports>) -> Self {
+        let runtime = tokio::runtime::Handle::current();
+        CheckedSpanExporter { inner: Arc::new(tokio::sync::Mutex::new(inner)), exports, runtime }
+    }
+}
+
+impl SpanExporter for CheckedSpanExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let (inner, exports) = (self.inner.clone(), self.exports.clone());
+        let export = self.runtime.spawn(async move {
+            let mut inner = inner.lock().await;
+            let bytes = ExportTraceServiceRequest {
+                resource_spans: batch.iter().cloned().map(Into::into).collect(),
+            }
+            .encoded_len();
+            exports.start(batch.len());
+            for retry in 0.. {
+                let started = Instant::now();
+                let result = inner.export(batch.clone()).await;
+                if let Some(result) = exports.attempted(retry, bytes, started.elapsed(), result).await {
+                    return result;
+                }
+            }
+            unreachable!()
+        });
+        Box::pin(async move { export.await.unwrap_or_else(|e| Err(TraceError::from(e.to_string()))) })
+    }
+
+    fn shutdown(&mut self) {
+        if let Ok(mut inner) = self.inner.try_lock() {
+            inner.shutdown();
+        }
+    }
+}
+
+/// log exporter retrying the exports of its inner exporter
+#[derive(Debug)]
+pub struct CheckedLogExporter<E> {
+    pub inner: E,
+    pub exports: Arc<Exports>,
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for CheckedLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> Result<(), LogError> {
+        let bytes = ExportLogsServiceRequest {
+            resource_logs: batch.iter().cloned().map(Into::into).collect(),
+        }
+        .encoded_len();
+        self.exports.start(batch.len());
+        for retry in 0.. {
+            let started = Instant::now();
+            let result = self.inner.export(batch.clone()).await;
+            if let Some(result) = self.exports.attempted(retry, bytes, started.elapsed(), result).await {
+                return result;
+            }
+        }
+        unreachable!()
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+/// metrics exporter retrying the exports of its inner exporter
+#[derive(Debug)]
+pub struct CheckedMetricsExporter<E> {
+    pub inner: E,
+    pub exports: Arc<Exports>,
+}
+
+impl<E: AggregationSelector> AggregationSelector for CheckedMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for CheckedMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for CheckedMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let request = ExportMetricsServiceRequest::from(&*metrics);
+        self.exports.start(points(&request));
+        for retry in 0.. {
+            let started = Instant::now();
+            let result = self.inner.export(metrics).await;
+            if let Some(result) = self.exports.attempted(retry, request.encoded_len(), started.elapsed(), result).await {
+                return result;
+            }
+        }
+        unreachable!()
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// data points of all metrics of a request
+fn points(request: &ExportMetricsServiceRequest) -> usize {
+    let metrics = request.resource_metrics.iter().flat_map(|rm| &rm.scope_metrics).flat_map(|sm| &sm.metrics);
+    metrics
+        .map(|m| match &m.data {
+            Some(Data::Gauge(g)) => g.data_points.len(),
+            Some(Data::Sum(s)) => s.data_points.len(),
+            Some(Data::Histogram(h)) => h.data_points.len(),
+            Some(Data::ExponentialHistogram(h)) => h.data_points.len(),
+            Some(Data::Summary(s)) => s.data_points.len(),
+            None => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(status: u16) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err(Box::new(HttpStatusError { status, body: String::new() }))
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff(0), Duration::from_millis(500));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn transient_failures() {
+        assert!(http(503).unwrap_err().failure().transient);
+        assert!(!http(400).unwrap_err().failure().transient);
+        let status = |code| opentelemetry_otlp::Error::Status { code, message: String::new() };
+        assert!(TraceError::Other(Box::new(status(Code::Unavailable))).failure().transient);
+        assert!(!TraceError::Other(Box::new(status(Code::InvalidArgument))).failure().transient);
+        assert!(!TraceError::from("boom").failure().transient);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_failures() {
+        let exports = Exports::new(2, "spans");
+        exports.start(10);
+        let started = tokio::time::Instant::now();
+        assert!(exports.attempted(0, 100, Duration::ZERO, http(503)).await.is_none());
+        assert!(exports.attempted(1, 100, Duration::ZERO, http(503)).await.is_none());
+        assert!(exports.attempted(2, 100, Duration::ZERO, http(503)).await.unwrap().is_err());
+        assert_eq!(started.elapsed(), backoff(0) + backoff(1));
+        exports.start(5);
+        assert!(exports.attempted(0, 50, Duration::ZERO, http(400)).await.unwrap().is_err());
+        exports.start(1);
+        assert!(exports.attempted(0, 10, Duration::ZERO, Ok::<_, Box<dyn Error + Send + Sync>>(())).await.unwrap().is_ok());
+        assert_eq!(exports.retried.load(Ordering::Relaxed), 2);
+        assert_eq!(exports.requests.load(Ordering::Relaxed), 5);
+        assert_eq!(exports.bytes.load(Ordering::Relaxed), 360);
+        assert_eq!(exports.finish().unwrap_err().to_string(), "Check failed: 2 of 3 exports failed (2 retries)");
+    }
+
+
+    /// fails with UNAVAILABLE `failures` times, then succeeds
+    #[derive(Debug)]
+    struct Flaky {
+        failures: u32,
+        calls: Arc<AtomicU64>,
+    }
+
+    impl Flaky {
+        fn call(&mut self) -> Result<(), opentelemetry_otlp::Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match self.failures.checked_sub(1) {
+                Some(left) => {
+                    self.failures = left;
+                    Err(opentelemetry_otlp::Error::Status { code: Code::Unavailable, message: String::new() })
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl SpanExporter for Flaky {
+        fn export(&mut self, _batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            let result = self.call().map_err(|e| TraceError::Other(Box::new(e)));
+            Box::pin(async move { result })
+        }
+    }
+
+    #[async_trait]
+    impl LogExporter for Flaky {
+        async fn export(&mut self, _batch: Vec<LogData>) -> Result<(), LogError> {
+            self.call().map_err(|e| LogError::Other(Box::new(e)))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn checked_exporters_retry() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let exports = Arc::new(Exports::new(2, "spans"));
+        let flaky = Flaky { failures: 2, calls: calls.clone() };
+        let mut spans = CheckedSpanExporter::new(Box::new(flaky), exports.clone());
+        assert!(spans.export(vec![]).await.is_ok());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert!(exports.finish().is_ok());
+
+        let mut logs = CheckedLogExporter { inner: Flaky { failures: 3, calls: calls.clone() }, exports: exports.clone() };
+        assert!(logs.export(vec![]).await.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 6);
+        assert_eq!(exports.finish().unwrap_err().to_string(), "Check failed: 1 of 2 exports failed (4 retries)");
+    }
+
+    #[derive(Debug)]
+    struct Answer(u16);
+
+    #[async_trait]
+    impl HttpClient for Answer {
+        async fn send(&self, _request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+            Ok(Response::builder().status(self.0).body(Bytes::from_static(b"busy"))?)
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_unaccepted_requests() {
+        let request = || Request::post("http://localhost/v1/traces").body(vec![]).unwrap();
+        assert!(StatusClient { inner: Answer(200) }.send(request()).await.is_ok());
+        let err = StatusClient { inner: Answer(503) }.send(request()).await.unwrap_err();
+        assert_eq!(err.to_string(), "HTTP 503: busy");
+        assert!(err.failure().transient);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::common::{any_value_to_string, KeyValue};
+use crate::otk_error::OTKError;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::common::v1::{AnyValue, InstrumentationScope, KeyValue as ProtoKeyValue};
+use crate::proto::logs::v1::LogRecord;
+use crate::proto::metrics::v1::{metric::Data, Exemplar, Metric};
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::Span;
+use regex::Regex;
+use std::str::FromStr;
+
+/// a span together with the resource and scope it was reported under
+#[derive(Debug, Clone, Copy)]
+pub struct SpanRef<'a> {
+    pub resource: Option<&'a Resource>,
+    pub scope: Option<&'a InstrumentationScope>,
+    pub span: &'a Span,
+}
+
+/// a log record together with the resource and scope it was reported under
+#[derive(Debug, Clone, Copy)]
+pub struct LogRef<'a> {
+    pub resource: Option<&'a Resource>,
+    pub scope: Option<&'a InstrumentationScope>,
+    pub log: &'a LogRecord,
+}
+
+/// a single data point of a metric, whatever the metric type
+#[derive(Debug, Clone, Copy)]
+pub struct PointRef<'a> {
+    pub resource: Option<&'a Resource>,
+    pub scope: Option<&'a InstrumentationScope>,
+    pub metric: &'a Metric,
+    pub attributes: &'a [ProtoKeyValue],
+    pub time_unix_nano: u64,
+    pub exemplars: &'a [Exemplar],
+}
+
+/// attribute lookup over an item, then its scope, then its resource
+pub trait Attributes<'a> {
+    fn attr(&self, key: &str) -> Option<&'a AnyValue>;
+    fn resource(&self) -> Option<&'a Resource>;
+    fn scope(&self) -> Option<&'a InstrumentationScope>;
+}
+
+fn lookup<'a>(
+    own: &'a [ProtoKeyValue],
+    scope: Option<&'a InstrumentationScope>,
+    resource: Option<&'a Resource>,
+    key: &str,
+) -> Option<&'a AnyValue> {
+    let scope_attrs = scope.map(|s| s.attributes.as_slice()).unwrap_or_default();
+    let resource_attrs = resource.map(|r| r.attributes.as_slice()).unwrap_or_default();
+    own.iter()
+        .chain(scope_attrs)
+        .chain(resource_attrs)
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.as_ref())
+}
+
+impl<'a> Attributes<'a> for SpanRef<'a> {
+    fn attr(&self, key: &str) -> Option<&'a AnyValue> {
+        lookup(&self.span.attributes, self.scope, self.resource, key)
+    }
+
+    fn resource(&self) -> Option<&'a Resource> {
+        self.resource
+    }
+
+    fn scope(&self) -> Option<&'a InstrumentationScope> {
+        self.scope
+    }
+}
+
+impl<'a> Attributes<'a> for LogRef<'a> {
+    fn attr(&self, key: &str) -> Option<&'a AnyValue> {
+        lookup(&self.log.attributes, self.scope, self.resource, key)
+    }
+
+    fn resource(&self) -> Option<&'a Resource> {
+        self.resource
+    }
+
+    fn scope(&self) -> Option<&'a InstrumentationScope> {
+        self.scope
+    }
+}
+
+impl<'a> Attributes<'a> for PointRef<'a> {
+    fn attr(&self, key: &str) -> Option<&'a AnyValue> {
+        lookup(self.attributes, self.scope, self.resource, key)
+    }
+
+    fn resource(&self) -> Option<&'a Resource> {
+        self.resource
+    }
+
+    fn scope(&self) -> Option<&'a InstrumentationScope> {
+        self.scope
+    }
+}
+
+/// iterate all spans of a request along with their resource and scope
+pub fn spans(req: &ExportTraceServiceRequest) -> impl Iterator<Item = SpanRef<'_>> {
+    req.resource_spans.iter().flat_map(|rs| {
+        rs.scope_spans.iter().flat_map(move |ss| {
+            ss.spans.iter().map(move |span| SpanRef {
+                resource: rs.resource.as_ref(),
+                scope: ss.scope.as_ref(),
+                span,
+            })
+        })
+    })
+}
+
+/// iterate all log records of a request along with their resource and scope
+pub fn logs(req: &ExportLogsServiceRequest) -> impl Iterator<Item = LogRef<'_>> {
+    req.resource_logs.iter().flat_map(|rl| {
+        rl.scope_logs.iter().flat_map(move |sl| {
+            sl.log_records.iter().map(move |log| LogRef {
+                resource: rl.resource.as_ref(),
+                scope: sl.scope.as_ref(),
+                log,
+            })
+        })
+    })
+}
+
+/// iterate all data points of a request along with their metric, resource
+/// and scope
+pub fn points(req: &ExportMetricsServiceRequest) -> impl Iterator<Item = PointRef<'_>> {
+    req.resource_metrics.iter().flat_map(|rm| {
+        rm.scope_metrics.iter().flat_map(move |sm| {
+            sm.metrics
+                .iter()
+                .flat_map(move |metric| metric_points(rm.resource.as_ref(), sm.scope.as_ref(), metric))
+        })
+    })
+}
+
+/// data points of a single metric
+pub fn metric_points<'a>(
+    resource: Option<&'a Resource>,
+    scope: Option<&'a InstrumentationScope>,
+    metric: &'a Metric,
+) -> Vec<PointRef<'a>> {
+    let point = |attributes: &'a [ProtoKeyValue], time_unix_nano: u64, exemplars: &'a [Exemplar]| PointRef {
+        resource,
+        scope,
+        metric,
+        attributes,
+        time_unix_nano,
+        exemplars,
+    };
+    match &metric.data {
+        Some(Data::Gauge(g)) => g
+            .data_points
+            .iter()
+            .map(|dp| point(&dp.attributes, dp.time_unix_nano, &dp.exemplars))
+            .collect(),
+        Some(Data::Sum(s)) => s
+            .data_points
+            .iter()
+            .map(|dp| point(&dp.attributes, dp.time_unix_nano, &dp.exemplars))
+            .collect(),
+        Some(Data::Histogram(h)) => h
+            .data_points
+            .iter()
+            .map(|dp| point(&dp.attributes, dp.time_unix_nano, &dp.exemplars))
+            .collect(),
+        Some(Data::ExponentialHistogram(h)) => h
+            .data_points
+            .iter()
+            .map(|dp| point(&dp.attributes, dp.time_unix_nano, &dp.exemplars))
+            .collect(),
+        Some(Data::Summary(s)) => s
+            .data_points
+            .iter()
+            .map(|dp| point(&dp.attributes, dp.time_unix_nano, &[]))
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[derive(Debug, Clone)]
+enum AttrOp {
+    Eq(String),
+    Ne(String),
+    Regex(Regex),
+    Exists,
+}
+
+/// attribute condition: `key=value`, `key!=value` (also matches when the key
+/// is absent), `key~=regex`, or existence of a key
+#[derive(Debug, Clone)]
+pub struct AttrFilter {
+    key: String,
+    op: AttrOp,
+}
+
+impl AttrFilter {
+    pub fn exists(key: &str) -> Self {
+        AttrFilter {
+            key: key.to_string(),
+            op: AttrOp::Exists,
+        }
+    }
+
+    pub fn matches(&self, value: Option<&AnyValue>) -> bool {
+        let value = value.map(any_value_to_string);
+        match (&self.op, value) {
+            (AttrOp::Exists, v) => v.is_some(),
+            (AttrOp::Eq(expected), Some(v)) => v == *expected,
+            (AttrOp::Ne(expected), Some(v)) => v != *expected,
+            (AttrOp::Ne(_), None) => true,
+            (AttrOp::Regex(re), Some(v)) => re.is_match(&v),
+            (_, None) => false,
+        }
+    }
+
+    pub fn matches_attrs<'a>(&self, item: &impl Attributes<'a>) -> bool {
+        self.matches(item.attr(&self.key))
+    }
+
+    /// the index term (`key` or `key=value`) a line must contain for an item
+    /// of it to match, `None` when the index can't narrow this filter down
+    pub fn index_term(&self) -> Option<String> {
+        match &self.op {
+            AttrOp::Eq(v) => Some(format!("{}={}", self.key, v)),
+            AttrOp::Exists => Some(self.key.clone()),
+            AttrOp::Ne(_) | AttrOp::Regex(_) => None,
+        }
+    }
+
+    /// like `matches_attrs` but only looks at the resource attributes
+    pub fn matches_resource<'a>(&self, item: &impl Attributes<'a>) -> bool {
+        self.matches(lookup(&[], None, item.resource(), &self.key))
+    }
+}
+
+impl FromStr for AttrFilter {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the regex is taken as written, unescaping it would turn `\d` into `d`
+        if let Some((key, re)) = s.split_once("~=") {
+            if !key.contains('=') {
+                let re = unquote(re);
+                let re = Regex::new(re).map_err(|e| OTKError::ParseError(e.to_string()))?;
+                return Ok(AttrFilter {
+                    key: KeyValue::from_str(&format!("{}=", key))?.k,
+                    op: AttrOp::Regex(re),
+                });
+            }
+        }
+        let kv = KeyValue::from_str(s)?;
+        if let Some(key) = kv.k.strip_suffix('!') {
+            Ok(AttrFilter {
+                key: key.to_string(),
+                op: AttrOp::Ne(kv.v),
+            })
+        } else {
+            Ok(AttrFilter {
+                key: kv.k,
+                op: AttrOp::Eq(kv.v),
+            })
+        }
+    }
+}
+
+/// `s` without the quotes around it, if it is quoted
+fn unquote(s: &str) -> &str {
+    match s.chars().next() {
+        Some(q @ ('"' | '\'')) if s.len() > 1 && s.ends_with(q) => &s[1..s.len() - 1],
+        _ => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> AnyValue {
+        AnyValue {
+            value: Some(crate::proto::common::v1::any_value::Value::StringValue(s.to_string())),
+        }
+    }
+
+    #[test]
+    fn regex_keeps_backslashes() {
+        let f = AttrFilter::from_str(r"k~=^\d+$").unwrap();
+        assert!(f.matches(Some(&string("123"))));
+        assert!(!f.matches(Some(&string("d"))));
+        let f = AttrFilter::from_str(r#"host~="^api\.example\.com$""#).unwrap();
+        assert!(f.matches(Some(&string("api.example.com"))));
+        assert!(!f.matches(Some(&string("apixexample.com"))));
+        assert_eq!(f.key, "host");
+    }
+
+    #[test]
+    fn regex_with_escaped_key() {
+        let f = AttrFilter::from_str(r"a\:b~=x").unwrap();
+        assert_eq!(f.key, "a:b");
+        assert!(f.matches(Some(&string("xy"))));
+    }
+
+    #[test]
+    fn eq_and_ne() {
+        let f = AttrFilter::from_str("k=v").unwrap();
+        assert!(f.matches(Some(&string("v"))));
+        assert!(!f.matches(None));
+        assert_eq!(f.index_term().as_deref(), Some("k=v"));
+        let f = AttrFilter::from_str("k!=v").unwrap();
+        assert!(!f.matches(Some(&string("v"))));
+        assert!(f.matches(None));
+        // a `~=` in the value of an equality is not a regex
+        let f = AttrFilter::from_str("k=a~=b").unwrap();
+        assert!(f.matches(Some(&string("a~=b"))));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(AttrFilter::from_str("k~=(").is_err());
+    }
+}
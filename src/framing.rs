@@ -0,0 +1,234 @@
+use crate::otk_error::OTKError;
+use std::error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::ControlFlow;
+use strum_macros::{Display, EnumString};
+
+/// how protobuf messages are laid out in an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Framing {
+    /// one base64 encoded message per line
+    #[strum(serialize = "base64", serialize = "b64")]
+    Base64,
+    /// the whole input is a single message
+    #[strum(serialize = "raw")]
+    Raw,
+    /// messages prefixed by their varint length (protobuf writeDelimitedTo)
+    #[strum(serialize = "length-delimited", serialize = "varint")]
+    LengthDelimited,
+    /// messages prefixed by their length as 4 byte big endian integer (the
+    /// collector file exporter with `format: proto`)
+    #[strum(serialize = "length-prefixed", serialize = "u32")]
+    LengthPrefixed,
+}
+
+/// expand glob patterns among the inputs (in alphabetical order, for quoted
+/// patterns or shells that don't expand them), other inputs are kept as is
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<String>, Box<dyn error::Error>> {
+    let mut expanded = vec![];
+    for input in inputs {
+        if input == "-" || !input.contains(&['*', '?', '['][..]) {
+            expanded.push(input.clone());
+            continue;
+        }
+        let before = expanded.len();
+        for path in glob::glob(input)? {
+            expanded.push(path?.to_string_lossy().into_owned());
+        }
+        if expanded.len() == before {
+            return Err(Box::new(OTKError::InvalidArgumentError(format!("no files match {}", input))));
+        }
+    }
+    Ok(expanded)
+}
+
+/// name of an input in output and messages
+pub fn input_name(input: &str) -> &str {
+    if input == "-" {
+        "(standard input)"
+    } else {
+        input
+    }
+}
+
+/// open a file, or stdin for `-`
+pub fn open_input(input: &str) -> io::Result<Box<dyn BufRead>> {
+    if input == "-" {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(input)?)))
+    }
+}
+
+/// call `f` with every message of the input as it is read, until it breaks
+pub fn for_each_message(
+    input: &str,
+    framing: Framing,
+    mut f: impl FnMut(&[u8]) -> Result<ControlFlow<()>, Box<dyn error::Error>>,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut reader = open_input(input)?;
+    match framing {
+        Framing::Base64 => {
+            for line in reader.lines() {
+                if f(&base64::decode_config(line?, base64::STANDARD)?)?.is_break() {
+                    break;
+                }
+            }
+        }
+        Framing::Raw => {
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf)?;
+            // a single message, nothing left to stop
+            let _ = f(&buf)?;
+        }
+        Framing::LengthDelimited => {
+            while let Some(len) = read_varint(&mut reader)? {
+                if f(&read_message(&mut reader, len)?)?.is_break() {
+                    break;
+                }
+            }
+        }
+        Framing::LengthPrefixed => loop {
+            let mut len = [0u8; 4];
+            if !read_exact_or_eof(&mut reader, &mut len)? {
+                break;
+            }
+            if f(&read_message(&mut reader, u32::from_be_bytes(len) as u64)?)?.is_break() {
+                break;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// varint length prefix, `None` on a clean end of input
+fn read_varint(reader: &mut impl Read) -> Result<Option<u64>, Box<dyn error::Error>> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8];
+        if !read_exact_or_eof(reader, &mut byte)? {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(Box::new(OTKError::ParseError("input ends inside a length prefix".into())));
+        }
+        value |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(Box::new(OTKError::ParseError("length prefix is longer than 10 bytes".into())))
+}
+
+fn read_message(reader: &mut impl Read, len: u64) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let mut buf = vec![];
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(Box::new(OTKError::ParseError(format!(
+            "input ends inside a message ({} of {} bytes)",
+            buf.len(),
+            len
+        ))));
+    }
+    Ok(buf)
+}
+
+/// fill `buf`, returning false if the input ended before its first byte
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, Box<dyn error::Error>> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(Box::new(OTKError::ParseError("input ends inside a length prefix".into()))),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the messages of `data` written to a file, read with `framing`
+    fn messages(data: &[u8], framing: Framing) -> Result<Vec<Vec<u8>>, Box<dyn error::Error>> {
+        let path = std::env::temp_dir().join(format!("otk-framing-{}-{}", std::process::id(), framing));
+        std::fs::write(&path, data).unwrap();
+        let mut messages = vec![];
+        let result = for_each_message(path.to_str().unwrap(), framing, |bs| {
+            messages.push(bs.to_vec());
+            Ok(ControlFlow::Continue(()))
+        });
+        std::fs::remove_file(&path).unwrap();
+        result.map(|_| messages)
+    }
+
+    #[test]
+    fn reads_every_framing() {
+        let expected = vec![b"first".to_vec(), vec![], vec![7; 300]];
+        let base64 = expected.iter().map(|m| base64::encode(m) + "\n").collect::<String>();
+        assert_eq!(messages(base64.as_bytes(), Framing::Base64).unwrap(), expected);
+        assert_eq!(messages(b"first", Framing::Raw).unwrap(), [b"first"]);
+        // 300 is the two byte varint ac 02
+        let mut varint = vec![5];
+        varint.extend(b"first");
+        varint.extend([0, 0xac, 0x02]);
+        varint.extend([7; 300]);
+        assert_eq!(messages(&varint, Framing::LengthDelimited).unwrap(), expected);
+        let mut u32 = vec![];
+        for m in &expected {
+            u32.extend((m.len() as u32).to_be_bytes());
+            u32.extend(m);
+        }
+        assert_eq!(messages(&u32, Framing::LengthPrefixed).unwrap(), expected);
+        assert_eq!("varint".parse::<Framing>().unwrap(), Framing::LengthDelimited);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let err = messages(&[5, b'a', b'b'], Framing::LengthDelimited).unwrap_err();
+        assert!(err.to_string().contains("ends inside a message (2 of 5 bytes)"), "{}", err);
+        let err = messages(&[0x80], Framing::LengthDelimited).unwrap_err();
+        assert!(err.to_string().contains("ends inside a length prefix"), "{}", err);
+        let err = messages(&[0, 0], Framing::LengthPrefixed).unwrap_err();
+        assert!(err.to_string().contains("ends inside a length prefix"), "{}", err);
+        assert!(messages(&[0xff; 11], Framing::LengthDelimited).is_err());
+        assert!(messages(b"not base64!\n", Framing::Base64).is_err());
+    }
+
+    #[test]
+    fn stops_on_break() {
+        let path = std::env::temp_dir().join(format!("otk-framing-break-{}", std::process::id()));
+        std::fs::write(&path, "YQ==\nYg==\n").unwrap();
+        let mut seen = 0;
+        for_each_message(path.to_str().unwrap(), Framing::Base64, |_| {
+            seen += 1;
+            Ok(ControlFlow::Break(()))
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(seen, 1);
+    }
+
+
+    #[test]
+    fn expands_globs() {
+        let dir = std::env::temp_dir().join(format!("otk-glob-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.b64", "a.b64", "c.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let dir_name = dir.to_str().unwrap();
+        let inputs = [format!("{}/*.b64", dir_name), "-".into(), "missing.b64".into()];
+        let expanded = expand_inputs(&inputs).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // matches in alphabetical order, other inputs as given
+        assert_eq!(expanded, [format!("{}/a.b64", dir_name), format!("{}/b.b64", dir_name), "-".into(), "missing.b64".into()]);
+        let err = expand_inputs(&[format!("{}/*.b64", dir_name)]).unwrap_err();
+        assert!(err.to_string().contains("no files match"), "{}", err);
+        assert_eq!(input_name("-"), "(standard input)");
+    }
+}
@@ -0,0 +1,387 @@
+use crate::filter;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::KeyValue;
+use crate::proto::resource::v1::Resource;
+use crate::sanity::{is_zero_id, plausible_time};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+
+/// lowercase dot separated snake_case namespaces, as semantic conventions
+/// name attributes and metrics
+static SEMCONV_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z0-9_]+(\.[a-z0-9_]+)*$").unwrap());
+
+/// attribute keys replaced in newer semantic conventions
+pub(crate) const DEPRECATED_KEYS: &[(&str, &str)] = &[
+    ("http.method", "http.request.method"),
+    ("http.status_code", "http.response.status_code"),
+    ("http.url", "url.full"),
+    ("http.target", "url.path"),
+    ("http.scheme", "url.scheme"),
+    ("http.user_agent", "user_agent.original"),
+    ("http.request_content_length", "http.request.body.size"),
+    ("http.response_content_length", "http.response.body.size"),
+    ("net.peer.name", "server.address"),
+    ("net.peer.port", "server.port"),
+    ("net.host.name", "server.address"),
+    ("net.host.port", "server.port"),
+    ("net.sock.peer.addr", "network.peer.address"),
+    ("net.protocol.name", "network.protocol.name"),
+    ("net.protocol.version", "network.protocol.version"),
+    ("db.statement", "db.query.text"),
+];
+
+/// how many checks of one kind were done and passed
+#[derive(Debug, Default)]
+struct Category {
+    checked: u64,
+    passed: u64,
+}
+
+/// grades the requests an sdk under test sends
+#[derive(Debug)]
+pub struct Grader {
+    required: Vec<String>,
+    max_batch: usize,
+    categories: BTreeMap<&'static str, Category>,
+    /// problem -> occurrences, over all categories
+    findings: HashMap<String, u64>,
+    /// requests per signal
+    requests: BTreeMap<&'static str, u64>,
+    batch_sizes: Vec<usize>,
+    /// problems of the requests graded since the last `take_problems`
+    problems: Vec<String>,
+}
+
+impl Grader {
+    /// grade against the resource attributes every resource must have and
+    /// the largest batch not counted as oversized
+    pub fn new(required: Vec<String>, max_batch: usize) -> Self {
+        Grader {
+            required,
+            max_batch,
+            categories: BTreeMap::new(),
+            findings: HashMap::new(),
+            requests: BTreeMap::new(),
+            batch_sizes: vec![],
+            problems: vec![],
+        }
+    }
+
+    /// distinct problems found since the last call, e.g. in the latest request
+    pub fn take_problems(&mut self) -> Vec<String> {
+        let mut problems = std::mem::take(&mut self.problems);
+        problems.sort();
+        problems.dedup();
+        problems
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.values().sum()
+    }
+
+    /// note a problem with a request that could not be graded
+    pub fn reject(&mut self, signal: &'static str, finding: String) {
+        *self.requests.entry(signal).or_insert(0) += 1;
+        self.check("decoding", vec![finding]);
+    }
+
+    pub fn add_traces(&mut self, req: &ExportTraceServiceRequest) {
+        self.check("decoding", vec![]);
+        for rs in &req.resource_spans {
+            self.check_resource(rs.resource.as_ref());
+        }
+        let mut items = 0;
+        for span in filter::spans(req) {
+            items += 1;
+            let s = span.span;
+            let mut problems = vec![];
+            if s.trace_id.len() != 16 || is_zero_id(&s.trace_id) {
+                problems.push("span with invalid trace id".to_string());
+            }
+            if s.span_id.len() != 8 || is_zero_id(&s.span_id) {
+                problems.push("span with invalid span id".to_string());
+            }
+            if !s.parent_span_id.is_empty() && (s.parent_span_id.len() != 8 || is_zero_id(&s.parent_span_id)) {
+                problems.push("span with invalid parent span id".to_string());
+            }
+            self.check("ids", problems);
+
+            let mut problems = vec![];
+            if !plausible_time(s.start_time_unix_nano) || !plausible_time(s.end_time_unix_nano) {
+                problems.push("span with unset or implausible start or end time".to_string());
+            } else if s.end_time_unix_nano < s.start_time_unix_nano {
+                problems.push("span ending before it starts".to_string());
+            }
+            self.check("timestamps", problems);
+            self.check_semconv(&s.attributes, None);
+        }
+        self.add_batch("traces", items);
+    }
+
+    pub fn add_logs(&mut self, req: &ExportLogsServiceRequest) {
+        self.check("decoding", vec![]);
+        for rl in &req.resource_logs {
+            self.check_resource(rl.resource.as_ref());
+        }
+        let mut items = 0;
+        for log in filter::logs(req) {
+            items += 1;
+            let l = log.log;
+            // trace context is optional on logs
+            let mut problems = vec![];
+            if !l.trace_id.is_empty() && (l.trace_id.len() != 16 || is_zero_id(&l.trace_id)) {
+                problems.push("log record with invalid trace id".to_string());
+            }
+            if !l.span_id.is_empty() && (l.span_id.len() != 8 || is_zero_id(&l.span_id)) {
+                problems.push("log record with invalid span id".to_string());
+            }
+            self.check("ids", problems);
+
+            let mut problems = vec![];
+            if l.time_unix_nano != 0 && !plausible_time(l.time_unix_nano) {
+                problems.push("log record with implausible time".to_string());
+            }
+            if !plausible_time(l.observed_time_unix_nano) && l.time_unix_nano == 0 {
+                problems.push("log record with neither time nor observed time".to_string());
+            }
+            self.check("timestamps", problems);
+            self.check_semconv(&l.attributes, None);
+        }
+        self.add_batch("logs", items);
+    }
+
+    pub fn add_metrics(&mut self, req: &ExportMetricsServiceRequest) {
+        self.check("decoding", vec![]);
+        for rm in &req.resource_metrics {
+            self.check_resource(rm.resource.as_ref());
+        }
+        let mut items = 0;
+        for point in filter::points(req) {
+            items += 1;
+            let problems = if plausible_time(point.time_unix_nano) {
+                vec![]
+            } else {
+                vec!["data point with unset or implausible time".to_string()]
+            };
+            self.check("timestamps", problems);
+            self.check_semconv(point.attributes, Some(&point.metric.name));
+            // exemplars link to spans, their ids must be valid when set
+            let invalid = point.exemplars.iter().any(|e| {
+                (!e.trace_id.is_empty() && (e.trace_id.len() != 16 || is_zero_id(&e.trace_id)))
+                    || (!e.span_id.is_empty() && (e.span_id.len() != 8 || is_zero_id(&e.span_id)))
+            });
+            if !point.exemplars.is_empty() {
+                self.check("ids", if invalid { vec!["exemplar with invalid trace or span id".to_string()] } else { vec![] });
+            }
+        }
+        self.add_batch("metrics", items);
+    }
+
+    fn check(&mut self, category: &'static str, problems: Vec<String>) {
+        let c = self.categories.entry(category).or_default();
+        c.checked += 1;
+        if problems.is_empty() {
+            c.passed += 1;
+        }
+        for problem in problems {
+            *self.findings.entry(problem.clone()).or_insert(0) += 1;
+            self.problems.push(problem);
+        }
+    }
+
+    fn check_resource(&mut self, resource: Option<&Resource>) {
+        let attributes = resource.map_or(&[][..], |r| r.attributes.as_slice());
+        let mut problems = vec![];
+        for key in &self.required {
+            if !attributes.iter().any(|kv| kv.key == *key) {
+                problems.push(format!("resource without {}", key));
+            }
+        }
+        let service = attributes.iter().find(|kv| kv.key == "service.name");
+        if service.is_some_and(|kv| crate::common::attr_value(kv).starts_with("unknown_service")) {
+            problems.push("service.name is the unknown_service default".to_string());
+        }
+        self.check("resource attributes", problems);
+        self.check_semconv(attributes, None);
+    }
+
+    fn check_semconv(&mut self, attributes: &[KeyValue], metric_name: Option<&str>) {
+        let mut problems = vec![];
+        if let Some(name) = metric_name {
+            if !SEMCONV_NAME.is_match(name) {
+                problems.push(format!("metric name {:?} is not lowercase dotted snake_case", name));
+            }
+        }
+        for kv in attributes {
+            if !SEMCONV_NAME.is_match(&kv.key) {
+                problems.push(format!("attribute key {:?} is not lowercase dotted snake_case", kv.key));
+            }
+            if let Some((_, new)) = DEPRECATED_KEYS.iter().find(|(old, _)| *old == kv.key) {
+                problems.push(format!("attribute key {} is deprecated, use {}", kv.key, new));
+            }
+        }
+        self.check("semantic conventions", problems);
+    }
+
+    fn add_batch(&mut self, signal: &'static str, items: usize) {
+        *self.requests.entry(signal).or_insert(0) += 1;
+        self.batch_sizes.push(items);
+        let problems = if items > self.max_batch {
+            vec![format!("request with more than {} items", self.max_batch)]
+        } else {
+            vec![]
+        };
+        self.check("batching", problems);
+    }
+
+    /// print the scored report, returning the score (percent of checks
+    /// passed, averaged over the categories)
+    pub fn report(&mut self) -> f64 {
+        let requests = self.requests.iter().map(|(s, n)| format!("{} {}", s, n)).collect::<Vec<_>>();
+        let items: usize = self.batch_sizes.iter().sum();
+        println!("received {} requests ({}) with {} items", self.requests(), requests.join(", "), items);
+        if let (Some(min), Some(max)) = (self.batch_sizes.iter().min(), self.batch_sizes.iter().max()) {
+            println!(
+                "batch size min {}, avg {:.1}, max {}",
+                min,
+                items as f64 / self.batch_sizes.len() as f64,
+                max
+            );
+        }
+        // many single item requests mean spans are exported one by one
+        if self.batch_sizes.len() >= 10 && self.batch_sizes.iter().all(|n| *n <= 1) {
+            self.findings.insert("every request carries a single item, is a batch processor configured?".into(), 1);
+            if let Some(c) = self.categories.get_mut("batching") {
+                c.passed = 0;
+            }
+        }
+        println!();
+        let mut total = 0.0;
+        for (name, c) in &self.categories {
+            let percent = 100.0 * c.passed as f64 / c.checked as f64;
+            total += percent;
+            println!("{:<24}{:>10}  {:>6.1}%", name, format!("{}/{}", c.passed, c.checked), percent);
+        }
+        let score = if self.categories.is_empty() { 0.0 } else { total / self.categories.len() as f64 };
+        println!("score {:.1}", score);
+        if !self.findings.is_empty() {
+            println!("\nfindings:");
+            let mut findings = self.findings.iter().collect::<Vec<_>>();
+            findings.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (finding, n) in findings {
+                println!("{:>10}  {}", n, finding);
+            }
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::v1::any_value::Value;
+    use crate::proto::common::v1::AnyValue;
+    use crate::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+    /// 2023-11-14
+    const NOW: u64 = 1_700_000_000_000_000_000;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(Value::StringValue(value.into())) }) }
+    }
+
+    fn span(attributes: Vec<KeyValue>) -> Span {
+        Span {
+            trace_id: vec![1; 16],
+            span_id: vec![2; 8],
+            start_time_unix_nano: NOW,
+            end_time_unix_nano: NOW + 1,
+            attributes,
+            ..Default::default()
+        }
+    }
+
+    fn traces(service: &str, spans: Vec<Span>) -> ExportTraceServiceRequest {
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource { attributes: vec![kv("service.name", service)], ..Default::default() }),
+                scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn passes_valid_requests() {
+        let mut grader = Grader::new(vec!["service.name".into()], 10);
+        grader.add_traces(&traces("checkout", vec![span(vec![kv("http.request.method", "GET")])]));
+        assert!(grader.take_problems().is_empty());
+        assert_eq!(grader.requests(), 1);
+        assert_eq!(grader.report(), 100.0);
+    }
+
+    #[test]
+    fn finds_problems() {
+        let mut grader = Grader::new(vec!["service.name".into(), "deployment.environment".into()], 1);
+        let bad = Span {
+            trace_id: vec![0; 16],
+            span_id: vec![2; 4],
+            end_time_unix_nano: NOW - 1,
+            ..span(vec![kv("http.method", "GET"), kv("Cart-Size", "3")])
+        };
+        grader.add_traces(&traces("unknown_service:java", vec![bad, span(vec![])]));
+        assert_eq!(
+            grader.take_problems(),
+            [
+                "attribute key \"Cart-Size\" is not lowercase dotted snake_case",
+                "attribute key http.method is deprecated, use http.request.method",
+                "request with more than 1 items",
+                "resource without deployment.environment",
+                "service.name is the unknown_service default",
+                "span ending before it starts",
+                "span with invalid span id",
+                "span with invalid trace id",
+            ]
+        );
+        // problems are taken once
+        assert!(grader.take_problems().is_empty());
+        grader.reject("traces", "invalid protobuf".into());
+        assert_eq!(grader.take_problems(), ["invalid protobuf"]);
+        assert_eq!(grader.requests(), 2);
+        assert!(grader.report() < 100.0);
+    }
+
+    #[test]
+    fn logs_need_no_trace_context() {
+        let mut grader = Grader::new(vec![], 10);
+        let logs = |log_records| ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource { attributes: vec![kv("service.name", "checkout")], ..Default::default() }),
+                scope_logs: vec![ScopeLogs { log_records, ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+        grader.add_logs(&logs(vec![LogRecord { observed_time_unix_nano: NOW, ..Default::default() }]));
+        assert!(grader.take_problems().is_empty());
+        grader.add_logs(&logs(vec![LogRecord { span_id: vec![0; 8], ..Default::default() }]));
+        assert_eq!(
+            grader.take_problems(),
+            ["log record with invalid span id", "log record with neither time nor observed time"]
+        );
+    }
+
+    #[test]
+    fn flags_unbatched_exports() {
+        let mut grader = Grader::new(vec![], 10);
+        for _ in 0..10 {
+            grader.add_traces(&traces("checkout", vec![span(vec![])]));
+        }
+        assert!(grader.take_problems().is_empty());
+        // batching scores 0 of 100, the other categories 100
+        assert_eq!(grader.report(), 500.0 / 6.0);
+    }
+}
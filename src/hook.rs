@@ -0,0 +1,120 @@
+use crate::https::{self, HttpsClient};
+use crate::otk_error::OTKError;
+use opentelemetry_http::{HttpClient, Request};
+use serde_json::Value as Json;
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// how long a webhook may take before it is given up
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// external automation triggered by an event. `exec:<command>` runs the
+/// command with `sh -c`, the event as a json line on its stdin and its kind
+/// in $OTK_EVENT, `webhook:<url>` POSTs the event json to an http or https
+/// url (trusting the system CA bundle)
+#[derive(Debug, Clone)]
+pub enum Hook {
+    Exec(String),
+    Webhook(String),
+}
+
+impl FromStr for Hook {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("exec", command)) if !command.is_empty() => Ok(Hook::Exec(command.to_string())),
+            Some(("webhook", url)) if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(Hook::Webhook(url.to_string()))
+            }
+            _ => Err(OTKError::ParseError(format!(
+                "invalid hook {} (expect exec:<command> or webhook:<url>)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hook::Exec(command) => write!(f, "exec:{}", command),
+            Hook::Webhook(url) => write!(f, "webhook:{}", url),
+        }
+    }
+}
+
+impl Hook {
+    /// run the hook and wait for it. failures are only logged, so a broken
+    /// hook doesn't stop what fired it
+    pub fn fire(&self, event: &Json) {
+        if let Err(e) = self.run(event) {
+            eprintln!("hook {} failed: {}", self, e);
+        }
+    }
+
+    fn run(&self, event: &Json) -> Result<(), String> {
+        match self {
+            Hook::Exec(command) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("OTK_EVENT", event["event"].as_str().unwrap_or_default())
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| e.to_string())?;
+                // a command that doesn't read its stdin is fine
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = writeln!(stdin, "{}", event);
+                }
+                let status = child.wait().map_err(|e| e.to_string())?;
+                if !status.success() {
+                    return Err(format!("command {}", status));
+                }
+                Ok(())
+            }
+            Hook::Webhook(url) => {
+                // on a thread of its own, a runtime can't be started inside
+                // the async code some hooks fire from
+                let (url, body) = (url.clone(), event.to_string());
+                thread::spawn(move || post(&url, body))
+                    .join()
+                    .unwrap_or_else(|_| Err("webhook thread panicked".into()))
+            }
+        }
+    }
+}
+
+/// fire every hook with the same event
+pub fn fire_all(hooks: &[Hook], event: &Json) {
+    for hook in hooks {
+        hook.fire(event);
+    }
+}
+
+fn post(url: &str, body: String) -> Result<(), String> {
+    // reqwest is built without tls, https goes through the exporters' client
+    let client: Box<dyn HttpClient> = match url.starts_with("https://") {
+        true => {
+            let config = https::client_config(None, false, false).map_err(|e| e.to_string())?;
+            Box::new(HttpsClient::new(config, None, false, WEBHOOK_TIMEOUT))
+        }
+        false => Box::new(reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build().map_err(|e| e.to_string())?),
+    };
+    let request = Request::post(url)
+        .header("content-type", "application/json")
+        .body(body.into_bytes())
+        .map_err(|e| e.to_string())?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let response = runtime.block_on(client.send(request)).map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    Ok(())
+}
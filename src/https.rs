@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::Body;
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// where distributions keep their CA bundle, used when no --ca-cert is given
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// https client of the otlp/http exporters. every request opens its own
+/// connection, exports are too infrequent for pooling to matter
+#[derive(Clone)]
+pub struct HttpsClient {
+    tls: TlsConnector,
+    /// name the certificate is verified against instead of the url host
+    domain: Option<String>,
+    http2: bool,
+    timeout: Duration,
+}
+
+impl fmt::Debug for HttpsClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpsClient")
+            .field("domain", &self.domain)
+            .field("http2", &self.http2)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl HttpsClient {
+    pub fn new(config: ClientConfig, domain: Option<String>, http2: bool, timeout: Duration) -> Self {
+        HttpsClient {
+            tls: TlsConnector::from(Arc::new(config)),
+            domain,
+            http2,
+            timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for HttpsClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        tokio::time::timeout(self.timeout, self.send_request(request))
+            .await
+            .map_err(|_| format!("request timed out after {:?}", self.timeout))?
+    }
+}
+
+impl HttpsClient {
+    async fn send_request(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let response = self.send_body(request.map(Body::from)).await?;
+        let (parts, body) = response.into_parts();
+        Ok(Response::from_parts(parts, hyper::body::to_bytes(body).await?))
+    }
+
+    /// send on a new connection, the response body still streaming (and
+    /// keeping its trailers)
+    pub async fn send_body(&self, mut request: Request<Body>) -> Result<Response<Body>, HttpError> {
+        let uri = request.uri().clone();
+        let host = uri.host().ok_or("url without host")?.to_string();
+        let tcp = TcpStream::connect((host.as_str(), uri.port_u16().unwrap_or(443))).await?;
+        let name = ServerName::try_from(self.domain.as_deref().unwrap_or(&host))?;
+        let stream = self.tls.connect(name, tcp).await?;
+        if !self.http2 {
+            // http/1.1 wants the path in the request line and the host apart
+            let authority = uri.authority().map_or(host, |a| a.to_string());
+            request.headers_mut().insert("host", authority.parse()?);
+            *request.uri_mut() = uri.path_and_query().map_or("/", |p| p.as_str()).parse()?;
+        }
+        send_on(stream, request, self.http2).await
+    }
+}
+
+pub async fn send_on<S>(stream: S, request: Request<Body>, http2: bool) -> Result<Response<Body>, HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(http2)
+        .handshake(stream)
+        .await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    Ok(sender.send_request(request).await?)
+}
+
+/// $SSL_CERT_FILE or the CA bundle of the distribution
+pub fn system_ca_bundle() -> Option<String> {
+    std::env::var("SSL_CERT_FILE")
+        .ok()
+        .or_else(|| SYSTEM_CA_BUNDLES.iter().find(|p| std::path::Path::new(p).exists()).map(|p| p.to_string()))
+}
+
+/// tls client config trusting `ca_cert` (a pem file) or else the system CA
+/// bundle, or any certificate when `insecure`
+pub fn client_config(
+    ca_cert: Option<&str>,
+    insecure: bool,
+    http2: bool,
+) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    let mut roots = RootCertStore::empty();
+    if !insecure {
+        let path = match ca_cert {
+            Some(path) => path.to_string(),
+            None => system_ca_bundle().ok_or("no system CA bundle found, pass --ca-cert")?,
+        };
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&path)?))?;
+        let (added, _) = roots.add_parsable_certificates(&certs);
+        if added == 0 {
+            return Err(format!("no certificates in {}", path).into());
+        }
+    }
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    if insecure {
+        config.dangerous().set_certificate_verifier(Arc::new(AnyCertificate));
+    }
+    config.alpn_protocols = vec![if http2 { b"h2".to_vec() } else { b"http/1.1".to_vec() }];
+    Ok(config)
+}
+
+/// accepts every server certificate, for --insecure-skip-verify
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,281 @@
+use crate::common::attr_value;
+use crate::filter;
+use crate::mmap::Mmap;
+use crate::otk_error::OTKError;
+use crate::proto;
+use hex::ToHex;
+use prost::Message;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &str = "otkidx 2";
+
+/// bloom filter bits per term, about 1% false positives with `BLOOM_HASHES`
+const BLOOM_BITS_PER_TERM: usize = 10;
+const BLOOM_HASHES: u64 = 7;
+
+/// index path of a capture file
+pub fn index_path(capture: &str) -> String {
+    format!("{}.otkidx", capture)
+}
+
+/// size and mtime of the capture, recorded in the index to detect staleness
+fn fingerprint(capture: &str) -> Result<String, Box<dyn error::Error>> {
+    let meta = fs::metadata(capture)?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok(format!("{} {}", meta.len(), mtime))
+}
+
+/// scan a capture of base64 trace requests and write the trace id -> line
+/// offsets index next to it, along with a bloom filter of the attributes of
+/// each line. returns the number of indexed trace ids
+pub fn build(capture: &str) -> Result<usize, Box<dyn error::Error>> {
+    let mut offsets: BTreeMap<String, BTreeSet<u64>> = BTreeMap::new();
+    let mut blooms = vec![];
+    let mut reader = BufReader::new(File::open(capture)?);
+    let mut offset = 0u64;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let n = reader.read_line(&mut line)?;
+        if n == 0 {
+            break;
+        }
+        let payload = line.trim_end();
+        if !payload.is_empty() {
+            let bs = base64::decode_config(payload, base64::STANDARD)?;
+            let req = proto::collector::trace::v1::ExportTraceServiceRequest::decode(&bs as &[u8])?;
+            let mut terms = HashSet::new();
+            for span in filter::spans(&req) {
+                offsets.entry(span.span.trace_id.encode_hex()).or_default().insert(offset);
+                let scope = span.scope.map_or(&[][..], |s| &s.attributes[..]);
+                let resource = span.resource.map_or(&[][..], |r| &r.attributes[..]);
+                for kv in span.span.attributes.iter().chain(scope).chain(resource) {
+                    terms.insert(kv.key.clone());
+                    terms.insert(format!("{}={}", kv.key, attr_value(kv)));
+                }
+            }
+            blooms.push((offset, Bloom::new(&terms)));
+        }
+        offset += n as u64;
+    }
+
+    let mut out = BufWriter::new(File::create(index_path(capture))?);
+    writeln!(out, "{} {}", MAGIC, fingerprint(capture)?)?;
+    for (trace_id, offsets) in &offsets {
+        let offsets = offsets.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        writeln!(out, "{} {}", trace_id, offsets.join(","))?;
+    }
+    for (offset, bloom) in &blooms {
+        writeln!(out, "bloom {} {}", offset, base64::encode(&bloom.bits))?;
+    }
+    out.flush()?;
+    Ok(offsets.len())
+}
+
+/// sorted line offsets holding any of `trace_ids` (any line if not given)
+/// whose attributes may contain all `terms` (`key` or `key=value`), or `None`
+/// when there is no index or it is out of date with the capture
+pub fn lookup(
+    capture: &str,
+    trace_ids: Option<&HashSet<String>>,
+    terms: &[String],
+) -> Result<Option<Vec<u64>>, Box<dyn error::Error>> {
+    let file = match File::open(index_path(capture)) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    let mut lines = BufReader::new(file).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if header != format!("{} {}", MAGIC, fingerprint(capture)?) {
+        eprintln!("index {} is stale, rebuild it with `otk index`", index_path(capture));
+        return Ok(None);
+    }
+    let corrupt = |line: &str| OTKError::ParseError(format!("corrupt index line: {}", line));
+    let mut found = BTreeSet::new();
+    let mut candidates = BTreeSet::new();
+    for line in lines {
+        let line = line?;
+        if let Some(bloom) = line.strip_prefix("bloom ") {
+            let (offset, bits) = bloom.split_once(' ').ok_or_else(|| corrupt(&line))?;
+            let bloom = Bloom { bits: base64::decode(bits).map_err(|_| corrupt(&line))? };
+            if terms.iter().all(|t| bloom.may_contain(t)) {
+                candidates.insert(offset.parse().map_err(|_| corrupt(&line))?);
+            }
+            continue;
+        }
+        let (id, offsets) = line.split_once(' ').ok_or_else(|| corrupt(&line))?;
+        if trace_ids.is_some_and(|ids| ids.contains(id)) {
+            for offset in offsets.split(',') {
+                found.insert(offset.parse().map_err(|_| corrupt(&line))?);
+            }
+        }
+    }
+    Ok(Some(match trace_ids {
+        Some(_) => found.intersection(&candidates).copied().collect(),
+        None => candidates.into_iter().collect(),
+    }))
+}
+
+/// which attribute terms a line may contain
+struct Bloom {
+    bits: Vec<u8>,
+}
+
+impl Bloom {
+    fn new(terms: &HashSet<String>) -> Bloom {
+        let mut bloom = Bloom { bits: vec![0; (terms.len() * BLOOM_BITS_PER_TERM).div_ceil(8).max(8)] };
+        for term in terms {
+            for bit in bloom.positions(term) {
+                bloom.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bloom
+    }
+
+    fn may_contain(&self, term: &str) -> bool {
+        !self.bits.is_empty() && self.positions(term).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// double hashing over two fnv-1a hashes, stable across builds unlike
+    /// the std hasher
+    fn positions(&self, term: &str) -> impl Iterator<Item = usize> {
+        let fnv = |seed: u64| {
+            term.bytes()
+                .fold(0xcbf2_9ce4_8422_2325 ^ seed, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3))
+        };
+        let (h1, h2) = (fnv(0), fnv(0x9e37_79b9_7f4a_7c15) | 1);
+        let m = self.bits.len() as u64 * 8;
+        (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+}
+
+/// 1-based line numbers of the lines starting at the given sorted offsets
+pub fn line_numbers(capture: &str, offsets: &[u64]) -> Result<Vec<usize>, Box<dyn error::Error>> {
+    let map = Mmap::open(capture)?;
+    let mut numbers = vec![];
+    let (mut pos, mut line) = (0, 1);
+    for offset in offsets {
+        let offset = (*offset as usize).clamp(pos, map.len());
+        line += map[pos..offset].iter().filter(|b| **b == b'\n').count();
+        pos = offset;
+        numbers.push(line);
+    }
+    Ok(numbers)
+}
+
+/// read the capture lines starting at the given offsets
+pub fn read_lines_at(capture: &str, offsets: &[u64]) -> Result<Vec<String>, Box<dyn error::Error>> {
+    let mut reader = BufReader::new(File::open(capture)?);
+    let mut lines = vec![];
+    for offset in offsets {
+        reader.seek(SeekFrom::Start(*offset))?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        lines.push(line.trim_end().to_string());
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::proto::common::v1::{any_value, AnyValue, KeyValue};
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+    /// a base64 line of a request with a span of each trace id and attribute
+    fn line(spans: &[(u8, &str)]) -> String {
+        let spans = spans
+            .iter()
+            .map(|(id, attr)| {
+                let (key, value) = attr.split_once('=').unwrap();
+                let value = AnyValue { value: Some(any_value::Value::StringValue(value.into())) };
+                Span {
+                    trace_id: vec![*id; 16],
+                    attributes: vec![KeyValue { key: key.into(), value: Some(value) }],
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+        base64::encode(request.encode_to_vec()) + "\n"
+    }
+
+    /// a capture of three lines, removed with its index when dropped
+    struct Capture(String);
+
+    impl Capture {
+        fn new(name: &str) -> Capture {
+            let path = std::env::temp_dir().join(format!("otk-index-{}-{}.b64", name, std::process::id()));
+            let lines = [line(&[(1, "tier=web")]), line(&[(2, "tier=db"), (3, "tier=web")]), line(&[(1, "tier=db")])];
+            fs::write(&path, lines.concat()).unwrap();
+            Capture(path.to_str().unwrap().to_string())
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(index_path(&self.0));
+        }
+    }
+
+    fn ids(ids: &[u8]) -> HashSet<String> {
+        ids.iter().map(|id| format!("{:02x}", id).repeat(16)).collect()
+    }
+
+    #[test]
+    fn looks_up_trace_ids() {
+        let capture = Capture::new("ids");
+        assert_eq!(lookup(&capture.0, Some(&ids(&[1])), &[]).unwrap(), None);
+        assert_eq!(build(&capture.0).unwrap(), 3);
+        let offsets = lookup(&capture.0, Some(&ids(&[1])), &[]).unwrap().unwrap();
+        assert_eq!(offsets.len(), 2);
+        let lines = read_lines_at(&capture.0, &offsets).unwrap();
+        let expected = [line(&[(1, "tier=web")]), line(&[(1, "tier=db")])];
+        assert_eq!(lines, expected.map(|l| l.trim_end().to_string()));
+        assert_eq!(lookup(&capture.0, Some(&ids(&[3, 4])), &[]).unwrap().unwrap().len(), 1);
+        assert!(lookup(&capture.0, Some(&ids(&[4])), &[]).unwrap().unwrap().is_empty());
+
+        // a capture that changed since is not looked up
+        fs::write(&capture.0, line(&[(4, "tier=web")])).unwrap();
+        assert_eq!(lookup(&capture.0, Some(&ids(&[4])), &[]).unwrap(), None);
+    }
+
+
+    #[test]
+    fn line_numbers_of_offsets() {
+        let capture = Capture::new("lines");
+        build(&capture.0).unwrap();
+        let offsets = lookup(&capture.0, Some(&ids(&[1, 3])), &[]).unwrap().unwrap();
+        assert_eq!(line_numbers(&capture.0, &offsets).unwrap(), [1, 2, 3]);
+        assert_eq!(line_numbers(&capture.0, &offsets[2..]).unwrap(), [3]);
+    }
+
+
+    #[test]
+    fn narrows_by_attributes() {
+        let capture = Capture::new("bloom");
+        build(&capture.0).unwrap();
+        let terms = |terms: &[&str]| terms.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let lines = |ids: Option<&HashSet<String>>, t: &[&str]| lookup(&capture.0, ids, &terms(t)).unwrap().unwrap().len();
+        assert_eq!(lines(None, &["tier=web"]), 2);
+        assert_eq!(lines(None, &["tier=db", "tier=web"]), 1);
+        assert_eq!(lines(None, &["tier"]), 3);
+        assert_eq!(lines(Some(&ids(&[1])), &["tier=db"]), 1);
+
+        let set = ["a", "b=1"].iter().map(|t| t.to_string()).collect();
+        let bloom = Bloom::new(&set);
+        assert!(bloom.may_contain("a") && bloom.may_contain("b=1"));
+        assert!(!Bloom::new(&HashSet::new()).may_contain("a"));
+    }
+}
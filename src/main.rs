@@ -1,4 +1,3 @@
-#![feature(str_split_remainder)]
 #[macro_use] extern crate quick_error;
 use clap::Parser;
 use std::error;
@@ -9,8 +8,49 @@ mod cmd_report_trace;
 mod cmd_report_metric;
 mod cmd_report_log;
 mod cmd_search;
+mod cmd_inject;
+mod cmd_soak;
+mod cmd_stats;
+mod cmd_index;
+mod cmd_values;
+mod cmd_check;
+mod cmd_conformance;
+mod cmd_examples;
+mod cmd_watchdog;
+mod cmd_explain_sampling;
+mod cmd_bench_encode;
+mod cmd_slice;
+mod cmd_demo;
+mod cmd_schema;
+mod cmd_query;
 mod otk_error;
 mod common;
+mod sizes;
+mod sanity;
+mod grade;
+mod compression;
+mod hook;
+mod https;
+mod token;
+mod replay;
+mod framing;
+mod mmap;
+mod transport;
+mod report;
+mod filter;
+mod query;
+mod openmetrics;
+mod index;
+mod convert;
+mod otlp_json;
+mod scenario;
+mod store;
+mod verify;
+mod api;
+mod dry_run;
+mod export;
+mod unix_socket;
+mod render;
 
 #[derive(Parser, Debug)]
 /// OpenTelemetry Toolkits
@@ -20,6 +60,7 @@ struct Opts {
 }
 
 #[derive(Parser, Debug)]
+#[allow(clippy::large_enum_variant)]
 enum SubCommand {
     #[clap(version="1.0", aliases=&["d", "de", "dec"])]
     Decode(cmd_decode::Decode),
@@ -30,7 +71,37 @@ enum SubCommand {
     #[clap(version="1.0", aliases=&["l", "rl", "repl", "log"])]
     ReportLog(cmd_report_log::Report),
     #[clap(version="1.0", aliases=&["s", "st"])]
-    Search(cmd_search::Search)
+    Search(cmd_search::Search),
+    #[clap(version="1.0", aliases=&["i", "inj"])]
+    Inject(cmd_inject::Inject),
+    #[clap(version="1.0")]
+    Soak(cmd_soak::Soak),
+    #[clap(version="1.0", aliases=&["stat"])]
+    Stats(cmd_stats::Stats),
+    #[clap(version="1.0", aliases=&["idx"])]
+    Index(cmd_index::Index),
+    #[clap(version="1.0", aliases=&["v", "val"])]
+    Values(cmd_values::Values),
+    #[clap(version="1.0", aliases=&["c", "chk"])]
+    Check(cmd_check::Check),
+    #[clap(version="1.0", aliases=&["conf"])]
+    Conformance(cmd_conformance::Conformance),
+    #[clap(version="1.0", aliases=&["ex", "eg"])]
+    Examples(cmd_examples::Examples),
+    #[clap(version="1.0", aliases=&["wd", "watch"])]
+    Watchdog(cmd_watchdog::Watchdog),
+    #[clap(version="1.0", aliases=&["es", "sampling"])]
+    ExplainSampling(cmd_explain_sampling::ExplainSampling),
+    #[clap(version="1.0", aliases=&["bench"])]
+    BenchEncode(cmd_bench_encode::BenchEncode),
+    #[clap(version="1.0")]
+    Slice(cmd_slice::Slice),
+    #[clap(version="1.0")]
+    Demo(cmd_demo::Demo),
+    #[clap(version="1.0")]
+    Schema(cmd_schema::Schema),
+    #[clap(version="1.0", aliases=&["q", "sql"])]
+    Query(cmd_query::Query),
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
@@ -51,6 +122,51 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         SubCommand::Search(search) => {
             cmd_search::do_search(search)?
         },
+        SubCommand::Inject(inject) => {
+            cmd_inject::do_inject(inject)?
+        },
+        SubCommand::Soak(soak) => {
+            cmd_soak::do_soak(soak)?
+        },
+        SubCommand::Stats(stats) => {
+            cmd_stats::do_stats(stats)?
+        },
+        SubCommand::Index(index) => {
+            cmd_index::do_index(index)?
+        },
+        SubCommand::Values(values) => {
+            cmd_values::do_values(values)?
+        },
+        SubCommand::Check(check) => {
+            cmd_check::do_check(check)?
+        },
+        SubCommand::Conformance(conformance) => {
+            cmd_conformance::do_conformance(conformance)?
+        },
+        SubCommand::Examples(examples) => {
+            cmd_examples::do_examples(examples)?
+        },
+        SubCommand::Watchdog(watchdog) => {
+            cmd_watchdog::do_watchdog(watchdog)?
+        },
+        SubCommand::ExplainSampling(explain) => {
+            cmd_explain_sampling::do_explain_sampling(explain)?
+        },
+        SubCommand::BenchEncode(bench) => {
+            cmd_bench_encode::do_bench_encode(bench)?
+        },
+        SubCommand::Slice(slice) => {
+            cmd_slice::do_slice(slice)?
+        },
+        SubCommand::Demo(demo) => {
+            cmd_demo::do_demo(demo)?
+        },
+        SubCommand::Schema(schema) => {
+            cmd_schema::do_schema(schema)?
+        },
+        SubCommand::Query(query) => {
+            cmd_query::do_query(query)?
+        },
     }
     Ok(())
 }
@@ -0,0 +1,70 @@
+use std::fmt::Write;
+
+/// minimal OpenMetrics text exposition writer. samples of a family have to
+/// be written right after its `family` call
+#[derive(Debug, Default)]
+pub struct OpenMetrics {
+    out: String,
+}
+
+impl OpenMetrics {
+    /// start a metric family, `kind` is an OpenMetrics type like gauge or counter
+    pub fn family(&mut self, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        if let Some(unit) = unit {
+            let _ = writeln!(self.out, "# UNIT {} {}", name, unit);
+        }
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect::<Vec<_>>();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    /// terminate the exposition and write it to `path`
+    pub fn write_to(mut self, path: &str) -> std::io::Result<()> {
+        self.out.push_str("# EOF\n");
+        std::fs::write(path, self.out)
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_exposition() {
+        let path = std::env::temp_dir().join(format!("otk-{}.om", std::process::id()));
+        let mut om = OpenMetrics::default();
+        om.family("otk_latency_seconds", "gauge", Some("seconds"), "latency");
+        om.sample("otk_latency_seconds", &[("capture", "a \"b\"\\c\n")], 0.5);
+        om.family("otk_spans", "counter", None, "spans");
+        om.sample("otk_spans", &[], 3.);
+        om.write_to(path.to_str().unwrap()).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "# TYPE otk_latency_seconds gauge\n\
+             # UNIT otk_latency_seconds seconds\n\
+             # HELP otk_latency_seconds latency\n\
+             otk_latency_seconds{capture=\"a \\\"b\\\"\\\\c\\n\"} 0.5\n\
+             # TYPE otk_spans counter\n\
+             # HELP otk_spans spans\n\
+             otk_spans 3\n\
+             # EOF\n"
+        );
+    }
+}
@@ -10,5 +10,8 @@ quick_error! {
         InvalidArgumentError(err: String) {
             display("Invalid argument: {}", err)
         }
+        CheckError(err: String) {
+            display("Check failed: {}", err)
+        }
     }
 }
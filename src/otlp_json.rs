@@ -0,0 +1,639 @@
+use crate::convert;
+use crate::compression::Compression;
+use crate::export::HttpStatusError;
+use crate::token::TokenSource;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::{any_value::Value, AnyValue, InstrumentationScope, KeyValue};
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::{span, Span, Status};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hex::ToHex;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, StatusCode};
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use crate::otk_error::OTKError;
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::error;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// otlp messages and enums by full name (`.opentelemetry.proto.trace.v1.Span`)
+struct Descriptors {
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, EnumDescriptorProto>,
+}
+
+static DESCRIPTORS: Lazy<Descriptors> = Lazy::new(|| {
+    let set = FileDescriptorSet::decode(&include_bytes!(concat!(env!("OUT_DIR"), "/otlp_descriptors.bin"))[..])
+        .expect("invalid otlp descriptors");
+    let mut descriptors = Descriptors { messages: HashMap::new(), enums: HashMap::new() };
+    for file in set.file {
+        descriptors.add(&format!(".{}", file.package()), &file.message_type, &file.enum_type);
+    }
+    descriptors
+});
+
+impl Descriptors {
+    fn add(&mut self, scope: &str, messages: &[DescriptorProto], enums: &[EnumDescriptorProto]) {
+        for e in enums {
+            self.enums.insert(format!("{}.{}", scope, e.name()), e.clone());
+        }
+        for m in messages {
+            let name = format!("{}.{}", scope, m.name());
+            self.add(&name, &m.nested_type, &m.enum_type);
+            self.messages.insert(name, m.clone());
+        }
+    }
+}
+
+/// decode an OTLP/JSON request body into the protobuf message `name` (like
+/// `opentelemetry.proto.collector.trace.v1.ExportTraceServiceRequest`),
+/// unknown fields are ignored
+pub fn from_json<M: Message + Default>(name: &str, body: &[u8]) -> Result<M, Box<dyn error::Error>> {
+    let json: Json = serde_json::from_slice(body)?;
+    let mut buf = vec![];
+    encode_message(&format!(".{}", name), &json, &mut buf).map_err(OTKError::ParseError)?;
+    Ok(M::decode(&buf[..])?)
+}
+
+fn encode_message(name: &str, json: &Json, out: &mut Vec<u8>) -> Result<(), String> {
+    let message = DESCRIPTORS.messages.get(name).ok_or_else(|| format!("unknown message {}", name))?;
+    let object = json.as_object().ok_or_else(|| format!("expect an object for {}", short_name(name)))?;
+    for (key, value) in object {
+        let Some(field) = message.field.iter().find(|f| f.json_name() == key || f.name() == key) else {
+            continue;
+        };
+        match value {
+            Json::Null => {}
+            Json::Array(values) if field.label() == Label::Repeated => {
+                for value in values {
+                    encode_field(field, value, out)?;
+                }
+            }
+            _ if field.label() == Label::Repeated => return Err(format!("expect an array for {}", key)),
+            _ => encode_field(field, value, out)?,
+        }
+    }
+    Ok(())
+}
+
+/// the protobuf json mapping, except that otlp ids are hex instead of base64
+fn encode_field(field: &FieldDescriptorProto, value: &Json, out: &mut Vec<u8>) -> Result<(), String> {
+    let number = field.number() as u64;
+    let err = || format!("invalid value {} for {}", value, field.json_name());
+    let bytes = |out: &mut Vec<u8>, data: &[u8]| {
+        put_varint(out, number << 3 | 2);
+        put_varint(out, data.len() as u64);
+        out.extend_from_slice(data);
+    };
+    match field.r#type() {
+        Type::Message => {
+            let mut buf = vec![];
+            encode_message(field.type_name(), value, &mut buf)?;
+            bytes(out, &buf);
+        }
+        Type::String => bytes(out, value.as_str().ok_or_else(err)?.as_bytes()),
+        Type::Bytes => {
+            let s = value.as_str().ok_or_else(err)?;
+            let data = if matches!(field.name(), "trace_id" | "span_id" | "parent_span_id") {
+                hex::decode(s).map_err(|_| err())?
+            } else {
+                base64::decode(s).map_err(|_| err())?
+            };
+            bytes(out, &data);
+        }
+        Type::Bool => {
+            put_varint(out, number << 3);
+            put_varint(out, value.as_bool().ok_or_else(err)? as u64);
+        }
+        Type::Double => {
+            put_varint(out, number << 3 | 1);
+            out.extend(json_f64(value).ok_or_else(err)?.to_le_bytes());
+        }
+        Type::Float => {
+            put_varint(out, number << 3 | 5);
+            out.extend((json_f64(value).ok_or_else(err)? as f32).to_le_bytes());
+        }
+        Type::Int64 | Type::Int32 => {
+            put_varint(out, number << 3);
+            put_varint(out, json_i64(value).ok_or_else(err)? as u64);
+        }
+        Type::Uint64 | Type::Uint32 => {
+            put_varint(out, number << 3);
+            put_varint(out, json_u64(value).ok_or_else(err)?);
+        }
+        Type::Sint64 | Type::Sint32 => {
+            let v = json_i64(value).ok_or_else(err)?;
+            put_varint(out, number << 3);
+            put_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+        }
+        Type::Fixed64 | Type::Sfixed64 => {
+            let v = match field.r#type() {
+                Type::Fixed64 => json_u64(value),
+                _ => json_i64(value).map(|v| v as u64),
+            };
+            put_varint(out, number << 3 | 1);
+            out.extend(v.ok_or_else(err)?.to_le_bytes());
+        }
+        Type::Fixed32 | Type::Sfixed32 => {
+            let v = match field.r#type() {
+                Type::Fixed32 => json_u64(value),
+                _ => json_i64(value).map(|v| v as u64),
+            };
+            put_varint(out, number << 3 | 5);
+            out.extend((v.ok_or_else(err)? as u32).to_le_bytes());
+        }
+        Type::Enum => {
+            // enums are integers in otlp json, but accept their names too
+            let v = match value.as_str() {
+                Some(name) => DESCRIPTORS
+                    .enums
+                    .get(field.type_name())
+                    .and_then(|e| e.value.iter().find(|v| v.name() == name))
+                    .map(|v| v.number() as i64),
+                None => json_i64(value),
+            };
+            put_varint(out, number << 3);
+            put_varint(out, v.ok_or_else(err)? as u64);
+        }
+        Type::Group => return Err(format!("unsupported group field {}", field.name())),
+    }
+    Ok(())
+}
+
+/// 64 bit integers are strings in json, but numbers are accepted too
+fn json_i64(value: &Json) -> Option<i64> {
+    value.as_i64().or_else(|| value.as_str()?.parse().ok())
+}
+
+fn json_u64(value: &Json) -> Option<u64> {
+    value.as_u64().or_else(|| value.as_str()?.parse().ok())
+}
+
+fn json_f64(value: &Json) -> Option<f64> {
+    match value.as_str() {
+        Some("NaN") => Some(f64::NAN),
+        Some("Infinity") => Some(f64::INFINITY),
+        Some("-Infinity") => Some(f64::NEG_INFINITY),
+        Some(s) => s.parse().ok(),
+        None => value.as_f64(),
+    }
+}
+
+/// encode an otlp protobuf message `name` as OTLP/JSON (the inverse of
+/// from_json), for signals without a hand written encoding like
+/// trace_request
+pub fn to_json(name: &str, body: &[u8]) -> Result<Json, OTKError> {
+    decode_message(&format!(".{}", name), body).map_err(OTKError::ParseError)
+}
+
+fn decode_message(name: &str, mut data: &[u8]) -> Result<Json, String> {
+    let message = DESCRIPTORS.messages.get(name).ok_or_else(|| format!("unknown message {}", name))?;
+    let truncated = || format!("truncated {}", short_name(name));
+    let mut object = serde_json::Map::new();
+    while !data.is_empty() {
+        let tag = get_varint(&mut data).ok_or_else(truncated)?;
+        let (number, wire_type) = ((tag >> 3) as i32, tag & 7);
+        let raw = match wire_type {
+            0 => Wire::Varint(get_varint(&mut data).ok_or_else(truncated)?),
+            1 => Wire::Fixed64(take::<8>(&mut data).ok_or_else(truncated)?),
+            2 => {
+                let len = get_varint(&mut data).ok_or_else(truncated)? as usize;
+                let bytes = data.get(..len).ok_or_else(truncated)?;
+                data = &data[len..];
+                Wire::Bytes(bytes)
+            }
+            5 => Wire::Fixed32(take::<4>(&mut data).ok_or_else(truncated)?),
+            other => return Err(format!("unsupported wire type {} in {}", other, short_name(name))),
+        };
+        // unknown fields are skipped
+        let Some(field) = message.field.iter().find(|f| f.number() == number) else {
+            continue;
+        };
+        let values = match raw {
+            // packed repeated scalars
+            Wire::Bytes(mut packed) if !matches!(field.r#type(), Type::Message | Type::String | Type::Bytes) => {
+                let mut values = vec![];
+                while !packed.is_empty() {
+                    let raw = match field.r#type() {
+                        Type::Double | Type::Fixed64 | Type::Sfixed64 => Wire::Fixed64(take::<8>(&mut packed).ok_or_else(truncated)?),
+                        Type::Float | Type::Fixed32 | Type::Sfixed32 => Wire::Fixed32(take::<4>(&mut packed).ok_or_else(truncated)?),
+                        _ => Wire::Varint(get_varint(&mut packed).ok_or_else(truncated)?),
+                    };
+                    values.push(decode_field(field, raw)?);
+                }
+                values
+            }
+            raw => vec![decode_field(field, raw)?],
+        };
+        let key = field.json_name().to_string();
+        match field.label() {
+            Label::Repeated => {
+                let entry = object.entry(key).or_insert_with(|| Json::Array(vec![]));
+                entry.as_array_mut().unwrap().extend(values);
+            }
+            _ => {
+                object.insert(key, values.into_iter().last().unwrap_or(Json::Null));
+            }
+        }
+    }
+    Ok(Json::Object(object))
+}
+
+/// a field as read off the wire
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+/// the protobuf json mapping, except that otlp ids are hex instead of base64
+fn decode_field(field: &FieldDescriptorProto, raw: Wire) -> Result<Json, String> {
+    let f64_json = |v: f64| match v {
+        v if v.is_nan() => json!("NaN"),
+        v if v == f64::INFINITY => json!("Infinity"),
+        v if v == f64::NEG_INFINITY => json!("-Infinity"),
+        v => json!(v),
+    };
+    Ok(match (field.r#type(), raw) {
+        (Type::Message, Wire::Bytes(bytes)) => decode_message(field.type_name(), bytes)?,
+        (Type::String, Wire::Bytes(bytes)) => json!(String::from_utf8_lossy(bytes)),
+        (Type::Bytes, Wire::Bytes(bytes)) => match field.name() {
+            "trace_id" | "span_id" | "parent_span_id" => json!(bytes.encode_hex::<String>()),
+            _ => json!(base64::encode(bytes)),
+        },
+        (Type::Bool, Wire::Varint(v)) => json!(v != 0),
+        (Type::Int64, Wire::Varint(v)) => json!((v as i64).to_string()),
+        (Type::Uint64, Wire::Varint(v)) => json!(v.to_string()),
+        (Type::Sint64, Wire::Varint(v)) => json!(((v >> 1) as i64 ^ -((v & 1) as i64)).to_string()),
+        (Type::Int32 | Type::Enum, Wire::Varint(v)) => json!(v as i32),
+        (Type::Uint32, Wire::Varint(v)) => json!(v as u32),
+        (Type::Sint32, Wire::Varint(v)) => json!((v >> 1) as i32 ^ -((v & 1) as i32)),
+        (Type::Double, Wire::Fixed64(b)) => f64_json(f64::from_le_bytes(b)),
+        (Type::Fixed64, Wire::Fixed64(b)) => json!(u64::from_le_bytes(b).to_string()),
+        (Type::Sfixed64, Wire::Fixed64(b)) => json!(i64::from_le_bytes(b).to_string()),
+        (Type::Float, Wire::Fixed32(b)) => f64_json(f32::from_le_bytes(b) as f64),
+        (Type::Fixed32, Wire::Fixed32(b)) => json!(u32::from_le_bytes(b)),
+        (Type::Sfixed32, Wire::Fixed32(b)) => json!(i32::from_le_bytes(b)),
+        _ => return Err(format!("unexpected wire type for {}", field.json_name())),
+    })
+}
+
+fn get_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut v = 0u64;
+    for (i, b) in data.iter().enumerate().take(10) {
+        v |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Some(v);
+        }
+    }
+    None
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+    let bytes = data.get(..N)?.try_into().ok()?;
+    *data = &data[N..];
+    Some(bytes)
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn short_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+/// OTLP/JSON encoding of a trace request: camelCase fields, hex ids, enums
+/// as numbers and 64 bit integers as strings
+pub fn trace_request(req: &ExportTraceServiceRequest) -> Json {
+    json!({
+        "resourceSpans": req.resource_spans.iter().map(|rs| json!({
+            "resource": rs.resource.as_ref().map(resource),
+            "scopeSpans": rs.scope_spans.iter().map(|ss| json!({
+                "scope": ss.scope.as_ref().map(scope),
+                "spans": ss.spans.iter().map(span).collect::<Vec<_>>(),
+                "schemaUrl": ss.schema_url,
+            })).collect::<Vec<_>>(),
+            "schemaUrl": rs.schema_url,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn resource(resource: &Resource) -> Json {
+    json!({
+        "attributes": attributes(&resource.attributes),
+        "droppedAttributesCount": resource.dropped_attributes_count,
+    })
+}
+
+fn scope(scope: &InstrumentationScope) -> Json {
+    json!({
+        "name": scope.name,
+        "version": scope.version,
+        "attributes": attributes(&scope.attributes),
+        "droppedAttributesCount": scope.dropped_attributes_count,
+    })
+}
+
+fn span(s: &Span) -> Json {
+    json!({
+        "traceId": s.trace_id.encode_hex::<String>(),
+        "spanId": s.span_id.encode_hex::<String>(),
+        "traceState": s.trace_state,
+        "parentSpanId": s.parent_span_id.encode_hex::<String>(),
+        "name": s.name,
+        "kind": s.kind,
+        "startTimeUnixNano": s.start_time_unix_nano.to_string(),
+        "endTimeUnixNano": s.end_time_unix_nano.to_string(),
+        "attributes": attributes(&s.attributes),
+        "droppedAttributesCount": s.dropped_attributes_count,
+        "events": s.events.iter().map(event).collect::<Vec<_>>(),
+        "droppedEventsCount": s.dropped_events_count,
+        "links": s.links.iter().map(link).collect::<Vec<_>>(),
+        "droppedLinksCount": s.dropped_links_count,
+        "status": s.status.as_ref().map(status),
+    })
+}
+
+fn event(e: &span::Event) -> Json {
+    json!({
+        "timeUnixNano": e.time_unix_nano.to_string(),
+        "name": e.name,
+        "attributes": attributes(&e.attributes),
+        "droppedAttributesCount": e.dropped_attributes_count,
+    })
+}
+
+fn link(l: &span::Link) -> Json {
+    json!({
+        "traceId": l.trace_id.encode_hex::<String>(),
+        "spanId": l.span_id.encode_hex::<String>(),
+        "traceState": l.trace_state,
+        "attributes": attributes(&l.attributes),
+        "droppedAttributesCount": l.dropped_attributes_count,
+    })
+}
+
+fn status(s: &Status) -> Json {
+    json!({
+        "message": s.message,
+        "code": s.code,
+    })
+}
+
+fn attributes(attributes: &[KeyValue]) -> Json {
+    Json::Array(
+        attributes
+            .iter()
+            .map(|kv| json!({"key": kv.key, "value": kv.value.as_ref().map(any_value)}))
+            .collect(),
+    )
+}
+
+fn any_value(value: &AnyValue) -> Json {
+    match &value.value {
+        None => json!({}),
+        Some(Value::StringValue(s)) => json!({ "stringValue": s }),
+        Some(Value::BoolValue(b)) => json!({ "boolValue": b }),
+        Some(Value::IntValue(i)) => json!({ "intValue": i.to_string() }),
+        Some(Value::DoubleValue(d)) => json!({ "doubleValue": d }),
+        Some(Value::ArrayValue(a)) => json!({ "arrayValue": { "values": a.values.iter().map(any_value).collect::<Vec<_>>() } }),
+        Some(Value::KvlistValue(l)) => json!({ "kvlistValue": { "values": attributes(&l.values) } }),
+        Some(Value::BytesValue(b)) => json!({ "bytesValue": base64::encode(b) }),
+    }
+}
+
+/// sdk span exporter posting OTLP/JSON to `<endpoint>/v1/traces`, which
+/// opentelemetry-otlp does not support yet
+#[derive(Debug)]
+pub struct JsonSpanExporter {
+    client: Client<HttpConnector>,
+    url: String,
+    headers: Vec<(String, String)>,
+    token: Option<Arc<TokenSource>>,
+    compression: Compression,
+    timeout: Duration,
+}
+
+impl JsonSpanExporter {
+    pub fn new(
+        endpoint: &str,
+        headers: Vec<(String, String)>,
+        token: Option<Arc<TokenSource>>,
+        compression: Compression,
+        timeout: Duration,
+        http2: bool,
+    ) -> Self {
+        JsonSpanExporter {
+            client: Client::builder().http2_only(http2).build_http(),
+            url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            headers,
+            token,
+            compression,
+            timeout,
+        }
+    }
+}
+
+impl SpanExporter for JsonSpanExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let json = trace_request(&convert::span_data_to_otlp(&batch)).to_string();
+        let body = Bytes::from(self.compression.compress(json.as_bytes()));
+        let (client, url, headers) = (self.client.clone(), self.url.clone(), self.headers.clone());
+        let (token, timeout, encoding) = (self.token.clone(), self.timeout, self.compression.encoding());
+        let request = move |authorization: Option<&str>| {
+            let mut request = Request::post(&url).header("content-type", "application/json");
+            let extra = authorization.map(|a| ("authorization", a)).into_iter().chain(encoding.map(|e| ("content-encoding", e)));
+            for (k, v) in headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).chain(extra) {
+                request = request.header(k, v);
+            }
+            request.body(Body::from(body.clone())).map_err(|e| TraceError::from(e.to_string()))
+        };
+        Box::pin(async move {
+            let send = |request| async {
+                tokio::time::timeout(timeout, client.request(request))
+                    .await
+                    .map_err(|_| TraceError::ExportTimedOut(timeout))?
+                    .map_err(|e| TraceError::from(e.to_string()))
+            };
+            let authorization = match &token {
+                Some(token) => Some(token.header_async().await.map_err(TraceError::from)?),
+                None => None,
+            };
+            let mut response = send(request(authorization.as_deref())?).await?;
+            if let (Some(token), Some(rejected)) = (&token, &authorization) {
+                if response.status() == StatusCode::UNAUTHORIZED {
+                    token.invalidate(rejected);
+                    let authorization = token.header_async().await.map_err(TraceError::from)?;
+                    response = send(request(Some(&authorization))?).await?;
+                }
+            }
+            if !response.status().is_success() {
+                let status = response.status().as_u16();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+                let body = String::from_utf8_lossy(&body).into_owned();
+                return Err(TraceError::Other(Box::new(HttpStatusError { status, body })));
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+    use crate::proto::common::v1::ArrayValue;
+    use crate::proto::metrics::v1::{metric, Histogram, HistogramDataPoint, Metric, ResourceMetrics, ScopeMetrics};
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans};
+
+    const TRACE: &str = "opentelemetry.proto.collector.trace.v1.ExportTraceServiceRequest";
+    const METRICS: &str = "opentelemetry.proto.collector.metrics.v1.ExportMetricsServiceRequest";
+
+    fn kv(key: &str, value: Value) -> KeyValue {
+        KeyValue { key: key.to_string(), value: Some(AnyValue { value: Some(value) }) }
+    }
+
+    fn trace() -> ExportTraceServiceRequest {
+        let span = Span {
+            trace_id: (1..=16).collect(),
+            span_id: vec![0xab; 8],
+            parent_span_id: vec![0, 0, 0, 0, 0, 0, 0, 1],
+            name: "GET /".to_string(),
+            kind: 2,
+            start_time_unix_nano: 1_700_000_000_000_000_000,
+            end_time_unix_nano: u64::MAX,
+            attributes: vec![
+                kv("s", Value::StringValue("x".to_string())),
+                kv("i", Value::IntValue(-3)),
+                kv("d", Value::DoubleValue(0.5)),
+                kv("b", Value::BoolValue(true)),
+                kv("bytes", Value::BytesValue(vec![0, 255])),
+                kv("a", Value::ArrayValue(ArrayValue { values: vec![AnyValue { value: Some(Value::IntValue(1)) }] })),
+            ],
+            events: vec![span::Event { time_unix_nano: 5, name: "retry".to_string(), ..Default::default() }],
+            links: vec![span::Link { trace_id: vec![2; 16], span_id: vec![3; 8], ..Default::default() }],
+            status: Some(Status { message: "boom".to_string(), code: 2 }),
+            ..Default::default()
+        };
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource { attributes: vec![kv("service.name", Value::StringValue("otk".to_string()))], ..Default::default() }),
+                scope_spans: vec![ScopeSpans {
+                    scope: Some(InstrumentationScope { name: "otk".to_string(), ..Default::default() }),
+                    spans: vec![span],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn trace_round_trip() {
+        let req = trace();
+        let json = trace_request(&req);
+        let span = &json["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "0102030405060708090a0b0c0d0e0f10");
+        assert_eq!(span["endTimeUnixNano"], u64::MAX.to_string());
+        assert_eq!(span["attributes"][1]["value"], json!({"intValue": "-3"}));
+        let decoded: ExportTraceServiceRequest = from_json(TRACE, json.to_string().as_bytes()).unwrap();
+        assert_eq!(decoded, req);
+
+        // the generic encoding agrees with the hand written one
+        let generic = to_json(TRACE, &req.encode_to_vec()).unwrap();
+        assert_eq!(generic["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["spanId"], "abababababababab");
+        let decoded: ExportTraceServiceRequest = from_json(TRACE, generic.to_string().as_bytes()).unwrap();
+        assert_eq!(decoded, req);
+    }
+
+    #[test]
+    fn metrics_round_trip() {
+        let point = HistogramDataPoint {
+            time_unix_nano: 7,
+            count: 3,
+            sum: Some(f64::NAN),
+            bucket_counts: vec![1, 0, 2],
+            explicit_bounds: vec![0.5, f64::INFINITY],
+            min: Some(-1.5),
+            ..Default::default()
+        };
+        let req = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "latency".to_string(),
+                        data: Some(metric::Data::Histogram(Histogram { data_points: vec![point], aggregation_temporality: 2 })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let json = to_json(METRICS, &req.encode_to_vec()).unwrap();
+        let histogram = &json["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0]["histogram"];
+        let point = &histogram["dataPoints"][0];
+        assert_eq!(point["timeUnixNano"], "7");
+        assert_eq!(point["bucketCounts"], json!(["1", "0", "2"]));
+        assert_eq!(point["explicitBounds"], json!([0.5, "Infinity"]));
+        assert_eq!(point["sum"], "NaN");
+        assert_eq!(histogram["aggregationTemporality"], 2);
+
+        let decoded: ExportMetricsServiceRequest = from_json(METRICS, json.to_string().as_bytes()).unwrap();
+        // NaN != NaN, compare the encoding instead
+        assert_eq!(decoded.encode_to_vec(), req.encode_to_vec());
+    }
+
+    #[test]
+    fn lenient_json() {
+        let body = br#"{"resourceSpans": [{"scopeSpans": [{"spans": [{
+            "name": "x",
+            "kind": "SPAN_KIND_CLIENT",
+            "startTimeUnixNano": 12,
+            "status": {"code": "STATUS_CODE_ERROR"},
+            "trace_state": "k=v",
+            "unknown": {"ignored": true},
+            "links": null
+        }]}]}]}"#;
+        let req: ExportTraceServiceRequest = from_json(TRACE, body).unwrap();
+        let span = &req.resource_spans[0].scope_spans[0].spans[0];
+        assert_eq!(span.kind, 3);
+        assert_eq!(span.start_time_unix_nano, 12);
+        assert_eq!(span.status.as_ref().unwrap().code, 2);
+        assert_eq!(span.trace_state, "k=v");
+    }
+
+    #[test]
+    fn invalid_json() {
+        for (body, message) in [
+            (&br#"{"resourceSpans": {}}"#[..], "expect an array for resourceSpans"),
+            (br#"{"resourceSpans": [1]}"#, "expect an object for ResourceSpans"),
+            (br#"{"resourceSpans": [{"scopeSpans": [{"spans": [{"traceId": "xyz"}]}]}]}"#, "invalid value \"xyz\" for traceId"),
+            (br#"{"resourceSpans": [{"scopeSpans": [{"spans": [{"kind": "SPAN_KIND_NOPE"}]}]}]}"#, "invalid value"),
+        ] {
+            let err = from_json::<ExportTraceServiceRequest>(TRACE, body).unwrap_err().to_string();
+            assert!(err.contains(message), "{}", err);
+        }
+        assert!(from_json::<ExportTraceServiceRequest>(TRACE, b"[").is_err());
+        assert!(to_json("opentelemetry.proto.trace.v1.Nope", b"").is_err());
+        let encoded = trace().encode_to_vec();
+        let err = to_json(TRACE, &encoded[..encoded.len() - 1]).unwrap_err().to_string();
+        assert!(err.contains("truncated"), "{}", err);
+    }
+}
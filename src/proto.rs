@@ -1,33 +1,40 @@
+// the generated code is not written to clippy's taste
+#[allow(clippy::all)]
 pub mod common {
     pub mod v1 {
         include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.common.v1.rs"));
     }
 }
 
+#[allow(clippy::all)]
 pub mod metrics {
     pub mod v1 {
         include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.metrics.v1.rs"));
     }
 }
 
+#[allow(clippy::all)]
 pub mod resource {
     pub mod v1 {
         include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.resource.v1.rs"));
     }
 }
 
+#[allow(clippy::all)]
 pub mod trace {
     pub mod v1 {
         include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.trace.v1.rs"));
     }
 }
 
+#[allow(clippy::all)]
 pub mod logs {
     pub mod v1 {
         include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.logs.v1.rs"));
     }
 }
 
+#[allow(clippy::all)]
 pub mod collector {
     pub mod trace {
         pub mod v1 {
@@ -45,3 +52,18 @@ pub mod collector {
         }
     }
 }
+
+// only the span messages of jaeger and zipkin are used
+#[allow(dead_code, clippy::all)]
+pub mod jaeger {
+    pub mod api_v2 {
+        include!(concat!(env!("OUT_DIR"), "/jaeger.api_v2.rs"));
+    }
+}
+
+#[allow(dead_code, clippy::all)]
+pub mod zipkin {
+    pub mod proto3 {
+        include!(concat!(env!("OUT_DIR"), "/zipkin.proto3.rs"));
+    }
+}
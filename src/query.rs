@@ -0,0 +1,431 @@
+use crate::common::{any_value_to_string, parse_duration};
+use crate::filter::{Attributes, SpanRef};
+use crate::otk_error::OTKError;
+use crate::proto::trace::v1::{span::SpanKind, status::StatusCode};
+use hex::ToHex;
+use regex::Regex;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// boolean filter over spans, e.g.
+/// `service.name == "checkout" && (duration > 500ms || status == ERROR)`
+///
+/// `name`, `duration`, `status`, `kind`, `trace_id`, `span_id` and
+/// `parent_span_id` refer to the span itself, any other identifier is an
+/// attribute looked up on the span, its scope, then its resource. operators
+/// are `== != =~ !~ < <= > >=`, combined with `&& || !` and parentheses; a
+/// bare identifier checks that the attribute exists. in quoted strings only
+/// the quote and `\\` are escaped, other backslashes are kept for regexes
+#[derive(Debug, Clone)]
+pub struct Query {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Field),
+    Cmp(Field, Op, Literal),
+}
+
+#[derive(Debug, Clone)]
+enum Field {
+    Name,
+    Duration,
+    Status,
+    Kind,
+    TraceId,
+    SpanId,
+    ParentSpanId,
+    Attr(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Text(String),
+    Num(f64),
+    Dur(Duration),
+    Re(Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl FromStr for Query {
+    type Err = OTKError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(tok) = parser.peek() {
+            return Err(parse_error(format!("unexpected {:?}", tok)));
+        }
+        Ok(Query { expr })
+    }
+}
+
+impl Query {
+    pub fn matches(&self, span: &SpanRef) -> bool {
+        self.expr.eval(span)
+    }
+}
+
+fn parse_error(msg: String) -> OTKError {
+    OTKError::ParseError(format!("query: {}", msg))
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, OTKError> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let two = |chars: &mut std::iter::Peekable<std::str::Chars>, next: char| {
+            chars.next();
+            chars.next_if_eq(&next).is_some()
+        };
+        let tok = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => {
+                chars.next();
+                Token::LParen
+            }
+            ')' => {
+                chars.next();
+                Token::RParen
+            }
+            '&' if two(&mut chars, '&') => Token::And,
+            '|' if two(&mut chars, '|') => Token::Or,
+            '=' => {
+                chars.next();
+                match chars.next() {
+                    Some('=') => Token::Op(Op::Eq),
+                    Some('~') => Token::Op(Op::Match),
+                    _ => return Err(parse_error(String::from("expect == or =~"))),
+                }
+            }
+            '!' => {
+                chars.next();
+                match chars.peek() {
+                    Some('=') => {
+                        chars.next();
+                        Token::Op(Op::Ne)
+                    }
+                    Some('~') => {
+                        chars.next();
+                        Token::Op(Op::NotMatch)
+                    }
+                    _ => Token::Not,
+                }
+            }
+            '<' => Token::Op(if two(&mut chars, '=') { Op::Le } else { Op::Lt }),
+            '>' => Token::Op(if two(&mut chars, '=') { Op::Ge } else { Op::Gt }),
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        // only the quote and the backslash itself are escaped,
+                        // regexes like "GET\s/cart" keep their backslashes
+                        Some('\\') => match chars.next_if(|&n| n == c || n == '\\') {
+                            Some(escaped) => text.push(escaped),
+                            None => text.push('\\'),
+                        },
+                        Some(q) if q == c => break,
+                        Some(ch) => text.push(ch),
+                        None => return Err(parse_error(String::from("unterminated quote"))),
+                    }
+                }
+                Token::Str(text)
+            }
+            '&' | '|' => return Err(parse_error(format!("expect {}{}", c, c))),
+            _ => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || "()!=<>&|\"'".contains(ch) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(tok);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn or(&mut self) -> Result<Expr, OTKError> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, OTKError> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, OTKError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(parse_error(String::from("expect )"))),
+                }
+            }
+            Some(Token::Word(ident)) | Some(Token::Str(ident)) => {
+                let field = field(ident);
+                match self.peek() {
+                    Some(Token::Op(op)) => {
+                        let op = *op;
+                        self.next();
+                        let literal = match self.next() {
+                            Some(Token::Word(v)) | Some(Token::Str(v)) => literal(&field, op, v)?,
+                            _ => return Err(parse_error(format!("expect value after {:?}", op))),
+                        };
+                        Ok(Expr::Cmp(field, op, literal))
+                    }
+                    _ => Ok(Expr::Exists(field)),
+                }
+            }
+            Some(tok) => Err(parse_error(format!("unexpected {:?}", tok))),
+            None => Err(parse_error(String::from("unexpected end of query"))),
+        }
+    }
+}
+
+fn field(ident: String) -> Field {
+    match ident.as_str() {
+        "name" => Field::Name,
+        "duration" => Field::Duration,
+        "status" => Field::Status,
+        "kind" => Field::Kind,
+        "trace_id" => Field::TraceId,
+        "span_id" => Field::SpanId,
+        "parent_span_id" => Field::ParentSpanId,
+        _ => Field::Attr(ident),
+    }
+}
+
+fn literal(field: &Field, op: Op, v: String) -> Result<Literal, OTKError> {
+    match (field, op) {
+        (Field::Duration, Op::Match | Op::NotMatch) => {
+            Err(parse_error(String::from("duration can not be matched by regex")))
+        }
+        (_, Op::Match | Op::NotMatch) => Regex::new(&v)
+            .map(Literal::Re)
+            .map_err(|e| parse_error(e.to_string())),
+        (Field::Duration, _) => Ok(Literal::Dur(parse_duration(&v)?)),
+        (_, Op::Lt | Op::Le | Op::Gt | Op::Ge) => v
+            .parse()
+            .map(Literal::Num)
+            .map_err(|_| parse_error(format!("expect a number, got {}", v))),
+        _ => Ok(Literal::Text(v)),
+    }
+}
+
+pub fn status_name(code: i32) -> &'static str {
+    match StatusCode::from_i32(code) {
+        Some(StatusCode::Ok) => "OK",
+        Some(StatusCode::Error) => "ERROR",
+        _ => "UNSET",
+    }
+}
+
+pub fn kind_name(kind: i32) -> &'static str {
+    match SpanKind::from_i32(kind) {
+        Some(SpanKind::Internal) => "INTERNAL",
+        Some(SpanKind::Server) => "SERVER",
+        Some(SpanKind::Client) => "CLIENT",
+        Some(SpanKind::Producer) => "PRODUCER",
+        Some(SpanKind::Consumer) => "CONSUMER",
+        _ => "UNSPECIFIED",
+    }
+}
+
+fn compare<T: PartialOrd>(op: Op, a: T, b: T) -> bool {
+    match op {
+        Op::Eq => a == b,
+        Op::Ne => a != b,
+        Op::Lt => a < b,
+        Op::Le => a <= b,
+        Op::Gt => a > b,
+        Op::Ge => a >= b,
+        Op::Match | Op::NotMatch => false,
+    }
+}
+
+impl Expr {
+    fn eval(&self, span: &SpanRef) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(span) && b.eval(span),
+            Expr::Or(a, b) => a.eval(span) || b.eval(span),
+            Expr::Not(a) => !a.eval(span),
+            Expr::Exists(Field::Attr(key)) => span.attr(key).is_some(),
+            Expr::Exists(_) => true,
+            Expr::Cmp(Field::Duration, op, Literal::Dur(d)) => {
+                let s = span.span;
+                let duration = Duration::from_nanos(s.end_time_unix_nano.saturating_sub(s.start_time_unix_nano));
+                compare(*op, duration, *d)
+            }
+            Expr::Cmp(field, op, literal) => {
+                let value = match field {
+                    Field::Name => Some(span.span.name.clone()),
+                    Field::Status => Some(status_name(span.span.status.as_ref().map_or(0, |s| s.code)).into()),
+                    Field::Kind => Some(kind_name(span.span.kind).into()),
+                    Field::TraceId => Some(span.span.trace_id.encode_hex()),
+                    Field::SpanId => Some(span.span.span_id.encode_hex()),
+                    Field::ParentSpanId => Some(span.span.parent_span_id.encode_hex()),
+                    Field::Attr(key) => span.attr(key).map(any_value_to_string),
+                    Field::Duration => None,
+                };
+                // status and kind names are matched case insensitively
+                let fold = matches!(field, Field::Status | Field::Kind);
+                match (value, literal) {
+                    (None, _) => matches!(op, Op::Ne | Op::NotMatch),
+                    (Some(v), Literal::Re(re)) => re.is_match(&v) == (*op == Op::Match),
+                    (Some(v), Literal::Text(t)) if fold => compare(*op, v, t.to_uppercase()),
+                    (Some(v), Literal::Text(t)) => compare(*op, &v, t),
+                    (Some(v), Literal::Num(n)) => v.parse::<f64>().is_ok_and(|v| compare(*op, v, *n)),
+                    (Some(_), Literal::Dur(_)) => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(s: &str) -> Vec<String> {
+        tokenize(s)
+            .unwrap()
+            .into_iter()
+            .filter_map(|t| match t {
+                Token::Str(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_operators() {
+        let tokens = tokenize(r#"!(a == "x" || b != 1) && c =~ 'y' && d !~ "z" && e <= 2 && f > 3ms"#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Not,
+                Token::LParen,
+                Token::Word("a".into()),
+                Token::Op(Op::Eq),
+                Token::Str("x".into()),
+                Token::Or,
+                Token::Word("b".into()),
+                Token::Op(Op::Ne),
+                Token::Word("1".into()),
+                Token::RParen,
+                Token::And,
+                Token::Word("c".into()),
+                Token::Op(Op::Match),
+                Token::Str("y".into()),
+                Token::And,
+                Token::Word("d".into()),
+                Token::Op(Op::NotMatch),
+                Token::Str("z".into()),
+                Token::And,
+                Token::Word("e".into()),
+                Token::Op(Op::Le),
+                Token::Word("2".into()),
+                Token::And,
+                Token::Word("f".into()),
+                Token::Op(Op::Gt),
+                Token::Word("3ms".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_regex_backslashes() {
+        assert_eq!(strings(r#"name =~ "GET\s/cart""#), vec![r"GET\s/cart"]);
+        assert_eq!(strings(r#"x =~ '^\d+\.\d+$'"#), vec![r"^\d+\.\d+$"]);
+    }
+
+    #[test]
+    fn tokenize_escapes() {
+        assert_eq!(strings(r#"a == "say \"hi\"""#), vec![r#"say "hi""#]);
+        assert_eq!(strings(r#"a == 'it\'s'"#), vec!["it's"]);
+        assert_eq!(strings(r#"a == "back\\slash""#), vec![r"back\slash"]);
+        // the other quote needs no escape
+        assert_eq!(strings(r#"a == "it\'s""#), vec![r"it\'s"]);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert!(tokenize(r#"a == "open"#).is_err());
+        assert!(tokenize(r#"a == "trailing\"#).is_err());
+        assert!(tokenize("a = b").is_err());
+        assert!(tokenize("a & b").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Query::from_str("a ==").is_err());
+        assert!(Query::from_str("(a").is_err());
+        assert!(Query::from_str("a b").is_err());
+        assert!(Query::from_str(r#"name =~ "(""#).is_err());
+    }
+}
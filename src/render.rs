@@ -0,0 +1,78 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use strum_macros::{Display, EnumString};
+
+/// ids printed by Debug, as byte lists (spread over lines when pretty)
+static DEBUG_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(trace_id|span_id|parent_span_id): (\[[\d,\s]+\])").unwrap());
+/// ids printed as otlp json, hex encoded
+static JSON_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#""(traceId|spanId|parentSpanId)"(: ?)"([0-9a-fA-F]+)""#).unwrap());
+
+/// foreground colors ids are given, readable on dark and light terminals
+const PALETTE: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+
+/// when to color output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Color {
+    /// when printing to a terminal and NO_COLOR is not set
+    #[strum(serialize = "auto")]
+    Auto,
+    #[strum(serialize = "always")]
+    Always,
+    #[strum(serialize = "never")]
+    Never,
+}
+
+/// prints decoded messages with every trace and span id colored by its
+/// value, so the same trace (or a span and its children's parent_span_id)
+/// has the same color in every message and every command
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: Color) -> Self {
+        let color = match color {
+            Color::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            Color::Always => true,
+            Color::Never => false,
+        };
+        Renderer { color }
+    }
+
+    /// `text` (Debug output or otlp json) with its ids colored
+    pub fn ids<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !self.color {
+            return Cow::Borrowed(text);
+        }
+        let text = DEBUG_ID.replace_all(text, |c: &Captures| {
+            let id = c[2]
+                .trim_matches(|c| c == '[' || c == ']')
+                .split(',')
+                .filter_map(|b| b.trim().parse::<u8>().ok())
+                .collect::<Vec<_>>();
+            format!("{}: {}", &c[1], paint(&c[1], &id, &c[2]))
+        });
+        let text = JSON_ID.replace_all(&text, |c: &Captures| {
+            let id = hex::decode(&c[3]).unwrap_or_default();
+            format!("\"{}\"{}\"{}\"", &c[1], &c[2], paint(&c[1], &id, &c[3]))
+        });
+        Cow::Owned(text.into_owned())
+    }
+}
+
+/// `shown` in the color of `id`, bold for trace ids
+fn paint(field: &str, id: &[u8], shown: &str) -> String {
+    if id.iter().all(|b| *b == 0) {
+        return shown.to_string();
+    }
+    // fnv-1a, the color of an id must not change between runs
+    let hash = id.iter().fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+    let color = PALETTE[(hash % PALETTE.len() as u64) as usize];
+    let bold = if field.starts_with("trace") { "1;" } else { "" };
+    format!("\x1b[{}{}m{}\x1b[0m", bold, color, shown)
+}
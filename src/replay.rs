@@ -0,0 +1,398 @@
+use crate::cmd_conformance::{grpc_path, http_path, percent_decode};
+use crate::cmd_search::Signal;
+use crate::common::KeyValue;
+use crate::export::StatusClient;
+use crate::filter;
+use crate::framing::{self, Framing};
+use crate::https::{self, HttpsClient};
+use crate::otk_error::OTKError;
+use crate::otlp_json;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::any_value::Value;
+use crate::proto::common::v1::{AnyValue, ArrayValue, KeyValue as ProtoKeyValue};
+use crate::proto::resource::v1::Resource;
+use crate::token::TokenClient;
+use crate::transport::{Protocol, TransportOpts};
+use crate::unix_socket;
+use hyper::body::HttpBody;
+use hyper::{Body, Client};
+use opentelemetry::{Array, Value as OTLPValue};
+use opentelemetry_http::{HttpClient, Request};
+use prost::Message;
+use std::error;
+use std::io;
+use std::ops::ControlFlow;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tonic::Code;
+
+/// what sending a request fails with, classified by `Exports` like the
+/// failures of the exporters
+type SendError = Box<dyn error::Error + Send + Sync>;
+
+/// requests read ahead of the one being sent
+const READ_AHEAD: usize = 16;
+
+/// send the export requests of a capture (--from-capture) as they are (but
+/// for the `add_resource` attributes), bypassing the sdk. they are counted
+/// and retried with the transport's `Exports`
+pub async fn replay(
+    transport: &TransportOpts,
+    signal: Signal,
+    input: &str,
+    framing: Framing,
+    add_resource: &[KeyValue],
+) -> Result<(), Box<dyn error::Error>> {
+    let sender = Sender::new(transport, signal)?;
+    // read on a blocking thread as the requests are sent, a capture needn't
+    // fit in memory
+    let (tx, mut rx) = mpsc::channel(READ_AHEAD);
+    let reader = {
+        let (input, add_resource) = (input.to_string(), add_resource.to_vec());
+        tokio::task::spawn_blocking(move || {
+            let mut read = 0;
+            framing::for_each_message(&input, framing, |message| {
+                read += 1;
+                let items = count_items(signal, message).map_err(|e| {
+                    // usually a capture of another signal
+                    OTKError::ParseError(format!(
+                        "message #{} of {} is not a {} export request: {}",
+                        read,
+                        framing::input_name(&input),
+                        signal,
+                        e
+                    ))
+                })?;
+                let message = match add_resource.is_empty() {
+                    true => message.to_vec(),
+                    false => tag_resources(signal, message, &add_resource).map_err(|e| OTKError::ParseError(e.to_string()))?,
+                };
+                Ok(match tx.blocking_send((message, items)) {
+                    Ok(()) => ControlFlow::Continue(()),
+                    Err(_) => ControlFlow::Break(()),
+                })
+            })
+            // sent back to the runtime, keeping otk's own errors as they are
+            .map_err(|e| -> SendError {
+                match e.downcast::<OTKError>() {
+                    Ok(e) => e,
+                    Err(e) => match e.downcast::<io::Error>() {
+                        Ok(e) => e,
+                        Err(e) => e.to_string().into(),
+                    },
+                }
+            })
+        })
+    };
+
+    let exports = &transport.exports;
+    while let Some((message, items)) = rx.recv().await {
+        exports.start(items);
+        for retry in 0.. {
+            let started = Instant::now();
+            let result = match tokio::time::timeout(transport.timeout(), sender.send(&message)).await {
+                Ok(result) => result,
+                Err(elapsed) => Err(elapsed.into()),
+            };
+            if exports.attempted(retry, message.len(), started.elapsed(), result).await.is_some() {
+                break;
+            }
+        }
+    }
+    reader.await?.map_err(|e| e as Box<dyn error::Error>)?;
+    Ok(())
+}
+
+/// spans, log records or metric data points of an encoded request
+fn count_items(signal: Signal, message: &[u8]) -> Result<usize, prost::DecodeError> {
+    Ok(match signal {
+        Signal::Traces => filter::spans(&ExportTraceServiceRequest::decode(message)?).count(),
+        Signal::Logs => filter::logs(&ExportLogsServiceRequest::decode(message)?).count(),
+        Signal::Metrics => filter::points(&ExportMetricsServiceRequest::decode(message)?).count(),
+    })
+}
+
+/// an encoded request with `attrs` set on all its resources, replacing
+/// attributes of the same key
+fn tag_resources(signal: Signal, message: &[u8], attrs: &[KeyValue]) -> Result<Vec<u8>, prost::DecodeError> {
+    let tag = |resource: &mut Option<Resource>| {
+        let resource = resource.get_or_insert_with(Resource::default);
+        for kv in attrs {
+            let value = Some(AnyValue { value: Some(proto_value(kv.value())) });
+            match resource.attributes.iter_mut().find(|a| a.key == kv.k) {
+                Some(existing) => existing.value = value,
+                None => resource.attributes.push(ProtoKeyValue { key: kv.k.clone(), value }),
+            }
+        }
+    };
+    Ok(match signal {
+        Signal::Traces => {
+            let mut req = ExportTraceServiceRequest::decode(message)?;
+            req.resource_spans.iter_mut().for_each(|rs| tag(&mut rs.resource));
+            req.encode_to_vec()
+        }
+        Signal::Logs => {
+            let mut req = ExportLogsServiceRequest::decode(message)?;
+            req.resource_logs.iter_mut().for_each(|rl| tag(&mut rl.resource));
+            req.encode_to_vec()
+        }
+        Signal::Metrics => {
+            let mut req = ExportMetricsServiceRequest::decode(message)?;
+            req.resource_metrics.iter_mut().for_each(|rm| tag(&mut rm.resource));
+            req.encode_to_vec()
+        }
+    })
+}
+
+fn proto_value(value: OTLPValue) -> Value {
+    match value {
+        OTLPValue::Bool(b) => Value::BoolValue(b),
+        OTLPValue::I64(i) => Value::IntValue(i),
+        OTLPValue::F64(d) => Value::DoubleValue(d),
+        OTLPValue::String(s) => Value::StringValue(s.to_string()),
+        OTLPValue::Array(Array::String(items)) => Value::ArrayValue(ArrayValue {
+            values: items
+                .into_iter()
+                .map(|s| AnyValue { value: Some(Value::StringValue(s.to_string())) })
+                .collect(),
+        }),
+        // --add-resource-attr only gives string arrays
+        OTLPValue::Array(other) => Value::StringValue(other.to_string()),
+    }
+}
+
+/// posts encoded requests with the transport's endpoint, tls, headers, token
+/// and compression
+struct Sender<'a> {
+    transport: &'a TransportOpts,
+    signal: Signal,
+    /// client of http and http_json
+    http: Option<Box<dyn HttpClient>>,
+    /// client of grpc over tls
+    grpc_tls: Option<HttpsClient>,
+    grpc: Client<hyper::client::HttpConnector>,
+    /// the transport's endpoint, a placeholder over a unix socket
+    endpoint: String,
+}
+
+impl<'a> Sender<'a> {
+    fn new(transport: &'a TransportOpts, signal: Signal) -> Result<Self, Box<dyn error::Error>> {
+        transport.check_token()?;
+        transport.check_unix_socket()?;
+        if transport.protocol == Protocol::HttpJson && signal != Signal::Traces {
+            return Err(Box::new(OTKError::UnimplementedError(
+                "http_json replay only supports traces".into(),
+            )));
+        }
+        let grpc = transport.protocol == Protocol::Grpc;
+        let http = match (grpc, transport.tls) {
+            (true, _) => None,
+            (false, true) => {
+                let config = https::client_config(transport.ca_cert.as_deref(), transport.insecure_skip_verify, transport.http2())?;
+                let client = HttpsClient::new(config, transport.domain.clone(), transport.http2(), transport.timeout());
+                Some(with_token(transport, client))
+            }
+            (false, false) => {
+                let mut client = reqwest::Client::builder();
+                if transport.http2() {
+                    client = client.http2_prior_knowledge();
+                }
+                Some(with_token(transport, client.build()?))
+            }
+        };
+        let grpc_tls = match grpc && transport.tls {
+            true if transport.insecure_skip_verify => {
+                return Err(Box::new(OTKError::UnimplementedError(
+                    "--insecure-skip-verify is not supported with grpc".into(),
+                )))
+            }
+            true => Some(HttpsClient::new(
+                https::client_config(transport.ca_cert.as_deref(), false, true)?,
+                transport.domain.clone(),
+                true,
+                transport.timeout(),
+            )),
+            false => None,
+        };
+        Ok(Sender {
+            transport,
+            signal,
+            http,
+            grpc_tls,
+            grpc: Client::builder().http2_only(true).build_http(),
+            endpoint: match transport.unix_socket() {
+                Some(_) => unix_socket::URL.to_string(),
+                None => transport.endpoint(),
+            },
+        })
+    }
+
+    async fn send(&self, message: &[u8]) -> Result<(), SendError> {
+        let endpoint = self.endpoint.trim_end_matches('/');
+        let compression = self.transport.compression;
+        let Some(client) = &self.http else {
+            return self.send_grpc(&format!("{}{}", endpoint, grpc_path(self.signal)), message).await;
+        };
+        let (body, content_type) = match self.transport.protocol {
+            Protocol::HttpJson => {
+                let json = otlp_json::trace_request(&ExportTraceServiceRequest::decode(message)?).to_string();
+                (json.into_bytes(), "application/json")
+            }
+            _ => (message.to_vec(), "application/x-protobuf"),
+        };
+        let mut request = Request::post(format!("{}{}", endpoint, http_path(self.signal))).header("content-type", content_type);
+        if let Some(encoding) = compression.encoding() {
+            request = request.header("content-encoding", encoding);
+        }
+        for (k, v) in self.transport.headers()? {
+            request = request.header(k, v);
+        }
+        client.send(request.body(compression.compress(&body))?).await?;
+        Ok(())
+    }
+
+    async fn send_grpc(&self, url: &str, message: &[u8]) -> Result<(), SendError> {
+        let compression = self.transport.compression;
+        let payload = compression.compress(message);
+        let mut frame = vec![compression.encoding().is_some() as u8];
+        frame.extend((payload.len() as u32).to_be_bytes());
+        frame.extend(payload);
+        let mut request = hyper::Request::post(url)
+            .header("content-type", "application/grpc")
+            .header("te", "trailers");
+        if let Some(encoding) = compression.encoding() {
+            request = request.header("grpc-encoding", encoding);
+        }
+        if let Some(deadline) = self.transport.call_deadline {
+            request = request.header("grpc-timeout", format!("{}m", deadline.as_millis()));
+        }
+        for kv in &self.transport.metadata {
+            request = request.header(kv.k.as_str(), kv.v.as_str());
+        }
+        // an UNAUTHENTICATED answer drops the token, see `Exports`
+        if let Some(token) = self.transport.token() {
+            request = request.header("authorization", token.header_async().await?);
+        }
+        let request = request.body(Body::from(frame))?;
+        let response = match (&self.grpc_tls, self.transport.unix_socket()) {
+            (Some(client), _) => client.send_body(request).await?,
+            (None, Some(path)) => unix_socket::send_body(path, request).await?,
+            (None, None) => self.grpc.request(request).await?,
+        };
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()).into());
+        }
+        // trailers-only responses carry the status in the headers
+        let mut headers = response.headers().clone();
+        if !headers.contains_key("grpc-status") {
+            let mut body = response.into_body();
+            while let Some(chunk) = body.data().await {
+                chunk?;
+            }
+            headers = body.trailers().await?.unwrap_or_default();
+        }
+        match headers.get("grpc-status").map(|code| code.to_str()) {
+            Some(Ok("0")) => Ok(()),
+            Some(code) => {
+                let code = code?.parse().map_err(|_| "invalid grpc-status in the response")?;
+                let message = headers.get("grpc-message").map(|m| percent_decode(m.as_bytes())).unwrap_or_default();
+                // as the grpc exporters fail, so `Exports` tells what to retry
+                Err(Box::new(opentelemetry_otlp::Error::Status {
+                    code: Code::from_i32(code),
+                    message: if message.is_empty() { message } else { format!(": {}", message) },
+                }))
+            }
+            None => Err("no grpc-status in the response".into()),
+        }
+    }
+}
+
+/// `client` with the transport's token, failing requests the receiver
+/// didn't accept
+fn with_token<C: HttpClient + 'static>(transport: &TransportOpts, client: C) -> Box<dyn HttpClient> {
+    match transport.token() {
+        Some(token) => Box::new(StatusClient { inner: TokenClient { inner: client, token } }),
+        None => Box::new(StatusClient { inner: client }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd_conformance::{listen, Conformance};
+    use crate::export::Exports;
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+    use clap::Parser;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+
+    #[derive(Parser, Debug)]
+    struct Opts {
+        #[clap(flatten)]
+        transport: TransportOpts,
+    }
+
+    fn traces(resource: Option<Resource>, spans: usize) -> ExportTraceServiceRequest {
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource,
+                scope_spans: vec![ScopeSpans { spans: vec![Span::default(); spans], ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn kv(key: &str, value: &str) -> ProtoKeyValue {
+        ProtoKeyValue { key: key.into(), value: Some(AnyValue { value: Some(Value::StringValue(value.into())) }) }
+    }
+
+    #[test]
+    fn tags_resources() {
+        let resource = Resource { attributes: vec![kv("service.name", "checkout"), kv("env", "prod")], ..Default::default() };
+        let message = traces(Some(resource), 1).encode_to_vec();
+        let attrs = ["env=test".parse().unwrap(), "run=1".parse().unwrap()];
+        let tagged = ExportTraceServiceRequest::decode(tag_resources(Signal::Traces, &message, &attrs).unwrap().as_slice()).unwrap();
+        let attributes = &tagged.resource_spans[0].resource.as_ref().unwrap().attributes;
+        assert_eq!(*attributes, [kv("service.name", "checkout"), kv("env", "test"), kv("run", "1")]);
+        // requests without a resource get one
+        let tagged = tag_resources(Signal::Traces, &traces(None, 1).encode_to_vec(), &attrs[1..]).unwrap();
+        let tagged = ExportTraceServiceRequest::decode(tagged.as_slice()).unwrap();
+        assert_eq!(tagged.resource_spans[0].resource.as_ref().unwrap().attributes, [kv("run", "1")]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replays_captures() {
+        let capture = std::env::temp_dir().join(format!("otk-replay-{}.b64", std::process::id()));
+        let lines = [traces(None, 2), traces(None, 3)].map(|req| base64::encode(req.encode_to_vec()) + "\n");
+        std::fs::write(&capture, lines.concat()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let conformance = Conformance::try_parse_from(["conformance", "--listen", "127.0.0.1:0", "--requests", "4"]).unwrap();
+        let server = tokio::spawn(async move { listen(&conformance, listener, Arc::new(Notify::new())).await.unwrap() });
+
+        for protocol in ["grpc", "http"] {
+            let mut transport = Opts::try_parse_from(["otk", "--url", &url, "--protocol", protocol]).unwrap().transport;
+            transport.exports = Arc::new(Exports::new(0, "spans"));
+            replay(&transport, Signal::Traces, capture.to_str().unwrap(), Framing::Base64, &[]).await.unwrap();
+            transport.exports.finish().unwrap();
+        }
+        std::fs::remove_file(&capture).unwrap();
+        assert_eq!(server.await.unwrap().requests(), 4);
+    }
+
+    #[tokio::test]
+    async fn counts_failed_replays() {
+        let capture = std::env::temp_dir().join(format!("otk-replay-failed-{}.b64", std::process::id()));
+        std::fs::write(&capture, base64::encode(traces(None, 1).encode_to_vec()) + "\n").unwrap();
+        // nothing listens on the discard port
+        let mut transport = Opts::try_parse_from(["otk", "--url", "http://127.0.0.1:9", "--protocol", "http"]).unwrap().transport;
+        transport.exports = Arc::new(Exports::new(0, "spans"));
+        let replayed = replay(&transport, Signal::Traces, capture.to_str().unwrap(), Framing::Base64, &[]).await;
+        std::fs::remove_file(&capture).unwrap();
+        replayed.unwrap();
+        assert_eq!(transport.exports.finish().unwrap_err().to_string(), "Check failed: 1 of 1 exports failed (0 retries)");
+    }
+}
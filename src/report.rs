@@ -0,0 +1,48 @@
+use crate::common::KeyValue;
+use crate::dry_run::DryRun;
+use crate::framing::Framing;
+use clap::Args;
+
+/// export options shared by the report commands
+#[derive(Args, Debug, Clone)]
+pub struct ReportOpts {
+    /// retries of an export failing with a transient error (grpc unavailable,
+    /// http 503, refused connection...), with exponential backoff from 500ms
+    /// up to 30s
+    #[clap(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// stamp every span, data point or log record with an "otk.run_id"
+    /// attribute to find the data of this invocation, `auto` for a random id
+    /// (printed)
+    #[clap(long)]
+    pub run_id: Option<String>,
+
+
+    /// re-send the export requests of a capture (as written by decode and
+    /// searched by search) verbatim instead of generating telemetry
+    #[clap(long)]
+    pub from_capture: Option<String>,
+
+    /// message framing of --from-capture (base64, raw, length-delimited or
+    /// length-prefixed)
+    #[clap(long, default_value = "base64", requires = "from_capture")]
+    pub framing: Framing,
+
+    /// set a resource attribute (key=value) on every request of --from-capture
+    /// before re-sending it (like the collector's resource processor)
+    #[clap(long, num_args = 0.., requires = "from_capture")]
+    pub add_resource_attr: Vec<KeyValue>,
+
+
+    /// schema url of the resource and the instrumentation scope, e.g.
+    /// https://opentelemetry.io/schemas/1.21.0
+    #[clap(long)]
+    pub schema_url: Option<String>,
+
+
+    /// build the telemetry and print the export requests (json, base64 or
+    /// raw protobuf) instead of sending them
+    #[clap(long, num_args = 0..=1, default_missing_value = "json", conflicts_with = "from_capture")]
+    pub dry_run: Option<DryRun>,
+}
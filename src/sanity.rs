@@ -0,0 +1,336 @@
+use crate::proto::common::v1::KeyValue;
+use crate::proto::logs::v1::ResourceLogs;
+use crate::proto::metrics::v1::{metric, ResourceMetrics};
+use crate::proto::resource::v1::Resource;
+use crate::proto::common::v1::any_value::Value;
+use crate::proto::trace::v1::span::SpanKind;
+use crate::proto::trace::v1::status::StatusCode;
+use crate::proto::trace::v1::{ResourceSpans, Span};
+use hex::ToHex;
+
+/// 1971-01-01, timestamps before it are most likely unset or in seconds
+const MIN_PLAUSIBLE_NANOS: u64 = 31_536_000 * 1_000_000_000;
+/// 2100-01-01, timestamps after it are most likely garbage (u32 seconds
+/// overflow at 2106)
+const MAX_PLAUSIBLE_NANOS: u64 = 4_102_444_800 * 1_000_000_000;
+
+/// red flags in decoded trace data, one message per problem
+pub fn check_resource_spans(resource_spans: &[ResourceSpans]) -> Vec<String> {
+    let mut warnings = vec![];
+    for rs in resource_spans {
+        check_resource(rs.resource.as_ref(), &mut warnings);
+        for ss in &rs.scope_spans {
+            if let Some(scope) = &ss.scope {
+                check_keys("scope", &scope.attributes, &mut warnings);
+            }
+            for span in &ss.spans {
+                let what = format!("span {:?} ({})", span.name, span.span_id.encode_hex::<String>());
+                if is_zero_id(&span.trace_id) {
+                    warnings.push(format!("{}: zero trace id", what));
+                }
+                if is_zero_id(&span.span_id) {
+                    warnings.push(format!("{}: zero span id", what));
+                }
+                check_time(&what, "start", span.start_time_unix_nano, &mut warnings);
+                check_time(&what, "end", span.end_time_unix_nano, &mut warnings);
+                if span.end_time_unix_nano < span.start_time_unix_nano {
+                    warnings.push(format!("{}: ends before it starts", what));
+                }
+                check_keys(&what, &span.attributes, &mut warnings);
+                if let Some(mismatch) = http_status_mismatch(span) {
+                    warnings.push(format!("{}: {}", what, mismatch));
+                }
+                for event in &span.events {
+                    check_keys(&format!("{} event {:?}", what, event.name), &event.attributes, &mut warnings);
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// red flags in decoded log data, one message per problem
+pub fn check_resource_logs(resource_logs: &[ResourceLogs]) -> Vec<String> {
+    let mut warnings = vec![];
+    for rl in resource_logs {
+        check_resource(rl.resource.as_ref(), &mut warnings);
+        for sl in &rl.scope_logs {
+            if let Some(scope) = &sl.scope {
+                check_keys("scope", &scope.attributes, &mut warnings);
+            }
+            for (i, log) in sl.log_records.iter().enumerate() {
+                let what = format!("log record #{}", i);
+                // trace context is optional on logs, only all-zero ids are suspicious
+                if !log.trace_id.is_empty() && is_zero_id(&log.trace_id) {
+                    warnings.push(format!("{}: zero trace id", what));
+                }
+                if log.time_unix_nano != 0 {
+                    check_time(&what, "time", log.time_unix_nano, &mut warnings);
+                }
+                if log.observed_time_unix_nano != 0 {
+                    check_time(&what, "observed time", log.observed_time_unix_nano, &mut warnings);
+                }
+                check_keys(&what, &log.attributes, &mut warnings);
+            }
+        }
+    }
+    warnings
+}
+
+/// red flags in decoded metric data, one message per problem
+pub fn check_resource_metrics(resource_metrics: &[ResourceMetrics]) -> Vec<String> {
+    let mut warnings = vec![];
+    for rm in resource_metrics {
+        check_resource(rm.resource.as_ref(), &mut warnings);
+        for sm in &rm.scope_metrics {
+            if let Some(scope) = &sm.scope {
+                check_keys("scope", &scope.attributes, &mut warnings);
+            }
+            for metric in &sm.metrics {
+                let what = format!("metric {:?}", metric.name);
+                for (start, time, attributes) in point_times(metric.data.as_ref()) {
+                    check_time(&what, "point time", time, &mut warnings);
+                    if start != 0 && start > time {
+                        warnings.push(format!("{}: point starts after its time", what));
+                    }
+                    check_keys(&what, attributes, &mut warnings);
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// (start time, time, attributes) of every data point
+fn point_times(data: Option<&metric::Data>) -> Vec<(u64, u64, &[KeyValue])> {
+    macro_rules! times {
+        ($points:expr) => {
+            $points
+                .iter()
+                .map(|p| (p.start_time_unix_nano, p.time_unix_nano, p.attributes.as_slice()))
+                .collect()
+        };
+    }
+    match data {
+        Some(metric::Data::Gauge(g)) => times!(g.data_points),
+        Some(metric::Data::Sum(s)) => times!(s.data_points),
+        Some(metric::Data::Histogram(h)) => times!(h.data_points),
+        Some(metric::Data::ExponentialHistogram(h)) => times!(h.data_points),
+        Some(metric::Data::Summary(s)) => times!(s.data_points),
+        None => vec![],
+    }
+}
+
+/// a span status contradicting the span's http status code: 5xx without
+/// error status, or error status on a success (or, for servers, a 4xx that
+/// is the client's fault)
+pub fn http_status_mismatch(span: &Span) -> Option<String> {
+    let http = span
+        .attributes
+        .iter()
+        .find(|kv| kv.key == "http.response.status_code" || kv.key == "http.status_code")
+        .and_then(|kv| match kv.value.as_ref()?.value.as_ref()? {
+            Value::IntValue(i) => Some(*i),
+            Value::StringValue(s) => s.parse().ok(),
+            _ => None,
+        })?;
+    let code = span.status.as_ref().map_or(StatusCode::Unset as i32, |s| s.code);
+    let server = span.kind == SpanKind::Server as i32;
+    if http >= 500 && code != StatusCode::Error as i32 {
+        let status = if code == StatusCode::Ok as i32 { "ok" } else { "unset" };
+        Some(format!("http status {} but span status is {}", http, status))
+    } else if code == StatusCode::Error as i32 && (http < 400 || (server && http < 500)) {
+        Some(format!("span status is error but http status is {}", http))
+    } else {
+        None
+    }
+}
+
+fn check_resource(resource: Option<&Resource>, warnings: &mut Vec<String>) {
+    match resource {
+        Some(resource) if !resource.attributes.is_empty() => check_keys("resource", &resource.attributes, warnings),
+        _ => warnings.push("empty resource".to_string()),
+    }
+}
+
+fn check_keys(what: &str, attributes: &[KeyValue], warnings: &mut Vec<String>) {
+    for kv in attributes {
+        if kv.key.is_empty() {
+            warnings.push(format!("{}: empty attribute key", what));
+        } else if kv.key.chars().any(char::is_whitespace) {
+            warnings.push(format!("{}: attribute key {:?} contains whitespace", what, kv.key));
+        }
+    }
+}
+
+fn check_time(what: &str, field: &str, nanos: u64, warnings: &mut Vec<String>) {
+    if nanos == 0 {
+        warnings.push(format!("{}: {} is not set", what, field));
+    } else if !plausible_time(nanos) {
+        warnings.push(format!("{}: implausible {} {}", what, field, nanos));
+    }
+}
+
+/// whether a set timestamp looks like unix nanos of a sane date
+pub fn plausible_time(nanos: u64) -> bool {
+    (MIN_PLAUSIBLE_NANOS..MAX_PLAUSIBLE_NANOS).contains(&nanos)
+}
+
+pub fn is_zero_id(id: &[u8]) -> bool {
+    id.iter().all(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::v1::{AnyValue, InstrumentationScope};
+    use crate::proto::logs::v1::{LogRecord, ScopeLogs};
+    use crate::proto::metrics::v1::{Gauge, Metric, NumberDataPoint, ScopeMetrics};
+    use crate::proto::trace::v1::{ScopeSpans, Status};
+
+    const SECOND: u64 = 1_000_000_000;
+    /// 2023-11-14
+    const NOW: u64 = 1_700_000_000 * SECOND;
+
+    fn kv(key: &str) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(Value::StringValue("v".into())) }) }
+    }
+
+    fn resource() -> Option<Resource> {
+        Some(Resource { attributes: vec![kv("service.name")], ..Default::default() })
+    }
+
+    fn spans(spans: Vec<Span>) -> Vec<String> {
+        check_resource_spans(&[ResourceSpans {
+            resource: resource(),
+            scope_spans: vec![ScopeSpans { spans, ..Default::default() }],
+            ..Default::default()
+        }])
+    }
+
+    fn span(start: u64, end: u64) -> Span {
+        Span {
+            name: "s".into(),
+            trace_id: vec![1; 16],
+            span_id: vec![2; 8],
+            start_time_unix_nano: start,
+            end_time_unix_nano: end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn spans_red_flags() {
+        assert!(spans(vec![span(NOW, NOW + SECOND)]).is_empty());
+
+        let what = "span \"s\" (0202020202020202)";
+        let zero = Span { trace_id: vec![0; 16], span_id: vec![0; 8], ..span(NOW, NOW) };
+        let zeroed = "span \"s\" (0000000000000000)";
+        assert_eq!(spans(vec![zero]), [format!("{}: zero trace id", zeroed), format!("{}: zero span id", zeroed)]);
+        assert_eq!(
+            spans(vec![span(0, 1_700_000_000)]),
+            [
+                format!("{}: start is not set", what),
+                format!("{}: implausible end 1700000000", what),
+            ]
+        );
+        assert_eq!(spans(vec![span(NOW, NOW - SECOND)]), [format!("{}: ends before it starts", what)]);
+        let keys = Span { attributes: vec![kv(""), kv("http method")], ..span(NOW, NOW) };
+        assert_eq!(
+            spans(vec![keys]),
+            [
+                format!("{}: empty attribute key", what),
+                format!("{}: attribute key \"http method\" contains whitespace", what),
+            ]
+        );
+    }
+
+    #[test]
+    fn resource_and_scope_red_flags() {
+        let warnings = check_resource_spans(&[ResourceSpans {
+            scope_spans: vec![ScopeSpans {
+                scope: Some(InstrumentationScope { attributes: vec![kv(" ")], ..Default::default() }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }]);
+        assert_eq!(warnings, ["empty resource", "scope: attribute key \" \" contains whitespace"]);
+    }
+
+    #[test]
+    fn logs_red_flags() {
+        let logs = |log_records| {
+            check_resource_logs(&[ResourceLogs {
+                resource: resource(),
+                scope_logs: vec![ScopeLogs { log_records, ..Default::default() }],
+                ..Default::default()
+            }])
+        };
+        // neither trace context nor times are required on logs
+        assert!(logs(vec![LogRecord::default()]).is_empty());
+        let log = LogRecord { trace_id: vec![0; 16], time_unix_nano: 42, ..Default::default() };
+        assert_eq!(logs(vec![LogRecord::default(), log]), ["log record #1: zero trace id", "log record #1: implausible time 42"]);
+    }
+
+    #[test]
+    fn metrics_red_flags() {
+        let metrics = |data_points| {
+            check_resource_metrics(&[ResourceMetrics {
+                resource: resource(),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "m".into(),
+                        data: Some(metric::Data::Gauge(Gauge { data_points })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }])
+        };
+        let point = |start, time| NumberDataPoint { start_time_unix_nano: start, time_unix_nano: time, ..Default::default() };
+        assert!(metrics(vec![point(0, NOW), point(NOW - SECOND, NOW)]).is_empty());
+        assert_eq!(
+            metrics(vec![point(NOW + SECOND, NOW), point(0, 0)]),
+            ["metric \"m\": point starts after its time", "metric \"m\": point time is not set"]
+        );
+    }
+
+
+    fn http(code: Value, kind: SpanKind, status: StatusCode) -> Span {
+        Span {
+            kind: kind as i32,
+            attributes: vec![KeyValue { key: "http.response.status_code".into(), value: Some(AnyValue { value: Some(code) }) }],
+            status: Some(Status { code: status as i32, ..Default::default() }),
+            ..span(NOW, NOW)
+        }
+    }
+
+    #[test]
+    fn http_status_mismatches() {
+        let mismatch = |code, kind, status| http_status_mismatch(&http(code, kind, status));
+        assert_eq!(mismatch(Value::IntValue(200), SpanKind::Server, StatusCode::Unset), None);
+        assert_eq!(mismatch(Value::IntValue(503), SpanKind::Server, StatusCode::Error), None);
+        // a 404 is an error of the client, not the server
+        assert_eq!(mismatch(Value::IntValue(404), SpanKind::Client, StatusCode::Error), None);
+        assert_eq!(
+            mismatch(Value::IntValue(500), SpanKind::Server, StatusCode::Unset).unwrap(),
+            "http status 500 but span status is unset"
+        );
+        assert_eq!(
+            mismatch(Value::StringValue("502".into()), SpanKind::Client, StatusCode::Ok).unwrap(),
+            "http status 502 but span status is ok"
+        );
+        assert_eq!(
+            mismatch(Value::IntValue(404), SpanKind::Server, StatusCode::Error).unwrap(),
+            "span status is error but http status is 404"
+        );
+        assert_eq!(
+            mismatch(Value::IntValue(204), SpanKind::Client, StatusCode::Error).unwrap(),
+            "span status is error but http status is 204"
+        );
+        // neither without an http status code
+        let unknown = Span { attributes: vec![], ..http(Value::IntValue(500), SpanKind::Server, StatusCode::Unset) };
+        assert_eq!(http_status_mismatch(&unknown), None);
+    }
+}
@@ -0,0 +1,556 @@
+use crate::cmd_report_trace::Kind;
+use crate::common::{any_value_to_json, parse_duration};
+use crate::otk_error::OTKError;
+use crate::proto::common::v1::KeyValue as ProtoKeyValue;
+use crate::proto::trace::v1::Span;
+use opentelemetry::trace::Status;
+use opentelemetry::{Array, KeyValue, StringValue, Value};
+use serde::de::{Deserializer, Error as _};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value as Json;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// a whole trace described in a yaml file (report-trace --scenario):
+///
+/// ```yaml
+/// spans:
+///   - id: root                # how children refer to it, the name if not given
+///     name: GET /checkout
+///     kind: server
+///     duration: 250ms
+///     attributes: {http.request.method: GET, http.response.status_code: 200}
+///   - name: SELECT orders
+///     parent: root
+///     kind: client
+///     offset: 20ms            # start after the start of the parent
+///     duration: 120ms
+///     status: error
+///     status_message: timeout
+///     events:
+///       - {name: retry, offset: 60ms, attributes: {attempt: 2}}
+/// ```
+///
+/// parents come before their children, spans without one are roots
+#[derive(Debug)]
+pub struct Scenario {
+    pub spans: Vec<ScenarioSpan>,
+}
+
+#[derive(Debug)]
+pub struct ScenarioSpan {
+    pub name: String,
+    /// index of the parent in `Scenario::spans`
+    pub parent: Option<usize>,
+    pub kind: Kind,
+    /// start after the start of the parent (of the trace for roots)
+    pub offset: Duration,
+    pub duration: Duration,
+    pub attributes: Vec<KeyValue>,
+    pub events: Vec<ScenarioEvent>,
+    pub status: Status,
+}
+
+#[derive(Debug)]
+pub struct ScenarioEvent {
+    pub name: String,
+    /// after the start of the span
+    pub offset: Duration,
+    pub attributes: Vec<KeyValue>,
+}
+
+/// the file as written, unknown keys are rejected as typos would silently
+/// change the trace otherwise. The doc comments end up in `otk schema scenario`
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(title = "otk scenario", description = "a trace for otk report-trace --scenario")]
+struct ScenarioFile {
+    /// spans of the trace, parents before their children
+    #[schemars(length(min = 1))]
+    spans: Vec<SpanEntry>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SpanEntry {
+    /// how children refer to the span, its name if not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[schemars(length(min = 1))]
+    name: String,
+    /// id of an earlier span, none for a root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    #[serde(default = "internal", skip_serializing_if = "is_internal")]
+    kind: Kind,
+    /// start after the start of the parent (of the trace for roots)
+    #[serde(default, deserialize_with = "duration", serialize_with = "duration_text", skip_serializing_if = "Duration::is_zero")]
+    #[schemars(schema_with = "duration_schema")]
+    offset: Duration,
+    #[serde(default, deserialize_with = "duration", serialize_with = "duration_text", skip_serializing_if = "Duration::is_zero")]
+    #[schemars(schema_with = "duration_schema")]
+    duration: Duration,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(schema_with = "attributes_schema")]
+    attributes: BTreeMap<String, Json>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    events: Vec<EventEntry>,
+    #[serde(default, skip_serializing_if = "StatusCode::is_unset")]
+    status: StatusCode,
+    /// description of an error status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EventEntry {
+    name: String,
+    /// after the start of the span
+    #[serde(default, deserialize_with = "duration", serialize_with = "duration_text", skip_serializing_if = "Duration::is_zero")]
+    #[schemars(schema_with = "duration_schema")]
+    offset: Duration,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(schema_with = "attributes_schema")]
+    attributes: BTreeMap<String, Json>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum StatusCode {
+    #[default]
+    Unset,
+    Ok,
+    Error,
+}
+
+impl StatusCode {
+    fn is_unset(&self) -> bool {
+        *self == StatusCode::Unset
+    }
+}
+
+fn internal() -> Kind {
+    Kind::Internal
+}
+
+fn is_internal(kind: &Kind) -> bool {
+    *kind == Kind::Internal
+}
+
+/// a duration with unit like 150ms, or a number of seconds
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = match Json::deserialize(deserializer)? {
+        Json::String(s) => s,
+        other => other.to_string(),
+    };
+    parse_duration(&text).map_err(D::Error::custom)
+}
+
+/// the shortest exact text of a duration, like 120ms
+fn duration_text<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    let text = match duration.as_nanos() {
+        0 => "0s".to_string(),
+        n if n % 1_000_000_000 == 0 => format!("{}s", n / 1_000_000_000),
+        n if n % 1_000_000 == 0 => format!("{}ms", n / 1_000_000),
+        n if n % 1_000 == 0 => format!("{}us", n / 1_000),
+        n => format!("{}ns", n),
+    };
+    serializer.serialize_str(&text)
+}
+
+fn duration_schema(_: &mut SchemaGenerator) -> Schema {
+    from_json(serde_json::json!({
+        "description": "a duration with unit like 150ms or 1m30s, or a number of seconds",
+        "type": ["string", "number"],
+        "pattern": "^[0-9.]+$|^([0-9.]+(ns|us|ms|s|m|h|d))+$",
+        "minimum": 0,
+    }))
+}
+
+fn attributes_schema(_: &mut SchemaGenerator) -> Schema {
+    let scalar = serde_json::json!({"type": ["string", "number", "boolean"]});
+    from_json(serde_json::json!({
+        "type": "object",
+        "additionalProperties": {"anyOf": [scalar, {"type": "array", "items": scalar}]},
+    }))
+}
+
+fn from_json(schema: Json) -> Schema {
+    serde_json::from_value(schema).expect("valid schema")
+}
+
+/// JSON Schema of the scenario files, for editors to validate them
+pub fn schema() -> Json {
+    serde_json::to_value(schemars::schema_for!(ScenarioFile)).expect("schema serializes")
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Scenario, OTKError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| OTKError::InvalidArgumentError(format!("read scenario {} failed: {}", path, e)))?;
+        Scenario::parse(&text).map_err(|e| OTKError::ParseError(format!("scenario {}: {}", path, e)))
+    }
+
+    fn parse(text: &str) -> Result<Scenario, String> {
+        let file: ScenarioFile = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+        if file.spans.is_empty() {
+            return Err("expect a non-empty `spans` list".into());
+        }
+        let mut ids: Vec<String> = vec![];
+        let mut spans = vec![];
+        for (i, span) in file.spans.into_iter().enumerate() {
+            let label = format!("span #{} ({})", i + 1, span.name);
+            if span.name.is_empty() {
+                return Err(format!("{}: name is empty", label));
+            }
+            let id = span.id.clone().unwrap_or_else(|| span.name.clone());
+            if ids.contains(&id) {
+                return Err(format!("{}: duplicate id {}", label, id));
+            }
+            spans.push(resolve_span(span, &ids).map_err(|e| format!("{}: {}", label, e))?);
+            ids.push(id);
+        }
+        Ok(Scenario { spans })
+    }
+
+    /// from the start of the trace to the end of its last span
+    pub fn length(&self) -> Duration {
+        let mut starts: Vec<Duration> = vec![];
+        let mut length = Duration::ZERO;
+        for span in &self.spans {
+            let start = span.parent.map_or(Duration::ZERO, |p| starts[p]) + span.offset;
+            length = length.max(start + span.duration);
+            starts.push(start);
+        }
+        length
+    }
+}
+
+/// a span of the list, whose parent must be one of the `earlier` ids
+fn resolve_span(span: SpanEntry, earlier: &[String]) -> Result<ScenarioSpan, String> {
+    let parent = match span.parent {
+        None => None,
+        Some(parent) => {
+            let index = earlier.iter().position(|id| *id == parent);
+            Some(index.ok_or_else(|| format!("unknown parent {} (parents must come before their children)", parent))?)
+        }
+    };
+    let status = match span.status {
+        StatusCode::Unset => Status::Unset,
+        StatusCode::Ok => Status::Ok,
+        StatusCode::Error => Status::error(span.status_message.unwrap_or_default()),
+    };
+    let events = span
+        .events
+        .into_iter()
+        .map(|event| {
+            Ok(ScenarioEvent {
+                attributes: attributes(&event.attributes).map_err(|e| format!("event {}: {}", event.name, e))?,
+                name: event.name,
+                offset: event.offset,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(ScenarioSpan {
+        name: span.name,
+        parent,
+        kind: span.kind,
+        offset: span.offset,
+        duration: span.duration,
+        attributes: attributes(&span.attributes)?,
+        events,
+        status,
+    })
+}
+
+type Shape<'a> = Vec<(&'a str, i32, Option<usize>)>;
+
+/// a scenario recorded from received spans (conformance --emit-scenario)
+pub struct Recording {
+    pub yaml: String,
+    /// traces received and the number of distinct shapes among them
+    pub traces: usize,
+    pub shapes: usize,
+    /// traces of the shape written
+    pub recorded: usize,
+}
+
+/// the scenario of the most common trace shape among `spans`, where a shape
+/// is the names, kinds and parents of the spans. Every trace of that shape
+/// counts towards the timings, which are medians, while attributes, events
+/// and statuses are those of its first trace
+pub fn record<'a>(spans: impl Iterator<Item = &'a Span>) -> Option<Recording> {
+    let mut traces: Vec<Vec<&Span>> = vec![];
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for span in spans {
+        let i = *index.entry(&span.trace_id).or_insert_with(|| {
+            traces.push(vec![]);
+            traces.len() - 1
+        });
+        traces[i].push(span);
+    }
+    let traces = traces.into_iter().map(tree).collect::<Vec<_>>();
+    // names, kinds and parent indexes of the spans, and the traces of it
+    let mut shapes: Vec<(Shape, Vec<usize>)> = vec![];
+    for (i, trace) in traces.iter().enumerate() {
+        let shape = trace.iter().map(|(s, parent)| (s.name.as_str(), s.kind, *parent)).collect::<Vec<_>>();
+        match shapes.iter_mut().find(|(known, _)| *known == shape) {
+            Some((_, members)) => members.push(i),
+            None => shapes.push((shape, vec![i])),
+        }
+    }
+    // the first seen wins ties
+    let (_, members) = shapes.iter().rev().max_by_key(|(_, members)| members.len())?;
+    let first = &traces[members[0]];
+    let mut ids: Vec<String> = vec![];
+    let mut entries = vec![];
+    for (i, (span, parent)) in first.iter().enumerate() {
+        let times = members.iter().map(|&t| span_times(&traces[t], i)).collect::<Vec<_>>();
+        let same_name = ids.iter().filter(|id| id.split(" #").next() == Some(&span.name)).count();
+        let id = match same_name {
+            0 => span.name.clone(),
+            n => format!("{} #{}", span.name, n + 1),
+        };
+        let status = span.status.as_ref();
+        entries.push(SpanEntry {
+            id: (id != span.name).then(|| id.clone()),
+            name: span.name.clone(),
+            parent: parent.map(|p| ids[p].clone()),
+            kind: proto_kind(span.kind),
+            offset: median(times.iter().map(|t| t.0).collect()),
+            duration: median(times.iter().map(|t| t.1).collect()),
+            attributes: attribute_map(&span.attributes),
+            events: span
+                .events
+                .iter()
+                .map(|e| EventEntry {
+                    name: e.name.clone(),
+                    offset: Duration::from_nanos(e.time_unix_nano.saturating_sub(span.start_time_unix_nano)),
+                    attributes: attribute_map(&e.attributes),
+                })
+                .collect(),
+            status: match status.map_or(0, |s| s.code) {
+                1 => StatusCode::Ok,
+                2 => StatusCode::Error,
+                _ => StatusCode::Unset,
+            },
+            status_message: status.map(|s| s.message.clone()).filter(|m| !m.is_empty()),
+        });
+        ids.push(id);
+    }
+    let yaml = serde_yaml::to_string(&ScenarioFile { spans: entries }).ok()?;
+    Some(Recording { yaml, traces: traces.len(), shapes: shapes.len(), recorded: members.len() })
+}
+
+/// the spans of a trace parents first, each with the index of its parent.
+/// Spans whose parent wasn't received are roots
+fn tree(mut spans: Vec<&Span>) -> Vec<(&Span, Option<usize>)> {
+    spans.sort_by(|a, b| (a.start_time_unix_nano, &a.name).cmp(&(b.start_time_unix_nano, &b.name)));
+    let mut ordered: Vec<(&Span, Option<usize>)> = vec![];
+    let mut stack = spans
+        .iter()
+        .rev()
+        .filter(|s| !spans.iter().any(|p| p.span_id == s.parent_span_id))
+        .map(|s| (*s, None))
+        .collect::<Vec<_>>();
+    while let Some((span, parent)) = stack.pop() {
+        let index = ordered.len();
+        ordered.push((span, parent));
+        stack.extend(spans.iter().rev().filter(|c| c.parent_span_id == span.span_id).map(|c| (*c, Some(index))));
+    }
+    ordered
+}
+
+/// offset and duration of the `i`th span of a trace ordered by `tree`
+fn span_times(trace: &[(&Span, Option<usize>)], i: usize) -> (Duration, Duration) {
+    let (span, parent) = trace[i];
+    let start = match parent {
+        Some(p) => trace[p].0.start_time_unix_nano,
+        None => trace.iter().map(|(s, _)| s.start_time_unix_nano).min().unwrap_or_default(),
+    };
+    (
+        Duration::from_nanos(span.start_time_unix_nano.saturating_sub(start)),
+        Duration::from_nanos(span.end_time_unix_nano.saturating_sub(span.start_time_unix_nano)),
+    )
+}
+
+fn median(mut values: Vec<Duration>) -> Duration {
+    values.sort();
+    values[values.len() / 2]
+}
+
+fn proto_kind(kind: i32) -> Kind {
+    match kind {
+        2 => Kind::Server,
+        3 => Kind::Client,
+        4 => Kind::Producer,
+        5 => Kind::Consumer,
+        _ => Kind::Internal,
+    }
+}
+
+/// the attributes a scenario can hold, others are left out
+fn attribute_map(attributes: &[ProtoKeyValue]) -> BTreeMap<String, Json> {
+    attributes
+        .iter()
+        .filter_map(|kv| {
+            let value = any_value_to_json(kv.value.as_ref()?);
+            attribute_value(&value)?;
+            Some((kv.key.clone(), value))
+        })
+        .collect()
+}
+
+/// attributes from strings, numbers, booleans or lists of one of them
+fn attributes(fields: &BTreeMap<String, Json>) -> Result<Vec<KeyValue>, String> {
+    fields
+        .iter()
+        .map(|(k, v)| {
+            let value = attribute_value(v).ok_or_else(|| format!("unsupported value of attribute {}: {}", k, v))?;
+            Ok(KeyValue::new(k.clone(), value))
+        })
+        .collect()
+}
+
+fn attribute_value(value: &Json) -> Option<Value> {
+    Some(match value {
+        Json::String(s) => Value::from(s.clone()),
+        Json::Bool(b) => Value::Bool(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::I64(i),
+            None => Value::F64(n.as_f64()?),
+        },
+        Json::Array(items) => Value::Array(match items.first() {
+            Some(Json::String(_)) => Array::String(
+                items.iter().map(|i| i.as_str().map(|s| StringValue::from(s.to_string()))).collect::<Option<_>>()?,
+            ),
+            Some(Json::Bool(_)) => Array::Bool(items.iter().map(Json::as_bool).collect::<Option<_>>()?),
+            Some(Json::Number(_)) if items.iter().all(|i| i.is_i64()) => {
+                Array::I64(items.iter().map(Json::as_i64).collect::<Option<_>>()?)
+            }
+            Some(Json::Number(_)) => Array::F64(items.iter().map(Json::as_f64).collect::<Option<_>>()?),
+            None => Array::String(vec![]),
+            _ => return None,
+        }),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKOUT: &str = "
+spans:
+  - id: root
+    name: GET /checkout
+    kind: server
+    duration: 250ms
+    attributes: {http.request.method: GET, http.response.status_code: 200}
+  - name: SELECT orders
+    parent: root
+    kind: client
+    offset: 20ms
+    duration: 0.12
+    status: error
+    status_message: timeout
+    events:
+      - {name: retry, offset: 60ms, attributes: {attempt: 2, tags: [a, b]}}
+";
+
+    #[test]
+    fn parses_spans() {
+        let scenario = Scenario::parse(CHECKOUT).unwrap();
+        let [root, query] = &scenario.spans[..] else { panic!("expect 2 spans") };
+        assert_eq!(root.parent, None);
+        assert!(matches!(root.kind, Kind::Server));
+        assert_eq!(root.attributes.len(), 2);
+        assert_eq!(query.parent, Some(0));
+        assert_eq!(query.offset, Duration::from_millis(20));
+        assert_eq!(query.duration, Duration::from_millis(120));
+        assert_eq!(query.status, Status::error("timeout"));
+        assert_eq!(query.events[0].attributes[0], KeyValue::new("attempt", 2));
+        assert_eq!(scenario.length(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn defaults() {
+        let scenario = Scenario::parse("spans: [{name: a}]").unwrap();
+        let span = &scenario.spans[0];
+        assert!(matches!(span.kind, Kind::Internal));
+        assert_eq!(span.status, Status::Unset);
+        assert_eq!(span.duration, Duration::ZERO);
+    }
+
+    #[test]
+    fn schema_describes_the_format() {
+        let schema = schema();
+        let span = &schema["definitions"]["SpanEntry"];
+        assert_eq!(span["additionalProperties"], false);
+        assert_eq!(span["required"], serde_json::json!(["name"]));
+        assert_eq!(span["properties"]["kind"]["$ref"], "#/definitions/Kind");
+        assert_eq!(schema["definitions"]["StatusCode"]["enum"], serde_json::json!(["unset", "ok", "error"]));
+        assert!(span["properties"]["duration"]["pattern"].is_string());
+    }
+
+    fn proto_span(trace: u8, id: u8, parent: u8, name: &str, start: u64, end: u64) -> Span {
+        Span {
+            trace_id: vec![trace; 16],
+            span_id: vec![id; 8],
+            parent_span_id: if parent == 0 { vec![] } else { vec![parent; 8] },
+            name: name.to_string(),
+            start_time_unix_nano: start * 1_000_000,
+            end_time_unix_nano: end * 1_000_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_most_common_shape() {
+        let spans = [
+            proto_span(1, 1, 0, "root", 0, 100),
+            proto_span(1, 2, 1, "child", 10, 30),
+            proto_span(2, 1, 0, "other", 0, 5),
+            // the child before its parent, and orphans are roots
+            proto_span(3, 2, 1, "child", 1020, 1060),
+            proto_span(3, 1, 0, "root", 1000, 1200),
+            proto_span(4, 1, 0, "root", 0, 300),
+            proto_span(4, 2, 1, "child", 30, 50),
+        ];
+        let recording = record(spans.iter()).unwrap();
+        assert_eq!((recording.recorded, recording.traces, recording.shapes), (3, 4, 2));
+        let scenario = Scenario::parse(&recording.yaml).unwrap();
+        let [root, child] = &scenario.spans[..] else { panic!("expect 2 spans") };
+        assert_eq!((root.name.as_str(), root.duration), ("root", Duration::from_millis(200)));
+        assert_eq!((child.parent, child.offset), (Some(0), Duration::from_millis(20)));
+        assert_eq!(child.duration, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn records_repeated_names() {
+        let spans = [
+            proto_span(1, 1, 0, "root", 0, 100),
+            proto_span(1, 2, 1, "query", 10, 20),
+            proto_span(1, 3, 1, "query", 30, 40),
+        ];
+        let scenario = Scenario::parse(&record(spans.iter()).unwrap().yaml).unwrap();
+        assert_eq!(scenario.spans.iter().map(|s| s.parent).collect::<Vec<_>>(), vec![None, Some(0), Some(0)]);
+        assert_eq!(scenario.spans[2].offset, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn rejects_mistakes() {
+        let error = |text| Scenario::parse(text).unwrap_err();
+        assert!(error("spans: []").contains("non-empty"));
+        assert!(error("spans: [{name: a, durtion: 1s}]").contains("unknown field `durtion`"));
+        assert!(error("spans: [{name: a, kind: sever}]").contains("unknown variant `sever`"));
+        assert!(error("spans: [{name: a, duration: 1x}]").contains("expect seconds"));
+        assert!(error("spans: [{name: a, parent: b}, {name: b}]").contains("unknown parent b"));
+        assert!(error("spans: [{name: a}, {name: a}]").contains("duplicate id a"));
+        assert!(error("spans: [{name: a, attributes: {x: {y: 1}}}]").contains("unsupported value of attribute x"));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::common::{any_value_to_string, attr_value};
+use crate::proto::common::v1::{InstrumentationScope, KeyValue};
+use crate::proto::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+use crate::proto::metrics::v1::{metric::Data, Metric, ResourceMetrics, ScopeMetrics};
+use crate::proto::resource::v1::Resource;
+use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+use hex::ToHex;
+use prost::Message;
+use std::collections::HashMap;
+
+/// encoded size breakdown of an otlp message, per resource, scope,
+/// item (span, metric or log record) and attribute key
+#[derive(Debug, Default)]
+pub struct SizeReport {
+    total: usize,
+    resources: Vec<(String, usize)>,
+    scopes: Vec<(String, usize)>,
+    item_kind: &'static str,
+    items: Vec<(String, usize)>,
+    attr_keys: HashMap<String, usize>,
+}
+
+impl SizeReport {
+    pub fn new(total: usize) -> Self {
+        SizeReport {
+            total,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_resource(&mut self, resource: &Resource) {
+        let label = resource_label(Some(resource), self.resources.len());
+        self.resources.push((label, resource.encoded_len()));
+        self.add_attributes(&resource.attributes);
+    }
+
+    pub fn add_resource_spans(&mut self, rs: &ResourceSpans) {
+        self.add_resource_entry(rs.resource.as_ref(), rs.encoded_len());
+        for ss in &rs.scope_spans {
+            self.add_scope_spans(ss);
+        }
+    }
+
+    pub fn add_scope_spans(&mut self, ss: &ScopeSpans) {
+        self.add_scope_entry(ss.scope.as_ref(), ss.encoded_len());
+        for span in &ss.spans {
+            self.add_span(span);
+        }
+    }
+
+    pub fn add_span(&mut self, span: &Span) {
+        let label = format!("{} ({})", span.name, span.span_id.encode_hex::<String>());
+        self.item_kind = "spans";
+        self.items.push((label, span.encoded_len()));
+        self.add_attributes(&span.attributes);
+        for event in &span.events {
+            self.add_attributes(&event.attributes);
+        }
+        for link in &span.links {
+            self.add_attributes(&link.attributes);
+        }
+    }
+
+    pub fn add_resource_metrics(&mut self, rm: &ResourceMetrics) {
+        self.add_resource_entry(rm.resource.as_ref(), rm.encoded_len());
+        for sm in &rm.scope_metrics {
+            self.add_scope_metrics(sm);
+        }
+    }
+
+    pub fn add_scope_metrics(&mut self, sm: &ScopeMetrics) {
+        self.add_scope_entry(sm.scope.as_ref(), sm.encoded_len());
+        for metric in &sm.metrics {
+            self.add_metric(metric);
+        }
+    }
+
+    pub fn add_metric(&mut self, metric: &Metric) {
+        self.item_kind = "metrics";
+        self.items.push((metric.name.clone(), metric.encoded_len()));
+        match &metric.data {
+            Some(Data::Gauge(g)) => g.data_points.iter().for_each(|dp| self.add_attributes(&dp.attributes)),
+            Some(Data::Sum(s)) => s.data_points.iter().for_each(|dp| self.add_attributes(&dp.attributes)),
+            Some(Data::Histogram(h)) => h.data_points.iter().for_each(|dp| self.add_attributes(&dp.attributes)),
+            Some(Data::ExponentialHistogram(h)) => h.data_points.iter().for_each(|dp| self.add_attributes(&dp.attributes)),
+            Some(Data::Summary(s)) => s.data_points.iter().for_each(|dp| self.add_attributes(&dp.attributes)),
+            None => {}
+        }
+    }
+
+    pub fn add_resource_logs(&mut self, rl: &ResourceLogs) {
+        self.add_resource_entry(rl.resource.as_ref(), rl.encoded_len());
+        for sl in &rl.scope_logs {
+            self.add_scope_logs(sl);
+        }
+    }
+
+    pub fn add_scope_logs(&mut self, sl: &ScopeLogs) {
+        self.add_scope_entry(sl.scope.as_ref(), sl.encoded_len());
+        for log in &sl.log_records {
+            self.add_log_record(log);
+        }
+    }
+
+    pub fn add_log_record(&mut self, log: &LogRecord) {
+        let body = log.body.as_ref().map(any_value_to_string).unwrap_or_default();
+        let label = format!("{} {}", log.severity_text, body.chars().take(40).collect::<String>());
+        self.item_kind = "log records";
+        self.items.push((label, log.encoded_len()));
+        self.add_attributes(&log.attributes);
+    }
+
+    pub fn print(&self) {
+        println!("total: {} bytes", self.total);
+        print_section("resources", &self.resources, self.total);
+        print_section("scopes", &self.scopes, self.total);
+        print_section(self.item_kind, &self.items, self.total);
+        let attr_keys = self
+            .attr_keys
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>();
+        print_section("attribute keys", &attr_keys, self.total);
+    }
+
+    fn add_resource_entry(&mut self, resource: Option<&Resource>, size: usize) {
+        let label = resource_label(resource, self.resources.len());
+        self.resources.push((label, size));
+        if let Some(resource) = resource {
+            self.add_attributes(&resource.attributes);
+        }
+    }
+
+    fn add_scope_entry(&mut self, scope: Option<&InstrumentationScope>, size: usize) {
+        let label = match scope {
+            Some(scope) if !scope.version.is_empty() => format!("{}@{}", scope.name, scope.version),
+            Some(scope) => scope.name.clone(),
+            None => format!("scope #{}", self.scopes.len()),
+        };
+        self.scopes.push((label, size));
+        if let Some(scope) = scope {
+            self.add_attributes(&scope.attributes);
+        }
+    }
+
+    fn add_attributes(&mut self, attrs: &[KeyValue]) {
+        for kv in attrs {
+            *self.attr_keys.entry(kv.key.clone()).or_insert(0) += kv.encoded_len();
+        }
+    }
+}
+
+fn resource_label(resource: Option<&Resource>, idx: usize) -> String {
+    resource
+        .and_then(|r| r.attributes.iter().find(|kv| kv.key == "service.name"))
+        .map(attr_value)
+        .unwrap_or_else(|| format!("resource #{}", idx))
+}
+
+fn print_section(title: &str, entries: &[(String, usize)], total: usize) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut entries = entries.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    println!("{}:", title);
+    for (label, size) in entries {
+        let pct = if total == 0 { 0. } else { *size as f64 * 100. / total as f64 };
+        println!("{:>12} {:>6.2}%  {}", size, pct, label);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::v1::any_value::Value;
+    use crate::proto::common::v1::AnyValue;
+    use crate::proto::metrics::v1::{Gauge, NumberDataPoint};
+    use crate::proto::trace::v1::span::Event;
+
+    fn kv(key: &str, value: &str) -> KeyValue {
+        KeyValue { key: key.into(), value: Some(AnyValue { value: Some(Value::StringValue(value.into())) }) }
+    }
+
+    #[test]
+    fn breaks_down_spans() {
+        let span = Span {
+            name: "GET /".into(),
+            span_id: vec![1; 8],
+            attributes: vec![kv("http.route", "/")],
+            events: vec![Event { attributes: vec![kv("http.route", "/cart")], ..Default::default() }],
+            ..Default::default()
+        };
+        let rs = ResourceSpans {
+            resource: Some(Resource { attributes: vec![kv("service.name", "checkout")], ..Default::default() }),
+            scope_spans: vec![
+                ScopeSpans {
+                    scope: Some(InstrumentationScope { name: "http".into(), version: "1.0".into(), ..Default::default() }),
+                    spans: vec![span.clone()],
+                    ..Default::default()
+                },
+                ScopeSpans::default(),
+            ],
+            ..Default::default()
+        };
+        let mut report = SizeReport::new(rs.encoded_len());
+        report.add_resource_spans(&rs);
+        assert_eq!(report.resources, [("checkout".to_string(), rs.encoded_len())]);
+        assert_eq!(
+            report.scopes,
+            [("http@1.0".to_string(), rs.scope_spans[0].encoded_len()), ("scope #1".to_string(), 0)]
+        );
+        assert_eq!(report.item_kind, "spans");
+        assert_eq!(report.items, [("GET / (0101010101010101)".to_string(), span.encoded_len())]);
+        // keys add up over the resource, spans and their events
+        assert_eq!(report.attr_keys["http.route"], kv("http.route", "/").encoded_len() + kv("http.route", "/cart").encoded_len());
+        assert_eq!(report.attr_keys["service.name"], kv("service.name", "checkout").encoded_len());
+    }
+
+    #[test]
+    fn breaks_down_metrics_and_logs() {
+        let metric = Metric {
+            name: "queue.size".into(),
+            data: Some(Data::Gauge(Gauge {
+                data_points: vec![NumberDataPoint { attributes: vec![kv("queue", "a")], ..Default::default() }; 2],
+            })),
+            ..Default::default()
+        };
+        let mut report = SizeReport::new(0);
+        report.add_resource_metrics(&ResourceMetrics {
+            scope_metrics: vec![ScopeMetrics { metrics: vec![metric.clone()], ..Default::default() }],
+            ..Default::default()
+        });
+        assert_eq!(report.resources[0].0, "resource #0");
+        assert_eq!(report.items, [("queue.size".to_string(), metric.encoded_len())]);
+        assert_eq!(report.attr_keys["queue"], 2 * kv("queue", "a").encoded_len());
+
+        let log = LogRecord {
+            severity_text: "WARN".into(),
+            body: Some(AnyValue { value: Some(Value::StringValue("x".repeat(50))) }),
+            ..Default::default()
+        };
+        let mut report = SizeReport::new(0);
+        report.add_log_record(&log);
+        assert_eq!(report.item_kind, "log records");
+        // bodies are cut to 40 characters
+        assert_eq!(report.items, [(format!("WARN {}", "x".repeat(40)), log.encoded_len())]);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::common::{any_value_to_string, attributes_to_json};
+use crate::otk_error::OTKError;
+use crate::proto::collector::logs::v1::ExportLogsServiceRequest;
+use crate::proto::collector::metrics::v1::ExportMetricsServiceRequest;
+use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+use crate::proto::common::v1::{InstrumentationScope, KeyValue as ProtoKeyValue};
+use crate::proto::metrics::v1::{metric, number_data_point, NumberDataPoint};
+use crate::proto::resource::v1::Resource;
+use hex::ToHex;
+use rusqlite::{params, Connection};
+
+/// tables of a store. ids are hex, times nanoseconds since the epoch and
+/// attributes json objects, for `json_extract(attributes, '$."http.route"')`
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS spans (
+    trace_id TEXT NOT NULL,
+    span_id TEXT NOT NULL,
+    parent_span_id TEXT,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    start_time_unix_nano INTEGER NOT NULL,
+    end_time_unix_nano INTEGER NOT NULL,
+    duration_ns INTEGER NOT NULL,
+    status_code TEXT NOT NULL,
+    status_message TEXT,
+    service_name TEXT,
+    scope_name TEXT,
+    attributes TEXT NOT NULL,
+    resource_attributes TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS spans_trace_id ON spans (trace_id);
+CREATE TABLE IF NOT EXISTS logs (
+    time_unix_nano INTEGER NOT NULL,
+    severity_number INTEGER NOT NULL,
+    severity_text TEXT,
+    body TEXT,
+    trace_id TEXT,
+    span_id TEXT,
+    service_name TEXT,
+    scope_name TEXT,
+    attributes TEXT NOT NULL,
+    resource_attributes TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS metric_points (
+    name TEXT NOT NULL,
+    unit TEXT,
+    type TEXT NOT NULL,
+    time_unix_nano INTEGER NOT NULL,
+    value REAL,
+    count INTEGER,
+    sum REAL,
+    service_name TEXT,
+    scope_name TEXT,
+    attributes TEXT NOT NULL,
+    resource_attributes TEXT NOT NULL
+);
+";
+
+/// path of a `sqlite:path.db` store
+pub fn parse_store(s: &str) -> Result<String, OTKError> {
+    match s.strip_prefix("sqlite:") {
+        Some(path) if !path.is_empty() => Ok(path.to_string()),
+        _ => Err(OTKError::ParseError(format!("invalid store {} (expect sqlite:path.db)", s))),
+    }
+}
+
+/// received telemetry persisted in sqlite (conformance --listen --store), one
+/// row per span, log record and metric data point
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// open or create the database, appending to what it holds
+    pub fn open(path: &str) -> Result<Store, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store { conn })
+    }
+
+    pub fn add_traces(&mut self, req: &ExportTraceServiceRequest) -> Result<(), rusqlite::Error> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO spans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            )?;
+            for rs in &req.resource_spans {
+                let (service, resource) = resource_columns(rs.resource.as_ref());
+                for ss in &rs.scope_spans {
+                    for span in &ss.spans {
+                        let status = span.status.as_ref();
+                        insert.execute(params![
+                            span.trace_id.encode_hex::<String>(),
+                            span.span_id.encode_hex::<String>(),
+                            optional_id(&span.parent_span_id),
+                            span.name,
+                            span_kind(span.kind),
+                            span.start_time_unix_nano as i64,
+                            span.end_time_unix_nano as i64,
+                            span.end_time_unix_nano.saturating_sub(span.start_time_unix_nano) as i64,
+                            status_code(status.map_or(0, |s| s.code)),
+                            status.map(|s| s.message.as_str()).filter(|m| !m.is_empty()),
+                            service,
+                            scope_name(ss.scope.as_ref()),
+                            attributes_to_json(&span.attributes).to_string(),
+                            resource,
+                        ])?;
+                    }
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn add_logs(&mut self, req: &ExportLogsServiceRequest) -> Result<(), rusqlite::Error> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare_cached("INSERT INTO logs VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+            for rl in &req.resource_logs {
+                let (service, resource) = resource_columns(rl.resource.as_ref());
+                for sl in &rl.scope_logs {
+                    for log in &sl.log_records {
+                        // the observed time stands in for records without one
+                        let time = if log.time_unix_nano > 0 { log.time_unix_nano } else { log.observed_time_unix_nano };
+                        insert.execute(params![
+                            time as i64,
+                            log.severity_number,
+                            Some(log.severity_text.as_str()).filter(|t| !t.is_empty()),
+                            log.body.as_ref().map(any_value_to_string),
+                            optional_id(&log.trace_id),
+                            optional_id(&log.span_id),
+                            service,
+                            scope_name(sl.scope.as_ref()),
+                            attributes_to_json(&log.attributes).to_string(),
+                            resource,
+                        ])?;
+                    }
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn add_metrics(&mut self, req: &ExportMetricsServiceRequest) -> Result<(), rusqlite::Error> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare_cached("INSERT INTO metric_points VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)")?;
+            for rm in &req.resource_metrics {
+                let (service, resource) = resource_columns(rm.resource.as_ref());
+                for sm in &rm.scope_metrics {
+                    let scope = scope_name(sm.scope.as_ref());
+                    for m in &sm.metrics {
+                        for point in points(m.data.as_ref()) {
+                            insert.execute(params![
+                                m.name,
+                                Some(m.unit.as_str()).filter(|u| !u.is_empty()),
+                                point.kind,
+                                point.time as i64,
+                                point.value,
+                                point.count.map(|c| c as i64),
+                                point.sum,
+                                service,
+                                scope,
+                                attributes_to_json(point.attributes).to_string(),
+                                resource,
+                            ])?;
+                        }
+                    }
+                }
+            }
+        }
+        tx.commit()
+    }
+}
+
+/// a metric data point as a row: gauges and sums have a value, histograms
+/// and summaries a count and sum
+struct Point<'a> {
+    kind: &'static str,
+    time: u64,
+    value: Option<f64>,
+    count: Option<u64>,
+    sum: Option<f64>,
+    attributes: &'a [ProtoKeyValue],
+}
+
+fn number<'a>(kind: &'static str, p: &'a NumberDataPoint) -> Point<'a> {
+    let value = match p.value {
+        Some(number_data_point::Value::AsDouble(d)) => Some(d),
+        Some(number_data_point::Value::AsInt(i)) => Some(i as f64),
+        None => None,
+    };
+    Point { kind, time: p.time_unix_nano, value, count: None, sum: None, attributes: &p.attributes }
+}
+
+fn points(data: Option<&metric::Data>) -> Vec<Point<'_>> {
+    match data {
+        Some(metric::Data::Gauge(g)) => g.data_points.iter().map(|p| number("gauge", p)).collect(),
+        Some(metric::Data::Sum(s)) => s.data_points.iter().map(|p| number("sum", p)).collect(),
+        Some(metric::Data::Histogram(h)) => h
+            .data_points
+            .iter()
+            .map(|p| Point {
+                kind: "histogram",
+                time: p.time_unix_nano,
+                value: None,
+                count: Some(p.count),
+                sum: p.sum,
+                attributes: &p.attributes,
+            })
+            .collect(),
+        Some(metric::Data::ExponentialHistogram(h)) => h
+            .data_points
+            .iter()
+            .map(|p| Point {
+                kind: "exponential_histogram",
+                time: p.time_unix_nano,
+                value: None,
+                count: Some(p.count),
+                sum: p.sum,
+                attributes: &p.attributes,
+            })
+            .collect(),
+        Some(metric::Data::Summary(s)) => s
+            .data_points
+            .iter()
+            .map(|p| Point {
+                kind: "summary",
+                time: p.time_unix_nano,
+                value: None,
+                count: Some(p.count),
+                sum: Some(p.sum),
+                attributes: &p.attributes,
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// service.name and the attributes as json of a resource
+fn resource_columns(resource: Option<&Resource>) -> (Option<String>, String) {
+    let attributes = resource.map_or(&[][..], |r| &r.attributes);
+    let service = attributes
+        .iter()
+        .find(|kv| kv.key == "service.name")
+        .and_then(|kv| kv.value.as_ref())
+        .map(any_value_to_string);
+    (service, attributes_to_json(attributes).to_string())
+}
+
+fn scope_name(scope: Option<&InstrumentationScope>) -> Option<&str> {
+    scope.map(|s| s.name.as_str()).filter(|n| !n.is_empty())
+}
+
+/// hex of an id, NULL when absent
+fn optional_id(id: &[u8]) -> Option<String> {
+    (!id.is_empty()).then(|| id.encode_hex())
+}
+
+fn span_kind(kind: i32) -> &'static str {
+    match kind {
+        1 => "internal",
+        2 => "server",
+        3 => "client",
+        4 => "producer",
+        5 => "consumer",
+        _ => "unspecified",
+    }
+}
+
+fn status_code(code: i32) -> &'static str {
+    match code {
+        1 => "ok",
+        2 => "error",
+        _ => "unset",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::v1::{any_value::Value, AnyValue};
+    use crate::proto::metrics::v1::{Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics};
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+    fn kv(key: &str, value: Value) -> ProtoKeyValue {
+        ProtoKeyValue { key: key.to_string(), value: Some(AnyValue { value: Some(value) }) }
+    }
+
+    fn resource() -> Option<Resource> {
+        Some(Resource { attributes: vec![kv("service.name", Value::StringValue("shop".into()))], ..Default::default() })
+    }
+
+    #[test]
+    fn store_paths() {
+        assert_eq!(parse_store("sqlite:otk.db").unwrap(), "otk.db");
+        assert!(parse_store("otk.db").is_err());
+        assert!(parse_store("sqlite:").is_err());
+    }
+
+    #[test]
+    fn stores_spans() {
+        let mut store = Store::open(":memory:").unwrap();
+        let span = Span {
+            trace_id: vec![1; 16],
+            span_id: vec![2; 8],
+            name: "GET /".into(),
+            kind: 2,
+            start_time_unix_nano: 1_000,
+            end_time_unix_nano: 3_500,
+            attributes: vec![kv("http.status_code", Value::IntValue(200))],
+            ..Default::default()
+        };
+        let req = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: resource(),
+                scope_spans: vec![ScopeSpans { spans: vec![span], ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+        store.add_traces(&req).unwrap();
+        let row = store
+            .conn
+            .query_row(
+                "SELECT trace_id, parent_span_id, kind, duration_ns, status_code, service_name,
+                    json_extract(attributes, '$.\"http.status_code\"') FROM spans",
+                [],
+                |r| {
+                    Ok((
+                        r.get::<_, String>(0)?,
+                        r.get::<_, Option<String>>(1)?,
+                        r.get::<_, String>(2)?,
+                        r.get::<_, i64>(3)?,
+                        r.get::<_, String>(4)?,
+                        r.get::<_, String>(5)?,
+                        r.get::<_, i64>(6)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            ("01".repeat(16), None, "server".into(), 2_500, "unset".into(), "shop".into(), 200)
+        );
+    }
+
+    #[test]
+    fn stores_metric_points() {
+        let mut store = Store::open(":memory:").unwrap();
+        let point = |v| NumberDataPoint { value: Some(number_data_point::Value::AsInt(v)), ..Default::default() };
+        let req = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: resource(),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "queue.size".into(),
+                        data: Some(metric::Data::Gauge(Gauge { data_points: vec![point(3), point(5)] })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        store.add_metrics(&req).unwrap();
+        let sum: f64 = store
+            .conn
+            .query_row("SELECT sum(value) FROM metric_points WHERE type = 'gauge'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(sum, 8.0);
+    }
+}
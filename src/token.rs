@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::header::AUTHORIZATION;
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use opentelemetry_http::{HttpClient, HttpError, Request, Response};
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// token sources by command, so the exporters of all signals share a token
+static SOURCES: Lazy<Mutex<HashMap<String, Arc<TokenSource>>>> = Lazy::new(Default::default);
+
+/// access token printed by a command (--token-command), rerun when the token
+/// is older than `refresh` or the endpoint rejected it
+#[derive(Debug)]
+pub struct TokenSource {
+    command: String,
+    refresh: Duration,
+    current: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenSource {
+    pub fn shared(command: &str, refresh: Duration) -> Arc<TokenSource> {
+        let mut sources = SOURCES.lock().unwrap();
+        sources
+            .entry(command.to_string())
+            .or_insert_with(|| {
+                Arc::new(TokenSource {
+                    command: command.to_string(),
+                    refresh,
+                    current: Mutex::new(None),
+                })
+            })
+            .clone()
+    }
+
+    /// authorization header value, `Bearer <token>` unless the command
+    /// already printed a scheme
+    pub fn header(&self) -> Result<String, String> {
+        let mut current = self.current.lock().unwrap();
+        match &*current {
+            Some((header, fetched)) if fetched.elapsed() < self.refresh => Ok(header.clone()),
+            _ => {
+                let header = self.run()?;
+                *current = Some((header.clone(), Instant::now()));
+                Ok(header)
+            }
+        }
+    }
+
+    /// `header` from async code: a token still fresh is used right away,
+    /// otherwise the command runs on a blocking thread so it doesn't hold up
+    /// a runtime worker
+    pub async fn header_async(self: &Arc<Self>) -> Result<String, String> {
+        if let Some(header) = self.fresh() {
+            return Ok(header);
+        }
+        let source = self.clone();
+        tokio::task::spawn_blocking(move || source.header())
+            .await
+            .map_err(|e| format!("token command: {}", e))?
+    }
+
+    /// `header` from sync code that may run on a runtime worker (tonic's
+    /// interceptors), which hands its other tasks over while the command runs
+    pub fn header_in_place(&self) -> Result<String, String> {
+        if let Some(header) = self.fresh() {
+            return Ok(header);
+        }
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| self.header())
+            }
+            _ => self.header(),
+        }
+    }
+
+    /// the current token if it is still fresh, without waiting for a
+    /// command that is running
+    fn fresh(&self) -> Option<String> {
+        match &*self.current.try_lock().ok()? {
+            Some((header, fetched)) if fetched.elapsed() < self.refresh => Some(header.clone()),
+            _ => None,
+        }
+    }
+
+    /// drop the token after the endpoint rejected `header`, unless a
+    /// concurrent request already replaced it
+    pub fn invalidate(&self, header: &str) {
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref().is_some_and(|(h, _)| h == header) {
+            eprintln!("token rejected, rerunning --token-command");
+            *current = None;
+        }
+    }
+
+    fn run(&self) -> Result<String, String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(|e| format!("token command: {}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("token command {} {}", output.status, stderr.trim()).trim_end().to_string());
+        }
+        let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        match token.as_str() {
+            "" => Err("token command printed nothing".into()),
+            t if t.contains(' ') => Ok(token),
+            t => Ok(format!("Bearer {}", t)),
+        }
+    }
+}
+
+/// drop the token of every --token-command after a grpc endpoint answered
+/// UNAUTHENTICATED, which doesn't tell which token it rejected. false when
+/// there was no token to drop
+pub fn invalidate_all() -> bool {
+    let mut dropped = false;
+    for source in SOURCES.lock().unwrap().values() {
+        // a command already running is bringing a new token
+        dropped |= source.current.try_lock().map_or(true, |mut current| current.take().is_some());
+    }
+    if dropped {
+        eprintln!("token rejected, rerunning --token-command");
+    }
+    dropped
+}
+
+/// http client setting the authorization header from a token source, and
+/// retrying once with a fresh token when the endpoint answers 401
+#[derive(Debug)]
+pub struct TokenClient<C> {
+    pub inner: C,
+    pub token: Arc<TokenSource>,
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for TokenClient<C> {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let header = self.token.header_async().await?;
+        let response = self.inner.send(with_authorization(&request, &header)?).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        self.token.invalidate(&header);
+        let header = self.token.header_async().await?;
+        self.inner.send(with_authorization(&request, &header)?).await
+    }
+}
+
+fn with_authorization(request: &Request<Vec<u8>>, header: &str) -> Result<Request<Vec<u8>>, HttpError> {
+    let mut copy = Request::builder()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version())
+        .body(request.body().clone())?;
+    *copy.headers_mut() = request.headers().clone();
+    copy.headers_mut().insert(AUTHORIZATION, header.parse()?);
+    Ok(copy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a token source whose command prints `token<n>`, n counting its runs
+    fn counting(name: &str, refresh: Duration) -> Arc<TokenSource> {
+        let counter = std::env::temp_dir().join(format!("otk-token-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&counter);
+        let command = format!("n=$(($(cat {0} 2>/dev/null || echo 0) + 1)); echo $n > {0}; echo token$n", counter.display());
+        TokenSource::shared(&command, refresh)
+    }
+
+    #[test]
+    fn reruns_when_rejected_or_stale() {
+        let token = counting("rejected", Duration::from_secs(60));
+        assert_eq!(token.header().unwrap(), "Bearer token1");
+        assert_eq!(token.header().unwrap(), "Bearer token1");
+        // a rejection of an older token keeps the current one
+        token.invalidate("Bearer token0");
+        assert_eq!(token.header().unwrap(), "Bearer token1");
+        token.invalidate("Bearer token1");
+        assert_eq!(token.header().unwrap(), "Bearer token2");
+
+        let token = counting("stale", Duration::ZERO);
+        assert_eq!(token.header().unwrap(), "Bearer token1");
+        assert_eq!(token.header().unwrap(), "Bearer token2");
+    }
+
+    #[test]
+    fn keeps_a_printed_scheme() {
+        let token = TokenSource::shared("echo Basic dXNlcjpwYXNz", Duration::from_secs(60));
+        assert_eq!(token.header().unwrap(), "Basic dXNlcjpwYXNz");
+        assert!(TokenSource::shared("true", Duration::from_secs(60)).header().is_err());
+        assert!(TokenSource::shared("exit 3", Duration::from_secs(60)).header().is_err());
+    }
+
+    #[test]
+    fn header_from_runtimes() {
+        let token = counting("runtimes", Duration::from_secs(60));
+        let multi = tokio::runtime::Runtime::new().unwrap();
+        assert_eq!(multi.block_on(token.header_async()).unwrap(), "Bearer token1");
+        token.invalidate("Bearer token1");
+        let in_place = multi.block_on(multi.spawn({
+            let token = token.clone();
+            async move { token.header_in_place() }
+        }));
+        assert_eq!(in_place.unwrap().unwrap(), "Bearer token2");
+        token.invalidate("Bearer token2");
+        let current = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        assert_eq!(current.block_on(async { token.header_in_place() }).unwrap(), "Bearer token3");
+    }
+}
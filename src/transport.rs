@@ -0,0 +1,599 @@
+use crate::common::{parse_duration, KeyValue, ValueType};
+use crate::compression::{CompressingClient, Compression};
+use crate::dry_run::{DryRun, DryRunClient};
+use crate::export::{Exports, StatusClient};
+use crate::https::{self, HttpsClient};
+use crate::otk_error::OTKError;
+use crate::otlp_json::JsonSpanExporter;
+use crate::token::{TokenClient, TokenSource};
+use crate::unix_socket;
+use async_trait::async_trait;
+use clap::Args;
+use hyper::header::{HeaderName, HeaderValue};
+use opentelemetry::logs::LogError;
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry::trace::TraceError;
+use opentelemetry_http::HttpClient;
+use opentelemetry_otlp::{
+    HttpExporterBuilder, LogExporter, LogExporterBuilder, MetricsExporterBuilder, SpanExporter, SpanExporterBuilder,
+    TonicExporterBuilder, WithExportConfig,
+};
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, TemporalitySelector};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use std::collections::HashMap;
+use std::error;
+use std::fs::read_to_string;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use strum_macros::{Display, EnumString};
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::{AsciiMetadataKey, MetadataMap};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, ClientTlsConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq, Display, EnumString)]
+pub enum Protocol {
+    #[strum(serialize = "grpc", serialize = "g")]
+    Grpc,
+    #[strum(serialize = "http", serialize = "h")]
+    Http,
+    #[strum(serialize = "http_json", serialize = "hj")]
+    HttpJson,
+}
+
+/// http version of the http exporters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum HttpVersion {
+    #[strum(serialize = "1.1", serialize = "1")]
+    Http1,
+    /// http/2 with prior knowledge (h2c without tls)
+    #[strum(serialize = "2")]
+    Http2,
+}
+
+/// hosted backends with a --vendor preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+pub enum Vendor {
+    /// otlp gateway over http, basic auth from GRAFANA_CLOUD_INSTANCE_ID and
+    /// GRAFANA_CLOUD_API_KEY, region from GRAFANA_CLOUD_ZONE (e.g.
+    /// prod-eu-west-2)
+    #[strum(serialize = "grafana-cloud", serialize = "grafana")]
+    GrafanaCloud,
+    /// grpc with HONEYCOMB_API_KEY, and HONEYCOMB_DATASET if set (classic
+    /// environments and metrics need it)
+    #[strum(serialize = "honeycomb")]
+    Honeycomb,
+    /// agentless otlp intake over http with DD_API_KEY, site from DD_SITE
+    #[strum(serialize = "datadog")]
+    Datadog,
+    /// grpc with NEW_RELIC_LICENSE_KEY, the eu endpoint when
+    /// NEW_RELIC_REGION=eu
+    #[strum(serialize = "newrelic", serialize = "new-relic")]
+    NewRelic,
+}
+
+pub static DEFAULT_GRPC_PORT: u16 = 4317;
+pub static DEFAULT_HTTP_PORT: u16 = 4318;
+pub static DEFAULT_HTTP_JSON_PORT: u16 = 4318;
+
+/// protocol, endpoint, tls and metadata options shared by all commands that
+/// talk to an otlp receiver
+#[derive(Args, Debug, Clone)]
+pub struct TransportOpts {
+    /// protocol to use (grpc, http or http_json)
+    #[clap(long, default_value = "grpc")]
+    pub protocol: Protocol,
+
+    /// whether to use tls
+    #[clap(long)]
+    pub tls: bool,
+
+    /// CA cert path if tls is enabled
+    #[clap(long, requires = "tls")]
+    pub ca_cert: Option<String>,
+
+    /// server host name to verify
+    #[clap(long, requires = "tls")]
+    pub domain: Option<String>,
+
+    /// accept any server certificate (http only), e.g. the self-signed one
+    /// of a test ingress
+    #[clap(long, requires = "tls")]
+    pub insecure_skip_verify: bool,
+
+    /// preset protocol, endpoint and auth headers of a hosted backend
+    /// (grafana-cloud, honeycomb, datadog or newrelic), the api key is taken
+    /// from the environment
+    #[clap(long, conflicts_with_all = ["protocol", "url", "host", "port"])]
+    pub vendor: Option<Vendor>,
+
+    /// full url as base (overrides host and port), or unix:///path/to/socket
+    /// for grpc over a unix domain socket
+    #[clap(long, alias = "endpoint")]
+    pub url: Option<String>,
+
+    /// server host
+    #[clap(long, default_value = "localhost", env = "OTK_REPORT_HOST")]
+    pub host: String,
+
+    /// server port (default value depends on protocol)
+    #[clap(long, env = "OTK_REPORT_PORT")]
+    pub port: Option<u16>,
+
+    /// metadata map value, sent as request headers over http
+    #[clap(long, num_args = 0..)]
+    pub metadata: Vec<KeyValue>,
+
+    /// repeat the whole send once per value with this metadata key set to
+    /// it (key=a,b,c)
+    #[clap(long)]
+    pub sweep_metadata: Option<KeyValue>,
+
+    /// send timeout (this is a general timeout and might be restricted by other
+    /// timeout, like batch processor timeout)
+    #[clap(long, value_parser = parse_duration, default_value = "10s")]
+    pub timeout: Duration,
+
+    /// http version for http and http_json (1.1 or 2), grpc is always 2
+    #[clap(long)]
+    pub http_version: Option<HttpVersion>,
+
+    /// grpc per-call deadline sent as `grpc-timeout` (e.g. 500ms), separate
+    /// from --timeout so deadline propagation by proxies can be tested
+    #[clap(long, value_parser = parse_duration)]
+    pub call_deadline: Option<Duration>,
+
+    /// command printing an access token (e.g. `gcloud auth print-access-token`)
+    /// sent as `authorization: Bearer <token>`. it is rerun every
+    /// --token-refresh, and when a request gets a 401 (UNAUTHENTICATED over
+    /// grpc), which is then retried once
+    #[clap(long)]
+    pub token_command: Option<String>,
+
+    /// how long a --token-command token is used before rerunning the command
+    #[clap(long, default_value = "45m", value_parser = parse_duration)]
+    pub token_refresh: Duration,
+
+    /// request compression (none, gzip or zstd), zstd only over http and
+    /// http_json as the grpc exporters only compress with gzip
+    #[clap(long, default_value = "none")]
+    pub compression: Compression,
+
+    /// print the requests instead of sending them, set from the --dry-run of
+    /// the report commands
+    #[clap(skip)]
+    pub dry_run: Option<DryRun>,
+
+    /// retries and results of the exports, set from the --retries of the
+    /// report commands
+    #[clap(skip)]
+    pub exports: Arc<Exports>,
+}
+
+/// exporter builder for the selected protocol, building the span, log and
+/// metrics exporters
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum ExporterBuilder {
+    Tonic(TonicExporterBuilder),
+    Http(HttpExporterBuilder),
+    /// grpc to a unix:// --url
+    Unix(unix_socket::Exporter),
+}
+
+impl TransportOpts {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.protocol {
+            Protocol::Grpc => DEFAULT_GRPC_PORT,
+            Protocol::Http => DEFAULT_HTTP_PORT,
+            Protocol::HttpJson => DEFAULT_HTTP_JSON_PORT,
+        })
+    }
+
+    pub fn endpoint(&self) -> String {
+        if let Some(url) = &self.url {
+            return url.clone();
+        }
+        let scheme = if self.tls { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, self.host, self.port())
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// path of a unix:// --url
+    pub fn unix_socket(&self) -> Option<&str> {
+        self.url.as_deref()?.strip_prefix("unix://")
+    }
+
+    /// unix sockets carry plaintext grpc only
+    pub fn check_unix_socket(&self) -> Result<(), OTKError> {
+        match self.unix_socket() {
+            Some(_) if self.protocol != Protocol::Grpc => Err(OTKError::InvalidArgumentError(
+                "unix:// endpoints only apply to grpc".into(),
+            )),
+            Some(_) if self.tls => Err(OTKError::InvalidArgumentError(
+                "--tls does not apply to unix:// endpoints".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// one transport per `--sweep-metadata` value with that metadata entry
+    /// added, or just this one when not sweeping, with the --vendor preset
+    /// applied
+    pub fn sweep(&self) -> Result<Vec<TransportOpts>, OTKError> {
+        let preset = self.preset()?;
+        Ok(match &preset.sweep_metadata {
+            None => vec![preset],
+            Some(sweep) => sweep
+                .v
+                .split(',')
+                .map(|v| {
+                    let mut transport = preset.clone();
+                    transport.sweep_metadata = None;
+                    transport.metadata.push(KeyValue {
+                        v: v.to_string(),
+                        ..sweep.clone()
+                    });
+                    transport
+                })
+                .collect(),
+        })
+    }
+
+    /// these options with the protocol, url, tls and headers of --vendor
+    /// filled in. headers given with --metadata take precedence
+    pub fn preset(&self) -> Result<TransportOpts, OTKError> {
+        let vendor = match self.vendor {
+            Some(vendor) => vendor,
+            None => return Ok(self.clone()),
+        };
+        let env = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| OTKError::InvalidArgumentError(format!("--vendor {} needs ${}", vendor, name)))
+        };
+        let optional = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let (protocol, url, mut headers) = match vendor {
+            Vendor::GrafanaCloud => {
+                let credentials = format!("{}:{}", env("GRAFANA_CLOUD_INSTANCE_ID")?, env("GRAFANA_CLOUD_API_KEY")?);
+                (
+                    Protocol::Http,
+                    format!("https://otlp-gateway-{}.grafana.net/otlp", env("GRAFANA_CLOUD_ZONE")?),
+                    vec![("authorization", format!("Basic {}", base64::encode(credentials)))],
+                )
+            }
+            Vendor::Honeycomb => {
+                let mut headers = vec![("x-honeycomb-team", env("HONEYCOMB_API_KEY")?)];
+                headers.extend(optional("HONEYCOMB_DATASET").map(|d| ("x-honeycomb-dataset", d)));
+                (Protocol::Grpc, "https://api.honeycomb.io:443".to_string(), headers)
+            }
+            Vendor::Datadog => (
+                Protocol::Http,
+                format!("https://otlp.{}", optional("DD_SITE").unwrap_or_else(|| "datadoghq.com".into())),
+                vec![("dd-api-key", env("DD_API_KEY")?)],
+            ),
+            Vendor::NewRelic => {
+                let host = match optional("NEW_RELIC_REGION").as_deref() {
+                    Some("eu") | Some("EU") => "otlp.eu01.nr-data.net",
+                    _ => "otlp.nr-data.net",
+                };
+                (Protocol::Grpc, format!("https://{}:4317", host), vec![("api-key", env("NEW_RELIC_LICENSE_KEY")?)])
+            }
+        };
+        headers.retain(|(k, _)| !self.metadata.iter().any(|kv| kv.k.eq_ignore_ascii_case(k)));
+        if self.token_command.is_some() {
+            headers.retain(|(k, _)| *k != "authorization");
+        }
+        let mut preset = self.clone();
+        preset.vendor = None;
+        preset.protocol = protocol;
+        preset.url = Some(url);
+        preset.tls = true;
+        preset.metadata = headers
+            .into_iter()
+            .map(|(k, v)| KeyValue { k: k.to_string(), v, ty: ValueType::String })
+            .chain(self.metadata.iter().cloned())
+            .collect();
+        Ok(preset)
+    }
+
+    pub fn exporter(&self) -> Result<ExporterBuilder, Box<dyn error::Error>> {
+        if let Some(format) = self.dry_run {
+            // http whatever the protocol, the client sees the encoded requests
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(self.endpoint())
+                .with_http_client(DryRunClient { format });
+            return Ok(ExporterBuilder::Http(exporter));
+        }
+        self.check_token()?;
+        self.check_unix_socket()?;
+        if self.call_deadline.is_some() && self.protocol != Protocol::Grpc {
+            return Err(Box::new(OTKError::InvalidArgumentError(
+                "--call-deadline only applies to grpc".into(),
+            )));
+        }
+        match self.protocol {
+            Protocol::Grpc if self.http_version.is_some() => Err(Box::new(OTKError::InvalidArgumentError(
+                "--http-version only applies to http and http_json".into(),
+            ))),
+            Protocol::Grpc => match self.unix_socket() {
+                Some(path) => Ok(ExporterBuilder::Unix(self.unix_exporter(path)?)),
+                None => Ok(ExporterBuilder::Tonic(self.tonic_exporter()?)),
+            },
+            Protocol::Http => Ok(ExporterBuilder::Http(self.http_exporter()?)),
+            Protocol::HttpJson => Err(Box::new(OTKError::UnimplementedError("httpjson".into()))),
+        }
+    }
+
+    fn tonic_exporter(&self) -> Result<TonicExporterBuilder, Box<dyn error::Error>> {
+        if self.insecure_skip_verify {
+            return Err(Box::new(OTKError::UnimplementedError(
+                "--insecure-skip-verify is not supported with grpc".into(),
+            )));
+        }
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(self.endpoint())
+            .with_timeout(self.timeout());
+        let exporter = match self.grpc_compression()? {
+            Some(_) => exporter.with_compression(opentelemetry_otlp::Compression::Gzip),
+            None => exporter,
+        };
+        let exporter = if self.tls {
+            let mut tls_config = ClientTlsConfig::new();
+            // tonic trusts nothing by default, unlike the http client
+            if let Some(ca_cert) = self.ca_cert.clone().or_else(https::system_ca_bundle) {
+                let pem = read_to_string(ca_cert)?;
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+            };
+            if let Some(domain) = &self.domain {
+                tls_config = tls_config.domain_name(domain.clone());
+            }
+            exporter.with_tls_config(tls_config)
+        } else {
+            exporter
+        };
+        let exporter = exporter.with_metadata(self.grpc_metadata()?);
+        Ok(match (self.call_deadline, self.token()) {
+            (None, None) => exporter,
+            (deadline, token) => exporter.with_interceptor(CallOptions { deadline, token }),
+        })
+    }
+
+    /// grpc over the unix socket at `path`, the metadata and call options
+    /// set by one interceptor
+    fn unix_exporter(&self, path: &str) -> Result<unix_socket::Exporter, Box<dyn error::Error>> {
+        let intercept = UnixCall {
+            metadata: self.grpc_metadata()?,
+            options: CallOptions { deadline: self.call_deadline, token: self.token() },
+        };
+        unix_socket::Exporter::new(path, self.timeout(), intercept, self.grpc_compression()?)
+    }
+
+    fn grpc_metadata(&self) -> Result<MetadataMap, Box<dyn error::Error>> {
+        let mut meta_map = MetadataMap::new();
+        for kv in &self.metadata {
+            meta_map.append(
+                AsciiMetadataKey::from_str(kv.k.as_str())?,
+                kv.v.as_str().parse()?,
+            );
+        }
+        Ok(meta_map)
+    }
+
+    /// tonic only compresses with gzip
+    fn grpc_compression(&self) -> Result<Option<CompressionEncoding>, OTKError> {
+        match self.compression {
+            Compression::None => Ok(None),
+            Compression::Gzip => Ok(Some(CompressionEncoding::Gzip)),
+            Compression::Zstd => Err(OTKError::UnimplementedError(
+                "--compression zstd is not supported with grpc, use gzip or --protocol http".into(),
+            )),
+        }
+    }
+
+    /// span exporter for --protocol http_json, which opentelemetry_otlp
+    /// does not provide
+    pub fn json_span_exporter(&self) -> Result<JsonSpanExporter, Box<dyn error::Error>> {
+        if self.tls {
+            return Err(Box::new(OTKError::UnimplementedError(
+                "http_json does not support tls for now".into(),
+            )));
+        }
+        if self.call_deadline.is_some() {
+            return Err(Box::new(OTKError::InvalidArgumentError(
+                "--call-deadline only applies to grpc".into(),
+            )));
+        }
+        self.check_token()?;
+        self.check_unix_socket()?;
+        Ok(JsonSpanExporter::new(
+            &self.endpoint(),
+            self.headers()?,
+            self.token(),
+            self.compression,
+            self.timeout(),
+            self.http2(),
+        ))
+    }
+
+    fn http_exporter(&self) -> Result<HttpExporterBuilder, Box<dyn error::Error>> {
+        let headers = self.headers()?.into_iter().collect::<HashMap<_, _>>();
+        let exporter = opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(self.endpoint())
+            .with_timeout(self.timeout())
+            .with_headers(headers);
+        if self.tls {
+            let config = https::client_config(self.ca_cert.as_deref(), self.insecure_skip_verify, self.http2())?;
+            let client = HttpsClient::new(config, self.domain.clone(), self.http2(), self.timeout());
+            return Ok(self.with_client(exporter, client));
+        }
+        let mut client = reqwest::Client::builder();
+        if self.http2() {
+            client = client.http2_prior_knowledge();
+        }
+        Ok(self.with_client(exporter, client.build()?))
+    }
+
+    fn with_client<C: HttpClient + 'static>(&self, exporter: HttpExporterBuilder, client: C) -> HttpExporterBuilder {
+        match self.compression {
+            Compression::None => self.with_token(exporter, client),
+            compression => self.with_token(exporter, CompressingClient { inner: client, compression }),
+        }
+    }
+
+    fn with_token<C: HttpClient + 'static>(&self, exporter: HttpExporterBuilder, client: C) -> HttpExporterBuilder {
+        match self.token() {
+            Some(token) => exporter.with_http_client(StatusClient { inner: TokenClient { inner: client, token } }),
+            None => exporter.with_http_client(StatusClient { inner: client }),
+        }
+    }
+
+    pub fn token(&self) -> Option<Arc<TokenSource>> {
+        self.token_command.as_ref().map(|command| TokenSource::shared(command, self.token_refresh))
+    }
+
+    /// the token command owns the authorization header
+    pub fn check_token(&self) -> Result<(), OTKError> {
+        if self.token_command.is_some() && self.metadata.iter().any(|kv| kv.k.eq_ignore_ascii_case("authorization")) {
+            return Err(OTKError::InvalidArgumentError(
+                "--token-command sets the authorization header, drop it from --metadata".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// --metadata as http request headers. the http exporter silently skips
+    /// headers it can't send, so they are checked here
+    pub fn headers(&self) -> Result<Vec<(String, String)>, OTKError> {
+        self.metadata
+            .iter()
+            .map(|kv| {
+                HeaderName::from_str(&kv.k)
+                    .map_err(|_| OTKError::InvalidArgumentError(format!("invalid http header name {:?}", kv.k)))?;
+                HeaderValue::from_str(&kv.v)
+                    .map_err(|_| OTKError::InvalidArgumentError(format!("invalid value of http header {}", kv.k)))?;
+                Ok((kv.k.clone(), kv.v.clone()))
+            })
+            .collect()
+    }
+
+    pub fn http2(&self) -> bool {
+        self.http_version == Some(HttpVersion::Http2)
+    }
+}
+
+/// sets the `grpc-timeout` and the --token-command authorization of every
+/// call. an UNAUTHENTICATED answer drops the token, see `Exports`
+#[derive(Clone)]
+struct CallOptions {
+    deadline: Option<Duration>,
+    token: Option<Arc<TokenSource>>,
+}
+
+impl Interceptor for CallOptions {
+    fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(deadline) = self.deadline {
+            req.set_timeout(deadline);
+        }
+        if let Some(token) = &self.token {
+            let header = token.header_in_place().map_err(tonic::Status::unauthenticated)?;
+            let value = header.parse().map_err(|_| tonic::Status::unauthenticated("token is not a valid header"))?;
+            req.metadata_mut().insert("authorization", value);
+        }
+        Ok(req)
+    }
+}
+
+/// --metadata and the call options, over a unix socket where the exporters
+/// get no `with_metadata`
+struct UnixCall {
+    metadata: MetadataMap,
+    options: CallOptions,
+}
+
+impl Interceptor for UnixCall {
+    fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        *req.metadata_mut() = self.metadata.clone();
+        self.options.call(req)
+    }
+}
+
+impl ExporterBuilder {
+    pub fn build_span_exporter(self) -> Result<SpanExporter, TraceError> {
+        match self {
+            ExporterBuilder::Tonic(b) => SpanExporterBuilder::from(b).build_span_exporter(),
+            ExporterBuilder::Http(b) => SpanExporterBuilder::from(b).build_span_exporter(),
+            ExporterBuilder::Unix(e) => Ok(SpanExporter::new(e)),
+        }
+    }
+
+    pub fn build_log_exporter(self) -> Result<LogExporter, LogError> {
+        match self {
+            ExporterBuilder::Tonic(b) => LogExporterBuilder::from(b).build_log_exporter(),
+            ExporterBuilder::Http(b) => LogExporterBuilder::from(b).build_log_exporter(),
+            ExporterBuilder::Unix(e) => Ok(LogExporter::new(e)),
+        }
+    }
+
+    pub fn build_metrics_exporter(
+        self,
+        temporality: Box<dyn TemporalitySelector>,
+        aggregation: Box<dyn AggregationSelector>,
+    ) -> MetricsResult<MetricsExporter> {
+        let inner: Box<dyn PushMetricsExporter> = match self {
+            ExporterBuilder::Tonic(b) => {
+                Box::new(MetricsExporterBuilder::from(b).build_metrics_exporter(temporality, aggregation)?)
+            }
+            ExporterBuilder::Http(b) => {
+                Box::new(MetricsExporterBuilder::from(b).build_metrics_exporter(temporality, aggregation)?)
+            }
+            ExporterBuilder::Unix(exporter) => {
+                Box::new(unix_socket::MetricsExporter { exporter, temporality, aggregation })
+            }
+        };
+        Ok(MetricsExporter { inner })
+    }
+}
+
+/// the metrics exporter of a transport, opentelemetry_otlp's or one over a
+/// unix socket
+pub struct MetricsExporter {
+    inner: Box<dyn PushMetricsExporter>,
+}
+
+impl TemporalitySelector for MetricsExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl AggregationSelector for MetricsExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for MetricsExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        self.inner.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,259 @@
+#[cfg(unix)]
+use crate::https;
+#[cfg(not(unix))]
+use crate::otk_error::OTKError;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use hyper::Body;
+use opentelemetry::logs::LogResult;
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry_http::{HttpError, Request as HttpRequest, Response};
+use opentelemetry_otlp::Error;
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_client::TraceServiceClient;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, TemporalitySelector};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+use tonic::service::Interceptor;
+use tonic::transport::Channel;
+use tonic::Request;
+
+/// the url of requests over a unix socket, which only gives them their
+/// authority
+pub const URL: &str = "http://localhost";
+
+/// the otlp grpc exporters, over a tonic channel connecting to a unix socket.
+/// opentelemetry-otlp 0.14 keeps the channel of `with_channel` but never
+/// uses it, dialing tcp whatever it's given, so the clients are put together
+/// here
+#[derive(Clone)]
+pub struct Exporter {
+    channel: Channel,
+    /// sets the metadata of every call
+    intercept: Arc<Mutex<dyn Interceptor + Send>>,
+    compression: Option<CompressionEncoding>,
+}
+
+impl fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unix_socket::Exporter")
+    }
+}
+
+impl Exporter {
+    /// connects on the first export. must be called within a tokio runtime
+    #[cfg(unix)]
+    pub fn new(
+        path: &str,
+        timeout: Duration,
+        intercept: impl Interceptor + Send + 'static,
+        compression: Option<CompressionEncoding>,
+    ) -> Result<Exporter, Box<dyn error::Error>> {
+        let path = path.to_string();
+        let intercept = Arc::new(Mutex::new(intercept));
+        let channel = tonic::transport::Endpoint::from_static(URL)
+            .timeout(timeout)
+            .connect_with_connector_lazy(tower::service_fn(move |_| tokio::net::UnixStream::connect(path.clone())));
+        Ok(Exporter { channel, intercept, compression })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(
+        _path: &str,
+        _timeout: Duration,
+        _intercept: impl Interceptor + Send + 'static,
+        _compression: Option<CompressionEncoding>,
+    ) -> Result<Exporter, Box<dyn error::Error>> {
+        Err(Box::new(OTKError::UnimplementedError("unix sockets are not available on this platform".into())))
+    }
+
+    fn request<T>(&self, message: T) -> Result<Request<T>, Error> {
+        let call = self.intercept.lock().unwrap().call(Request::new(()));
+        let (metadata, extensions, _) = call?.into_parts();
+        Ok(Request::from_parts(metadata, extensions, message))
+    }
+
+    fn traces(&self) -> TraceServiceClient<Channel> {
+        let client = TraceServiceClient::new(self.channel.clone());
+        match self.compression {
+            Some(compression) => client.send_compressed(compression),
+            None => client,
+        }
+    }
+
+    fn logs(&self) -> LogsServiceClient<Channel> {
+        let client = LogsServiceClient::new(self.channel.clone());
+        match self.compression {
+            Some(compression) => client.send_compressed(compression),
+            None => client,
+        }
+    }
+
+    fn metrics(&self) -> MetricsServiceClient<Channel> {
+        let client = MetricsServiceClient::new(self.channel.clone());
+        match self.compression {
+            Some(compression) => client.send_compressed(compression),
+            None => client,
+        }
+    }
+}
+
+impl SpanExporter for Exporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let exporter = self.clone();
+        Box::pin(async move {
+            let resource_spans = batch.into_iter().map(Into::into).collect();
+            let request = exporter.request(ExportTraceServiceRequest { resource_spans })?;
+            exporter.traces().export(request).await.map_err(Error::from)?;
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl LogExporter for Exporter {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let resource_logs = batch.into_iter().map(Into::into).collect();
+        let request = self.request(ExportLogsServiceRequest { resource_logs })?;
+        self.logs().export(request).await.map_err(Error::from)?;
+        Ok(())
+    }
+}
+
+/// the metrics exporter, as opentelemetry-otlp's takes no client from
+/// outside the crate
+pub struct MetricsExporter {
+    pub exporter: Exporter,
+    pub temporality: Box<dyn TemporalitySelector>,
+    pub aggregation: Box<dyn AggregationSelector>,
+}
+
+impl TemporalitySelector for MetricsExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.temporality.temporality(kind)
+    }
+}
+
+impl AggregationSelector for MetricsExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.aggregation.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for MetricsExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let request = self.exporter.request(ExportMetricsServiceRequest::from(&*metrics))?;
+        self.exporter.metrics().export(request).await.map_err(Error::from)?;
+        Ok(())
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
+
+/// send a request on a new connection to the unix socket at `path`, the
+/// response body still streaming
+#[cfg(unix)]
+pub async fn send_body(path: &str, request: HttpRequest<Body>) -> Result<Response<Body>, HttpError> {
+    let stream = tokio::net::UnixStream::connect(path).await?;
+    https::send_on(stream, request, true).await
+}
+
+#[cfg(not(unix))]
+pub async fn send_body(_path: &str, _request: HttpRequest<Body>) -> Result<Response<Body>, HttpError> {
+    Err(Box::new(OTKError::UnimplementedError("unix sockets are not available on this platform".into())))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use tonic::Status;
+
+    struct Metadata;
+
+    impl Interceptor for Metadata {
+        fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+            req.metadata_mut().insert("x-test", "1".parse().unwrap());
+            Ok(req)
+        }
+    }
+
+    /// answers every call with an OK, recording the paths and metadata
+    fn serve(path: &std::path::Path) -> Arc<Mutex<Vec<String>>> {
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorded = calls.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let calls = recorded.clone();
+                let service = service_fn(move |req: HttpRequest<Body>| {
+                    let call = format!("{} {:?}", req.uri().path(), req.headers().get("x-test"));
+                    calls.lock().unwrap().push(call);
+                    // an empty message, then the status in the trailers
+                    let (mut sender, body) = Body::channel();
+                    tokio::spawn(async move {
+                        let mut trailers = hyper::HeaderMap::new();
+                        trailers.insert("grpc-status", "0".parse().unwrap());
+                        sender.send_data(vec![0; 5].into()).await?;
+                        sender.send_trailers(trailers).await
+                    });
+                    async { Response::builder().header("content-type", "application/grpc").body(body) }
+                });
+                tokio::spawn(Http::new().http2_only(true).serve_connection(stream, service));
+            }
+        });
+        calls
+    }
+
+    #[tokio::test]
+    async fn exports_over_socket() {
+        let path = std::env::temp_dir().join(format!("otk-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let calls = serve(&path);
+        let mut exporter = Exporter::new(path.to_str().unwrap(), Duration::from_secs(5), Metadata, None).unwrap();
+        SpanExporter::export(&mut exporter, vec![]).await.unwrap();
+        LogExporter::export(&mut exporter, vec![]).await.unwrap();
+
+        let request = HttpRequest::post(format!("{}/opentelemetry.proto.collector.metrics.v1.MetricsService/Export", URL))
+            .body(Body::empty())
+            .unwrap();
+        let response = send_body(path.to_str().unwrap(), request).await.unwrap();
+        assert_eq!(response.headers()["content-type"], "application/grpc");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "/opentelemetry.proto.collector.trace.v1.TraceService/Export Some(\"1\")",
+                "/opentelemetry.proto.collector.logs.v1.LogsService/Export Some(\"1\")",
+                "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export None",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_without_socket() {
+        let mut exporter = Exporter::new("/nonexistent/otk.sock", Duration::from_secs(5), Metadata, None).unwrap();
+        let err = SpanExporter::export(&mut exporter, vec![]).await.unwrap_err();
+        assert!(err.to_string().contains("No such file"), "{}", err);
+    }
+}
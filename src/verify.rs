@@ -0,0 +1,138 @@
+use crate::https::{self, HttpsClient};
+use crate::otk_error::OTKError;
+use hyper::StatusCode;
+use opentelemetry::trace::TraceId;
+use opentelemetry_http::{HttpClient, Request};
+use std::time::{Duration, Instant};
+
+/// how long one query may take
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// budget of --verify without --max-e2e-latency
+pub const DEFAULT_BUDGET: Duration = Duration::from_secs(30);
+
+/// url of --verify, an http or https url with a `{trace_id}` placeholder
+pub fn parse_verify_url(s: &str) -> Result<String, OTKError> {
+    if !(s.starts_with("http://") || s.starts_with("https://")) || !s.contains("{trace_id}") {
+        return Err(OTKError::ParseError(format!(
+            "invalid url {} (expect an http or https url with {{trace_id}} in it)",
+            s
+        )));
+    }
+    Ok(s.to_string())
+}
+
+/// poll `url` for every trace until each one is answered with a success,
+/// returning how long the slowest took from the start, or fail once `budget`
+/// has passed
+pub async fn verify(url: &str, traces: &[TraceId], budget: Duration) -> Result<Duration, OTKError> {
+    let client = client(url)?;
+    let start = Instant::now();
+    let mut pending = traces.to_vec();
+    let mut last_error = None;
+    loop {
+        let mut still = vec![];
+        for trace_id in pending {
+            match query(client.as_ref(), &url.replace("{trace_id}", &format!("{:032x}", trace_id))).await {
+                Ok(true) => {}
+                Ok(false) => still.push(trace_id),
+                Err(e) => {
+                    last_error = Some(e);
+                    still.push(trace_id);
+                }
+            }
+        }
+        pending = still;
+        let elapsed = start.elapsed();
+        if pending.is_empty() {
+            return Ok(elapsed);
+        }
+        if elapsed >= budget {
+            let mut message = format!(
+                "{} of {} traces not queryable within {:?}, like {:032x}",
+                pending.len(),
+                traces.len(),
+                budget,
+                pending[0]
+            );
+            if let Some(e) = last_error {
+                message.push_str(&format!(" (last error: {})", e));
+            }
+            return Err(OTKError::CheckError(message));
+        }
+        tokio::time::sleep(POLL_INTERVAL.min(budget - elapsed)).await;
+    }
+}
+
+fn client(url: &str) -> Result<Box<dyn HttpClient>, OTKError> {
+    let failed = |e: String| OTKError::InvalidArgumentError(format!("--verify client: {}", e));
+    // reqwest is built without tls, https goes through the exporters' client
+    Ok(match url.starts_with("https://") {
+        true => {
+            let config = https::client_config(None, false, false).map_err(|e| failed(e.to_string()))?;
+            Box::new(HttpsClient::new(config, None, false, QUERY_TIMEOUT))
+        }
+        false => Box::new(reqwest::Client::builder().timeout(QUERY_TIMEOUT).build().map_err(|e| failed(e.to_string()))?),
+    })
+}
+
+/// whether the trace is there: a success, or a 404 while it isn't
+async fn query(client: &dyn HttpClient, url: &str) -> Result<bool, String> {
+    let request = Request::get(url).body(vec![]).map_err(|e| e.to_string())?;
+    let response = client.send(request).await.map_err(|e| e.to_string())?;
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => Err(format!("HTTP {}", status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{self, Received};
+    use crate::proto::collector::trace::v1::ExportTraceServiceRequest;
+    use crate::proto::trace::v1::{ResourceSpans, ScopeSpans, Span};
+    use std::sync::{Arc, Mutex};
+
+    fn trace(id: TraceId) -> ExportTraceServiceRequest {
+        let span = Span { trace_id: id.to_bytes().to_vec(), span_id: vec![1; 8], ..Default::default() };
+        ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                scope_spans: vec![ScopeSpans { spans: vec![span], ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn urls() {
+        assert!(parse_verify_url("http://localhost:8080/traces/{trace_id}").is_ok());
+        assert!(parse_verify_url("http://localhost:8080/traces").is_err());
+        assert!(parse_verify_url("localhost:8080/traces/{trace_id}").is_err());
+    }
+
+    #[tokio::test]
+    async fn waits_for_traces() {
+        let received = Arc::new(Mutex::new(Received::default()));
+        let addr = api::serve("127.0.0.1:0".parse().unwrap(), received.clone()).unwrap();
+        let url = format!("http://{}/traces/{{trace_id}}", addr);
+        let (early, late) = (TraceId::from(1), TraceId::from(2));
+        received.lock().unwrap().add_traces(&trace(early));
+        let arrive = {
+            let received = received.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                received.lock().unwrap().add_traces(&trace(late));
+            })
+        };
+        let latency = verify(&url, &[early, late], Duration::from_secs(5)).await.unwrap();
+        assert!(latency >= Duration::from_millis(300), "{:?}", latency);
+        arrive.await.unwrap();
+
+        let missing = verify(&url, &[TraceId::from(3)], Duration::from_millis(300)).await.unwrap_err();
+        assert!(missing.to_string().contains("1 of 1 traces not queryable within 300ms"), "{}", missing);
+    }
+}